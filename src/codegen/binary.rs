@@ -0,0 +1,610 @@
+use crate::codegen::instructions::{BlockType, Definition, HeapType, Opcodes, Types};
+use crate::codegen::module::{Function, Module, ENTRY_POINT};
+
+const MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6d];
+const VERSION: [u8; 4] = [0x01, 0x00, 0x00, 0x00];
+
+const TYPE_SECTION: u8 = 1;
+const IMPORT_SECTION: u8 = 2;
+const FUNCTION_SECTION: u8 = 3;
+const TABLE_SECTION: u8 = 4;
+const MEMORY_SECTION: u8 = 5;
+const GLOBAL_SECTION: u8 = 6;
+const EXPORT_SECTION: u8 = 7;
+const ELEMENT_SECTION: u8 = 9;
+const CODE_SECTION: u8 = 10;
+const DATA_SECTION: u8 = 11;
+
+const RECURSION_GROUP: u8 = 0x4e;
+const FUNCTION_TYPE: u8 = 0x60;
+const STRUCT_TYPE: u8 = 0x5f;
+const ARRAY_TYPE: u8 = 0x5e;
+const PACKED_I8: u8 = 0x78;
+const GC_PREFIX: u8 = 0xfb;
+const FUNCTION_REFERENCE: u8 = 0x70;
+const FUNCTION_KIND: u8 = 0x00;
+const MEMORY_KIND: u8 = 0x02;
+const EMPTY_BLOCK: u8 = 0x40;
+const END: u8 = 0x0b;
+
+type Signature = (Vec<Types>, Vec<Types>);
+
+pub fn write_unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn write_signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_unsigned(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_vector<T>(out: &mut Vec<u8>, items: &[T], mut write_item: impl FnMut(&mut Vec<u8>, &T)) {
+    write_unsigned(out, items.len() as u64);
+    for item in items {
+        write_item(out, item);
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, contents: Vec<u8>) {
+    out.push(id);
+    write_unsigned(out, contents.len() as u64);
+    out.extend(contents);
+}
+
+fn type_code(value_type: &Types) -> u8 {
+    match value_type {
+        Types::I32 => 0x7f,
+        Types::I64 => 0x7e,
+        Types::F64 => 0x7c,
+        Types::EqRef => 0x6d,
+    }
+}
+
+/// Abstract heap types have their own codes, defined ones are referred to by
+/// their index in the type section
+fn heap_type_code(heap_type: &HeapType) -> u8 {
+    match heap_type {
+        HeapType::Eq => 0x6d,
+        HeapType::I31 => 0x6c,
+        defined => HeapType::DEFINED
+            .iter()
+            .position(|candidate| candidate == defined)
+            .unwrap() as u8,
+    }
+}
+
+/// Instructions of the GC proposal share a prefix
+fn write_gc_instruction(out: &mut Vec<u8>, opcode: u8, heap_type: Option<&HeapType>) {
+    out.push(GC_PREFIX);
+    out.push(opcode);
+    if let Some(heap_type) = heap_type {
+        out.push(heap_type_code(heap_type));
+    }
+}
+
+/// Encodes `module` in the WebAssembly binary format
+pub fn encode(module: &Module) -> Vec<u8> {
+    let signatures = collect_signatures(module);
+    let heap_types = module.heap_types();
+    let signature_index = |signature: &Signature| {
+        let index = signatures
+            .iter()
+            .position(|candidate| candidate == signature)
+            .unwrap();
+        heap_types.len() + index
+    };
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&VERSION);
+
+    let mut types = Vec::new();
+    if heap_types.is_empty() {
+        write_unsigned(&mut types, signatures.len() as u64);
+    } else {
+        write_unsigned(&mut types, 1 + signatures.len() as u64);
+        types.push(RECURSION_GROUP);
+        write_unsigned(&mut types, heap_types.len() as u64);
+    }
+    for heap_type in heap_types {
+        match heap_type.definition() {
+            Some(Definition::Struct(fields)) => {
+                types.push(STRUCT_TYPE);
+                write_vector(&mut types, fields, |out, field| {
+                    out.push(type_code(field));
+                    out.push(0x00);
+                });
+            }
+            Some(Definition::Array(element)) => {
+                types.push(ARRAY_TYPE);
+                types.push(element.as_ref().map_or(PACKED_I8, type_code));
+                types.push(0x01);
+            }
+            None => {}
+        }
+    }
+    for (params, results) in &signatures {
+        types.push(FUNCTION_TYPE);
+        write_vector(&mut types, params, |out, param| out.push(type_code(param)));
+        write_vector(&mut types, results, |out, result| {
+            out.push(type_code(result))
+        });
+    }
+    write_section(&mut out, TYPE_SECTION, types);
+
+    if !module.imports.is_empty() {
+        let mut imports = Vec::new();
+        write_vector(&mut imports, &module.imports, |out, import| {
+            write_name(out, import.module());
+            write_name(out, import.name());
+            out.push(FUNCTION_KIND);
+            let index = signature_index(&(import.params(), import.results()));
+            write_unsigned(out, index as u64);
+        });
+        write_section(&mut out, IMPORT_SECTION, imports);
+    }
+
+    let mut functions = Vec::new();
+    write_vector(&mut functions, &module.functions, |out, function| {
+        let index = signature_index(&function_signature(function));
+        write_unsigned(out, index as u64);
+    });
+    write_section(&mut out, FUNCTION_SECTION, functions);
+
+    if !module.table.is_empty() {
+        let mut table = Vec::new();
+        write_unsigned(&mut table, 1);
+        table.push(FUNCTION_REFERENCE);
+        // the table has a fixed size
+        table.push(0x01);
+        write_unsigned(&mut table, module.table.len() as u64);
+        write_unsigned(&mut table, module.table.len() as u64);
+        write_section(&mut out, TABLE_SECTION, table);
+    }
+
+    let mut memory = Vec::new();
+    write_unsigned(&mut memory, 1);
+    memory.push(0x00);
+    write_unsigned(&mut memory, module.memory_pages as u64);
+    write_section(&mut out, MEMORY_SECTION, memory);
+
+    if !module.globals.is_empty() {
+        let mut globals = Vec::new();
+        write_vector(&mut globals, &module.globals, |out, global| {
+            out.push(type_code(&global.value_type));
+            out.push(global.mutable as u8);
+            encode_instruction(out, &global.value, module);
+            out.push(END);
+        });
+        write_section(&mut out, GLOBAL_SECTION, globals);
+    }
+
+    let mut exports = Vec::new();
+    let mut export_count = 1;
+    write_name(&mut exports, "memory");
+    exports.push(MEMORY_KIND);
+    write_unsigned(&mut exports, 0);
+    if let Some(index) = module.function_index(ENTRY_POINT) {
+        export_count += 1;
+        write_name(&mut exports, "_start");
+        exports.push(FUNCTION_KIND);
+        write_unsigned(&mut exports, index as u64);
+    }
+    let mut export_section = Vec::new();
+    write_unsigned(&mut export_section, export_count);
+    export_section.extend(exports);
+    write_section(&mut out, EXPORT_SECTION, export_section);
+
+    if !module.table.is_empty() {
+        let mut elements = Vec::new();
+        write_unsigned(&mut elements, 1);
+        // active segment in table 0, starting at its first slot
+        elements.push(0x00);
+        encode_instruction(&mut elements, &Opcodes::I32Const(0), module);
+        elements.push(END);
+        write_vector(&mut elements, &module.table, |out, name| {
+            write_unsigned(out, module.function_index(name).unwrap() as u64)
+        });
+        write_section(&mut out, ELEMENT_SECTION, elements);
+    }
+
+    let mut code = Vec::new();
+    write_vector(&mut code, &module.functions, |out, function| {
+        let body = encode_function_body(function, module);
+        write_unsigned(out, body.len() as u64);
+        out.extend(body);
+    });
+    write_section(&mut out, CODE_SECTION, code);
+
+    if !module.data.is_empty() {
+        let mut data = Vec::new();
+        write_vector(&mut data, &module.data, |out, segment| {
+            // active segment in memory 0
+            out.push(0x00);
+            encode_instruction(out, &segment.location, module);
+            out.push(END);
+            write_unsigned(out, segment.data.len() as u64);
+            out.extend_from_slice(&segment.data);
+        });
+        write_section(&mut out, DATA_SECTION, data);
+    }
+
+    out
+}
+
+fn function_signature(function: &Function) -> Signature {
+    (function.params.clone(), function.results.clone())
+}
+
+/// Signatures of the imported and defined functions, followed by those
+/// functions are called indirectly with
+fn collect_signatures(module: &Module) -> Vec<Signature> {
+    let mut signatures = Vec::new();
+    let imported = module
+        .imports
+        .iter()
+        .map(|import| (import.params(), import.results()));
+    let defined = module.functions.iter().map(function_signature);
+    let indirect = module
+        .functions
+        .iter()
+        .flat_map(|function| &function.body)
+        .filter_map(|instruction| match instruction {
+            Opcodes::CallIndirect(params, results)
+            | Opcodes::ReturnCallIndirect(params, results) => {
+                Some((params.clone(), results.clone()))
+            }
+            _ => None,
+        });
+    for signature in imported.chain(defined).chain(indirect) {
+        if !signatures.contains(&signature) {
+            signatures.push(signature);
+        }
+    }
+    signatures
+}
+
+/// Index of `signature` in the type section, after the heap types
+fn type_index(module: &Module, signature: &Signature) -> usize {
+    let index = collect_signatures(module)
+        .iter()
+        .position(|candidate| candidate == signature)
+        .unwrap();
+    module.heap_types().len() + index
+}
+
+fn encode_function_body(function: &Function, module: &Module) -> Vec<u8> {
+    let mut body = Vec::new();
+    // locals are declared as runs of the same type
+    let mut runs: Vec<(u32, Types)> = Vec::new();
+    for local in &function.locals {
+        match runs.last_mut() {
+            Some((count, value_type)) if value_type == local => *count += 1,
+            _ => runs.push((1, *local)),
+        }
+    }
+    write_vector(&mut body, &runs, |out, (count, value_type)| {
+        write_unsigned(out, *count as u64);
+        out.push(type_code(value_type));
+    });
+    for instruction in &function.body {
+        encode_instruction(&mut body, instruction, module);
+    }
+    body.push(END);
+    body
+}
+
+fn write_memory_argument(out: &mut Vec<u8>, alignment: u32, offset: u32) {
+    write_unsigned(out, alignment as u64);
+    write_unsigned(out, offset as u64);
+}
+
+fn block_type_code(block_type: &BlockType) -> u8 {
+    match block_type {
+        BlockType::Empty => EMPTY_BLOCK,
+        BlockType::Value(value_type) => type_code(value_type),
+    }
+}
+
+fn encode_instruction(out: &mut Vec<u8>, instruction: &Opcodes, module: &Module) {
+    match instruction {
+        Opcodes::Block(block_type) => {
+            out.push(0x02);
+            out.push(block_type_code(block_type));
+        }
+        Opcodes::Loop(block_type) => {
+            out.push(0x03);
+            out.push(block_type_code(block_type));
+        }
+        Opcodes::If(block_type) => {
+            out.push(0x04);
+            out.push(block_type_code(block_type));
+        }
+        Opcodes::Else => out.push(0x05),
+        Opcodes::End => out.push(END),
+        Opcodes::Br(depth) => {
+            out.push(0x0c);
+            write_unsigned(out, *depth as u64);
+        }
+        Opcodes::BrIf(depth) => {
+            out.push(0x0d);
+            write_unsigned(out, *depth as u64);
+        }
+        Opcodes::BrTable(depths, default) => {
+            out.push(0x0e);
+            write_unsigned(out, depths.len() as u64);
+            for depth in depths {
+                write_unsigned(out, *depth as u64);
+            }
+            write_unsigned(out, *default as u64);
+        }
+        Opcodes::LocalGet(index) => {
+            out.push(0x20);
+            write_unsigned(out, *index as u64);
+        }
+        Opcodes::LocalSet(index) => {
+            out.push(0x21);
+            write_unsigned(out, *index as u64);
+        }
+        Opcodes::LocalTee(index) => {
+            out.push(0x22);
+            write_unsigned(out, *index as u64);
+        }
+        Opcodes::GlobalGet(name) => {
+            out.push(0x23);
+            write_unsigned(out, module.global_index(name).unwrap() as u64);
+        }
+        Opcodes::GlobalSet(name) => {
+            out.push(0x24);
+            write_unsigned(out, module.global_index(name).unwrap() as u64);
+        }
+        Opcodes::I32Add => out.push(0x6a),
+        Opcodes::I32Sub => out.push(0x6b),
+        Opcodes::I32Mul => out.push(0x6c),
+        Opcodes::I32Eqz => out.push(0x45),
+        Opcodes::I32Eq => out.push(0x46),
+        Opcodes::I32Ne => out.push(0x47),
+        Opcodes::I32LtS => out.push(0x48),
+        Opcodes::I32GtS => out.push(0x4a),
+        Opcodes::I32GtU => out.push(0x4b),
+        Opcodes::I32LeS => out.push(0x4c),
+        Opcodes::I32GeS => out.push(0x4e),
+        Opcodes::I32GeU => out.push(0x4f),
+        Opcodes::I32And => out.push(0x71),
+        Opcodes::I32Or => out.push(0x72),
+        Opcodes::I32Shl => out.push(0x74),
+        Opcodes::I32ShrU => out.push(0x76),
+        Opcodes::I32Clz => out.push(0x67),
+        Opcodes::I32Xor => out.push(0x73),
+        Opcodes::I32Popcnt => out.push(0x69),
+        Opcodes::Select => out.push(0x1b),
+        Opcodes::RefNull(heap_type) => {
+            out.push(0xd0);
+            out.push(heap_type_code(heap_type));
+        }
+        Opcodes::RefIsNull => out.push(0xd1),
+        Opcodes::RefEq => out.push(0xd3),
+        Opcodes::RefI31 => write_gc_instruction(out, 0x1c, None),
+        Opcodes::I31GetU => write_gc_instruction(out, 0x1e, None),
+        Opcodes::RefTest(heap_type) => write_gc_instruction(out, 0x14, Some(heap_type)),
+        Opcodes::RefCast(heap_type) => write_gc_instruction(out, 0x16, Some(heap_type)),
+        Opcodes::StructNew(heap_type) => write_gc_instruction(out, 0x00, Some(heap_type)),
+        Opcodes::StructGet(heap_type, field) => {
+            write_gc_instruction(out, 0x02, Some(heap_type));
+            write_unsigned(out, *field as u64);
+        }
+        Opcodes::ArrayNewDefault(heap_type) => write_gc_instruction(out, 0x07, Some(heap_type)),
+        Opcodes::ArrayGet(heap_type) => write_gc_instruction(out, 0x0b, Some(heap_type)),
+        Opcodes::ArrayGetU(heap_type) => write_gc_instruction(out, 0x0d, Some(heap_type)),
+        Opcodes::ArraySet(heap_type) => write_gc_instruction(out, 0x0e, Some(heap_type)),
+        Opcodes::ArrayLen => write_gc_instruction(out, 0x0f, None),
+        Opcodes::I32Load(offset) => {
+            out.push(0x28);
+            write_memory_argument(out, 2, *offset);
+        }
+        Opcodes::I32Load8U(offset) => {
+            out.push(0x2d);
+            write_memory_argument(out, 0, *offset);
+        }
+        Opcodes::I64Load(offset) => {
+            out.push(0x29);
+            write_memory_argument(out, 3, *offset);
+        }
+        Opcodes::F64Load(offset) => {
+            out.push(0x2b);
+            write_memory_argument(out, 3, *offset);
+        }
+        Opcodes::I32Store(offset) => {
+            out.push(0x36);
+            write_memory_argument(out, 2, *offset);
+        }
+        Opcodes::I32Store8(offset) => {
+            out.push(0x3a);
+            write_memory_argument(out, 0, *offset);
+        }
+        Opcodes::I64Store(offset) => {
+            out.push(0x37);
+            write_memory_argument(out, 3, *offset);
+        }
+        Opcodes::F64Store(offset) => {
+            out.push(0x39);
+            write_memory_argument(out, 3, *offset);
+        }
+        // the zero byte refers to the only memory
+        Opcodes::MemorySize => out.extend_from_slice(&[0x3f, 0x00]),
+        Opcodes::MemoryGrow => out.extend_from_slice(&[0x40, 0x00]),
+        Opcodes::I32Const(constant) => {
+            out.push(0x41);
+            write_signed(out, *constant as i64);
+        }
+        Opcodes::I32TruncF64S => out.push(0xaa),
+        Opcodes::I64Const(constant) => {
+            out.push(0x42);
+            write_signed(out, *constant);
+        }
+        Opcodes::I64Add => out.push(0x7c),
+        Opcodes::I64Sub => out.push(0x7d),
+        Opcodes::I64Mul => out.push(0x7e),
+        Opcodes::I64DivS => out.push(0x7f),
+        Opcodes::I64DivU => out.push(0x80),
+        Opcodes::I64RemS => out.push(0x81),
+        Opcodes::I64RemU => out.push(0x82),
+        Opcodes::I64And => out.push(0x83),
+        Opcodes::I64Xor => out.push(0x85),
+        Opcodes::I64ShrU => out.push(0x88),
+        Opcodes::I64Eqz => out.push(0x50),
+        Opcodes::I64Eq => out.push(0x51),
+        Opcodes::I64Ne => out.push(0x52),
+        Opcodes::I64LtS => out.push(0x53),
+        Opcodes::I64GtS => out.push(0x55),
+        Opcodes::I64GtU => out.push(0x56),
+        Opcodes::I64LeS => out.push(0x57),
+        Opcodes::I64GeS => out.push(0x59),
+        Opcodes::I64GeU => out.push(0x5a),
+        Opcodes::I64ExtendI32S => out.push(0xac),
+        Opcodes::I64TruncF64S => out.push(0xb0),
+        Opcodes::I64ReinterpretF64 => out.push(0xbd),
+        Opcodes::I32WrapI64 => out.push(0xa7),
+        Opcodes::F64Const(constant) => {
+            out.push(0x44);
+            out.extend_from_slice(&constant.to_bits().to_le_bytes());
+        }
+        Opcodes::F64Add => out.push(0xa0),
+        Opcodes::F64Sub => out.push(0xa1),
+        Opcodes::F64Mul => out.push(0xa2),
+        Opcodes::F64Div => out.push(0xa3),
+        Opcodes::F64Min => out.push(0xa4),
+        Opcodes::F64Max => out.push(0xa5),
+        Opcodes::F64Abs => out.push(0x99),
+        Opcodes::F64Neg => out.push(0x9a),
+        Opcodes::F64Floor => out.push(0x9c),
+        Opcodes::F64Trunc => out.push(0x9d),
+        Opcodes::F64Nearest => out.push(0x9e),
+        Opcodes::F64Eq => out.push(0x61),
+        Opcodes::F64Ne => out.push(0x62),
+        Opcodes::F64Lt => out.push(0x63),
+        Opcodes::F64Gt => out.push(0x64),
+        Opcodes::F64Le => out.push(0x65),
+        Opcodes::F64Ge => out.push(0x66),
+        Opcodes::F64ConvertI64S => out.push(0xb9),
+        Opcodes::Call(name) => {
+            out.push(0x10);
+            write_unsigned(out, module.function_index(name).unwrap() as u64);
+        }
+        Opcodes::ReturnCall(name) => {
+            out.push(0x12);
+            write_unsigned(out, module.function_index(name).unwrap() as u64);
+        }
+        Opcodes::CallIndirect(params, results) | Opcodes::ReturnCallIndirect(params, results) => {
+            out.push(match instruction {
+                Opcodes::CallIndirect(..) => 0x11,
+                _ => 0x13,
+            });
+            let signature = (params.clone(), results.clone());
+            write_unsigned(out, type_index(module, &signature) as u64);
+            // the zero byte refers to the only table
+            out.push(0x00);
+        }
+        Opcodes::Return => out.push(0x0f),
+        Opcodes::Drop => out.push(0x1a),
+        Opcodes::Unreachable => out.push(0x00),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::binary::{encode, write_signed, write_unsigned};
+    use crate::codegen::instructions::{HeapType, Opcodes, Types};
+    use crate::codegen::module::{Function, Memory, Module};
+
+    #[test]
+    fn encode_unsigned_leb128() {
+        let mut out = Vec::new();
+        write_unsigned(&mut out, 624485);
+        assert_eq!(out, vec![0xe5, 0x8e, 0x26]);
+    }
+
+    #[test]
+    fn encode_signed_leb128() {
+        let mut out = Vec::new();
+        write_signed(&mut out, -123456);
+        write_signed(&mut out, 64);
+        write_signed(&mut out, -1);
+        assert_eq!(out, vec![0xc0, 0xbb, 0x78, 0xc0, 0x00, 0x7f]);
+    }
+
+    #[test]
+    fn encode_module() {
+        let mut module = Module::new(Memory::Arena);
+        module.functions.push(Function {
+            name: "main".to_owned(),
+            params: vec![],
+            results: vec![Types::I32],
+            locals: vec![],
+            body: vec![Opcodes::I32Const(1)],
+        });
+
+        let expected: Vec<u8> = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // type section
+            0x03, 0x02, 0x01, 0x00, // function section
+            0x05, 0x03, 0x01, 0x00, 0x01, // memory section
+            0x07, 0x13, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, 0x06, b'_',
+            b's', b't', b'a', b'r', b't', 0x00, 0x00, // export section
+            0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x01, 0x0b, // code section
+        ];
+        assert_eq!(encode(&module), expected);
+    }
+
+    #[test]
+    fn engine_managed_memory_defines_heap_types_first() {
+        let mut module = Module::new(Memory::Engine);
+        module.functions.push(Function {
+            name: "main".to_owned(),
+            params: vec![],
+            results: vec![Types::EqRef],
+            locals: vec![],
+            body: vec![Opcodes::RefNull(HeapType::Eq)],
+        });
+
+        let expected: Vec<u8> = vec![
+            0x01, 0x3d, 0x02, 0x4e, 0x0a, // type section
+            0x5f, 0x01, 0x7e, 0x00, // integers
+            0x5f, 0x01, 0x7c, 0x00, // floats
+            0x5e, 0x78, 0x01, // strings
+            0x5f, 0x05, 0x7f, 0x00, 0x7f, 0x00, 0x7f, 0x00, 0x6d, 0x00, 0x6d, 0x00, // vectors
+            0x5e, 0x6d, 0x01, // nodes
+            0x5f, 0x01, 0x6d, 0x00, // keywords
+            0x5f, 0x02, 0x6d, 0x00, 0x7f, 0x00, // maps
+            0x5f, 0x02, 0x7f, 0x00, 0x6d, 0x00, // map nodes
+            0x5f, 0x01, 0x6d, 0x00, // sets
+            0x5f, 0x03, 0x7f, 0x00, 0x7f, 0x00, 0x6d, 0x00, // closures
+            0x60, 0x00, 0x01, 0x6d, // main
+            0x03, 0x02, 0x01, 0x0a, // function section
+        ];
+        let encoded = encode(&module);
+        assert_eq!(encoded[8..75], expected[..]);
+        assert_eq!(encoded[encoded.len() - 3..], [0xd0, 0x6d, 0x0b]);
+    }
+}
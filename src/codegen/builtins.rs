@@ -0,0 +1,126 @@
+/// Functions of clojure.core which are called by name rather than being
+/// scanned as keywords. A user defined function with the same name takes
+/// precedence over them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Builtin {
+    Quot,
+    Rem,
+    Mod,
+    Inc,
+    Dec,
+    Max,
+    Min,
+    Abs,
+    Not,
+    UncheckedAdd,
+    UncheckedSubtract,
+    UncheckedMultiply,
+    UncheckedInc,
+    UncheckedDec,
+    UncheckedNegate,
+    Count,
+    Nth,
+    Get,
+    Conj,
+    Assoc,
+    Peek,
+    Pop,
+    Subvec,
+    Dissoc,
+    Disj,
+    ContainsKey,
+    Keys,
+    Vals,
+    Merge,
+    Update,
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "quot" => Some(Builtin::Quot),
+            "rem" => Some(Builtin::Rem),
+            "mod" => Some(Builtin::Mod),
+            "inc" => Some(Builtin::Inc),
+            "dec" => Some(Builtin::Dec),
+            "max" => Some(Builtin::Max),
+            "min" => Some(Builtin::Min),
+            "abs" => Some(Builtin::Abs),
+            "not" => Some(Builtin::Not),
+            "unchecked-add" => Some(Builtin::UncheckedAdd),
+            "unchecked-subtract" => Some(Builtin::UncheckedSubtract),
+            "unchecked-multiply" => Some(Builtin::UncheckedMultiply),
+            "unchecked-inc" => Some(Builtin::UncheckedInc),
+            "unchecked-dec" => Some(Builtin::UncheckedDec),
+            "unchecked-negate" => Some(Builtin::UncheckedNegate),
+            "count" => Some(Builtin::Count),
+            "nth" => Some(Builtin::Nth),
+            "get" => Some(Builtin::Get),
+            "conj" => Some(Builtin::Conj),
+            "assoc" => Some(Builtin::Assoc),
+            "peek" => Some(Builtin::Peek),
+            "pop" => Some(Builtin::Pop),
+            "subvec" => Some(Builtin::Subvec),
+            "dissoc" => Some(Builtin::Dissoc),
+            "disj" => Some(Builtin::Disj),
+            "contains?" => Some(Builtin::ContainsKey),
+            "keys" => Some(Builtin::Keys),
+            "vals" => Some(Builtin::Vals),
+            "merge" => Some(Builtin::Merge),
+            "update" => Some(Builtin::Update),
+            _ => None,
+        }
+    }
+
+    /// Whether the function can be called with `count` arguments
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Builtin::Quot
+            | Builtin::Rem
+            | Builtin::Mod
+            | Builtin::UncheckedAdd
+            | Builtin::UncheckedSubtract
+            | Builtin::UncheckedMultiply
+            | Builtin::ContainsKey => count == 2,
+            Builtin::Inc
+            | Builtin::Dec
+            | Builtin::Abs
+            | Builtin::Not
+            | Builtin::UncheckedInc
+            | Builtin::UncheckedDec
+            | Builtin::UncheckedNegate
+            | Builtin::Count
+            | Builtin::Peek
+            | Builtin::Pop
+            | Builtin::Keys
+            | Builtin::Vals => count == 1,
+            Builtin::Max | Builtin::Min | Builtin::Conj | Builtin::Dissoc | Builtin::Disj => {
+                count >= 1
+            }
+            Builtin::Nth | Builtin::Get | Builtin::Subvec => count == 2 || count == 3,
+            Builtin::Assoc => count >= 3 && count % 2 == 1,
+            Builtin::Merge => true,
+            Builtin::Update => count >= 3,
+        }
+    }
+    /// Number of arguments the function takes when it is passed around as a
+    /// value, a closure having a single arity
+    pub fn value_arity(&self) -> usize {
+        match self {
+            Builtin::Inc
+            | Builtin::Dec
+            | Builtin::Abs
+            | Builtin::Not
+            | Builtin::UncheckedInc
+            | Builtin::UncheckedDec
+            | Builtin::UncheckedNegate
+            | Builtin::Count
+            | Builtin::Peek
+            | Builtin::Pop
+            | Builtin::Keys
+            | Builtin::Vals => 1,
+            Builtin::Assoc | Builtin::Update => 3,
+            _ => 2,
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use crate::codegen::instructions::{BlockType, HeapType, Opcodes, Types};
+use crate::codegen::module::{Function, Memory};
+use crate::codegen::runtime::{reference, Runtime, ALLOCATE, STACK_POINTER};
+use crate::codegen::types::Tag;
+
+/// In linear memory, the index of the function of a closure in the table, its
+/// arity and the number of values it captures follow its tag. A reference to
+/// each captured value follows them at offset 16.
+const INDEX_OFFSET: u32 = 4;
+const ARITY_OFFSET: u32 = 8;
+const COUNT_OFFSET: u32 = 12;
+const CAPTURED: u32 = 16;
+
+/// Arity of the closures standing for a call left to the trampoline, which
+/// cannot be called like functions
+pub const THUNK: i32 = -1;
+
+/// Fields of a closure the engine manages, whose captured values are held in
+/// a node array which is null when there are none
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Field {
+    Index,
+    Arity,
+    Captured,
+}
+
+fn field(closure: usize, field: Field) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(closure),
+        RefCast(HeapType::Closure),
+        StructGet(HeapType::Closure, field as u32),
+    ]
+}
+
+/// Index in the table of the function of the closure in `closure`
+pub fn index(closure: usize, memory: Memory) -> Vec<Opcodes> {
+    match memory {
+        Memory::Engine => field(closure, Field::Index),
+        _ => vec![Opcodes::LocalGet(closure), Opcodes::I32Load(INDEX_OFFSET)],
+    }
+}
+
+fn arity(closure: usize, memory: Memory) -> Vec<Opcodes> {
+    match memory {
+        Memory::Engine => field(closure, Field::Arity),
+        _ => vec![Opcodes::LocalGet(closure), Opcodes::I32Load(ARITY_OFFSET)],
+    }
+}
+
+/// Whether the closures in `left` and `right` stand for the same function,
+/// which they do when they are made over the same function without capturing
+/// anything, as those made for functions defined with `defn` are
+pub fn same_function(left: usize, right: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    let captures_nothing = |closure: usize| match memory {
+        Memory::Engine => [field(closure, Field::Captured), vec![RefIsNull]].concat(),
+        _ => vec![LocalGet(closure), I32Load(COUNT_OFFSET), I32Eqz],
+    };
+    [
+        index(left, memory),
+        index(right, memory),
+        vec![I32Eq],
+        captures_nothing(left),
+        vec![I32And],
+        captures_nothing(right),
+        vec![I32And],
+    ]
+    .concat()
+}
+
+/// Stores the value `value` leaves on the stack as the captured value at
+/// `position` of the closure in `closure`
+pub fn capture(
+    closure: usize,
+    position: u32,
+    mut value: Vec<Opcodes>,
+    memory: Memory,
+) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    let mut body = match memory {
+        Memory::Engine => [
+            field(closure, Field::Captured),
+            vec![RefCast(HeapType::Node), I32Const(position as i32)],
+        ]
+        .concat(),
+        _ => vec![LocalGet(closure)],
+    };
+    body.append(&mut value);
+    body.push(match memory {
+        Memory::Engine => ArraySet(HeapType::Node),
+        _ => I32Store(CAPTURED + 4 * position),
+    });
+    body
+}
+
+/// The captured value at `position` of the closure in `closure`
+pub fn captured(closure: usize, position: u32, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => [
+            field(closure, Field::Captured),
+            vec![
+                RefCast(HeapType::Node),
+                I32Const(position as i32),
+                ArrayGet(HeapType::Node),
+            ],
+        ]
+        .concat(),
+        _ => vec![LocalGet(closure), I32Load(CAPTURED + 4 * position)],
+    }
+}
+
+/// Calls `mark` on each value captured by the object in `object` when it is
+/// a closure, whose tag is in `tag`
+pub fn mark_references(object: usize, tag: usize, index: usize, mark: Opcodes) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(tag),
+        I32Const(Tag::Closure as i32),
+        I32Eq,
+        If(BlockType::Empty),
+        I32Const(0),
+        LocalSet(index),
+        Block(BlockType::Empty),
+        Loop(BlockType::Empty),
+        LocalGet(index),
+        LocalGet(object),
+        I32Load(COUNT_OFFSET),
+        I32GeS,
+        BrIf(1),
+        LocalGet(object),
+        LocalGet(index),
+        I32Const(4),
+        I32Mul,
+        I32Add,
+        I32Load(CAPTURED),
+        mark,
+        LocalGet(index),
+        I32Const(1),
+        I32Add,
+        LocalSet(index),
+        Br(0),
+        End,
+        End,
+        End,
+    ]
+}
+
+/// Returns a closure over the function at the index in the table given as
+/// first argument, which takes the number of arguments given as second
+/// argument and captures the number of values given as third argument. The
+/// captured values are stored with `capture` once the closure is made.
+pub fn make_closure(name: &str, memory: Memory) -> Function {
+    const INDEX: usize = 0;
+    const ARITY: usize = 1;
+    const COUNT: usize = 2;
+    const CLOSURE: usize = 3;
+    use Opcodes::*;
+
+    if memory == Memory::Engine {
+        return Function {
+            name: name.to_owned(),
+            params: vec![Types::I32; 3],
+            results: vec![Types::EqRef],
+            locals: vec![],
+            body: vec![
+                LocalGet(INDEX),
+                LocalGet(ARITY),
+                LocalGet(COUNT),
+                If(BlockType::Value(Types::EqRef)),
+                LocalGet(COUNT),
+                ArrayNewDefault(HeapType::Node),
+                Else,
+                RefNull(HeapType::Eq),
+                End,
+                StructNew(HeapType::Closure),
+            ],
+        };
+    }
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32; 3],
+        results: vec![Types::I32],
+        locals: vec![Types::I32],
+        body: vec![
+            LocalGet(COUNT),
+            I32Const(4),
+            I32Mul,
+            I32Const(CAPTURED as i32),
+            I32Add,
+            Call(ALLOCATE.to_owned()),
+            LocalTee(CLOSURE),
+            I32Const(Tag::Closure as i32),
+            I32Store(0),
+            LocalGet(CLOSURE),
+            LocalGet(INDEX),
+            I32Store(INDEX_OFFSET),
+            LocalGet(CLOSURE),
+            LocalGet(ARITY),
+            I32Store(ARITY_OFFSET),
+            LocalGet(CLOSURE),
+            LocalGet(COUNT),
+            I32Store(COUNT_OFFSET),
+            // captured values start out nil, as the collector may mark the
+            // closure before they are all stored
+            Block(BlockType::Empty),
+            Loop(BlockType::Empty),
+            LocalGet(COUNT),
+            I32Eqz,
+            BrIf(1),
+            LocalGet(COUNT),
+            I32Const(1),
+            I32Sub,
+            LocalTee(COUNT),
+            I32Const(4),
+            I32Mul,
+            LocalGet(CLOSURE),
+            I32Add,
+            I32Const(0),
+            I32Store(CAPTURED),
+            Br(0),
+            End,
+            End,
+            LocalGet(CLOSURE),
+        ],
+    }
+}
+
+/// Index in the table of the function to call when calling the value given as
+/// first argument with the number of arguments given as second argument.
+/// Closures must take that many arguments, maps and keywords one or two and
+/// sets and vectors one, which are looked up by the functions at index 0 and
+/// 1. Other values raise a ClassCastException.
+pub fn entry(name: &str, memory: Memory) -> Function {
+    const CALLEE: usize = 0;
+    const ARITY: usize = 1;
+    const TAG: usize = 2;
+    use Opcodes::*;
+
+    let is_tag = |tag: Tag| vec![LocalGet(TAG), I32Const(tag as i32), I32Eq];
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), Types::I32],
+        results: vec![Types::I32],
+        locals: vec![Types::I32],
+        body: [
+            vec![LocalGet(CALLEE), call(Runtime::TypeOf), LocalSet(TAG)],
+            is_tag(Tag::Closure),
+            vec![If(BlockType::Empty)],
+            arity(CALLEE, memory),
+            vec![
+                LocalGet(ARITY),
+                I32Ne,
+                If(BlockType::Empty),
+                call(Runtime::WrongArgumentCount),
+                End,
+            ],
+            index(CALLEE, memory),
+            vec![Return, End],
+            is_tag(Tag::Map),
+            is_tag(Tag::Keyword),
+            vec![
+                I32Or,
+                If(BlockType::Empty),
+                LocalGet(ARITY),
+                I32Const(1),
+                I32Sub,
+                LocalTee(ARITY),
+                I32Const(1),
+                I32GtU,
+                If(BlockType::Empty),
+                call(Runtime::WrongArgumentCount),
+                End,
+                LocalGet(ARITY),
+                Return,
+                End,
+            ],
+            is_tag(Tag::Set),
+            is_tag(Tag::Vector),
+            vec![
+                I32Or,
+                If(BlockType::Empty),
+                LocalGet(ARITY),
+                I32Const(1),
+                I32Ne,
+                If(BlockType::Empty),
+                call(Runtime::WrongArgumentCount),
+                End,
+                I32Const(0),
+                Return,
+                End,
+                call(Runtime::NotAFunction),
+                I32Const(0),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Function of the closures standing for a call with `arity` arguments, which
+/// calls the closure captured first with the values captured after it
+pub fn thunk(name: &str, arity: usize, memory: Memory) -> Function {
+    const THUNK: usize = 0;
+    use Opcodes::*;
+
+    let mut body = vec![];
+    for position in 0..=arity {
+        body.append(captured(THUNK, position as u32, memory).as_mut());
+    }
+    body.append(captured(THUNK, 0, memory).as_mut());
+    body.push(I32Const(arity as i32));
+    body.push(call(Runtime::ClosureEntry));
+    body.push(CallIndirect(
+        vec![reference(memory); arity + 1],
+        vec![reference(memory)],
+    ));
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body,
+    }
+}
+
+/// Makes the calls its argument stands for until it gets a value which is not
+/// a thunk, and returns that value. With collected memory, only the last
+/// value stays rooted.
+pub fn trampoline(name: &str, memory: Memory) -> Function {
+    const VALUE: usize = 0;
+    const FRAME: usize = 1;
+    use Opcodes::*;
+
+    let collected = memory == Memory::Collected;
+    let mut body = vec![];
+    if collected {
+        body.append(vec![GlobalGet(STACK_POINTER.to_owned()), LocalSet(FRAME)].as_mut());
+    }
+    body.append(
+        vec![
+            Block(BlockType::Empty),
+            Loop(BlockType::Empty),
+            LocalGet(VALUE),
+            call(Runtime::TypeOf),
+            I32Const(Tag::Closure as i32),
+            I32Ne,
+            BrIf(1),
+        ]
+        .as_mut(),
+    );
+    body.append(arity(VALUE, memory).as_mut());
+    body.append(vec![I32Const(THUNK), I32Ne, BrIf(1), LocalGet(VALUE)].as_mut());
+    body.append(index(VALUE, memory).as_mut());
+    body.push(CallIndirect(
+        vec![reference(memory)],
+        vec![reference(memory)],
+    ));
+    body.push(LocalSet(VALUE));
+    if collected {
+        body.append(
+            vec![
+                LocalGet(FRAME),
+                GlobalSet(STACK_POINTER.to_owned()),
+                LocalGet(VALUE),
+                call(Runtime::Root),
+                Drop,
+            ]
+            .as_mut(),
+        );
+    }
+    body.append(vec![Br(0), End, End, LocalGet(VALUE)].as_mut());
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![reference(memory)],
+        locals: if collected { vec![Types::I32] } else { vec![] },
+        body,
+    }
+}
+
+fn call(runtime: Runtime) -> Opcodes {
+    Opcodes::Call(runtime.name().to_owned())
+}
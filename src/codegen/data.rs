@@ -0,0 +1,184 @@
+use crate::codegen::instructions::{OpData, Opcodes};
+use crate::codegen::types::Tag;
+use std::collections::HashMap;
+
+/// Every literal starts on an 8 byte boundary
+const DATA_ALIGNMENT: u32 = 8;
+
+/// A string is stored as a heap object, its tag being followed by its byte
+/// length and its bytes. Keywords are stored the same way with their name.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StaticString {
+    pub address: i32,
+    pub length: i32,
+}
+
+/// Lays out the literals of a program in linear memory. Each distinct
+/// literal gets its own non overlapping region and identical literals share
+/// the same one, so that keywords with the same name are the same object.
+/// Keywords are also numbered in the order they are laid out, which gives
+/// their index in the symbol table interning them when the engine manages
+/// memory.
+pub struct DataLayout {
+    next_address: u32,
+    strings: HashMap<String, StaticString>,
+    keywords: HashMap<String, StaticString>,
+    symbols: Vec<String>,
+    segments: Vec<OpData>,
+}
+
+impl DataLayout {
+    pub fn new(start: u32) -> Self {
+        DataLayout {
+            next_address: align(start),
+            strings: HashMap::new(),
+            keywords: HashMap::new(),
+            symbols: Vec::new(),
+            segments: Vec::new(),
+        }
+    }
+
+    pub fn add_string(&mut self, string: &str) -> StaticString {
+        if let Some(existing) = self.strings.get(string) {
+            return *existing;
+        }
+        let location = self.add_text(Tag::String, string);
+        self.strings.insert(string.to_owned(), location);
+        location
+    }
+
+    pub fn add_keyword(&mut self, name: &str) -> StaticString {
+        if let Some(existing) = self.keywords.get(name) {
+            return *existing;
+        }
+        let location = self.add_text(Tag::Keyword, name);
+        self.keywords.insert(name.to_owned(), location);
+        self.symbols.push(name.to_owned());
+        location
+    }
+
+    /// Index in the symbol table of a keyword which has been laid out
+    pub fn symbol(&self, name: &str) -> Option<i32> {
+        self.symbols
+            .iter()
+            .position(|symbol| symbol == name)
+            .map(|index| index as i32)
+    }
+
+    pub fn symbol_count(&self) -> u32 {
+        self.symbols.len() as u32
+    }
+
+    fn add_text(&mut self, tag: Tag, text: &str) -> StaticString {
+        let location = StaticString {
+            address: self.next_address as i32,
+            length: text.len() as i32,
+        };
+        let mut data = (tag as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        data.extend_from_slice(text.as_bytes());
+        self.next_address = align(self.next_address + data.len() as u32);
+        self.segments.push(OpData {
+            location: Opcodes::I32Const(location.address),
+            data,
+        });
+        location
+    }
+
+    /// Reserves `size` bytes the runtime uses as scratch space, which start
+    /// out zeroed
+    pub fn reserve(&mut self, size: u32) -> i32 {
+        let address = self.next_address;
+        self.next_address = align(address + size);
+        address as i32
+    }
+
+    /// First address past the laid out data
+    pub fn end(&self) -> u32 {
+        self.next_address
+    }
+
+    pub fn into_segments(self) -> Vec<OpData> {
+        self.segments
+    }
+}
+
+fn align(address: u32) -> u32 {
+    (address + DATA_ALIGNMENT - 1) / DATA_ALIGNMENT * DATA_ALIGNMENT
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::data::{DataLayout, StaticString};
+
+    #[test]
+    fn strings_do_not_overlap() {
+        let mut layout = DataLayout::new(60);
+
+        let hello = layout.add_string("Hello world\n");
+        let bye = layout.add_string("bye");
+
+        assert_eq!(
+            hello,
+            StaticString {
+                address: 64,
+                length: 12
+            }
+        );
+        assert_eq!(
+            bye,
+            StaticString {
+                address: 88,
+                length: 3
+            }
+        );
+        assert_eq!(layout.end(), 104);
+        assert_eq!(
+            layout.into_segments()[1].data,
+            vec![4, 0, 0, 0, 3, 0, 0, 0, b'b', b'y', b'e']
+        );
+    }
+
+    #[test]
+    fn identical_strings_are_shared() {
+        let mut layout = DataLayout::new(64);
+
+        let first = layout.add_string("again");
+        layout.add_string("other");
+        let second = layout.add_string("again");
+
+        assert_eq!(first, second);
+        assert_eq!(layout.into_segments().len(), 2);
+    }
+
+    #[test]
+    fn keywords_are_apart_from_strings() {
+        let mut layout = DataLayout::new(64);
+
+        let string = layout.add_string("key");
+        let keyword = layout.add_keyword("key");
+
+        assert_ne!(string, keyword);
+        assert_eq!(layout.add_keyword("key"), keyword);
+        layout.add_keyword("other");
+        assert_eq!(layout.symbol("other"), Some(1));
+        assert_eq!(layout.symbol("missing"), None);
+        assert_eq!(layout.symbol_count(), 2);
+        assert_eq!(
+            layout.into_segments()[1].data,
+            vec![7, 0, 0, 0, 3, 0, 0, 0, b'k', b'e', b'y']
+        );
+    }
+
+    #[test]
+    fn reserved_space_is_apart_from_literals() {
+        let mut layout = DataLayout::new(64);
+
+        let scratch = layout.reserve(10);
+        let string = layout.add_string("after");
+
+        assert_eq!(scratch, 64);
+        assert_eq!(string.address, 80);
+        assert_eq!(layout.into_segments().len(), 1);
+    }
+}
@@ -1,175 +1,3320 @@
-use crate::codegen::instructions::{OpData, Opcodes, SysCalls, Types, WASIImports};
-use crate::frontend::ast::{ConstantLiteral, ListDetails, MainDetails, Node};
-use crate::frontend::scanner::Lexeme;
-use crate::frontend::scanner::Lexeme::StringLiteral;
+use crate::codegen::builtins::Builtin;
+use crate::codegen::closure;
+use crate::codegen::data::DataLayout;
+use crate::codegen::environment::{Binding, Environment};
+use crate::codegen::instructions::BlockType;
+use crate::codegen::instructions::{Global, HeapType, Opcodes, ReferenceNumber, Types};
+use crate::codegen::module::{Function, Memory, Module, ENTRY_POINT};
+use crate::codegen::runtime::{
+    reference, Arithmetic, Runtime, DATA_START, FALSE, FREE_LISTS, FREE_LISTS_SIZE, GREY_LIST,
+    GREY_LIST_END, HEAP_POINTER, HEAP_START, SHADOW_STACK_SIZE, STACK_POINTER, SYMBOLS,
+    SYMBOL_COUNT,
+};
+use crate::codegen::types::{Tag, ValueType};
+use crate::frontend::analysis::{free_variables, tail_calls};
+use crate::frontend::ast::{
+    CaseClause, CaseDetails, Clause, CondpDetails, ConstantLiteral, FunctionDetails, IfDetails,
+    KeywordDetails, LambdaDetails, LetDetails, ListDetails, MainDetails, MapItem, Node,
+    VariableInformation,
+};
+use crate::frontend::scanner::{Lexeme, Position};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum EmitError {
+    UnresolvedSymbol(Position, String),
+    NonConstantGlobal(Position, String),
+    WrongArity(Position, Lexeme),
+    NotCallable(Position),
+    OperatorAsValue(Position, Lexeme),
+    NestedDefinition(Position, String),
+}
+
+impl fmt::Display for EmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmitError::UnresolvedSymbol(ref pos, ref name) => {
+                write!(f, "unable to resolve symbol {:?} at {:?}", name, pos)
+            }
+            EmitError::NonConstantGlobal(ref pos, ref name) => {
+                write!(f, "global {:?} at {:?} must be a constant", name, pos)
+            }
+            EmitError::WrongArity(ref pos, ref function) => write!(
+                f,
+                "wrong number of arguments passed to {:?} at {:?}",
+                function, pos
+            ),
+            EmitError::NotCallable(ref pos) => {
+                write!(f, "the form at {:?} cannot be called", pos)
+            }
+            EmitError::OperatorAsValue(ref pos, ref operator) => write!(
+                f,
+                "{:?} at {:?} can only be called, not used as a value",
+                operator, pos
+            ),
+            EmitError::NestedDefinition(ref pos, ref name) => write!(
+                f,
+                "{:?} at {:?} can only be defined at the top level",
+                name, pos
+            ),
+        }
+    }
+}
+
+type EmitResult = Result<Expression, EmitError>;
+
+/// Name the old value is bound to while `update` calls its function
+const UPDATED: &str = "update value";
+/// Names the predicate and the expression of `condp` are bound to while its
+/// clauses call the predicate
+const CONDP_PREDICATE: &str = "condp predicate";
+const CONDP_EXPRESSION: &str = "condp expression";
+/// Name the expression of `case` is bound to while its keys are compared
+/// with it
+const CASE_EXPRESSION: &str = "case expression";
+
+/// Instructions which leave exactly one value on the stack, along with the
+/// static type of that value
+struct Expression {
+    body: Vec<Opcodes>,
+    value_type: ValueType,
+}
+
+impl Expression {
+    fn new(body: Vec<Opcodes>, value_type: ValueType) -> Self {
+        Expression { body, value_type }
+    }
+}
+
+/// Types of the parameters and of the result of a user defined function,
+/// which are not known before a call or the body has been emitted
+#[derive(Debug, Clone, PartialEq)]
+struct Signature {
+    params: Vec<Option<ValueType>>,
+    result: Option<ValueType>,
+}
+
+/// An anonymous function whose body is emitted once the function creating its
+/// closures is done, under the name the table refers to it by. The values of
+/// the `captured` variables are loaded from the closure it is called with.
+struct PendingLambda {
+    name: String,
+    details: LambdaDetails,
+    captured: Vec<String>,
+}
+
+/// The loop or function `recur` jumps back to the start of, after assigning
+/// the new values of its bindings to `locals`. The types of loop bindings are
+/// inferred like parameter types, under `key`, which is left out for functions
+/// whose parameters are always references. `depth` is the number of blocks
+/// open inside the loop, and `frame` holds the top of the shadow stack when
+/// the loop started once a `recur` needs to drop what it rooted since.
+#[derive(Debug, Clone)]
+struct RecurTarget {
+    key: Option<String>,
+    locals: Vec<(ReferenceNumber, ValueType)>,
+    depth: u32,
+    frame: Option<ReferenceNumber>,
+    used: bool,
+}
+
+/// How calls in tail position of a function are made
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TailCalls {
+    /// Like any other call, so that every call takes room on the stack
+    Plain,
+    /// With `return_call` from the tail call proposal, which replaces the
+    /// frame of the caller with that of the callee
+    Native,
+    /// By returning a thunk standing for the call, which the trampoline of
+    /// the nearest caller outside tail position makes. Engines without the
+    /// tail call proposal run such calls without growing the stack.
+    Trampoline,
+}
+
+/// Choices made for a whole build
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Options {
+    pub memory: Memory,
+    pub tail_calls: TailCalls,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            memory: Memory::Collected,
+            tail_calls: TailCalls::Plain,
+        }
+    }
+}
 
 pub struct Emitter {
-    imports: Vec<WASIImports>,
-    data: Vec<OpData>,
+    options: Options,
+    module: Module,
+    data: DataLayout,
+    /// Signatures user defined functions are emitted and called with, as well
+    /// as the types of the bindings of each loop as parameters, under a key
+    /// naming where the loop is
+    functions: HashMap<String, Signature>,
+    /// Signatures widened by the argument types and holding the result types
+    /// seen while emitting, which replace `functions` for the next pass
+    observed: HashMap<String, Signature>,
+    environment: Environment,
+    lambdas: Vec<PendingLambda>,
+    /// Number of blocks open in the function being emitted
+    depth: u32,
+    recur: Option<RecurTarget>,
+    /// Positions of the names called in tail position
+    tail_positions: HashSet<Position>,
+    /// Type of the value the function being emitted returns, if any
+    result: Option<ValueType>,
+    /// Local holding the top of the shadow stack when the function being
+    /// emitted started, once a tail call needs to drop its frame
+    entry: Option<ReferenceNumber>,
 }
 
 impl Emitter {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(options: Options) -> Self {
         Emitter {
-            imports: Vec::new(),
-            data: Vec::new(),
+            options,
+            module: Module::new(options.memory),
+            data: DataLayout::new(DATA_START),
+            functions: HashMap::new(),
+            observed: HashMap::new(),
+            environment: Environment::new(),
+            lambdas: Vec::new(),
+            depth: 0,
+            recur: None,
+            tail_positions: HashSet::new(),
+            result: None,
+            entry: None,
+        }
+    }
+
+    /// Function signatures are inferred by emitting the program until they
+    /// stop changing. Parameter types only ever widen, while results are
+    /// worked out afresh on every pass so that a guess made before the
+    /// parameters were known does not stick.
+    pub fn emit(mut self, head: Vec<Node>) -> Result<Module, EmitError> {
+        self.declare_definitions(&head);
+        self.tail_positions = head.iter().flat_map(tail_calls).collect();
+        loop {
+            self.module = Module::new(self.options.memory);
+            self.data = DataLayout::new(DATA_START);
+            self.observed = self.functions.clone();
+            for signature in self.observed.values_mut() {
+                signature.result = None;
+            }
+            self.build_body(&head)?;
+            if self.observed == self.functions {
+                break;
+            }
+            self.functions = self.observed.clone();
         }
+        self.emit_heap();
+        self.module.data = self.data.into_segments();
+        Ok(self.module)
     }
 
-    pub fn emit(&mut self, head: Vec<Node>) -> String {
-        let body = self.build_body(&head);
-        self.get_body_with_header(body)
+    /// Every module can allocate on the heap, which starts after the data,
+    /// the free lists of the allocator and the shadow stack when memory is
+    /// collected. Memory initially ends with them. When the engine manages
+    /// memory, there is no heap and memory only holds the data.
+    fn emit_heap(&mut self) {
+        let memory = self.options.memory;
+        let page_size = 65536;
+        if memory == Memory::Engine {
+            self.module.memory_pages = (self.data.end() + page_size - 1) / page_size;
+            self.emit_symbol_table();
+            return;
+        }
+        self.module.add_runtime(Runtime::Allocate, &mut self.data);
+        self.module.add_runtime(Runtime::Free, &mut self.data);
+        let free_lists = self.data.end();
+        let mut heap_start = free_lists + FREE_LISTS_SIZE;
+        self.module.globals.push(Global {
+            name: FREE_LISTS.to_owned(),
+            value_type: Types::I32,
+            mutable: false,
+            value: Opcodes::I32Const(free_lists as i32),
+        });
+        if memory == Memory::Collected {
+            self.module.globals.push(Global {
+                name: STACK_POINTER.to_owned(),
+                value_type: Types::I32,
+                mutable: true,
+                value: Opcodes::I32Const(heap_start as i32),
+            });
+            heap_start += SHADOW_STACK_SIZE;
+            self.module.globals.push(Global {
+                name: GREY_LIST.to_owned(),
+                value_type: Types::I32,
+                mutable: true,
+                value: Opcodes::I32Const(GREY_LIST_END),
+            });
+            self.module.globals.push(Global {
+                name: HEAP_START.to_owned(),
+                value_type: Types::I32,
+                mutable: false,
+                value: Opcodes::I32Const(heap_start as i32),
+            });
+        }
+        self.module.globals.push(Global {
+            name: HEAP_POINTER.to_owned(),
+            value_type: Types::I32,
+            mutable: true,
+            value: Opcodes::I32Const(heap_start as i32),
+        });
+        self.module.memory_pages = (heap_start + page_size - 1) / page_size;
     }
 
-    fn get_body_with_header(&mut self, mut body: Vec<String>) -> String {
-        body.insert(0, "(module ".to_owned());
-        body.insert(
-            1,
-            self.imports
-                .iter()
-                .map(|item| return item.to_string())
-                .collect(),
-        );
-        body.insert(2, self.emit_memory_initializer());
-        body.insert(
-            3,
-            self.data
-                .iter()
-                .map(|item| return item.to_string())
-                .collect(),
-        );
-        body.append(self.emit_export().as_mut());
-        body.push(")".to_owned());
+    /// The symbol table starts out empty, with room for every keyword
+    fn emit_symbol_table(&mut self) {
+        if self.data.symbol_count() == 0 {
+            return;
+        }
+        self.module.globals.push(Global {
+            name: SYMBOLS.to_owned(),
+            value_type: Types::EqRef,
+            mutable: true,
+            value: Opcodes::RefNull(HeapType::Eq),
+        });
+        self.module.globals.push(Global {
+            name: SYMBOL_COUNT.to_owned(),
+            value_type: Types::I32,
+            mutable: false,
+            value: Opcodes::I32Const(self.data.symbol_count() as i32),
+        });
+    }
 
-        body.join("\n ")
+    /// Records the name of every top level function and global up front so
+    /// that code can refer to definitions further down in the file.
+    fn declare_definitions(&mut self, nodes: &Vec<Node>) {
+        for node in nodes {
+            match node {
+                Node::Function(FunctionDetails {
+                    name: box Node::Variable(name, _),
+                    args,
+                    ..
+                }) => {
+                    let signature = Signature {
+                        params: vec![None; args.len()],
+                        result: None,
+                    };
+                    self.functions.insert(name.to_owned(), signature);
+                }
+                Node::Def(VariableInformation {
+                    name: box Node::Variable(name, _),
+                    value,
+                }) => {
+                    let value_type = match value {
+                        box Node::Constant(ConstantLiteral::FloatLiteral(_)) => ValueType::Float,
+                        _ => ValueType::Integer,
+                    };
+                    self.environment.define_global(name, value_type)
+                }
+                _ => {}
+            }
+        }
     }
 
-    fn build_body(&mut self, nodes: &Vec<Node>) -> Vec<String> {
-        let mut body = Vec::<String>::new();
+    fn build_body(&mut self, nodes: &Vec<Node>) -> Result<(), EmitError> {
         for node in nodes {
-            body.append(self.emit_instructions(node).as_mut())
+            match node {
+                Node::Main(details) => self.emit_main_function(details)?,
+                Node::Function(details) => self.emit_function(details)?,
+                Node::Def(details) => self.emit_global(details)?,
+                // top level calls have no place to live in a module, `main` is
+                // exported as the entry point instead
+                _ => {}
+            }
         }
-        body
+        while !self.lambdas.is_empty() {
+            let lambda = self.lambdas.remove(0);
+            self.emit_lambda_function(lambda)?;
+        }
+        Ok(())
     }
 
-    fn emit_instructions(&mut self, tree: &Node) -> Vec<String> {
-        let mut body = Vec::<String>::new();
+    fn emit_expression(&mut self, tree: &Node) -> EmitResult {
         match tree {
-            Node::List(list) => body.append(self.emit_function_call(list).as_mut()),
-            Node::Null => {}
-            Node::Main(details) => body.append(self.emit_main_function(details).as_mut()),
-            Node::Def(_) => {}
-            Node::Function(_) => {}
-            Node::Constant(constant) => body.append(self.emit_constant(constant).as_mut()),
-            Node::Keyword(_) => {}
-            Node::Variable(_) => {}
-            Node::Map(_) => {}
-            Node::Vector(_) => {}
+            Node::List(list) => self.emit_function_call(list),
+            Node::Constant(constant) => Ok(self.emit_constant(constant)),
+            Node::Variable(name, position) => self.emit_variable(name, position),
+            Node::If(details) => self.emit_if(details),
+            Node::Do(body) => self.emit_body(body),
+            Node::Cond(clauses) => self.emit_cond(clauses),
+            Node::Condp(details) => self.emit_condp(details),
+            Node::Case(details) => self.emit_case(details),
+            Node::Let(details) => self.emit_let(details),
+            Node::Loop(details, position) => self.emit_loop(details, position),
+            Node::Recur(args, position) => self.emit_recur(args, position),
+            Node::KeywordLiteral(name, _) => Ok(self.emit_keyword(name)),
+            Node::Lambda(details) => Ok(self.emit_lambda(details)),
+            Node::Keyword(details) => Err(EmitError::OperatorAsValue(
+                details.position,
+                details.token.clone(),
+            )),
+            Node::Def(VariableInformation {
+                name: box Node::Variable(name, position),
+                ..
+            })
+            | Node::Function(FunctionDetails {
+                name: box Node::Variable(name, position),
+                ..
+            }) => Err(EmitError::NestedDefinition(*position, name.to_owned())),
+            Node::Main(details) => Err(EmitError::NestedDefinition(
+                details.position,
+                "main".to_owned(),
+            )),
+            // the parser only reads definitions named by a variable
+            Node::Def(_) | Node::Function(_) => unreachable!(),
+            Node::Vector(elements) => self.emit_vector(elements),
+            Node::Map(items) => self.emit_map(items),
+            Node::Set(elements) => self.emit_set(elements),
+        }
+    }
+
+    fn emit_main_function(&mut self, details: &MainDetails) -> Result<(), EmitError> {
+        let params = vec![ValueType::Any; details.args.len()];
+        self.result = None;
+        self.environment
+            .enter_function(&parameters(&details.args, &params));
+        let enclosing = self.enter_recur_target(None, params.iter().cloned().enumerate());
+        let result = self.emit_body(details.body.as_ref())?;
+        let mut body = self.leave_recur_target(enclosing, result).body;
+        body.push(Opcodes::Drop);
+        let locals = self.environment.leave_function();
+        let mut locals = self.machine_types(&locals);
+        let frame = params.len() + locals.len();
+        let body = self.emit_frame(body, None, frame, &mut locals);
+        self.module.functions.push(Function {
+            name: ENTRY_POINT.to_owned(),
+            params: self.machine_types(&params),
+            results: vec![],
+            locals,
+            body,
+        });
+        Ok(())
+    }
+
+    fn emit_function(&mut self, details: &FunctionDetails) -> Result<(), EmitError> {
+        let name = match &details.name {
+            box Node::Variable(name, _) => name,
+            _ => return Ok(()),
         };
+        let signature = self.functions[name].clone();
+        let params: Vec<ValueType> = signature.params.into_iter().map(assumed).collect();
+        let result_type = assumed(signature.result);
+        self.result = Some(result_type);
+        self.environment
+            .enter_function(&parameters(&details.args, &params));
+        let enclosing = self.enter_recur_target(Some(name), params.iter().cloned().enumerate());
+        let result = self.emit_body(details.body.as_ref())?;
+        let result = self.leave_recur_target(enclosing, result);
+        if let Some(observed) = self.observed.get_mut(name) {
+            widen(&mut observed.result, result.value_type);
+        }
+        let mut body = self.emit_entry(&params);
+        body.append(self.coerce(result, result_type).as_mut());
+        let locals = self.environment.leave_function();
+        let mut locals = self.machine_types(&locals);
+        let frame = params.len() + locals.len();
+        let body = self.emit_frame(body, Some(result_type), frame, &mut locals);
+        self.module.functions.push(Function {
+            name: name.to_owned(),
+            params: self.machine_types(&params),
+            results: vec![result_type.machine_type(self.options.memory)],
+            locals,
+            body,
+        });
+        Ok(())
+    }
+
+    /// The function of an anonymous function takes its closure followed by
+    /// its arguments, all of them references, and returns a reference. The
+    /// closure is bound to the name of the function, if it has one.
+    fn emit_lambda_function(&mut self, lambda: PendingLambda) -> Result<(), EmitError> {
+        let memory = self.options.memory;
+        let details = &lambda.details;
+        let mut params = vec![(
+            details.name.clone().unwrap_or_default(),
+            ValueType::Function,
+        )];
+        params.extend(parameters(
+            &details.args,
+            &vec![ValueType::Any; details.args.len()],
+        ));
+        self.result = Some(ValueType::Any);
+        self.environment.enter_function(&params);
+        let mut body = vec![];
+        for (position, name) in lambda.captured.iter().enumerate() {
+            body.append(closure::captured(0, position as u32, memory).as_mut());
+            let local = self.environment.declare_local(name, ValueType::Any);
+            body.push(Opcodes::LocalSet(local));
+        }
+        let arguments = (1..params.len()).map(|index| (index, ValueType::Any));
+        let enclosing = self.enter_recur_target(None, arguments);
+        let result = self.emit_body(&details.body)?;
+        let result = self.leave_recur_target(enclosing, result);
+        body.append(self.coerce(result, ValueType::Any).as_mut());
+        let types: Vec<ValueType> = params.iter().map(|(_, value_type)| *value_type).collect();
+        let body = [self.emit_entry(&types), body].concat();
+        let locals = self.environment.leave_function();
+        let mut locals = self.machine_types(&locals);
+        let frame = params.len() + locals.len();
+        let body = self.emit_frame(body, Some(ValueType::Any), frame, &mut locals);
+        self.module.functions.push(Function {
+            name: lambda.name,
+            params: vec![reference(memory); params.len()],
+            results: vec![reference(memory)],
+            locals,
+            body,
+        });
+        Ok(())
+    }
+
+    /// Instructions a function starts with, which keep the top of the shadow
+    /// stack in `entry` when a tail call needs it, before rooting parameters
+    fn emit_entry(&mut self, params: &[ValueType]) -> Vec<Opcodes> {
+        let mut body = vec![];
+        if let Some(entry) = self.entry.take() {
+            body.push(Opcodes::GlobalGet(STACK_POINTER.to_owned()));
+            body.push(Opcodes::LocalSet(entry));
+        }
+        body.append(self.emit_parameter_roots(params).as_mut());
+        body
+    }
+
+    /// With native tail calls and collected memory, functions root their own
+    /// reference parameters, as a caller making a tail call drops its frame
+    /// from the shadow stack before they start
+    fn emit_parameter_roots(&mut self, params: &[ValueType]) -> Vec<Opcodes> {
+        let mut body = vec![];
+        if self.options.tail_calls != TailCalls::Native || self.options.memory != Memory::Collected
+        {
+            return body;
+        }
+        for (index, value_type) in params.iter().enumerate() {
+            if value_type.is_reference() {
+                body.push(Opcodes::LocalGet(index));
+                body.append(self.emit_runtime_call(Runtime::Root).as_mut());
+                body.push(Opcodes::Drop);
+            }
+        }
         body
     }
 
-    fn emit_main_function(&mut self, details: &MainDetails) -> Vec<String> {
-        let mut types = Vec::new();
-        for (index, _) in details.args.iter().enumerate() {
-            types.push(Types::I32param(index).to_string());
+    /// With collected memory, the objects a function roots while it runs are
+    /// dropped from the shadow stack when it returns, keeping only the one it
+    /// returns. The start of its frame is kept in the `frame` local, which is
+    /// only added to `locals` by functions which call anything, since others
+    /// cannot allocate.
+    fn emit_frame(
+        &mut self,
+        body: Vec<Opcodes>,
+        result: Option<ValueType>,
+        frame: ReferenceNumber,
+        locals: &mut Vec<Types>,
+    ) -> Vec<Opcodes> {
+        let calls = body.iter().any(|instruction| match instruction {
+            Opcodes::Call(_) => true,
+            _ => false,
+        });
+        if self.options.memory != Memory::Collected || !calls {
+            return body;
         }
-        let mut body = self.emit_function_body(details.body.as_ref());
-        let mut function = vec!["(func $main ".to_owned()];
-        function.append(types.as_mut());
-        function.append(body.as_mut());
-        function.push(")".to_owned());
-        function
+        locals.push(Types::I32);
+        let mut framed = vec![
+            Opcodes::GlobalGet(STACK_POINTER.to_owned()),
+            Opcodes::LocalSet(frame),
+        ];
+        framed.extend(body);
+        framed.push(Opcodes::LocalGet(frame));
+        framed.push(Opcodes::GlobalSet(STACK_POINTER.to_owned()));
+        if result.map_or(false, ValueType::is_reference) {
+            framed.append(self.emit_runtime_call(Runtime::Root).as_mut());
+        }
+        framed
     }
 
-    fn emit_function_body(&mut self, body: &Vec<Node>) -> Vec<String> {
+    /// Emits a sequence of expressions which evaluates to the value of the
+    /// last one, or to nil when there are none. The values of all the other
+    /// expressions are dropped.
+    fn emit_body(&mut self, body: &Vec<Node>) -> EmitResult {
         let mut instructions = Vec::new();
-        for expression in body {
-            instructions.append(self.emit_instructions(expression).as_mut());
+        let mut value_type = ValueType::Nil;
+        for (index, expression) in body.iter().enumerate() {
+            let mut expression = self.emit_expression(expression)?;
+            instructions.append(expression.body.as_mut());
+            if index == body.len() - 1 {
+                value_type = expression.value_type;
+            } else {
+                instructions.push(Opcodes::Drop);
+            }
+        }
+        if body.is_empty() {
+            instructions.append(self.emit_nil().body.as_mut());
+        }
+        Ok(Expression::new(instructions, value_type))
+    }
+
+    fn emit_if(&mut self, details: &IfDetails) -> EmitResult {
+        self.depth += 1;
+        let then = self.emit_expression(&details.then)?;
+        let otherwise = match &details.otherwise {
+            Some(otherwise) => self.emit_expression(otherwise)?,
+            None => self.emit_nil(),
+        };
+        self.depth -= 1;
+        let value_type = then.value_type.unify(otherwise.value_type);
+
+        let mut body = self.emit_truthiness(&details.test)?;
+        body.push(Opcodes::If(BlockType::Value(
+            value_type.machine_type(self.options.memory),
+        )));
+        body.append(self.coerce(then, value_type).as_mut());
+        body.push(Opcodes::Else);
+        body.append(self.coerce(otherwise, value_type).as_mut());
+        body.push(Opcodes::End);
+        Ok(Expression::new(body, value_type))
+    }
+
+    /// Leaves 1 on the stack when `test` is truthy and 0 otherwise
+    fn emit_truthiness(&mut self, test: &Node) -> Result<Vec<Opcodes>, EmitError> {
+        let test = self.emit_expression(test)?;
+        let mut body = test.body;
+        body.append(self.emit_truthy(test.value_type).as_mut());
+        Ok(body)
+    }
+
+    /// Replaces the value of type `value_type` on top of the stack with 1 when
+    /// it is truthy and 0 otherwise. Only `false` and `nil` are falsey, so
+    /// every other type is always truthy.
+    fn emit_truthy(&mut self, value_type: ValueType) -> Vec<Opcodes> {
+        match value_type {
+            ValueType::Boolean | ValueType::Never => vec![],
+            ValueType::Nil => vec![Opcodes::Drop, Opcodes::I32Const(0)],
+            ValueType::Integer
+            | ValueType::Float
+            | ValueType::String
+            | ValueType::Keyword
+            | ValueType::Vector
+            | ValueType::Map
+            | ValueType::Set
+            | ValueType::Function => vec![Opcodes::Drop, Opcodes::I32Const(1)],
+            ValueType::Any if self.options.memory == Memory::Engine => {
+                self.emit_runtime_call(Runtime::Truthy)
+            }
+            ValueType::Any => vec![Opcodes::I32Const(FALSE), Opcodes::I32GtU],
+        }
+    }
+
+    /// `and` evaluates its arguments in turn until one is falsey and `or`
+    /// until one is truthy, to the value of that one or else of the last
+    /// one. Each argument but the last is kept in a local while it is tested,
+    /// the ones after it being evaluated in the branch of an `if` block.
+    /// Without arguments, `and` is true and `or` nil.
+    fn emit_logical(&mut self, operator: &Lexeme, args: &[Node]) -> EmitResult {
+        let (first, rest) = match args.split_first() {
+            Some((first, rest)) if !rest.is_empty() => (first, rest),
+            Some((only, _)) => return self.emit_expression(only),
+            None if *operator == Lexeme::And => {
+                return Ok(self.emit_constant(&ConstantLiteral::BooleanLiteral(true)))
+            }
+            None => return Ok(self.emit_nil()),
+        };
+        let first = self.emit_expression(first)?;
+        self.depth += 1;
+        let rest = self.emit_logical(operator, rest)?;
+        self.depth -= 1;
+        let value_type = first.value_type.unify(rest.value_type);
+
+        let first_type = first.value_type;
+        let kept = self.environment.declare_temporary(first_type);
+        let mut body = first.body;
+        body.push(Opcodes::LocalTee(kept));
+        body.append(self.emit_truthy(first_type).as_mut());
+        body.push(Opcodes::If(BlockType::Value(
+            value_type.machine_type(self.options.memory),
+        )));
+        let first = Expression::new(vec![Opcodes::LocalGet(kept)], first_type);
+        let (then, otherwise) = match operator {
+            Lexeme::And => (rest, first),
+            _ => (first, rest),
+        };
+        body.append(self.coerce(then, value_type).as_mut());
+        body.push(Opcodes::Else);
+        body.append(self.coerce(otherwise, value_type).as_mut());
+        body.push(Opcodes::End);
+        Ok(Expression::new(body, value_type))
+    }
+
+    /// Converts the value of an expression to `value_type`, which is either
+    /// its own type or one it unifies to. Values of every other type are
+    /// already represented the way `Any` expects them to be, except for nil
+    /// and booleans when the engine manages memory. Code after an expression
+    /// which never leaves a value is unreachable.
+    fn coerce(&mut self, expression: Expression, value_type: ValueType) -> Vec<Opcodes> {
+        let mut body = expression.body;
+        let engine = self.options.memory == Memory::Engine;
+        match (value_type, expression.value_type) {
+            (ValueType::Float, ValueType::Integer) => body.push(Opcodes::F64ConvertI64S),
+            (ValueType::Any, ValueType::Integer) => {
+                body.append(self.emit_runtime_call(Runtime::BoxInteger).as_mut())
+            }
+            (ValueType::Any, ValueType::Float) => {
+                body.append(self.emit_runtime_call(Runtime::BoxFloat).as_mut())
+            }
+            (ValueType::Any, ValueType::Boolean) if engine => body.push(Opcodes::RefI31),
+            (ValueType::Any, ValueType::Boolean) => {
+                body.append(vec![Opcodes::I32Const(FALSE), Opcodes::I32Add].as_mut())
+            }
+            (ValueType::Any, ValueType::Nil) if engine => {
+                body.append(vec![Opcodes::Drop, Opcodes::RefNull(HeapType::Eq)].as_mut())
+            }
+            (ValueType::Never, _) => {}
+            (_, ValueType::Never) => body.push(Opcodes::Unreachable),
+            _ => {}
+        }
+        body
+    }
+
+    /// Evaluates to the value of the branch the instructions of `dispatch`
+    /// branch to, those of `values` at the depths of their index and the
+    /// default right after them. Without a default, getting to it is an
+    /// error. Each branch is a block the instructions of the ones before it
+    /// follow, the instructions of `dispatch` coming first.
+    fn emit_branches(
+        &mut self,
+        values: &[&Node],
+        default: Option<&Node>,
+        dispatch: impl FnOnce(&mut Self) -> Result<Vec<Opcodes>, EmitError>,
+    ) -> EmitResult {
+        let base = self.depth;
+        let count = values.len() as u32;
+        let mut branches = vec![];
+        for (index, value) in values.iter().enumerate() {
+            self.depth = base + 1 + count - index as u32;
+            branches.push(self.emit_expression(value)?);
+        }
+        self.depth = base + 1;
+        let default = match default {
+            Some(default) => self.emit_expression(default)?,
+            None => {
+                let mut body = self.emit_runtime_call(Runtime::NoMatchingClause);
+                body.push(Opcodes::Unreachable);
+                Expression::new(body, ValueType::Never)
+            }
+        };
+        self.depth = base + 2 + count;
+        let mut dispatch = dispatch(self)?;
+        self.depth = base;
+        let value_type = branches
+            .iter()
+            .fold(default.value_type, |value_type, branch| {
+                value_type.unify(branch.value_type)
+            });
+
+        let mut body = vec![Opcodes::Block(BlockType::Value(
+            value_type.machine_type(self.options.memory),
+        ))];
+        for _ in 0..=count {
+            body.push(Opcodes::Block(BlockType::Empty));
+        }
+        body.append(dispatch.as_mut());
+        body.push(Opcodes::End);
+        for (index, branch) in branches.into_iter().enumerate() {
+            body.append(self.coerce(branch, value_type).as_mut());
+            body.push(Opcodes::Br(count - index as u32));
+            body.push(Opcodes::End);
+        }
+        body.append(self.coerce(default, value_type).as_mut());
+        body.push(Opcodes::End);
+        Ok(Expression::new(body, value_type))
+    }
+
+    /// Tests are evaluated in turn until one is truthy. A last test which
+    /// always is, such as `:else`, makes its clause the default.
+    fn emit_cond(&mut self, clauses: &[Clause]) -> EmitResult {
+        let nil = Node::Constant(ConstantLiteral::NilLiteral);
+        let (clauses, default) = match clauses.split_last() {
+            Some((last, clauses)) if always_truthy(&last.test) => (clauses, &last.value),
+            _ => (clauses, &nil),
+        };
+        let values: Vec<&Node> = clauses.iter().map(|clause| &clause.value).collect();
+        self.emit_branches(&values, Some(default), |emitter| {
+            let mut body = vec![];
+            for (index, clause) in clauses.iter().enumerate() {
+                body.append(emitter.emit_truthiness(&clause.test)?.as_mut());
+                body.push(Opcodes::BrIf(index as u32));
+            }
+            body.push(Opcodes::Br(clauses.len() as u32));
+            Ok(body)
+        })
+    }
+
+    /// The predicate is called the way a function named at the head of a
+    /// list is, so that `=` or `<` compare as they do there. A predicate
+    /// which is not a name is evaluated once, before the expression.
+    fn emit_condp(&mut self, details: &CondpDetails) -> EmitResult {
+        let values: Vec<&Node> = details.clauses.iter().map(|clause| &clause.value).collect();
+        self.emit_branches(&values, details.default.as_deref(), |emitter| {
+            let mut body = vec![];
+            emitter.environment.push_scope();
+            let predicate = match details.predicate.as_ref() {
+                predicate @ Node::Keyword(_) | predicate @ Node::Variable(..) => predicate.clone(),
+                predicate => {
+                    let mut predicate = emitter.emit_expression(predicate)?;
+                    body.append(predicate.body.as_mut());
+                    let index = emitter
+                        .environment
+                        .declare_local(CONDP_PREDICATE, predicate.value_type);
+                    body.push(Opcodes::LocalSet(index));
+                    Node::Variable(CONDP_PREDICATE.to_owned(), Position::reset())
+                }
+            };
+            let mut expression = emitter.emit_expression(&details.expression)?;
+            body.append(expression.body.as_mut());
+            let index = emitter
+                .environment
+                .declare_local(CONDP_EXPRESSION, expression.value_type);
+            body.push(Opcodes::LocalSet(index));
+            for (index, clause) in details.clauses.iter().enumerate() {
+                // the names always resolve, so their positions are never
+                // reported
+                let call = Node::List(ListDetails {
+                    head: Box::new(predicate.clone()),
+                    rest: vec![
+                        clause.test.clone(),
+                        Node::Variable(CONDP_EXPRESSION.to_owned(), Position::reset()),
+                    ],
+                    position: Position::reset(),
+                });
+                body.append(emitter.emit_truthiness(&call)?.as_mut());
+                body.push(Opcodes::BrIf(index as u32));
+            }
+            emitter.environment.pop_scope();
+            body.push(Opcodes::Br(details.clauses.len() as u32));
+            Ok(body)
+        })
+    }
+
+    /// Dense integer keys pick the branch from a table indexed by the value
+    /// of the expression less the lowest key, when it is an integer. Other
+    /// keys are compared with the value in turn, the way `=` compares them.
+    fn emit_case(&mut self, details: &CaseDetails) -> EmitResult {
+        let values: Vec<&Node> = details.clauses.iter().map(|clause| &clause.value).collect();
+        let default = details.clauses.len() as u32;
+        let table = dense_keys(&details.clauses);
+        self.emit_branches(&values, details.default.as_deref(), |emitter| {
+            let expression = emitter.emit_expression(&details.expression)?;
+            let (lowest, depths) = match table {
+                Some(table) => table,
+                None => return emitter.emit_case_comparisons(expression, details),
+            };
+            let mut body = expression.body;
+            match expression.value_type {
+                ValueType::Integer => {}
+                ValueType::Any => {
+                    let value = emitter.environment.declare_temporary(ValueType::Any);
+                    body.push(Opcodes::LocalTee(value));
+                    body.append(emitter.emit_runtime_call(Runtime::TypeOf).as_mut());
+                    body.push(Opcodes::I32Const(Tag::Integer as i32));
+                    body.push(Opcodes::I32Ne);
+                    body.push(Opcodes::BrIf(default));
+                    body.push(Opcodes::LocalGet(value));
+                    body.append(emitter.emit_runtime_call(Runtime::ToInteger).as_mut());
+                }
+                // values of other types are never equal to integers
+                _ => {
+                    body.push(Opcodes::Drop);
+                    body.push(Opcodes::Br(default));
+                    return Ok(body);
+                }
+            }
+            let offset = emitter.environment.declare_temporary(ValueType::Integer);
+            body.append(
+                vec![
+                    Opcodes::I64Const(lowest),
+                    Opcodes::I64Sub,
+                    Opcodes::LocalTee(offset),
+                    Opcodes::I64Const(depths.len() as i64),
+                    Opcodes::I64GeU,
+                    Opcodes::BrIf(default),
+                    Opcodes::LocalGet(offset),
+                    Opcodes::I32WrapI64,
+                    Opcodes::BrTable(depths, default),
+                ]
+                .as_mut(),
+            );
+            Ok(body)
+        })
+    }
+
+    fn emit_case_comparisons(
+        &mut self,
+        expression: Expression,
+        details: &CaseDetails,
+    ) -> Result<Vec<Opcodes>, EmitError> {
+        let mut body = expression.body;
+        self.environment.push_scope();
+        let index = self
+            .environment
+            .declare_local(CASE_EXPRESSION, expression.value_type);
+        body.push(Opcodes::LocalSet(index));
+        for (index, clause) in details.clauses.iter().enumerate() {
+            for key in &clause.keys {
+                let equal = Node::List(ListDetails {
+                    head: Box::new(Node::Keyword(KeywordDetails {
+                        token: Lexeme::Equal,
+                        position: Position::reset(),
+                    })),
+                    rest: vec![
+                        Node::Variable(CASE_EXPRESSION.to_owned(), Position::reset()),
+                        key.clone(),
+                    ],
+                    position: Position::reset(),
+                });
+                body.append(self.emit_truthiness(&equal)?.as_mut());
+                body.push(Opcodes::BrIf(index as u32));
+            }
         }
-        instructions
+        self.environment.pop_scope();
+        body.push(Opcodes::Br(details.clauses.len() as u32));
+        Ok(body)
     }
 
-    fn emit_function_call(&mut self, list: &ListDetails) -> Vec<String> {
-        if let box Node::Keyword(details) = &list.head {
-            match &details.token {
+    /// Every binding gets its own local in the enclosing function, so that
+    /// shadowed names keep their values once the inner `let` is left.
+    fn emit_let(&mut self, details: &LetDetails) -> EmitResult {
+        let mut body = Vec::new();
+        self.environment.push_scope();
+        for binding in &details.bindings {
+            // the value is emitted before the name is declared, which keeps
+            // an outer binding of the same name visible to it
+            let mut value = self.emit_expression(&binding.value)?;
+            body.append(value.body.as_mut());
+            if let box Node::Variable(name, _) = &binding.name {
+                let index = self.environment.declare_local(name, value.value_type);
+                body.push(Opcodes::LocalSet(index));
+            }
+        }
+        let mut result = self.emit_body(&details.body)?;
+        self.environment.pop_scope();
+        body.append(result.body.as_mut());
+        Ok(Expression::new(body, result.value_type))
+    }
+
+    /// The bindings of a loop are bound like those of `let`, their types
+    /// widening with the values `recur` binds them to on every pass
+    fn emit_loop(&mut self, details: &LetDetails, position: &Position) -> EmitResult {
+        let key = format!("loop at {}:{}", position.line, position.column);
+        let known = match self.functions.get(&key) {
+            Some(signature) => signature.params.clone(),
+            None => vec![None; details.bindings.len()],
+        };
+        self.observed.entry(key.clone()).or_insert(Signature {
+            params: known.clone(),
+            result: None,
+        });
+        let mut body = Vec::new();
+        let mut locals = Vec::new();
+        self.environment.push_scope();
+        for (index, binding) in details.bindings.iter().enumerate() {
+            let value = self.emit_expression(&binding.value)?;
+            widen(
+                &mut self.observed.get_mut(&key).unwrap().params[index],
+                value.value_type,
+            );
+            let value_type = match known[index] {
+                Some(known) => known.unify(value.value_type),
+                None => value.value_type,
+            };
+            body.append(self.coerce(value, value_type).as_mut());
+            if let box Node::Variable(name, _) = &binding.name {
+                let index = self.environment.declare_local(name, value_type);
+                body.push(Opcodes::LocalSet(index));
+                locals.push((index, value_type));
+            } else {
+                body.push(Opcodes::Drop);
+            }
+        }
+        let enclosing = self.enter_recur_target(Some(&key), locals.into_iter());
+        let result = self.emit_body(&details.body)?;
+        let mut result = self.leave_recur_target(enclosing, result);
+        self.environment.pop_scope();
+        body.append(result.body.as_mut());
+        Ok(Expression::new(body, result.value_type))
+    }
+
+    /// Makes the locals recur binds the target of the `recur` forms emitted
+    /// until `leave_recur_target`, returning the enclosing target
+    fn enter_recur_target(
+        &mut self,
+        key: Option<&str>,
+        locals: impl Iterator<Item = (ReferenceNumber, ValueType)>,
+    ) -> Option<RecurTarget> {
+        self.depth += 1;
+        let target = RecurTarget {
+            key: key.map(str::to_owned),
+            locals: locals.collect(),
+            depth: self.depth,
+            frame: None,
+            used: false,
+        };
+        self.recur.replace(target)
+    }
+
+    /// Runs the expression of a loop or function body in a block `recur` jumps
+    /// back to the start of, when it does
+    fn leave_recur_target(
+        &mut self,
+        enclosing: Option<RecurTarget>,
+        result: Expression,
+    ) -> Expression {
+        self.depth -= 1;
+        let target = std::mem::replace(&mut self.recur, enclosing).unwrap();
+        if !target.used {
+            return result;
+        }
+        let mut body = vec![];
+        if let Some(frame) = target.frame {
+            body.push(Opcodes::GlobalGet(STACK_POINTER.to_owned()));
+            body.push(Opcodes::LocalSet(frame));
+        }
+        body.push(Opcodes::Loop(BlockType::Value(
+            result.value_type.machine_type(self.options.memory),
+        )));
+        body.extend(result.body);
+        body.push(Opcodes::End);
+        Expression::new(body, result.value_type)
+    }
+
+    /// Evaluates every argument before assigning them to the bindings of the
+    /// enclosing loop or function, which are rooted anew when memory is
+    /// collected so that the objects rooted by the previous iteration can be
+    /// dropped from the shadow stack
+    fn emit_recur(&mut self, args: &Vec<Node>, position: &Position) -> EmitResult {
+        let target = match &self.recur {
+            Some(target) if target.locals.len() == args.len() => target.clone(),
+            _ => return Err(EmitError::WrongArity(*position, Lexeme::Recur)),
+        };
+        let mut body = vec![];
+        for (index, (argument, (_, value_type))) in args.iter().zip(&target.locals).enumerate() {
+            let argument = self.emit_expression(argument)?;
+            if let Some(observed) = target
+                .key
+                .as_ref()
+                .and_then(|key| self.observed.get_mut(key))
+            {
+                widen(&mut observed.params[index], argument.value_type);
+            }
+            body.append(self.coerce(argument, *value_type).as_mut());
+        }
+        for (local, _) in target.locals.iter().rev() {
+            body.push(Opcodes::LocalSet(*local));
+        }
+        let frame = match target.frame {
+            Some(frame) => Some(frame),
+            None if self.options.memory == Memory::Collected => {
+                Some(self.environment.declare_temporary(ValueType::Boolean))
+            }
+            None => None,
+        };
+        if let Some(frame) = frame {
+            body.push(Opcodes::LocalGet(frame));
+            body.push(Opcodes::GlobalSet(STACK_POINTER.to_owned()));
+            for (local, value_type) in &target.locals {
+                if value_type.is_reference() {
+                    body.push(Opcodes::LocalGet(*local));
+                    body.append(self.emit_runtime_call(Runtime::Root).as_mut());
+                    body.push(Opcodes::Drop);
+                }
+            }
+        }
+        body.push(Opcodes::Br(self.depth - target.depth));
+        if let Some(recur) = self.recur.as_mut() {
+            recur.frame = frame;
+            recur.used = true;
+        }
+        Ok(Expression::new(body, ValueType::Never))
+    }
+
+    fn emit_global(&mut self, details: &VariableInformation) -> Result<(), EmitError> {
+        if let box Node::Variable(name, position) = &details.name {
+            let (value_type, value) = match &details.value {
+                box Node::Constant(ConstantLiteral::IntegerLiteral(integer)) => {
+                    (Types::I64, Opcodes::I64Const(*integer))
+                }
+                box Node::Constant(ConstantLiteral::FloatLiteral(float)) => {
+                    (Types::F64, Opcodes::F64Const(*float))
+                }
+                _ => return Err(EmitError::NonConstantGlobal(*position, name.to_owned())),
+            };
+            self.module.globals.push(Global {
+                name: name.to_owned(),
+                value_type,
+                mutable: false,
+                value,
+            });
+        }
+        Ok(())
+    }
+
+    fn emit_variable(&mut self, name: &String, position: &Position) -> EmitResult {
+        match self.environment.resolve(name) {
+            Some(Binding::Local(index, value_type)) => {
+                Ok(Expression::new(vec![Opcodes::LocalGet(index)], value_type))
+            }
+            Some(Binding::Global(name, value_type)) => {
+                Ok(Expression::new(vec![Opcodes::GlobalGet(name)], value_type))
+            }
+            None if self.functions.contains_key(name) => Ok(self.emit_function_value(name)),
+            None => match Builtin::from_name(name) {
+                Some(builtin) => Ok(self.emit_builtin_value(builtin, name, position)),
+                None => Err(EmitError::UnresolvedSymbol(*position, name.to_owned())),
+            },
+        }
+    }
+
+    fn emit_function_call(&mut self, list: &ListDetails) -> EmitResult {
+        match &list.head {
+            box Node::Keyword(details) => match &details.token {
                 &Lexeme::Plus => self.emit_add_function(&list.rest),
-                &Lexeme::Minus => self.emit_subtract_function(&list.rest),
+                &Lexeme::Minus => self.emit_subtract_function(details, &list.rest),
+                &Lexeme::Star => self.emit_multiply_function(&list.rest),
+                &Lexeme::Slash => self.emit_divide_function(details, &list.rest),
                 &Lexeme::Print => self.emit_print_function(&list.rest),
-                _ => vec![],
+                &Lexeme::Less
+                | &Lexeme::LessEqual
+                | &Lexeme::Greater
+                | &Lexeme::GreaterEqual
+                | &Lexeme::Equal
+                | &Lexeme::DoubleEqual => self.emit_comparison(details, &list.rest),
+                &Lexeme::NotEqual => {
+                    let mut equal = self.emit_comparison(details, &list.rest)?;
+                    equal.body.push(Opcodes::I32Eqz);
+                    Ok(equal)
+                }
+                &Lexeme::And | &Lexeme::Or => self.emit_logical(&details.token, &list.rest),
+                _ => Err(EmitError::NotCallable(list.position)),
+            },
+            box Node::KeywordLiteral(name, position) => {
+                self.emit_keyword_lookup(name, position, &list.rest)
+            }
+            box Node::Variable(name, position) if self.functions.contains_key(name) => {
+                self.emit_user_function_call(name, position, &list.rest)
+            }
+            box Node::Variable(name, position) if self.environment.resolve(name).is_some() => {
+                let callee = self.emit_variable(name, position)?;
+                match callee.value_type {
+                    ValueType::Function | ValueType::Any => {
+                        self.emit_dynamic_call(callee, Some(position), &list.rest)
+                    }
+                    _ => self.emit_collection_invocation(name, position, &list.rest),
+                }
+            }
+            box Node::Variable(name, position) => match Builtin::from_name(name) {
+                Some(builtin) => self.emit_builtin_call(builtin, name, position, &list.rest),
+                None => Err(EmitError::UnresolvedSymbol(*position, name.to_owned())),
+            },
+            // forms evaluating to a value which is only known at runtime to be
+            // callable, or not
+            box Node::Lambda(_)
+            | box Node::List(_)
+            | box Node::Set(_)
+            | box Node::Map(_)
+            | box Node::Vector(_)
+            | box Node::Constant(_)
+            | box Node::If(_)
+            | box Node::Do(_)
+            | box Node::Cond(_)
+            | box Node::Condp(_)
+            | box Node::Case(_)
+            | box Node::Let(_)
+            | box Node::Loop(..) => {
+                let callee = self.emit_expression(&list.head)?;
+                self.emit_dynamic_call(callee, None, &list.rest)
+            }
+            _ => Err(EmitError::NotCallable(list.position)),
+        }
+    }
+
+    /// Arguments are converted to the parameter types of the function, while
+    /// their own types widen those for the next pass. A trampolined tail call
+    /// calls the function through its closure instead.
+    fn emit_user_function_call(
+        &mut self,
+        name: &String,
+        position: &Position,
+        args: &Vec<Node>,
+    ) -> EmitResult {
+        let signature = self.functions[name].clone();
+        if args.len() != signature.params.len() {
+            return Err(EmitError::WrongArity(
+                *position,
+                Lexeme::Identifier(name.to_owned()),
+            ));
+        }
+        let result = assumed(signature.result);
+        let tail_call = self.tail_call(Some(position), result);
+        if tail_call == TailCalls::Trampoline {
+            let callee = self.emit_function_value(name);
+            return self.emit_thunk(callee, args);
+        }
+        let mut body = vec![];
+        for (index, argument) in args.iter().enumerate() {
+            let argument = self.emit_expression(argument)?;
+            if let Some(observed) = self.observed.get_mut(name) {
+                widen(&mut observed.params[index], argument.value_type);
             }
+            let param = assumed(signature.params[index]);
+            body.append(self.coerce(argument, param).as_mut());
+        }
+        if tail_call == TailCalls::Native {
+            body.append(self.emit_tail_frame().as_mut());
+            body.push(Opcodes::ReturnCall(name.to_owned()));
         } else {
-            vec![]
+            body.push(Opcodes::Call(name.to_owned()));
+            body.append(self.emit_bounce(result).as_mut());
         }
+        Ok(Expression::new(body, result))
     }
 
-    fn emit_export(&self) -> Vec<String> {
-        vec!["(export \"_start\" (func $main))".to_owned()]
+    /// How the call of the name at `position`, whose value is of type
+    /// `value_type`, is made. Only calls in tail position of a function
+    /// which returns a value are tail calls, and native ones only when the
+    /// function returns the value of the call as it is.
+    fn tail_call(&self, position: Option<&Position>, value_type: ValueType) -> TailCalls {
+        let tail = position.map_or(false, |position| self.tail_positions.contains(position));
+        match (self.options.tail_calls, self.result) {
+            (TailCalls::Native, Some(result))
+                if tail
+                    && (value_type == result
+                        || (result == ValueType::Any && value_type.is_reference())) =>
+            {
+                TailCalls::Native
+            }
+            (TailCalls::Trampoline, Some(_)) if tail => TailCalls::Trampoline,
+            _ => TailCalls::Plain,
+        }
     }
 
-    // Perhaps these functions are collapsible
-    fn emit_add_function(&mut self, args: &Vec<Node>) -> Vec<String> {
-        let mut body = vec![Opcodes::Add.to_string()];
-        for argument in args {
-            body.append(self.emit_instructions(argument).as_mut())
+    /// Drops the frame of the function being emitted from the shadow stack
+    /// ahead of a native tail call, the callee rooting its own arguments
+    fn emit_tail_frame(&mut self) -> Vec<Opcodes> {
+        if self.options.memory != Memory::Collected {
+            return vec![];
         }
-        body.push(")".to_owned());
-        body
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => self.environment.declare_temporary(ValueType::Boolean),
+        };
+        self.entry = Some(entry);
+        vec![
+            Opcodes::LocalGet(entry),
+            Opcodes::GlobalSet(STACK_POINTER.to_owned()),
+        ]
     }
 
-    fn emit_subtract_function(&mut self, args: &Vec<Node>) -> Vec<String> {
-        let mut body = vec![Opcodes::Subtract.to_string()];
-        for argument in args {
-            body.append(self.emit_instructions(argument).as_mut())
+    /// With trampolined tail calls, a value of any type returned by a call
+    /// outside tail position may be a thunk, whose calls the trampoline makes
+    fn emit_bounce(&mut self, value_type: ValueType) -> Vec<Opcodes> {
+        match (self.options.tail_calls, value_type) {
+            (TailCalls::Trampoline, ValueType::Any) => {
+                self.emit_table();
+                self.emit_runtime_call(Runtime::Trampoline)
+            }
+            _ => vec![],
         }
-        body.push(")".to_owned());
-        body
     }
 
-    fn emit_print_function(&mut self, args: &Vec<Node>) -> Vec<String> {
-        self.imports.push(WASIImports::FDWrite);
-        let mut body = vec![];
+    /// A trampolined tail call evaluates to a thunk, a closure capturing the
+    /// callee and the arguments, whose function calls one with the others
+    fn emit_thunk(&mut self, callee: Expression, args: &Vec<Node>) -> EmitResult {
+        let memory = self.options.memory;
+        self.emit_table();
+        let name = format!("thunk#{}", args.len());
+        let index = match self.module.table.iter().position(|entry| *entry == name) {
+            Some(index) => index,
+            None => {
+                self.module
+                    .add_runtime(Runtime::ClosureEntry, &mut self.data);
+                let thunk = closure::thunk(&name, args.len(), memory);
+                self.module.functions.push(thunk);
+                self.module.table.push(name);
+                self.module.table.len() - 1
+            }
+        };
+        let mut body = vec![
+            Opcodes::I32Const(index as i32),
+            Opcodes::I32Const(closure::THUNK),
+            Opcodes::I32Const(args.len() as i32 + 1),
+        ];
+        body.append(self.emit_runtime_call(Runtime::MakeClosure).as_mut());
+        let thunk = self.environment.declare_temporary(ValueType::Function);
+        body.push(Opcodes::LocalSet(thunk));
+        let callee = self.coerce(callee, ValueType::Any);
+        body.append(closure::capture(thunk, 0, callee, memory).as_mut());
+        for (position, argument) in args.iter().enumerate() {
+            let argument = self.emit_reference(argument)?;
+            let position = position as u32 + 1;
+            body.append(closure::capture(thunk, position, argument, memory).as_mut());
+        }
+        body.push(Opcodes::LocalGet(thunk));
+        Ok(Expression::new(body, ValueType::Any))
+    }
+
+    /// Emits numeric arguments along with the type they are converted to.
+    /// Integers are converted to floats when any argument is a float, and
+    /// every argument is boxed when the type of any of them is not known to
+    /// be a number.
+    fn emit_numbers(&mut self, args: &[Node]) -> Result<(Vec<Vec<Opcodes>>, ValueType), EmitError> {
+        let mut numbers = vec![];
         for argument in args {
-            // build io vector
-            body.push(Opcodes::Store(0, 8).to_string());
-            body.push(Opcodes::Store(4, 12).to_string());
-            body.push(
-                SysCalls::Write(
-                    Opcodes::Const(1),
-                    Opcodes::Const(0),
-                    Opcodes::Const(1),
-                    Opcodes::Const(20),
-                )
-                .to_string(),
-            );
-            body.append(self.emit_instructions(argument).as_mut());
-            body.push(Opcodes::Drop.to_string());
+            numbers.push(self.emit_expression(argument)?);
         }
-        body
+        let value_type = if numbers.iter().any(|number| !number.value_type.is_number()) {
+            ValueType::Any
+        } else if numbers
+            .iter()
+            .any(|number| number.value_type == ValueType::Float)
+        {
+            ValueType::Float
+        } else {
+            ValueType::Integer
+        };
+        let mut operands = vec![];
+        for number in numbers {
+            operands.push(self.coerce(number, value_type));
+        }
+        Ok((operands, value_type))
     }
 
-    fn emit_constant(&mut self, constant: &ConstantLiteral) -> Vec<String> {
-        match constant {
-            ConstantLiteral::IntegerLiteral(integer) => self.emit_integer_constant(*integer),
-            ConstantLiteral::StringLiteral(string) => self.emit_string_bytes(string),
+    fn emit_add_function(&mut self, args: &Vec<Node>) -> EmitResult {
+        if args.is_empty() {
+            return Ok(self.emit_integer_constant(0));
+        }
+        let (operands, value_type) = self.emit_numbers(args)?;
+        let add = self.emit_arithmetic(Arithmetic::Add, value_type);
+        Ok(Expression::new(fold(operands, add), value_type))
+    }
+
+    /// `(- x)` negates its argument
+    fn emit_subtract_function(&mut self, details: &KeywordDetails, args: &Vec<Node>) -> EmitResult {
+        if args.is_empty() {
+            return Err(EmitError::WrongArity(
+                details.position,
+                details.token.clone(),
+            ));
+        }
+        let (mut operands, value_type) = self.emit_numbers(args)?;
+        let body = if operands.len() == 1 {
+            self.emit_negation(operands.remove(0), value_type, Arithmetic::Subtract)
+        } else {
+            let subtract = self.emit_arithmetic(Arithmetic::Subtract, value_type);
+            fold(operands, subtract)
+        };
+        Ok(Expression::new(body, value_type))
+    }
+
+    fn emit_multiply_function(&mut self, args: &Vec<Node>) -> EmitResult {
+        if args.is_empty() {
+            return Ok(self.emit_integer_constant(1));
+        }
+        let (operands, value_type) = self.emit_numbers(args)?;
+        let multiply = self.emit_arithmetic(Arithmetic::Multiply, value_type);
+        Ok(Expression::new(fold(operands, multiply), value_type))
+    }
+
+    /// Combines the two numbers of the given type on top of the stack.
+    /// Numbers whose type is only known at runtime are combined by a support
+    /// function dispatching on their tags.
+    fn emit_arithmetic(&mut self, operation: Arithmetic, value_type: ValueType) -> Vec<Opcodes> {
+        if value_type == ValueType::Any {
+            return self.emit_runtime_call(Runtime::Dynamic(operation));
+        }
+        let number = value_type.machine_type(self.options.memory);
+        if let Some(runtime) = operation.runtime(number) {
+            self.module.add_runtime(runtime, &mut self.data);
+        }
+        vec![operation.instruction(number)]
+    }
+
+    /// Subtracts the operand from zero with `subtract`, which decides whether
+    /// negating the smallest long overflows
+    fn emit_negation(
+        &mut self,
+        operand: Vec<Opcodes>,
+        value_type: ValueType,
+        subtract: Arithmetic,
+    ) -> Vec<Opcodes> {
+        if value_type == ValueType::Float {
+            return [operand, vec![Opcodes::F64Neg]].concat();
+        }
+        let zero = self.emit_integer_constant(0);
+        let zero = self.coerce(zero, value_type);
+        let subtract = self.emit_arithmetic(subtract, value_type);
+        fold(vec![zero, operand], subtract)
+    }
+
+    /// There are no ratios, so dividing integers gives an integer when the
+    /// quotient is exact and a float otherwise, which is only known at
+    /// runtime. Floats divide as usual. `(/ x)` is the reciprocal of `x`.
+    fn emit_divide_function(&mut self, details: &KeywordDetails, args: &Vec<Node>) -> EmitResult {
+        if args.is_empty() {
+            return Err(EmitError::WrongArity(
+                details.position,
+                details.token.clone(),
+            ));
+        }
+        let (mut operands, mut value_type) = self.emit_numbers(args)?;
+        if value_type == ValueType::Integer {
+            value_type = ValueType::Any;
+            operands = operands
+                .into_iter()
+                .map(|operand| {
+                    self.coerce(Expression::new(operand, ValueType::Integer), value_type)
+                })
+                .collect();
+        }
+        let divide = self.emit_arithmetic(Arithmetic::Divide, value_type);
+        if operands.len() == 1 {
+            let one = self.emit_integer_constant(1);
+            operands.insert(0, self.coerce(one, value_type));
+        }
+        Ok(Expression::new(fold(operands, divide), value_type))
+    }
+
+    fn emit_builtin_call(
+        &mut self,
+        builtin: Builtin,
+        name: &str,
+        position: &Position,
+        args: &Vec<Node>,
+    ) -> EmitResult {
+        if !builtin.accepts(args.len()) {
+            return Err(EmitError::WrongArity(
+                *position,
+                Lexeme::Identifier(name.to_owned()),
+            ));
+        }
+        let operation = match builtin {
+            Builtin::Quot => Arithmetic::Quotient,
+            Builtin::Rem => Arithmetic::Remainder,
+            Builtin::Mod => Arithmetic::Modulo,
+            Builtin::Max | Builtin::Abs => Arithmetic::Max,
+            Builtin::Min => Arithmetic::Min,
+            Builtin::Inc => Arithmetic::Add,
+            Builtin::Dec => Arithmetic::Subtract,
+            Builtin::UncheckedAdd | Builtin::UncheckedInc => Arithmetic::UncheckedAdd,
+            Builtin::UncheckedSubtract | Builtin::UncheckedDec | Builtin::UncheckedNegate => {
+                Arithmetic::UncheckedSubtract
+            }
+            Builtin::UncheckedMultiply => Arithmetic::UncheckedMultiply,
+            Builtin::Not => {
+                let mut body = self.emit_truthiness(&args[0])?;
+                body.push(Opcodes::I32Eqz);
+                return Ok(Expression::new(body, ValueType::Boolean));
+            }
+            _ => return self.emit_collection_call(builtin, args),
+        };
+        let (mut operands, value_type) = self.emit_numbers(args)?;
+        match builtin {
+            Builtin::Abs => return Ok(self.emit_absolute_value(operands.remove(0), value_type)),
+            Builtin::Inc | Builtin::Dec | Builtin::UncheckedInc | Builtin::UncheckedDec => {
+                let one = self.emit_integer_constant(1);
+                operands.push(self.coerce(one, value_type));
+            }
+            Builtin::UncheckedNegate => {
+                let negation = self.emit_negation(operands.remove(0), value_type, operation);
+                return Ok(Expression::new(negation, value_type));
+            }
+            _ => {}
         }
+        let operation = self.emit_arithmetic(operation, value_type);
+        Ok(Expression::new(fold(operands, operation), value_type))
     }
 
-    fn emit_integer_constant(&self, constant: i32) -> Vec<String> {
-        vec![Opcodes::Const(constant).to_string()]
+    /// Collections, keys and elements are passed to the runtime as references,
+    /// vector indices as i64 values. `conj`, `assoc`, `dissoc` and `disj` add
+    /// or remove their arguments in turn, `merge` merges its maps from the left,
+    /// and `subvec` ends at the end of the vector unless told otherwise.
+    fn emit_collection_call(&mut self, builtin: Builtin, args: &Vec<Node>) -> EmitResult {
+        match builtin {
+            Builtin::Merge if args.is_empty() => return Ok(self.emit_nil()),
+            Builtin::Conj | Builtin::Dissoc | Builtin::Disj | Builtin::Merge if args.len() == 1 => {
+                return self.emit_expression(&args[0])
+            }
+            Builtin::Update => return self.emit_update(args),
+            _ => {}
+        }
+        let collection = self.emit_expression(&args[0])?;
+        let collection_type = collection.value_type;
+        let mut body = self.coerce(collection, ValueType::Any);
+        let value_type = match builtin {
+            Builtin::Count => {
+                body.append(self.emit_runtime_call(Runtime::Count).as_mut());
+                ValueType::Integer
+            }
+            Builtin::Nth => {
+                body.append(self.emit_index(&args[1])?.as_mut());
+                match args.get(2) {
+                    Some(not_found) => {
+                        body.append(self.emit_reference(not_found)?.as_mut());
+                        body.push(Opcodes::I32Const(1));
+                    }
+                    None => {
+                        let nil = self.emit_nil();
+                        body.append(self.coerce(nil, ValueType::Any).as_mut());
+                        body.push(Opcodes::I32Const(0));
+                    }
+                }
+                body.append(self.emit_runtime_call(Runtime::Nth).as_mut());
+                ValueType::Any
+            }
+            Builtin::Get => {
+                body.append(self.emit_reference(&args[1])?.as_mut());
+                let not_found = match args.get(2) {
+                    Some(not_found) => self.emit_reference(not_found)?,
+                    None => {
+                        let nil = self.emit_nil();
+                        self.coerce(nil, ValueType::Any)
+                    }
+                };
+                body.extend(not_found);
+                body.append(self.emit_runtime_call(Runtime::Get).as_mut());
+                ValueType::Any
+            }
+            Builtin::Conj => {
+                let (conj, value_type) = match collection_type {
+                    ValueType::Set => (Runtime::SetConj, ValueType::Set),
+                    ValueType::Any => (Runtime::Conj, ValueType::Any),
+                    _ => (Runtime::VectorConj, ValueType::Vector),
+                };
+                for value in &args[1..] {
+                    body.append(self.emit_reference(value)?.as_mut());
+                    body.append(self.emit_runtime_call(conj).as_mut());
+                }
+                value_type
+            }
+            Builtin::Assoc => {
+                for pair in args[1..].chunks(2) {
+                    body.append(self.emit_reference(&pair[0])?.as_mut());
+                    body.append(self.emit_reference(&pair[1])?.as_mut());
+                    body.append(self.emit_runtime_call(Runtime::Assoc).as_mut());
+                }
+                associated(collection_type)
+            }
+            Builtin::Dissoc => {
+                for key in &args[1..] {
+                    body.append(self.emit_reference(key)?.as_mut());
+                    body.append(self.emit_runtime_call(Runtime::Dissoc).as_mut());
+                }
+                if collection_type == ValueType::Map {
+                    ValueType::Map
+                } else {
+                    ValueType::Any
+                }
+            }
+            Builtin::Disj => {
+                for key in &args[1..] {
+                    body.append(self.emit_reference(key)?.as_mut());
+                    body.append(self.emit_runtime_call(Runtime::Disj).as_mut());
+                }
+                if collection_type == ValueType::Set {
+                    ValueType::Set
+                } else {
+                    ValueType::Any
+                }
+            }
+            Builtin::ContainsKey => {
+                body.append(self.emit_reference(&args[1])?.as_mut());
+                body.append(self.emit_runtime_call(Runtime::Contains).as_mut());
+                ValueType::Boolean
+            }
+            Builtin::Keys => {
+                body.append(self.emit_runtime_call(Runtime::Keys).as_mut());
+                ValueType::Any
+            }
+            Builtin::Vals => {
+                body.append(self.emit_runtime_call(Runtime::Vals).as_mut());
+                ValueType::Any
+            }
+            Builtin::Merge => {
+                for map in &args[1..] {
+                    body.append(self.emit_reference(map)?.as_mut());
+                    body.append(self.emit_runtime_call(Runtime::Merge).as_mut());
+                }
+                if collection_type == ValueType::Map {
+                    ValueType::Map
+                } else {
+                    ValueType::Any
+                }
+            }
+            Builtin::Peek => {
+                body.append(self.emit_runtime_call(Runtime::Peek).as_mut());
+                ValueType::Any
+            }
+            Builtin::Pop => {
+                body.append(self.emit_runtime_call(Runtime::Pop).as_mut());
+                ValueType::Vector
+            }
+            _ => {
+                let vector = self.environment.declare_temporary(ValueType::Any);
+                body.push(Opcodes::LocalTee(vector));
+                body.append(self.emit_index(&args[1])?.as_mut());
+                match args.get(2) {
+                    Some(end) => body.append(self.emit_index(end)?.as_mut()),
+                    None => {
+                        body.push(Opcodes::LocalGet(vector));
+                        body.append(self.emit_runtime_call(Runtime::Count).as_mut());
+                    }
+                }
+                body.append(self.emit_runtime_call(Runtime::Subvec).as_mut());
+                ValueType::Vector
+            }
+        };
+        Ok(Expression::new(body, value_type))
     }
 
-    fn emit_string_bytes(&mut self, constant: &String) -> Vec<String> {
-        let location = Opcodes::Const(8);
-        let data = format!("{}\n", constant);
-        self.data.push(OpData {
-            location,
-            data: data.parse().unwrap(),
+    /// `(update m k f x)` associates `k` with `(f (get m k) x)`. The old value
+    /// is bound to a name no symbol can spell, so that the call to `f` is
+    /// emitted like any other.
+    fn emit_update(&mut self, args: &Vec<Node>) -> EmitResult {
+        let collection = self.emit_expression(&args[0])?;
+        let collection_type = collection.value_type;
+        let mut body = self.coerce(collection, ValueType::Any);
+        let map = self.environment.declare_temporary(ValueType::Any);
+        body.push(Opcodes::LocalSet(map));
+        body.append(self.emit_reference(&args[1])?.as_mut());
+        let key = self.environment.declare_temporary(ValueType::Any);
+        body.push(Opcodes::LocalSet(key));
+
+        body.push(Opcodes::LocalGet(map));
+        body.push(Opcodes::LocalGet(key));
+        let nil = self.emit_nil();
+        body.append(self.coerce(nil, ValueType::Any).as_mut());
+        body.append(self.emit_runtime_call(Runtime::Get).as_mut());
+        self.environment.push_scope();
+        let old = self.environment.declare_local(UPDATED, ValueType::Any);
+        body.push(Opcodes::LocalSet(old));
+
+        // the name always resolves, so its position is never reported
+        let mut rest = vec![Node::Variable(UPDATED.to_owned(), Position::reset())];
+        rest.extend(args[3..].iter().cloned());
+        let call = Node::List(ListDetails {
+            head: Box::new(args[2].clone()),
+            rest,
+            position: Position::reset(),
         });
-        vec![]
+        body.push(Opcodes::LocalGet(map));
+        body.push(Opcodes::LocalGet(key));
+        let value = self.emit_reference(&call);
+        self.environment.pop_scope();
+        body.append(value?.as_mut());
+        body.append(self.emit_runtime_call(Runtime::Assoc).as_mut());
+        Ok(Expression::new(body, associated(collection_type)))
     }
 
-    fn emit_memory_initializer(&self) -> String {
-        String::from("(memory 1) (export \"memory\" (memory 0))")
+    /// The value of an expression as a reference, whatever its type
+    fn emit_reference(&mut self, value: &Node) -> Result<Vec<Opcodes>, EmitError> {
+        let value = self.emit_expression(value)?;
+        Ok(self.coerce(value, ValueType::Any))
+    }
+
+    /// The value of an expression as an i64 index. Floats are truncated, and
+    /// values of other types are converted at runtime.
+    fn emit_index(&mut self, index: &Node) -> Result<Vec<Opcodes>, EmitError> {
+        let index = self.emit_expression(index)?;
+        match index.value_type {
+            ValueType::Integer => Ok(index.body),
+            ValueType::Float => {
+                let mut body = index.body;
+                body.push(Opcodes::I64TruncF64S);
+                Ok(body)
+            }
+            _ => {
+                let mut body = self.coerce(index, ValueType::Any);
+                body.append(self.emit_runtime_call(Runtime::ToInteger).as_mut());
+                Ok(body)
+            }
+        }
+    }
+
+    /// The operand or its negation, whichever is larger
+    fn emit_absolute_value(
+        &mut self,
+        mut operand: Vec<Opcodes>,
+        value_type: ValueType,
+    ) -> Expression {
+        if value_type == ValueType::Float {
+            operand.push(Opcodes::F64Abs);
+            return Expression::new(operand, value_type);
+        }
+        let value = self.environment.declare_temporary(value_type);
+        operand.push(Opcodes::LocalTee(value));
+        let negation = self.emit_negation(
+            vec![Opcodes::LocalGet(value)],
+            value_type,
+            Arithmetic::UncheckedSubtract,
+        );
+        let max = self.emit_arithmetic(Arithmetic::Max, value_type);
+        Expression::new(fold(vec![operand, negation], max), value_type)
+    }
+
+    /// Comparisons hold when they hold for every pair of neighbouring
+    /// arguments, as in `(< 1 2 3)`. Every argument is evaluated exactly once,
+    /// the right operand of a pair being kept in a local for the next one.
+    /// Numbers are compared as floats when any of them is a float, except
+    /// for `=` which never considers an integer equal to a float.
+    fn emit_comparison(&mut self, details: &KeywordDetails, args: &Vec<Node>) -> EmitResult {
+        if args.is_empty() {
+            return Err(EmitError::WrongArity(
+                details.position,
+                details.token.clone(),
+            ));
+        }
+        let mut operands: Vec<(Vec<Opcodes>, ValueType)> = match details.token {
+            Lexeme::Equal | Lexeme::NotEqual => self.emit_equality_operands(args)?,
+            _ => {
+                let (operands, value_type) = self.emit_numbers(args)?;
+                operands
+                    .into_iter()
+                    .map(|operand| (operand, value_type))
+                    .collect()
+            }
+        };
+
+        let (mut body, mut left_type) = operands.remove(0);
+        if operands.is_empty() {
+            body.push(Opcodes::Drop);
+            body.push(Opcodes::I32Const(1));
+            return Ok(Expression::new(body, ValueType::Boolean));
+        }
+
+        let mut kept = None;
+        for (index, (mut right, right_type)) in operands.into_iter().enumerate() {
+            if let Some(operand) = kept {
+                body.push(Opcodes::LocalGet(operand));
+            }
+            body.append(right.as_mut());
+            if index < args.len() - 2 {
+                let operand = self.environment.declare_temporary(right_type);
+                body.push(Opcodes::LocalTee(operand));
+                kept = Some(operand);
+            }
+            body.append(
+                self.emit_compare(&details.token, left_type, right_type)
+                    .as_mut(),
+            );
+            if index > 0 {
+                body.push(Opcodes::I32And);
+            }
+            left_type = right_type;
+        }
+        Ok(Expression::new(body, ValueType::Boolean))
+    }
+
+    /// Operands of `=` along with their types. Values which may be strings or
+    /// vectors are compared by their contents, so they are all boxed along
+    /// with the others when there is any.
+    fn emit_equality_operands(
+        &mut self,
+        args: &Vec<Node>,
+    ) -> Result<Vec<(Vec<Opcodes>, ValueType)>, EmitError> {
+        let mut operands = vec![];
+        for argument in args {
+            operands.push(self.emit_expression(argument)?);
+        }
+        let boxed = operands.iter().any(|operand| match operand.value_type {
+            ValueType::Any
+            | ValueType::String
+            | ValueType::Keyword
+            | ValueType::Vector
+            | ValueType::Map
+            | ValueType::Set
+            | ValueType::Function => true,
+            _ => false,
+        });
+        let mut typed = vec![];
+        for operand in operands {
+            let value_type = if boxed {
+                ValueType::Any
+            } else {
+                operand.value_type
+            };
+            typed.push((self.coerce(operand, value_type), value_type));
+        }
+        Ok(typed)
+    }
+
+    /// Compares the two values on top of the stack. Values of different types
+    /// are never equal, and would otherwise compare by their representation.
+    fn emit_compare(
+        &mut self,
+        operator: &Lexeme,
+        left: ValueType,
+        right: ValueType,
+    ) -> Vec<Opcodes> {
+        match (operator, left) {
+            (Lexeme::Equal, ValueType::Any) | (Lexeme::NotEqual, ValueType::Any) => {
+                self.emit_runtime_call(Runtime::Equals)
+            }
+            (_, ValueType::Any) => {
+                let mut body = self.emit_runtime_call(Runtime::CompareNumbers);
+                body.push(Opcodes::I32Const(0));
+                body.push(match operator {
+                    Lexeme::Less => Opcodes::I32LtS,
+                    Lexeme::LessEqual => Opcodes::I32LeS,
+                    Lexeme::Greater => Opcodes::I32GtS,
+                    Lexeme::GreaterEqual => Opcodes::I32GeS,
+                    _ => Opcodes::I32Eq,
+                });
+                body
+            }
+            _ => compare(operator, left, right),
+        }
+    }
+
+    /// Prints every argument according to its type and evaluates to nil
+    fn emit_print_function(&mut self, args: &Vec<Node>) -> EmitResult {
+        let mut body = vec![];
+        for argument in args {
+            let mut argument = self.emit_expression(argument)?;
+            body.append(argument.body.as_mut());
+            match argument.value_type {
+                ValueType::Integer => {
+                    body.append(self.emit_runtime_call(Runtime::PrintInteger).as_mut())
+                }
+                ValueType::Float => {
+                    body.append(self.emit_runtime_call(Runtime::PrintFloat).as_mut())
+                }
+                ValueType::String => {
+                    body.append(self.emit_runtime_call(Runtime::PrintString).as_mut())
+                }
+                ValueType::Boolean => {
+                    body.push(Opcodes::If(BlockType::Empty));
+                    body.append(self.emit_print_literal("true").as_mut());
+                    body.push(Opcodes::Else);
+                    body.append(self.emit_print_literal("false").as_mut());
+                    body.push(Opcodes::End);
+                }
+                ValueType::Nil => {
+                    body.push(Opcodes::Drop);
+                    body.append(self.emit_print_literal("nil").as_mut());
+                }
+                ValueType::Never => body.push(Opcodes::Drop),
+                ValueType::Keyword
+                | ValueType::Vector
+                | ValueType::Map
+                | ValueType::Set
+                | ValueType::Function
+                | ValueType::Any => {
+                    body.append(self.emit_runtime_call(Runtime::PrintValue).as_mut())
+                }
+            }
+        }
+        body.append(self.emit_nil().body.as_mut());
+        Ok(Expression::new(body, ValueType::Nil))
+    }
+
+    /// A vector literal is built by adding its elements in turn to an empty
+    /// vector
+    fn emit_vector(&mut self, elements: &Vec<Node>) -> EmitResult {
+        let mut body = self.emit_runtime_call(Runtime::EmptyVector);
+        for element in elements {
+            body.append(self.emit_reference(element)?.as_mut());
+            body.append(self.emit_runtime_call(Runtime::VectorConj).as_mut());
+        }
+        Ok(Expression::new(body, ValueType::Vector))
+    }
+
+    /// A map literal is built by associating its entries in turn with an
+    /// empty map, so a later entry wins over an earlier one with the same key
+    fn emit_map(&mut self, items: &Vec<MapItem>) -> EmitResult {
+        let mut body = self.emit_runtime_call(Runtime::EmptyMap);
+        for item in items {
+            body.append(self.emit_reference(&item.key)?.as_mut());
+            body.append(self.emit_reference(&item.value)?.as_mut());
+            body.append(self.emit_runtime_call(Runtime::MapAssoc).as_mut());
+        }
+        Ok(Expression::new(body, ValueType::Map))
+    }
+
+    /// A set literal is built by adding its elements in turn to an empty set
+    fn emit_set(&mut self, elements: &Vec<Node>) -> EmitResult {
+        let mut body = self.emit_runtime_call(Runtime::EmptySet);
+        for element in elements {
+            body.append(self.emit_reference(element)?.as_mut());
+            body.append(self.emit_runtime_call(Runtime::SetConj).as_mut());
+        }
+        Ok(Expression::new(body, ValueType::Set))
+    }
+
+    /// A keyword evaluates to the address of its name, which is shared by
+    /// every keyword with that name. When the engine manages memory, it is
+    /// looked up in the symbol table instead.
+    fn emit_keyword(&mut self, name: &str) -> Expression {
+        let location = self.data.add_keyword(name);
+        let mut body = vec![Opcodes::I32Const(location.address)];
+        if self.options.memory == Memory::Engine {
+            let symbol = self.data.symbol(name).unwrap_or_default();
+            body.insert(0, Opcodes::I32Const(symbol));
+            body.append(self.emit_runtime_call(Runtime::InternKeyword).as_mut());
+        }
+        Expression::new(body, ValueType::Keyword)
+    }
+
+    /// A keyword called with a map looks itself up in it, as `get` does
+    fn emit_keyword_lookup(
+        &mut self,
+        name: &str,
+        position: &Position,
+        args: &Vec<Node>,
+    ) -> EmitResult {
+        if args.is_empty() || args.len() > 2 {
+            return Err(EmitError::WrongArity(
+                *position,
+                Lexeme::MapKey(name.to_owned()),
+            ));
+        }
+        let mut body = self.emit_reference(&args[0])?;
+        let keyword = self.emit_keyword(name);
+        body.append(self.coerce(keyword, ValueType::Any).as_mut());
+        let not_found = match args.get(1) {
+            Some(not_found) => self.emit_reference(not_found)?,
+            None => {
+                let nil = self.emit_nil();
+                self.coerce(nil, ValueType::Any)
+            }
+        };
+        body.extend(not_found);
+        body.append(self.emit_runtime_call(Runtime::Get).as_mut());
+        Ok(Expression::new(body, ValueType::Any))
+    }
+
+    /// Calls the value of a variable as a function of a key, which a set or a
+    /// map is when looked up like `get` does. Sets and vectors, unlike maps,
+    /// aren't given a value for when the key is missing.
+    fn emit_collection_invocation(
+        &mut self,
+        name: &String,
+        position: &Position,
+        args: &Vec<Node>,
+    ) -> EmitResult {
+        let collection = self.emit_variable(name, position)?;
+        let arity = match collection.value_type {
+            ValueType::Set | ValueType::Vector => 1,
+            _ => 2,
+        };
+        if args.is_empty() || args.len() > arity {
+            return Err(EmitError::WrongArity(
+                *position,
+                Lexeme::Identifier(name.to_owned()),
+            ));
+        }
+        let mut body = self.coerce(collection, ValueType::Any);
+        body.append(self.emit_reference(&args[0])?.as_mut());
+        let not_found = match args.get(1) {
+            Some(not_found) => self.emit_reference(not_found)?,
+            None => {
+                let nil = self.emit_nil();
+                self.coerce(nil, ValueType::Any)
+            }
+        };
+        body.extend(not_found);
+        body.append(self.emit_runtime_call(Runtime::Invoke).as_mut());
+        Ok(Expression::new(body, ValueType::Any))
+    }
+
+    /// Calls a value which is only known at runtime to be a function. The
+    /// callee is passed to the function `closure_entry` finds in the table
+    /// ahead of the arguments, all of them references. The position of the
+    /// name of the callee, if any, tells whether it is a tail call.
+    fn emit_dynamic_call(
+        &mut self,
+        callee: Expression,
+        position: Option<&Position>,
+        args: &Vec<Node>,
+    ) -> EmitResult {
+        let memory = self.options.memory;
+        let tail_call = self.tail_call(position, ValueType::Any);
+        if tail_call == TailCalls::Trampoline {
+            return self.emit_thunk(callee, args);
+        }
+        let function = self.environment.declare_temporary(ValueType::Any);
+        let mut body = self.coerce(callee, ValueType::Any);
+        body.push(Opcodes::LocalTee(function));
+        for argument in args {
+            body.append(self.emit_reference(argument)?.as_mut());
+        }
+        body.push(Opcodes::LocalGet(function));
+        body.push(Opcodes::I32Const(args.len() as i32));
+        body.append(self.emit_runtime_call(Runtime::ClosureEntry).as_mut());
+        self.emit_table();
+        let params = vec![reference(memory); args.len() + 1];
+        if tail_call == TailCalls::Native {
+            body.append(self.emit_tail_frame().as_mut());
+            body.push(Opcodes::ReturnCallIndirect(params, vec![reference(memory)]));
+        } else {
+            body.push(Opcodes::CallIndirect(params, vec![reference(memory)]));
+            body.append(self.emit_bounce(ValueType::Any).as_mut());
+        }
+        Ok(Expression::new(body, ValueType::Any))
+    }
+
+    /// An anonymous function evaluates to a closure holding the values of the
+    /// locals its body refers to, its own body being emitted later on
+    fn emit_lambda(&mut self, details: &LambdaDetails) -> Expression {
+        let memory = self.options.memory;
+        self.emit_table();
+        let index = self.module.table.len();
+        let name = format!("{}#{}", details.name.as_deref().unwrap_or("fn"), index);
+        self.module.table.push(name.clone());
+        let captured: Vec<(String, usize, ValueType)> = free_variables(details)
+            .into_iter()
+            .filter_map(|name| match self.environment.resolve(&name) {
+                Some(Binding::Local(local, value_type)) => Some((name, local, value_type)),
+                _ => None,
+            })
+            .collect();
+
+        let mut body = vec![
+            Opcodes::I32Const(index as i32),
+            Opcodes::I32Const(details.args.len() as i32),
+            Opcodes::I32Const(captured.len() as i32),
+        ];
+        body.append(self.emit_runtime_call(Runtime::MakeClosure).as_mut());
+        if !captured.is_empty() {
+            let closure = self.environment.declare_temporary(ValueType::Function);
+            body.push(Opcodes::LocalSet(closure));
+            for (position, (_, local, value_type)) in captured.iter().enumerate() {
+                let value = Expression::new(vec![Opcodes::LocalGet(*local)], *value_type);
+                let value = self.coerce(value, ValueType::Any);
+                body.append(closure::capture(closure, position as u32, value, memory).as_mut());
+            }
+            body.push(Opcodes::LocalGet(closure));
+        }
+        self.lambdas.push(PendingLambda {
+            name,
+            details: details.clone(),
+            captured: captured.into_iter().map(|(name, _, _)| name).collect(),
+        });
+        Expression::new(body, ValueType::Function)
+    }
+
+    /// A builtin used as a value is an anonymous function calling it with its
+    /// arguments, as in `#(inc %)`
+    fn emit_builtin_value(
+        &mut self,
+        builtin: Builtin,
+        name: &str,
+        position: &Position,
+    ) -> Expression {
+        let args: Vec<Node> = (1..=builtin.value_arity())
+            .map(|index| Node::Variable(format!("%{}", index), *position))
+            .collect();
+        let call = Node::List(ListDetails {
+            head: Box::new(Node::Variable(name.to_owned(), *position)),
+            rest: args.clone(),
+            position: *position,
+        });
+        self.emit_lambda(&LambdaDetails {
+            name: None,
+            args,
+            body: vec![call],
+        })
+    }
+
+    /// A function defined with `defn` used as a value is a closure over an
+    /// adapter, which takes references and calls the function with them. The
+    /// parameters of the function widen so that they take references too.
+    /// With native tail calls, an adapter makes a tail call of a function
+    /// returning a reference.
+    fn emit_function_value(&mut self, name: &str) -> Expression {
+        self.emit_table();
+        let adapter = format!("{}#fn", name);
+        let index = match self.module.table.iter().position(|entry| *entry == adapter) {
+            Some(index) => index,
+            None => {
+                self.emit_adapter(name, &adapter);
+                self.module.table.push(adapter);
+                self.module.table.len() - 1
+            }
+        };
+        let mut body = vec![
+            Opcodes::I32Const(index as i32),
+            Opcodes::I32Const(self.functions[name].params.len() as i32),
+            Opcodes::I32Const(0),
+        ];
+        body.append(self.emit_runtime_call(Runtime::MakeClosure).as_mut());
+        Expression::new(body, ValueType::Function)
+    }
+
+    fn emit_adapter(&mut self, name: &str, adapter: &str) {
+        let memory = self.options.memory;
+        let signature = self.functions[name].clone();
+        if let Some(observed) = self.observed.get_mut(name) {
+            for param in observed.params.iter_mut() {
+                widen(param, ValueType::Any);
+            }
+        }
+        let arity = signature.params.len();
+        let result_type = assumed(signature.result);
+        let arguments = (1..=arity).map(Opcodes::LocalGet);
+        let mut locals = vec![];
+        let body = if self.options.tail_calls == TailCalls::Native && result_type.is_reference() {
+            // the function roots the arguments itself
+            arguments
+                .chain(vec![Opcodes::ReturnCall(name.to_owned())])
+                .collect()
+        } else {
+            let mut body = self.emit_parameter_roots(&vec![ValueType::Any; arity + 1]);
+            body.extend(arguments);
+            body.push(Opcodes::Call(name.to_owned()));
+            let result = Expression::new(body, result_type);
+            let body = self.coerce(result, ValueType::Any);
+            self.emit_frame(body, Some(ValueType::Any), arity + 1, &mut locals)
+        };
+        self.module.functions.push(Function {
+            name: adapter.to_owned(),
+            params: vec![reference(memory); arity + 1],
+            results: vec![reference(memory)],
+            locals,
+            body,
+        });
+    }
+
+    /// The first two functions of the table call a set, a map, a vector or a
+    /// keyword with one and two arguments, see `closure::entry`
+    fn emit_table(&mut self) {
+        if !self.module.table.is_empty() {
+            return;
+        }
+        for runtime in [Runtime::InvokeWithKey, Runtime::Invoke].iter() {
+            self.module.add_runtime(*runtime, &mut self.data);
+            self.module.table.push(runtime.name().to_owned());
+        }
+    }
+
+    fn emit_print_literal(&mut self, string: &str) -> Vec<Opcodes> {
+        let mut body = self.emit_string_bytes(string).body;
+        body.append(self.emit_runtime_call(Runtime::PrintString).as_mut());
+        body
+    }
+
+    fn emit_runtime_call(&mut self, runtime: Runtime) -> Vec<Opcodes> {
+        self.module.add_runtime(runtime, &mut self.data);
+        vec![Opcodes::Call(runtime.name().to_owned())]
+    }
+
+    fn emit_constant(&mut self, constant: &ConstantLiteral) -> Expression {
+        match constant {
+            ConstantLiteral::IntegerLiteral(integer) => self.emit_integer_constant(*integer),
+            ConstantLiteral::FloatLiteral(float) => {
+                Expression::new(vec![Opcodes::F64Const(*float)], ValueType::Float)
+            }
+            ConstantLiteral::StringLiteral(string) => self.emit_string_bytes(string),
+            ConstantLiteral::BooleanLiteral(boolean) => {
+                Expression::new(vec![Opcodes::I32Const(*boolean as i32)], ValueType::Boolean)
+            }
+            ConstantLiteral::NilLiteral => self.emit_nil(),
+        }
+    }
+
+    fn emit_nil(&self) -> Expression {
+        Expression::new(vec![Opcodes::I32Const(0)], ValueType::Nil)
+    }
+
+    fn emit_integer_constant(&self, constant: i64) -> Expression {
+        Expression::new(vec![Opcodes::I64Const(constant)], ValueType::Integer)
+    }
+
+    /// A string evaluates to the address of its data, which is copied into an
+    /// array when the engine manages memory
+    fn emit_string_bytes(&mut self, constant: &str) -> Expression {
+        let location = self.data.add_string(constant);
+        let mut body = vec![Opcodes::I32Const(location.address)];
+        if self.options.memory == Memory::Engine {
+            body.append(self.emit_runtime_call(Runtime::StringLiteral).as_mut());
+        }
+        Expression::new(body, ValueType::String)
+    }
+
+    fn machine_types(&self, types: &[ValueType]) -> Vec<Types> {
+        types
+            .iter()
+            .map(|value_type| value_type.machine_type(self.options.memory))
+            .collect()
+    }
+}
+
+/// Type of a collection once `assoc` has associated a key with a value in
+/// it, nil standing for an empty map
+fn associated(collection: ValueType) -> ValueType {
+    match collection {
+        ValueType::Vector => ValueType::Vector,
+        ValueType::Map | ValueType::Nil => ValueType::Map,
+        _ => ValueType::Any,
+    }
+}
+
+/// Combines operands from left to right with `operation`, which replaces the
+/// two values on top of the stack with a single one
+fn fold(operands: Vec<Vec<Opcodes>>, operation: Vec<Opcodes>) -> Vec<Opcodes> {
+    let mut body = vec![];
+    for (index, mut operand) in operands.into_iter().enumerate() {
+        body.append(operand.as_mut());
+        if index > 0 {
+            body.append(operation.clone().as_mut());
+        }
+    }
+    body
+}
+
+/// Compares the two values on top of the stack. Values of different types are
+/// never equal, and would otherwise compare by their representation.
+fn compare(operator: &Lexeme, left: ValueType, right: ValueType) -> Vec<Opcodes> {
+    let is_float = left == ValueType::Float && right == ValueType::Float;
+    match operator {
+        Lexeme::Less if is_float => vec![Opcodes::F64Lt],
+        Lexeme::LessEqual if is_float => vec![Opcodes::F64Le],
+        Lexeme::Greater if is_float => vec![Opcodes::F64Gt],
+        Lexeme::GreaterEqual if is_float => vec![Opcodes::F64Ge],
+        Lexeme::Less => vec![Opcodes::I64LtS],
+        Lexeme::LessEqual => vec![Opcodes::I64LeS],
+        Lexeme::Greater => vec![Opcodes::I64GtS],
+        Lexeme::GreaterEqual => vec![Opcodes::I64GeS],
+        _ if left != right => vec![Opcodes::Drop, Opcodes::Drop, Opcodes::I32Const(0)],
+        _ if is_float => vec![Opcodes::F64Eq],
+        _ if left == ValueType::Integer => vec![Opcodes::I64Eq],
+        _ => vec![Opcodes::I32Eq],
+    }
+}
+
+/// The type a value is assumed to have before anything is known about it,
+/// as for the parameters of a function nothing calls
+/// Whether a test is truthy whatever is in scope, as keywords are
+fn always_truthy(test: &Node) -> bool {
+    match test {
+        Node::KeywordLiteral(..) | Node::Constant(ConstantLiteral::BooleanLiteral(true)) => true,
+        _ => false,
+    }
+}
+
+/// When every key of a `case` is an integer and they span at most twice as
+/// many values as there are keys, the lowest key along with the depth of the
+/// branch for each value from it, the default being right after the clauses
+fn dense_keys(clauses: &[CaseClause]) -> Option<(i64, Vec<u32>)> {
+    let mut keys = vec![];
+    for (index, clause) in clauses.iter().enumerate() {
+        for key in &clause.keys {
+            match key {
+                Node::Constant(ConstantLiteral::IntegerLiteral(key)) => keys.push((*key, index)),
+                _ => return None,
+            }
+        }
+    }
+    let lowest = keys.iter().map(|(key, _)| *key).min()?;
+    let highest = keys.iter().map(|(key, _)| *key).max()?;
+    let span = highest.checked_sub(lowest)?.checked_add(1)?;
+    if span > 2 * keys.len() as i64 {
+        return None;
+    }
+    let mut depths = vec![clauses.len() as u32; span as usize];
+    for (key, index) in keys {
+        depths[(key - lowest) as usize] = index as u32;
+    }
+    Some((lowest, depths))
+}
+
+fn assumed(value_type: Option<ValueType>) -> ValueType {
+    value_type.unwrap_or(ValueType::Integer)
+}
+
+/// Widens a type so that it also covers values of `value_type`
+fn widen(known: &mut Option<ValueType>, value_type: ValueType) {
+    *known = Some(match known {
+        Some(known) => known.unify(value_type),
+        None => value_type,
+    });
+}
+
+/// Names and types parameters are bound with
+fn parameters(args: &Vec<Node>, types: &[ValueType]) -> Vec<(String, ValueType)> {
+    args.iter()
+        .zip(types)
+        .map(|(arg, value_type)| {
+            let value_type = *value_type;
+            match arg {
+                Node::Variable(name, _) => (name.to_owned(), value_type),
+                _ => (String::new(), value_type),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::emitter::{EmitError, Emitter, Options, TailCalls};
+    use crate::codegen::instructions::{BlockType, HeapType, Opcodes, Types, WASIImports};
+    use crate::codegen::module::{Function, Memory, Module};
+    use crate::frontend::parser::Parser;
+    use crate::frontend::scanner::{Lexeme, Position};
+
+    /// Compiles without a collector, which keeps the emitted code to the
+    /// point
+    fn compile(text: &str) -> Module {
+        compile_with(text, Memory::Arena)
+    }
+
+    fn compile_with(text: &str, memory: Memory) -> Module {
+        let nodes = Parser::new(text).parse().unwrap();
+        let options = Options {
+            memory,
+            ..Options::default()
+        };
+        Emitter::new(options).emit(nodes).unwrap()
+    }
+
+    /// Number of values left on the stack by straight line arithmetic
+    fn stack_height(body: &[Opcodes]) -> i32 {
+        body.iter()
+            .map(|instruction| match instruction {
+                Opcodes::I64Const(_) | Opcodes::LocalGet(_) => 1,
+                Opcodes::Call(_) | Opcodes::Drop => -1,
+                other => panic!("unexpected instruction {}", other),
+            })
+            .sum()
+    }
+
+    fn function<'a>(module: &'a Module, name: &str) -> &'a Function {
+        module
+            .functions
+            .iter()
+            .find(|function| function.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn print_integer_expressions() {
+        let module = compile("(defn main [] (print 42 7))");
+
+        assert_eq!(
+            function(&module, "main").body,
+            vec![
+                Opcodes::I64Const(42),
+                Opcodes::Call("print_integer".to_owned()),
+                Opcodes::I64Const(7),
+                Opcodes::Call("print_integer".to_owned()),
+                Opcodes::I32Const(0),
+                Opcodes::Drop,
+            ]
+        );
+        let runtime_count = module
+            .functions
+            .iter()
+            .filter(|function| function.name == "print_integer")
+            .count();
+        assert_eq!(runtime_count, 1);
+    }
+
+    #[test]
+    fn print_strings_from_their_own_data() {
+        let module = compile(r#"(defn main [] (print "Hello\n" "bye" "Hello\n"))"#);
+
+        let print = |address| {
+            vec![
+                Opcodes::I32Const(address),
+                Opcodes::Call("print_string".to_owned()),
+            ]
+        };
+        assert_eq!(
+            function(&module, "main").body,
+            [
+                print(64),
+                print(80),
+                print(64),
+                vec![Opcodes::I32Const(0), Opcodes::Drop],
+            ]
+            .concat()
+        );
+        // the other one is the message of the allocator
+        assert_eq!(module.data.len(), 3);
+    }
+
+    #[test]
+    fn if_tests_truthiness_by_type() {
+        let module = compile("(defn f [x] (if x 1 2)) (defn g [] (if false 1))");
+
+        assert_eq!(
+            function(&module, "f").body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::Drop,
+                Opcodes::I32Const(1),
+                Opcodes::If(BlockType::Value(Types::I64)),
+                Opcodes::I64Const(1),
+                Opcodes::Else,
+                Opcodes::I64Const(2),
+                Opcodes::End,
+            ]
+        );
+        assert_eq!(
+            function(&module, "g").body,
+            vec![
+                Opcodes::I32Const(0),
+                Opcodes::If(BlockType::Value(Types::I32)),
+                Opcodes::I64Const(1),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::Else,
+                Opcodes::I32Const(0),
+                Opcodes::End,
+            ]
+        );
+        assert_eq!(function(&module, "g").results, vec![Types::I32]);
+    }
+
+    #[test]
+    fn logical_operators_keep_the_deciding_value() {
+        let module = compile("(defn f [x] (and x 1) (or x 2.5)) (defn g [] (and) (or))");
+
+        assert_eq!(
+            function(&module, "f").body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::LocalTee(1),
+                Opcodes::Drop,
+                Opcodes::I32Const(1),
+                Opcodes::If(BlockType::Value(Types::I64)),
+                Opcodes::I64Const(1),
+                Opcodes::Else,
+                Opcodes::LocalGet(1),
+                Opcodes::End,
+                Opcodes::Drop,
+                Opcodes::LocalGet(0),
+                Opcodes::LocalTee(2),
+                Opcodes::Drop,
+                Opcodes::I32Const(1),
+                Opcodes::If(BlockType::Value(Types::I32)),
+                Opcodes::LocalGet(2),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::Else,
+                Opcodes::F64Const(2.5),
+                Opcodes::Call("box_float".to_owned()),
+                Opcodes::End,
+            ]
+        );
+        assert_eq!(
+            function(&module, "g").body,
+            vec![Opcodes::I32Const(1), Opcodes::Drop, Opcodes::I32Const(0)]
+        );
+    }
+
+    #[test]
+    fn dense_case_keys_branch_through_a_table() {
+        let module = compile(
+            "(defn f [x] (case x 1 10 (2 4) 20 30)) (defn g [x] (case x 1 10 100 20)) \
+             (defn main [] (f 1) (g 1))",
+        );
+
+        assert_eq!(
+            function(&module, "f").body,
+            vec![
+                Opcodes::Block(BlockType::Value(Types::I64)),
+                Opcodes::Block(BlockType::Empty),
+                Opcodes::Block(BlockType::Empty),
+                Opcodes::Block(BlockType::Empty),
+                Opcodes::LocalGet(0),
+                Opcodes::I64Const(1),
+                Opcodes::I64Sub,
+                Opcodes::LocalTee(1),
+                Opcodes::I64Const(4),
+                Opcodes::I64GeU,
+                Opcodes::BrIf(2),
+                Opcodes::LocalGet(1),
+                Opcodes::I32WrapI64,
+                Opcodes::BrTable(vec![0, 1, 2, 1], 2),
+                Opcodes::End,
+                Opcodes::I64Const(10),
+                Opcodes::Br(2),
+                Opcodes::End,
+                Opcodes::I64Const(20),
+                Opcodes::Br(1),
+                Opcodes::End,
+                Opcodes::I64Const(30),
+                Opcodes::End,
+            ]
+        );
+        let g = &function(&module, "g").body;
+        assert!(!g.iter().any(|opcode| match opcode {
+            Opcodes::BrTable(..) => true,
+            _ => false,
+        }));
+        assert!(g.contains(&Opcodes::Call("no_matching_clause".to_owned())));
+    }
+
+    #[test]
+    fn let_bindings_get_their_own_locals() {
+        let module = compile("(defn f [x] (let [x (+ x 1) y x] (let [x 2] x) y))");
+
+        let f = function(&module, "f");
+        assert_eq!(f.locals.len(), 3);
+        assert_eq!(
+            f.body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::I64Const(1),
+                Opcodes::Call("checked_add".to_owned()),
+                Opcodes::LocalSet(1),
+                Opcodes::LocalGet(1),
+                Opcodes::LocalSet(2),
+                Opcodes::I64Const(2),
+                Opcodes::LocalSet(3),
+                Opcodes::LocalGet(3),
+                Opcodes::Drop,
+                Opcodes::LocalGet(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn recur_assigns_the_bindings_and_jumps_back() {
+        let module = compile("(defn f [n] (loop [i 0] (if (< i n) (recur (inc i)) i)))");
+
+        let f = function(&module, "f");
+        assert_eq!(f.locals, vec![Types::I64]);
+        assert_eq!(
+            f.body,
+            vec![
+                Opcodes::I64Const(0),
+                Opcodes::LocalSet(1),
+                Opcodes::Loop(BlockType::Value(Types::I64)),
+                Opcodes::LocalGet(1),
+                Opcodes::LocalGet(0),
+                Opcodes::I64LtS,
+                Opcodes::If(BlockType::Value(Types::I64)),
+                Opcodes::LocalGet(1),
+                Opcodes::I64Const(1),
+                Opcodes::Call("checked_add".to_owned()),
+                Opcodes::LocalSet(1),
+                Opcodes::Br(1),
+                Opcodes::Unreachable,
+                Opcodes::Else,
+                Opcodes::LocalGet(1),
+                Opcodes::End,
+                Opcodes::End,
+            ]
+        );
+
+        let nodes = Parser::new("(defn f [x] (recur x x))").parse().unwrap();
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::WrongArity(
+                Position {
+                    line: 1,
+                    column: 14
+                },
+                Lexeme::Recur
+            ))
+        );
+    }
+
+    #[test]
+    fn collected_loops_root_their_bindings_anew() {
+        let module = compile_with(
+            "(defn f [v] (if (count v) (recur (conj v 1)) v)) (defn main [] (f []))",
+            Memory::Collected,
+        );
+
+        let f = function(&module, "f");
+        assert_eq!(
+            f.body[..5],
+            [
+                Opcodes::GlobalGet("stack_pointer".to_owned()),
+                Opcodes::LocalSet(2),
+                Opcodes::GlobalGet("stack_pointer".to_owned()),
+                Opcodes::LocalSet(1),
+                Opcodes::Loop(BlockType::Value(Types::I32)),
+            ]
+        );
+        assert_eq!(
+            f.body[14..21],
+            [
+                Opcodes::LocalSet(0),
+                Opcodes::LocalGet(1),
+                Opcodes::GlobalSet("stack_pointer".to_owned()),
+                Opcodes::LocalGet(0),
+                Opcodes::Call("root".to_owned()),
+                Opcodes::Drop,
+                Opcodes::Br(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn tail_calls_leave_the_frame_or_return_thunks() {
+        let text = "(defn f [n] (if n (g n) 1)) (defn g [n] (f n)) (defn main [] (f 1))";
+        let compile = |memory, tail_calls| {
+            let nodes = Parser::new(text).parse().unwrap();
+            Emitter::new(Options { memory, tail_calls })
+                .emit(nodes)
+                .unwrap()
+        };
+
+        let module = compile(Memory::Arena, TailCalls::Native);
+        assert_eq!(
+            function(&module, "g").body,
+            vec![Opcodes::LocalGet(0), Opcodes::ReturnCall("f".to_owned())]
+        );
+        assert_eq!(
+            function(&module, "main").body[..2],
+            [Opcodes::I64Const(1), Opcodes::Call("f".to_owned())]
+        );
+
+        let module = compile(Memory::Collected, TailCalls::Native);
+        assert_eq!(
+            function(&module, "g").body,
+            vec![
+                Opcodes::GlobalGet("stack_pointer".to_owned()),
+                Opcodes::LocalSet(1),
+                Opcodes::LocalGet(0),
+                Opcodes::LocalGet(1),
+                Opcodes::GlobalSet("stack_pointer".to_owned()),
+                Opcodes::ReturnCall("f".to_owned()),
+            ]
+        );
+
+        let module = compile(Memory::Arena, TailCalls::Trampoline);
+        assert_eq!(module.table[2..], ["g#fn", "thunk#1", "f#fn"]);
+        assert_eq!(
+            function(&module, "g").body,
+            vec![
+                Opcodes::I32Const(3),
+                Opcodes::I32Const(-1),
+                Opcodes::I32Const(2),
+                Opcodes::Call("make_closure".to_owned()),
+                Opcodes::LocalSet(1),
+                Opcodes::LocalGet(1),
+                Opcodes::I32Const(4),
+                Opcodes::I32Const(1),
+                Opcodes::I32Const(0),
+                Opcodes::Call("make_closure".to_owned()),
+                Opcodes::I32Store(16),
+                Opcodes::LocalGet(1),
+                Opcodes::LocalGet(0),
+                Opcodes::I32Store(20),
+                Opcodes::LocalGet(1),
+            ]
+        );
+        assert_eq!(
+            function(&module, "main").body,
+            vec![
+                Opcodes::I64Const(1),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::Call("f".to_owned()),
+                Opcodes::Call("trampoline".to_owned()),
+                Opcodes::Drop,
+            ]
+        );
+    }
+
+    #[test]
+    fn comparisons_hold_for_every_pair() {
+        let module = compile("(defn f [x] (< 1 x 3))");
+
+        let f = function(&module, "f");
+        assert_eq!(f.locals.len(), 1);
+        assert_eq!(
+            f.body,
+            vec![
+                Opcodes::I64Const(1),
+                Opcodes::LocalGet(0),
+                Opcodes::LocalTee(1),
+                Opcodes::I64LtS,
+                Opcodes::LocalGet(1),
+                Opcodes::I64Const(3),
+                Opcodes::I64LtS,
+                Opcodes::I32And,
+            ]
+        );
+    }
+
+    #[test]
+    fn comparisons_need_an_argument() {
+        let nodes = Parser::new("(defn f [] (not=))").parse().unwrap();
+
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::WrongArity(
+                Position {
+                    line: 1,
+                    column: 13
+                },
+                Lexeme::NotEqual
+            ))
+        );
+    }
+
+    #[test]
+    fn division_checks_the_divisor_at_runtime() {
+        let module = compile("(defn f [x] (quot 1 x) (mod x 3))");
+
+        assert_eq!(
+            function(&module, "f").body,
+            vec![
+                Opcodes::I64Const(1),
+                Opcodes::LocalGet(0),
+                Opcodes::Call("quotient".to_owned()),
+                Opcodes::Drop,
+                Opcodes::LocalGet(0),
+                Opcodes::I64Const(3),
+                Opcodes::Call("modulo".to_owned()),
+            ]
+        );
+        assert!(module.imports.contains(&WASIImports::ProcExit));
+        let runtime: Vec<&str> = module
+            .functions
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect();
+        assert_eq!(
+            runtime,
+            vec![
+                "divide_by_zero",
+                "integer_overflow",
+                "quotient",
+                "modulo",
+                "f",
+                "out_of_memory",
+                "allocate",
+                "free"
+            ]
+        );
+    }
+
+    #[test]
+    fn integers_divide_to_a_float_unless_exact() {
+        let module = compile("(defn f [x] (/ x 2))");
+
+        assert_eq!(
+            function(&module, "f").body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::I64Const(2),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::Call("dynamic_divide".to_owned()),
+            ]
+        );
+        let divide = function(&module, "dynamic_divide");
+        assert!(divide.body.contains(&Opcodes::Call("remainder".to_owned())));
+        assert!(divide.body.contains(&Opcodes::F64Div));
+    }
+
+    #[test]
+    fn builtins_check_their_arity() {
+        let nodes = Parser::new("(defn f [x] (inc x x))").parse().unwrap();
+
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::WrongArity(
+                Position {
+                    line: 1,
+                    column: 14
+                },
+                Lexeme::Identifier("inc".to_owned())
+            ))
+        );
+    }
+
+    #[test]
+    fn only_maps_are_called_with_a_default() {
+        let nodes = Parser::new("(defn f [] (let [s #{1} m {1 2}] (m 3 4) (s 1 :none)))")
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::WrongArity(
+                Position {
+                    line: 1,
+                    column: 43
+                },
+                Lexeme::Identifier("s".to_owned())
+            ))
+        );
+    }
+
+    #[test]
+    fn operators_are_only_called() {
+        let nodes = Parser::new("(defn f [] (let [g +] (g 1 2)))")
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::OperatorAsValue(
+                Position {
+                    line: 1,
+                    column: 20
+                },
+                Lexeme::Plus
+            ))
+        );
+    }
+
+    #[test]
+    fn builtins_as_values() {
+        let module = compile("(defn f [c] ((if c max inc) 4 5)) (defn g [] (let [h inc] (h 1)))");
+
+        assert_eq!(
+            module
+                .table
+                .iter()
+                .filter(|entry| entry.starts_with("fn#"))
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn definitions_are_only_at_the_top_level() {
+        let nodes = Parser::new("(defn f [] (def x 1))").parse().unwrap();
+
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::NestedDefinition(
+                Position {
+                    line: 1,
+                    column: 17
+                },
+                "x".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn addition_folds_any_number_of_arguments() {
+        let module = compile("(defn f [] (+)) (defn g [x] (+ x)) (defn h [x y] (+ x y 5))");
+
+        assert_eq!(function(&module, "f").body, vec![Opcodes::I64Const(0)]);
+        assert_eq!(function(&module, "g").body, vec![Opcodes::LocalGet(0)]);
+        assert_eq!(
+            function(&module, "h").body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::LocalGet(1),
+                Opcodes::Call("checked_add".to_owned()),
+                Opcodes::I64Const(5),
+                Opcodes::Call("checked_add".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn subtraction_folds_from_the_left() {
+        let module = compile("(defn f [x] (- x)) (defn g [x y] (- x y)) (defn h [x y] (- x y 5))");
+
+        let subtract = vec![Opcodes::Call("checked_subtract".to_owned())];
+        assert_eq!(
+            function(&module, "f").body,
+            [
+                vec![Opcodes::I64Const(0), Opcodes::LocalGet(0)],
+                subtract.clone(),
+            ]
+            .concat()
+        );
+        assert_eq!(
+            function(&module, "g").body,
+            [
+                vec![Opcodes::LocalGet(0), Opcodes::LocalGet(1)],
+                subtract.clone()
+            ]
+            .concat()
+        );
+        assert_eq!(
+            function(&module, "h").body,
+            [
+                vec![Opcodes::LocalGet(0), Opcodes::LocalGet(1)],
+                subtract.clone(),
+                vec![Opcodes::I64Const(5)],
+                subtract,
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn arithmetic_leaves_a_single_value() {
+        for count in 0..6 {
+            let args = vec!["1"; count].join(" ");
+            let module = compile(&format!("(defn f [] (+ {}) (* {}))", args, args));
+
+            assert_eq!(stack_height(&function(&module, "f").body), 1);
+        }
+    }
+
+    #[test]
+    fn integers_are_promoted_to_floats() {
+        let module = compile("(defn f [x] (+ x 1.5 2))");
+
+        assert_eq!(
+            function(&module, "f").body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::F64ConvertI64S,
+                Opcodes::F64Const(1.5),
+                Opcodes::F64Add,
+                Opcodes::I64Const(2),
+                Opcodes::F64ConvertI64S,
+                Opcodes::F64Add,
+            ]
+        );
+        assert_eq!(function(&module, "f").results, vec![Types::F64]);
+    }
+
+    #[test]
+    fn parameter_types_follow_the_arguments() {
+        let module = compile("(defn f [x y] (- x)) (defn main [] (f 2.5 1) (f -1.5 2))");
+
+        let f = function(&module, "f");
+        assert_eq!(f.params, vec![Types::F64, Types::I64]);
+        assert_eq!(f.body, vec![Opcodes::LocalGet(0), Opcodes::F64Neg]);
+        assert_eq!(
+            function(&module, "main").body[..4],
+            [
+                Opcodes::F64Const(2.5),
+                Opcodes::I64Const(1),
+                Opcodes::Call("f".to_owned()),
+                Opcodes::Drop,
+            ]
+        );
+    }
+
+    #[test]
+    fn unchecked_arithmetic_wraps_around() {
+        let module = compile("(defn f [x] (+ x 1) (unchecked-add x 1) (unchecked-negate x))");
+
+        assert_eq!(
+            function(&module, "f").body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::I64Const(1),
+                Opcodes::Call("checked_add".to_owned()),
+                Opcodes::Drop,
+                Opcodes::LocalGet(0),
+                Opcodes::I64Const(1),
+                Opcodes::I64Add,
+                Opcodes::Drop,
+                Opcodes::I64Const(0),
+                Opcodes::LocalGet(0),
+                Opcodes::I64Sub,
+            ]
+        );
+        let runtime: Vec<&str> = module
+            .functions
+            .iter()
+            .map(|function| function.name.as_str())
+            .collect();
+        assert_eq!(
+            runtime,
+            vec![
+                "integer_overflow",
+                "checked_add",
+                "f",
+                "out_of_memory",
+                "allocate",
+                "free"
+            ]
+        );
+    }
+
+    #[test]
+    fn values_of_mixed_types_are_boxed() {
+        let module = compile(r#"(defn f [x] (print (if x 1 "a")) (= x "a"))"#);
+
+        let f = function(&module, "f");
+        assert_eq!(f.params, vec![Types::I64]);
+        assert_eq!(
+            f.body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::Drop,
+                Opcodes::I32Const(1),
+                Opcodes::If(BlockType::Value(Types::I32)),
+                Opcodes::I64Const(1),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::Else,
+                Opcodes::I32Const(64),
+                Opcodes::End,
+                Opcodes::Call("print_value".to_owned()),
+                Opcodes::I32Const(0),
+                Opcodes::Drop,
+                Opcodes::LocalGet(0),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::I32Const(64),
+                Opcodes::Call("equals".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn vector_literals_add_their_elements_in_turn() {
+        let module = compile(r#"(defn f [x] [x "a" nil])"#);
+
+        let f = function(&module, "f");
+        assert_eq!(f.results, vec![Types::I32]);
+        assert_eq!(
+            f.body,
+            vec![
+                Opcodes::Call("empty_vector".to_owned()),
+                Opcodes::LocalGet(0),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::Call("vector_conj".to_owned()),
+                // past the message of vector_conj at 64
+                Opcodes::I32Const(128),
+                Opcodes::Call("vector_conj".to_owned()),
+                Opcodes::I32Const(0),
+                Opcodes::Call("vector_conj".to_owned()),
+            ]
+        );
+        assert!(module.function_index("push_tail").is_some());
+    }
+
+    #[test]
+    fn map_literals_associate_their_entries_in_turn() {
+        let module =
+            compile("(defn f [m] {:a 1 m :a}) (defn g [m] (:a m)) (defn main [] (f {}) (g {}))");
+
+        let f = function(&module, "f");
+        assert_eq!(f.params, vec![Types::I32]);
+        assert_eq!(
+            f.body,
+            vec![
+                Opcodes::Call("empty_map".to_owned()),
+                // the name of :a is the first data
+                Opcodes::I32Const(64),
+                Opcodes::I64Const(1),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::Call("map_assoc".to_owned()),
+                Opcodes::LocalGet(0),
+                Opcodes::I32Const(64),
+                Opcodes::Call("map_assoc".to_owned()),
+            ]
+        );
+        let g = function(&module, "g");
+        assert_eq!(
+            g.body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::I32Const(64),
+                Opcodes::I32Const(0),
+                Opcodes::Call("collection_get".to_owned()),
+            ]
+        );
+
+        let nodes = Parser::new("(defn f [m] (:a m 1 2))").parse().unwrap();
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::WrongArity(
+                Position {
+                    line: 1,
+                    column: 14
+                },
+                Lexeme::MapKey("a".to_owned())
+            ))
+        );
+    }
+
+    #[test]
+    fn sets_add_their_elements_and_look_keys_up_when_called() {
+        let module = compile("(defn f [s] (conj #{1 s} s) (s 1)) (defn main [] (f #{}))");
+
+        let f = function(&module, "f");
+        assert_eq!(f.params, vec![Types::I32]);
+        assert_eq!(
+            f.body,
+            vec![
+                Opcodes::Call("empty_set".to_owned()),
+                Opcodes::I64Const(1),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::Call("set_conj".to_owned()),
+                Opcodes::LocalGet(0),
+                Opcodes::Call("set_conj".to_owned()),
+                Opcodes::LocalGet(0),
+                Opcodes::Call("set_conj".to_owned()),
+                Opcodes::Drop,
+                Opcodes::LocalGet(0),
+                Opcodes::I64Const(1),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::I32Const(0),
+                Opcodes::Call("invoke_collection".to_owned()),
+            ]
+        );
+
+        let nodes = Parser::new("(defn f [s] (s))").parse().unwrap();
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::WrongArity(
+                Position {
+                    line: 1,
+                    column: 14
+                },
+                Lexeme::Identifier("s".to_owned())
+            ))
+        );
+
+        let module = compile("(defn main [] (#{1 2} 2))");
+        let main = function(&module, "main");
+        assert_eq!(
+            main.body[main.body.len() - 7..],
+            [
+                Opcodes::I64Const(2),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::LocalGet(0),
+                Opcodes::I32Const(1),
+                Opcodes::Call("closure_entry".to_owned()),
+                Opcodes::CallIndirect(vec![Types::I32; 2], vec![Types::I32]),
+                Opcodes::Drop,
+            ]
+        );
+
+        let nodes = Parser::new("(defn main [] ((def x 1) 2))").parse().unwrap();
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::NotCallable(Position {
+                line: 1,
+                column: 16
+            }))
+        );
+    }
+
+    #[test]
+    fn anonymous_functions_close_over_locals() {
+        let module = compile("(defn f [n] (fn [x] (+ x n))) (defn main [] ((f 1) 2))");
+
+        assert_eq!(
+            module.table,
+            vec!["invoke_with_key", "invoke_collection", "fn#2"]
+        );
+        assert_eq!(
+            function(&module, "f").body,
+            vec![
+                Opcodes::I32Const(2),
+                Opcodes::I32Const(1),
+                Opcodes::I32Const(1),
+                Opcodes::Call("make_closure".to_owned()),
+                Opcodes::LocalSet(1),
+                Opcodes::LocalGet(1),
+                Opcodes::LocalGet(0),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::I32Store(16),
+                Opcodes::LocalGet(1),
+            ]
+        );
+        let lambda = function(&module, "fn#2");
+        assert_eq!(lambda.params, vec![Types::I32; 2]);
+        assert_eq!(
+            lambda.body[..3],
+            [
+                Opcodes::LocalGet(0),
+                Opcodes::I32Load(16),
+                Opcodes::LocalSet(2)
+            ]
+        );
+        let main = function(&module, "main");
+        assert_eq!(
+            main.body[main.body.len() - 6..],
+            [
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::LocalGet(0),
+                Opcodes::I32Const(1),
+                Opcodes::Call("closure_entry".to_owned()),
+                Opcodes::CallIndirect(vec![Types::I32; 2], vec![Types::I32]),
+                Opcodes::Drop,
+            ]
+        );
+    }
+
+    #[test]
+    fn collection_functions_take_references_and_indices() {
+        let module = compile(
+            "(defn f [v] (nth v 1.5) (assoc v 0 1 1 v) (subvec v 1)) (defn main [] (f []))",
+        );
+
+        let f = function(&module, "f");
+        assert_eq!(f.params, vec![Types::I32]);
+        assert_eq!(
+            f.body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::F64Const(1.5),
+                Opcodes::I64TruncF64S,
+                Opcodes::I32Const(0),
+                Opcodes::I32Const(0),
+                Opcodes::Call("collection_nth".to_owned()),
+                Opcodes::Drop,
+                Opcodes::LocalGet(0),
+                Opcodes::I64Const(0),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::I64Const(1),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::Call("collection_assoc".to_owned()),
+                Opcodes::I64Const(1),
+                Opcodes::Call("box_integer".to_owned()),
+                Opcodes::LocalGet(0),
+                Opcodes::Call("collection_assoc".to_owned()),
+                Opcodes::Drop,
+                Opcodes::LocalGet(0),
+                Opcodes::LocalTee(1),
+                Opcodes::I64Const(1),
+                Opcodes::LocalGet(1),
+                Opcodes::Call("collection_count".to_owned()),
+                Opcodes::Call("vector_subvec".to_owned()),
+            ]
+        );
+
+        let nodes = Parser::new("(defn f [v] (assoc v 0))").parse().unwrap();
+        assert_eq!(
+            Emitter::new(Options::default()).emit(nodes).err(),
+            Some(EmitError::WrongArity(
+                Position {
+                    line: 1,
+                    column: 14
+                },
+                Lexeme::Identifier("assoc".to_owned())
+            ))
+        );
+    }
+
+    #[test]
+    fn heap_starts_after_the_free_lists() {
+        let module = compile(r#"(defn main [] (print "Hello\n"))"#);
+
+        let global = |name| {
+            module
+                .global_index(name)
+                .map(|index| &module.globals[index])
+        };
+        let free_lists = global("free_lists").unwrap();
+        let heap_pointer = global("heap_pointer").unwrap();
+        assert!(!free_lists.mutable);
+        assert!(heap_pointer.mutable);
+        // past "Hello\n" at 64 and the message of the allocator at 80
+        assert_eq!(free_lists.value, Opcodes::I32Const(128));
+        assert_eq!(heap_pointer.value, Opcodes::I32Const(128 + 32 * 4));
+        assert_eq!(module.memory_pages, 1);
+    }
+
+    #[test]
+    fn allocation_grows_memory_until_it_runs_out() {
+        let text = "(defn f [n] (loop [i 0 v []] (if (< i n) (recur (inc i) (conj v [i])) v)))";
+        for memory in [Memory::Arena, Memory::Collected].iter() {
+            let module = compile_with(text, *memory);
+
+            let body = &function(&module, "allocate").body;
+            let grow = body
+                .iter()
+                .position(|opcode| *opcode == Opcodes::MemoryGrow)
+                .unwrap();
+            assert_eq!(
+                body[grow + 1..grow + 5],
+                [
+                    Opcodes::I32Const(-1),
+                    Opcodes::I32Eq,
+                    Opcodes::If(BlockType::Empty),
+                    Opcodes::Call("out_of_memory".to_owned()),
+                ]
+            );
+            assert!(function(&module, "out_of_memory")
+                .body
+                .contains(&Opcodes::Call("proc_exit".to_owned())));
+        }
+    }
+
+    #[test]
+    fn collected_functions_pop_their_roots() {
+        let module = compile_with(
+            "(defn f [x] (if x 1 2.5)) (defn g [x] (< x 1))",
+            Memory::Collected,
+        );
+
+        let f = function(&module, "f");
+        assert_eq!(f.locals, vec![Types::I32]);
+        assert_eq!(
+            f.body[..2],
+            [
+                Opcodes::GlobalGet("stack_pointer".to_owned()),
+                Opcodes::LocalSet(1),
+            ]
+        );
+        assert_eq!(
+            f.body[f.body.len() - 3..],
+            [
+                Opcodes::LocalGet(1),
+                Opcodes::GlobalSet("stack_pointer".to_owned()),
+                Opcodes::Call("root".to_owned()),
+            ]
+        );
+        let g = function(&module, "g");
+        assert!(g.locals.is_empty());
+        assert_eq!(g.body.len(), 3);
+        assert!(module.global_index("heap_start").is_some());
+    }
+
+    #[test]
+    fn engine_managed_values_are_references() {
+        let module = compile_with(
+            r#"(defn f [x] (if x nil true)) (defn main [] (f 1) (f "a"))"#,
+            Memory::Engine,
+        );
+
+        let f = function(&module, "f");
+        assert_eq!(f.params, vec![Types::EqRef]);
+        assert_eq!(f.results, vec![Types::EqRef]);
+        assert_eq!(
+            f.body,
+            vec![
+                Opcodes::LocalGet(0),
+                Opcodes::Call("truthy".to_owned()),
+                Opcodes::If(BlockType::Value(Types::EqRef)),
+                Opcodes::I32Const(0),
+                Opcodes::Drop,
+                Opcodes::RefNull(HeapType::Eq),
+                Opcodes::Else,
+                Opcodes::I32Const(1),
+                Opcodes::RefI31,
+                Opcodes::End,
+            ]
+        );
+        assert!(module.globals.is_empty());
+        assert!(module.function_index("allocate").is_none());
+        assert_eq!(module.heap_types().len(), 10);
+    }
+
+    #[test]
+    fn engine_managed_keywords_are_interned() {
+        let module = compile_with(
+            "(defn f [] :a) (defn g [] ::b) (defn h [] :a) (defn main [] (f) (g) (h))",
+            Memory::Engine,
+        );
+
+        let interned = |symbol, address| {
+            vec![
+                Opcodes::I32Const(symbol),
+                Opcodes::I32Const(address),
+                Opcodes::Call("intern_keyword".to_owned()),
+            ]
+        };
+        assert_eq!(function(&module, "f").body, interned(0, 64));
+        // the name of ::b is resolved in the user namespace
+        assert_eq!(function(&module, "g").body, interned(1, 80));
+        assert_eq!(function(&module, "h").body, interned(0, 64));
+        let symbol_count = module.global_index("symbol_count").unwrap();
+        assert_eq!(module.globals[symbol_count].value, Opcodes::I32Const(2));
     }
 }
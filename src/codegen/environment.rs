@@ -1,4 +1,140 @@
-use crate::frontend::ast::Node;
+use crate::codegen::types::ValueType;
 use std::collections::HashMap;
 
-type Environment = HashMap<Node, Node>;
+type ReferenceNumber = usize;
+
+/// Where the value bound to a symbol lives in the generated module. Locals
+/// also remember the static type of the value stored in them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    Local(ReferenceNumber, ValueType),
+    Global(String, ValueType),
+}
+
+/// Lexical environment used while emitting code. Globals are visible
+/// everywhere, while every function gets its own stack of scopes which map
+/// symbols to the function's parameters and locals. Inner scopes shadow
+/// outer ones.
+pub struct Environment {
+    globals: HashMap<String, Binding>,
+    scopes: Vec<HashMap<String, Binding>>,
+    parameter_count: usize,
+    /// Type of every local slot of the current function, parameters first
+    locals: Vec<ValueType>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            globals: HashMap::new(),
+            scopes: Vec::new(),
+            parameter_count: 0,
+            locals: Vec::new(),
+        }
+    }
+
+    pub fn define_global(&mut self, name: &str, value_type: ValueType) {
+        self.globals.insert(
+            name.to_owned(),
+            Binding::Global(name.to_owned(), value_type),
+        );
+    }
+
+    /// Starts a fresh function scope where `parameters` occupy the first
+    /// local slots in order.
+    pub fn enter_function(&mut self, parameters: &[(String, ValueType)]) {
+        self.scopes = vec![HashMap::new()];
+        self.locals.clear();
+        for (parameter, value_type) in parameters {
+            self.declare_local(parameter, *value_type);
+        }
+        self.parameter_count = parameters.len();
+    }
+
+    /// Leaves the current function, returning the types of the locals that
+    /// were declared on top of its parameters.
+    pub fn leave_function(&mut self) -> Vec<ValueType> {
+        self.scopes.clear();
+        self.locals.split_off(self.parameter_count)
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Binds `name` to a new local slot in the innermost scope. Slots are
+    /// never reused, so shadowed locals keep their own storage.
+    pub fn declare_local(&mut self, name: &str, value_type: ValueType) -> ReferenceNumber {
+        let index = self.declare_temporary(value_type);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_owned(), Binding::Local(index, value_type));
+        }
+        index
+    }
+
+    /// Reserves a local slot which no symbol refers to, for values the
+    /// generated code needs to keep around.
+    pub fn declare_temporary(&mut self, value_type: ValueType) -> ReferenceNumber {
+        self.locals.push(value_type);
+        self.locals.len() - 1
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::environment::{Binding, Environment};
+    use crate::codegen::types::ValueType;
+
+    #[test]
+    fn resolve_parameters_and_globals() {
+        let mut environment = Environment::new();
+        environment.define_global("limit", ValueType::Float);
+        environment.enter_function(&[
+            ("x".to_owned(), ValueType::Integer),
+            ("y".to_owned(), ValueType::String),
+        ]);
+
+        assert_eq!(
+            environment.resolve("y"),
+            Some(Binding::Local(1, ValueType::String))
+        );
+        assert_eq!(
+            environment.resolve("limit"),
+            Some(Binding::Global("limit".to_owned(), ValueType::Float))
+        );
+        assert_eq!(environment.resolve("z"), None);
+    }
+
+    #[test]
+    fn inner_scopes_shadow_outer_ones() {
+        let mut environment = Environment::new();
+        environment.define_global("x", ValueType::Integer);
+        environment.enter_function(&[("x".to_owned(), ValueType::Integer)]);
+        environment.push_scope();
+        environment.declare_local("x", ValueType::String);
+
+        assert_eq!(
+            environment.resolve("x"),
+            Some(Binding::Local(1, ValueType::String))
+        );
+        environment.pop_scope();
+        assert_eq!(
+            environment.resolve("x"),
+            Some(Binding::Local(0, ValueType::Integer))
+        );
+        assert_eq!(environment.leave_function(), vec![ValueType::String]);
+    }
+}
@@ -1,89 +1,504 @@
 use std::fmt::{Display, Error, Formatter};
 
-type ReferenceNumber = usize;
+pub type ReferenceNumber = usize;
 
-/// Only operations on i32 numbers are supported at the moment
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Types {
-    I32param(ReferenceNumber),
-    I32result,
+    I32,
+    I64,
+    F64,
+    /// Nullable reference to a value of any type, when the engine manages
+    /// memory
+    EqRef,
+}
+
+/// Types of the objects references point to, from the GC proposal. Modules
+/// whose memory the engine manages define the types of boxed numbers, of
+/// strings, of keywords, of vectors and maps along with the nodes of their
+/// tries, of sets and of closures. They come first in their type section in
+/// the order given here, in a single recursion group so that types with the
+/// same fields are still told apart.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HeapType {
+    Eq,
+    I31,
+    Integer,
+    Float,
+    String,
+    Vector,
+    Node,
+    Keyword,
+    Map,
+    MapNode,
+    Set,
+    Closure,
+}
+
+/// What the objects of a defined heap type hold: the fields of a struct,
+/// which never change, or the mutable elements of an array. Arrays without
+/// an element type hold bytes.
+pub enum Definition {
+    Struct(&'static [Types]),
+    Array(Option<Types>),
+}
+
+impl HeapType {
+    pub const DEFINED: [HeapType; 10] = [
+        HeapType::Integer,
+        HeapType::Float,
+        HeapType::String,
+        HeapType::Vector,
+        HeapType::Node,
+        HeapType::Keyword,
+        HeapType::Map,
+        HeapType::MapNode,
+        HeapType::Set,
+        HeapType::Closure,
+    ];
+
+    pub fn definition(&self) -> Option<Definition> {
+        match self {
+            HeapType::Eq | HeapType::I31 => None,
+            HeapType::Integer => Some(Definition::Struct(&[Types::I64])),
+            HeapType::Float => Some(Definition::Struct(&[Types::F64])),
+            HeapType::String => Some(Definition::Array(None)),
+            HeapType::Vector => Some(Definition::Struct(&[
+                Types::I32,
+                Types::I32,
+                Types::I32,
+                Types::EqRef,
+                Types::EqRef,
+            ])),
+            HeapType::Node => Some(Definition::Array(Some(Types::EqRef))),
+            HeapType::Keyword => Some(Definition::Struct(&[Types::EqRef])),
+            HeapType::Map => Some(Definition::Struct(&[Types::EqRef, Types::I32])),
+            HeapType::MapNode => Some(Definition::Struct(&[Types::I32, Types::EqRef])),
+            HeapType::Set => Some(Definition::Struct(&[Types::EqRef])),
+            HeapType::Closure => Some(Definition::Struct(&[Types::I32, Types::I32, Types::EqRef])),
+        }
+    }
 }
 
 pub struct OpData {
     pub location: Opcodes,
-    pub data: String,
+    pub data: Vec<u8>,
+}
+
+pub struct Global {
+    pub name: String,
+    pub value_type: Types,
+    pub mutable: bool,
+    pub value: Opcodes,
+}
+
+/// Type of the values left on the stack by a block
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlockType {
+    Empty,
+    Value(Types),
 }
 
-#[derive(Copy, Clone)]
+/// Instructions in the order they are executed on the stack machine, which
+/// maps one to one onto both the text and the binary format.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Opcodes {
-    GetLocal,        // Get a local variable from the stack
-    Add,             // Add two i32 constants
-    Subtract,        // Subtract two i32 constants
-    Load,            // Load 4 bytes as an i32 from linear memory
-    Store(i32, i32), // Store 4 bytes as an i32 into linear memory
-    Const(i32),      // Push a constant on the stack
+    Block(BlockType),          // Start a block which branches jump out of
+    Loop(BlockType),           // Start a block which branches jump back to the start of
+    If(BlockType),             // Start a block which runs when the top of the stack is non zero
+    Else,                      // Start the alternative of an if block
+    End,                       // End the innermost block
+    Br(u32),                   // Branch to the block at the given depth
+    BrIf(u32),                 // Branch to the block at the given depth if the top is non zero
+    BrTable(Vec<u32>, u32),    // Branch to the depth at the index on top, or to the last one
+    LocalGet(ReferenceNumber), // Get a local variable from the stack
+    LocalSet(ReferenceNumber), // Pop the top of the stack into a local variable
+    LocalTee(ReferenceNumber), // Copy the top of the stack into a local variable
+    GlobalGet(String),         // Get a global variable
+    GlobalSet(String),         // Pop the top of the stack into a mutable global variable
+    I32Add,                    // Add two i32 values
+    I32Sub,                    // Subtract two i32 values
+    I32Mul,                    // Multiply two i32 values
+    I32Eqz,                    // Check if an i32 value is zero
+    I32Eq,                     // Check if two i32 values are equal
+    I32Ne,                     // Check if two i32 values are not equal
+    I32LtS,                    // Check if an i32 value is less than another
+    I32GtS,                    // Check if an i32 value is greater than another
+    I32GtU,                    // Check if an i32 value is greater than another, both unsigned
+    I32LeS,                    // Check if an i32 value is less than or equal to another
+    I32GeS,                    // Check if an i32 value is greater than or equal to another
+    I32GeU,                    // Check if an i32 value is at least another, both unsigned
+    I32And,                    // Bitwise and of two i32 values
+    I32Or,                     // Bitwise or of two i32 values
+    I32Shl,                    // Shift an i32 value left
+    I32ShrU,                   // Shift an i32 value right, filling with zeros
+    I32Clz,                    // Count the leading zero bits of an i32 value
+    I32Xor,                    // Bitwise exclusive or of two i32 values
+    I32Popcnt,                 // Count the bits set in an i32 value
+    Select,                    // Keep the first of two values if the top is non zero
+    RefNull(HeapType),         // Push a null reference
+    RefIsNull,                 // Check if a reference is null
+    RefEq,                     // Check if two references are the same, or hold the same i31
+    RefI31,                    // Turn the low 31 bits of an i32 value into a reference
+    I31GetU,                   // Get the value of an i31 reference as an unsigned i32
+    RefTest(HeapType),         // Check if a reference points to an object of a type
+    RefCast(HeapType),         // Cast a reference to a type, trapping when it is not of it
+    StructNew(HeapType),       // Create a struct from its fields
+    StructGet(HeapType, u32),  // Get the field of a struct with the given index
+    ArrayNewDefault(HeapType), // Create an array of some length filled with zeros
+    ArrayGet(HeapType),        // Get an element of an array
+    ArrayGetU(HeapType),       // Get a packed element of an array as an unsigned i32
+    ArraySet(HeapType),        // Set an element of an array
+    ArrayLen,                  // Get the length of an array
+    I32Load(u32),              // Load 4 bytes at an offset as an i32 from linear memory
+    I32Load8U(u32),            // Load a byte at an offset as an unsigned i32 from linear memory
+    I64Load(u32),              // Load 8 bytes at an offset as an i64 from linear memory
+    F64Load(u32),              // Load 8 bytes at an offset as an f64 from linear memory
+    I32Store(u32),             // Store 4 bytes at an offset as an i32 into linear memory
+    I32Store8(u32),            // Store the low byte of an i32 at an offset into linear memory
+    I64Store(u32),             // Store 8 bytes at an offset as an i64 into linear memory
+    F64Store(u32),             // Store 8 bytes at an offset as an f64 into linear memory
+    MemorySize,                // Push the size of linear memory in pages
+    MemoryGrow,    // Grow linear memory by a number of pages, pushing the old size or -1
+    I32Const(i32), // Push a constant on the stack
+    I32TruncF64S,  // Convert an f64 value to an i32, truncating towards zero
+    I64Const(i64), // Push a constant i64 on the stack
+    I64Add,        // Add two i64 values
+    I64Sub,        // Subtract two i64 values
+    I64Mul,        // Multiply two i64 values
+    I64DivS,       // Divide two i64 values, truncating towards zero
+    I64DivU,       // Divide two i64 values treated as unsigned
+    I64RemS,       // Remainder of two i64 values with the sign of the dividend
+    I64RemU,       // Remainder of two i64 values treated as unsigned
+    I64And,        // Bitwise and of two i64 values
+    I64Xor,        // Bitwise exclusive or of two i64 values
+    I64ShrU,       // Shift an i64 value right, filling with zeros
+    I64Eqz,        // Check if an i64 value is zero
+    I64Eq,         // Check if two i64 values are equal
+    I64Ne,         // Check if two i64 values are not equal
+    I64LtS,        // Check if an i64 value is less than another
+    I64GtS,        // Check if an i64 value is greater than another
+    I64GtU,        // Check if an i64 value is greater than another, both unsigned
+    I64LeS,        // Check if an i64 value is less than or equal to another
+    I64GeS,        // Check if an i64 value is greater than or equal to another
+    I64GeU,        // Check if an i64 value is at least another, both unsigned
+    I64ExtendI32S, // Convert a signed i32 value to an i64
+    I64TruncF64S,  // Convert an f64 value to an i64, truncating towards zero
+    I64ReinterpretF64, // Take the bits of an f64 value as an i64
+    I32WrapI64,    // Keep the low 32 bits of an i64 value
+    F64Const(f64), // Push a constant f64 on the stack
+    F64Add,        // Add two f64 values
+    F64Sub,        // Subtract two f64 values
+    F64Mul,        // Multiply two f64 values
+    F64Div,        // Divide two f64 values
+    F64Min,        // Smaller of two f64 values
+    F64Max,        // Larger of two f64 values
+    F64Abs,        // Absolute value of an f64 value
+    F64Neg,        // Negate an f64 value
+    F64Floor,      // Round an f64 value down to an integer
+    F64Trunc,      // Round an f64 value towards zero to an integer
+    F64Nearest,    // Round an f64 value to the nearest integer, ties to even
+    F64Eq,         // Check if two f64 values are equal
+    F64Ne,         // Check if two f64 values are not equal
+    F64Lt,         // Check if an f64 value is less than another
+    F64Gt,         // Check if an f64 value is greater than another
+    F64Le,         // Check if an f64 value is less than or equal to another
+    F64Ge,         // Check if an f64 value is greater than or equal to another
+    F64ConvertI64S, // Convert a signed i64 value to an f64
+    Call(String),  // Call a function defined in the module
+    /// Call the function of the table at the index on top of the stack,
+    /// which takes and returns values of the given types
+    CallIndirect(Vec<Types>, Vec<Types>),
+    /// Leave the current function by calling another one, whose results
+    /// are those of the current function
+    ReturnCall(String),
+    /// Leave the current function by calling the function of the table at
+    /// the index on top of the stack, as `CallIndirect` does
+    ReturnCallIndirect(Vec<Types>, Vec<Types>),
+    Return,      // Leave the current function with the values on the stack
+    Unreachable, // Trap, used after code which never returns
     Drop,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum WASIImports {
     FDWrite,
+    ProcExit,
 }
 
 pub enum SysCalls {
     Write(Opcodes, Opcodes, Opcodes, Opcodes),
+    Exit(Opcodes),
+}
+
+impl WASIImports {
+    pub fn module(&self) -> &'static str {
+        "wasi_unstable"
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WASIImports::FDWrite => "fd_write",
+            WASIImports::ProcExit => "proc_exit",
+        }
+    }
+
+    pub fn params(&self) -> Vec<Types> {
+        match self {
+            WASIImports::FDWrite => vec![Types::I32; 4],
+            WASIImports::ProcExit => vec![Types::I32],
+        }
+    }
+
+    pub fn results(&self) -> Vec<Types> {
+        match self {
+            WASIImports::FDWrite => vec![Types::I32],
+            WASIImports::ProcExit => vec![],
+        }
+    }
+}
+
+impl SysCalls {
+    /// Pushes the arguments of the system call and calls the matching import
+    pub fn instructions(self) -> Vec<Opcodes> {
+        match self {
+            SysCalls::Write(file_descriptor, iov_ptr, iov_len, num_written) => vec![
+                file_descriptor,
+                iov_ptr,
+                iov_len,
+                num_written,
+                Opcodes::Call(WASIImports::FDWrite.name().to_owned()),
+            ],
+            SysCalls::Exit(exit_code) => vec![
+                exit_code,
+                Opcodes::Call(WASIImports::ProcExit.name().to_owned()),
+            ],
+        }
+    }
 }
 
 impl Display for Types {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         match self {
-            Types::I32param(name) => write!(f, "(param $p{:?} i32)", name),
-            Types::I32result => write!(f, "(result i32)"),
+            Types::I32 => write!(f, "i32"),
+            Types::I64 => write!(f, "i64"),
+            Types::F64 => write!(f, "f64"),
+            Types::EqRef => write!(f, "eqref"),
+        }
+    }
+}
+
+impl Display for HeapType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            HeapType::Eq => write!(f, "eq"),
+            HeapType::I31 => write!(f, "i31"),
+            HeapType::Integer => write!(f, "$Integer"),
+            HeapType::Float => write!(f, "$Float"),
+            HeapType::String => write!(f, "$String"),
+            HeapType::Vector => write!(f, "$Vector"),
+            HeapType::Node => write!(f, "$Node"),
+            HeapType::Keyword => write!(f, "$Keyword"),
+            HeapType::Map => write!(f, "$Map"),
+            HeapType::MapNode => write!(f, "$MapNode"),
+            HeapType::Set => write!(f, "$Set"),
+            HeapType::Closure => write!(f, "$Closure"),
         }
     }
 }
 
 impl Display for OpData {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        write!(f, "(data {} {:?})", self.location, self.data)
+        write!(f, "(data ({}) \"", self.location)?;
+        for byte in &self.data {
+            match byte {
+                b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+                0x20..=0x7e => write!(f, "{}", *byte as char)?,
+                _ => write!(f, "\\{:02x}", byte)?,
+            }
+        }
+        write!(f, "\")")
     }
 }
 
-impl Display for Opcodes {
+impl Display for Global {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        match self {
-            Opcodes::GetLocal => write!(f, "(get_local)"),
-            Opcodes::Add => write!(f, "(i32.add"),
-            Opcodes::Subtract => write!(f, "(i32.sub"),
-            Opcodes::Load => write!(f, "(i32.load32_s)"),
-            Opcodes::Store(address, value) => write!(
+        if self.mutable {
+            write!(
                 f,
-                "(i32.store {} {})",
-                Opcodes::Const(*address),
-                Opcodes::Const(*value)
-            ),
-            Opcodes::Const(constant) => write!(f, "(i32.const {:?})", constant),
-            Opcodes::Drop => write!(f, "drop"),
+                "(global ${} (mut {}) ({}))",
+                self.name, self.value_type, self.value
+            )
+        } else {
+            write!(
+                f,
+                "(global ${} {} ({}))",
+                self.name, self.value_type, self.value
+            )
         }
     }
 }
 
-impl Display for SysCalls {
+impl Display for BlockType {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         match self {
-            SysCalls::Write(file_descriptor, iov_ptr, iov_len, num_written) => write!(
-                f,
-                "(call $fd_write {} {} {} {})",
-                file_descriptor, iov_ptr, iov_len, num_written
-            ),
+            BlockType::Empty => Ok(()),
+            BlockType::Value(value_type) => write!(f, " (result {})", value_type),
         }
     }
 }
 
-impl Display for WASIImports {
+impl Display for Opcodes {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         match self {
-            WASIImports::FDWrite =>
-                write!(f, "(import \"wasi_unstable\" \"fd_write\" (func $fd_write (param i32 i32 i32 i32) {}))",
-                       Types::I32result),
+            Opcodes::Block(block_type) => write!(f, "block{}", block_type),
+            Opcodes::Loop(block_type) => write!(f, "loop{}", block_type),
+            Opcodes::If(block_type) => write!(f, "if{}", block_type),
+            Opcodes::Else => write!(f, "else"),
+            Opcodes::End => write!(f, "end"),
+            Opcodes::Br(depth) => write!(f, "br {}", depth),
+            Opcodes::BrIf(depth) => write!(f, "br_if {}", depth),
+            Opcodes::BrTable(depths, default) => {
+                write!(f, "br_table")?;
+                for depth in depths {
+                    write!(f, " {}", depth)?;
+                }
+                write!(f, " {}", default)
+            }
+            Opcodes::LocalGet(index) => write!(f, "local.get {}", index),
+            Opcodes::LocalSet(index) => write!(f, "local.set {}", index),
+            Opcodes::LocalTee(index) => write!(f, "local.tee {}", index),
+            Opcodes::GlobalGet(name) => write!(f, "global.get ${}", name),
+            Opcodes::GlobalSet(name) => write!(f, "global.set ${}", name),
+            Opcodes::I32Add => write!(f, "i32.add"),
+            Opcodes::I32Sub => write!(f, "i32.sub"),
+            Opcodes::I32Mul => write!(f, "i32.mul"),
+            Opcodes::I32Eqz => write!(f, "i32.eqz"),
+            Opcodes::I32Eq => write!(f, "i32.eq"),
+            Opcodes::I32Ne => write!(f, "i32.ne"),
+            Opcodes::I32LtS => write!(f, "i32.lt_s"),
+            Opcodes::I32GtS => write!(f, "i32.gt_s"),
+            Opcodes::I32GtU => write!(f, "i32.gt_u"),
+            Opcodes::I32LeS => write!(f, "i32.le_s"),
+            Opcodes::I32GeS => write!(f, "i32.ge_s"),
+            Opcodes::I32GeU => write!(f, "i32.ge_u"),
+            Opcodes::I32And => write!(f, "i32.and"),
+            Opcodes::I32Or => write!(f, "i32.or"),
+            Opcodes::I32Shl => write!(f, "i32.shl"),
+            Opcodes::I32ShrU => write!(f, "i32.shr_u"),
+            Opcodes::I32Clz => write!(f, "i32.clz"),
+            Opcodes::I32Xor => write!(f, "i32.xor"),
+            Opcodes::I32Popcnt => write!(f, "i32.popcnt"),
+            Opcodes::Select => write!(f, "select"),
+            Opcodes::RefNull(heap_type) => write!(f, "ref.null {}", heap_type),
+            Opcodes::RefIsNull => write!(f, "ref.is_null"),
+            Opcodes::RefEq => write!(f, "ref.eq"),
+            Opcodes::RefI31 => write!(f, "ref.i31"),
+            Opcodes::I31GetU => write!(f, "i31.get_u"),
+            Opcodes::RefTest(heap_type) => write!(f, "ref.test (ref {})", heap_type),
+            Opcodes::RefCast(heap_type) => write!(f, "ref.cast (ref {})", heap_type),
+            Opcodes::StructNew(heap_type) => write!(f, "struct.new {}", heap_type),
+            Opcodes::StructGet(heap_type, field) => {
+                write!(f, "struct.get {} {}", heap_type, field)
+            }
+            Opcodes::ArrayNewDefault(heap_type) => write!(f, "array.new_default {}", heap_type),
+            Opcodes::ArrayGet(heap_type) => write!(f, "array.get {}", heap_type),
+            Opcodes::ArrayGetU(heap_type) => write!(f, "array.get_u {}", heap_type),
+            Opcodes::ArraySet(heap_type) => write!(f, "array.set {}", heap_type),
+            Opcodes::ArrayLen => write!(f, "array.len"),
+            Opcodes::I32Load(offset) => write!(f, "i32.load offset={}", offset),
+            Opcodes::I32Load8U(offset) => write!(f, "i32.load8_u offset={}", offset),
+            Opcodes::I64Load(offset) => write!(f, "i64.load offset={}", offset),
+            Opcodes::F64Load(offset) => write!(f, "f64.load offset={}", offset),
+            Opcodes::I32Store(offset) => write!(f, "i32.store offset={}", offset),
+            Opcodes::I32Store8(offset) => write!(f, "i32.store8 offset={}", offset),
+            Opcodes::I64Store(offset) => write!(f, "i64.store offset={}", offset),
+            Opcodes::F64Store(offset) => write!(f, "f64.store offset={}", offset),
+            Opcodes::MemorySize => write!(f, "memory.size"),
+            Opcodes::MemoryGrow => write!(f, "memory.grow"),
+            Opcodes::I32Const(constant) => write!(f, "i32.const {:?}", constant),
+            Opcodes::I32TruncF64S => write!(f, "i32.trunc_f64_s"),
+            Opcodes::I64Const(constant) => write!(f, "i64.const {:?}", constant),
+            Opcodes::I64Add => write!(f, "i64.add"),
+            Opcodes::I64Sub => write!(f, "i64.sub"),
+            Opcodes::I64Mul => write!(f, "i64.mul"),
+            Opcodes::I64DivS => write!(f, "i64.div_s"),
+            Opcodes::I64DivU => write!(f, "i64.div_u"),
+            Opcodes::I64RemS => write!(f, "i64.rem_s"),
+            Opcodes::I64RemU => write!(f, "i64.rem_u"),
+            Opcodes::I64And => write!(f, "i64.and"),
+            Opcodes::I64Xor => write!(f, "i64.xor"),
+            Opcodes::I64ShrU => write!(f, "i64.shr_u"),
+            Opcodes::I64Eqz => write!(f, "i64.eqz"),
+            Opcodes::I64Eq => write!(f, "i64.eq"),
+            Opcodes::I64Ne => write!(f, "i64.ne"),
+            Opcodes::I64LtS => write!(f, "i64.lt_s"),
+            Opcodes::I64GtS => write!(f, "i64.gt_s"),
+            Opcodes::I64GtU => write!(f, "i64.gt_u"),
+            Opcodes::I64LeS => write!(f, "i64.le_s"),
+            Opcodes::I64GeS => write!(f, "i64.ge_s"),
+            Opcodes::I64GeU => write!(f, "i64.ge_u"),
+            Opcodes::I64ExtendI32S => write!(f, "i64.extend_i32_s"),
+            Opcodes::I64TruncF64S => write!(f, "i64.trunc_f64_s"),
+            Opcodes::I64ReinterpretF64 => write!(f, "i64.reinterpret_f64"),
+            Opcodes::I32WrapI64 => write!(f, "i32.wrap_i64"),
+            Opcodes::F64Const(constant) if constant.is_nan() => write!(f, "f64.const nan"),
+            Opcodes::F64Const(constant) => write!(f, "f64.const {:?}", constant),
+            Opcodes::F64Add => write!(f, "f64.add"),
+            Opcodes::F64Sub => write!(f, "f64.sub"),
+            Opcodes::F64Mul => write!(f, "f64.mul"),
+            Opcodes::F64Div => write!(f, "f64.div"),
+            Opcodes::F64Min => write!(f, "f64.min"),
+            Opcodes::F64Max => write!(f, "f64.max"),
+            Opcodes::F64Abs => write!(f, "f64.abs"),
+            Opcodes::F64Neg => write!(f, "f64.neg"),
+            Opcodes::F64Floor => write!(f, "f64.floor"),
+            Opcodes::F64Trunc => write!(f, "f64.trunc"),
+            Opcodes::F64Nearest => write!(f, "f64.nearest"),
+            Opcodes::F64Eq => write!(f, "f64.eq"),
+            Opcodes::F64Ne => write!(f, "f64.ne"),
+            Opcodes::F64Lt => write!(f, "f64.lt"),
+            Opcodes::F64Gt => write!(f, "f64.gt"),
+            Opcodes::F64Le => write!(f, "f64.le"),
+            Opcodes::F64Ge => write!(f, "f64.ge"),
+            Opcodes::F64ConvertI64S => write!(f, "f64.convert_i64_s"),
+            Opcodes::Call(name) => write!(f, "call ${}", name),
+            Opcodes::ReturnCall(name) => write!(f, "return_call ${}", name),
+            Opcodes::CallIndirect(params, results)
+            | Opcodes::ReturnCallIndirect(params, results) => {
+                match self {
+                    Opcodes::CallIndirect(..) => write!(f, "call_indirect")?,
+                    _ => write!(f, "return_call_indirect")?,
+                }
+                for param in params {
+                    write!(f, " (param {})", param)?;
+                }
+                for result in results {
+                    write!(f, " (result {})", result)?;
+                }
+                Ok(())
+            }
+            Opcodes::Return => write!(f, "return"),
+            Opcodes::Drop => write!(f, "drop"),
+            Opcodes::Unreachable => write!(f, "unreachable"),
+        }
+    }
+}
+
+impl Display for WASIImports {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(
+            f,
+            "(import {:?} {:?} (func ${}",
+            self.module(),
+            self.name(),
+            self.name()
+        )?;
+        for param in self.params() {
+            write!(f, " (param {})", param)?;
+        }
+        for result in self.results() {
+            write!(f, " (result {})", result)?;
         }
+        write!(f, "))")
     }
 }
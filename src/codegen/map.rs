@@ -0,0 +1,1697 @@
+use crate::codegen::data::DataLayout;
+use crate::codegen::instructions::{BlockType, HeapType, Opcodes, Types};
+use crate::codegen::module::{Function, Memory};
+use crate::codegen::runtime::{reference, unbox, write_literal, Runtime, ALLOCATE, NIL, STDOUT};
+use crate::codegen::set;
+use crate::codegen::types::Tag;
+
+/// Every node of a trie has up to 32 entries, each level of the trie being
+/// indexed by the next 5 bits of the hash of a key
+const BITS: i32 = 5;
+const MASK: i32 = (1 << BITS) - 1;
+/// In linear memory, the root and the count of a map follow its tag
+const MAP_SIZE: i32 = 12;
+/// In linear memory, the bitmap and the number of entries of a node follow
+/// its tag, and its entries follow them at offset 12
+const BITMAP_OFFSET: u32 = 4;
+const SIZE_OFFSET: u32 = 8;
+const ENTRIES: u32 = 12;
+/// Key of the entries of a node holding a child node instead of a value. It
+/// follows the references to nil, false and true, so it is never a value.
+const ABSENT: i32 = 3;
+
+/// Fields of a map, which is a hash array mapped trie as in Clojure. Every
+/// node has an entry for each bit set in its bitmap, in the order of the
+/// bits, and each entry takes two slots: a key and its value, or `ABSENT`
+/// and a child node one level down. Keys whose hashes are the same are kept
+/// in a collision node, whose bitmap is 0 and whose entries are searched in
+/// turn. An empty map has a nil root.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Field {
+    Root,
+    /// Number of keys in the trie
+    Count,
+}
+
+impl Field {
+    /// Offset of the field in linear memory, past the tag
+    fn offset(self) -> u32 {
+        4 + 4 * self as u32
+    }
+}
+
+fn field(map: usize, field: Field, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![
+            LocalGet(map),
+            RefCast(HeapType::Map),
+            StructGet(HeapType::Map, field as u32),
+        ],
+        _ => vec![LocalGet(map), I32Load(field.offset())],
+    }
+}
+
+/// Number of keys of the map in `map`
+pub fn count(map: usize, memory: Memory) -> Vec<Opcodes> {
+    field(map, Field::Count, memory)
+}
+
+/// Root node of the map in `map`, which is nil when it is empty
+pub fn root(map: usize, memory: Memory) -> Vec<Opcodes> {
+    field(map, Field::Root, memory)
+}
+
+/// Whether the maps in `left` and `right` have as many keys
+pub fn same_count(left: usize, right: usize, memory: Memory) -> Vec<Opcodes> {
+    [
+        count(left, memory),
+        count(right, memory),
+        vec![Opcodes::I32Eq],
+    ]
+    .concat()
+}
+
+fn bitmap(node: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![
+            LocalGet(node),
+            RefCast(HeapType::MapNode),
+            StructGet(HeapType::MapNode, 0),
+        ],
+        _ => vec![LocalGet(node), I32Load(BITMAP_OFFSET)],
+    }
+}
+
+/// Number of slots of the node in `node`, two for each of its entries
+fn slot_count(node: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![
+            LocalGet(node),
+            RefCast(HeapType::MapNode),
+            StructGet(HeapType::MapNode, 1),
+            RefCast(HeapType::Node),
+            ArrayLen,
+        ],
+        _ => vec![LocalGet(node), I32Load(SIZE_OFFSET), I32Const(1), I32Shl],
+    }
+}
+
+/// Number of entries of the node in `node`
+fn size(node: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => [slot_count(node, memory), vec![I32Const(1), I32ShrU]].concat(),
+        _ => vec![LocalGet(node), I32Load(SIZE_OFFSET)],
+    }
+}
+
+/// The reference in the slot at `index` of the node in `node`
+fn slot(node: usize, index: Vec<Opcodes>, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => [
+            vec![
+                LocalGet(node),
+                RefCast(HeapType::MapNode),
+                StructGet(HeapType::MapNode, 1),
+                RefCast(HeapType::Node),
+            ],
+            index,
+            vec![ArrayGet(HeapType::Node)],
+        ]
+        .concat(),
+        _ => [
+            vec![LocalGet(node)],
+            index,
+            vec![I32Const(2), I32Shl, I32Add, I32Load(ENTRIES)],
+        ]
+        .concat(),
+    }
+}
+
+fn set_slot(node: usize, index: Vec<Opcodes>, value: Vec<Opcodes>, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => [
+            vec![
+                LocalGet(node),
+                RefCast(HeapType::MapNode),
+                StructGet(HeapType::MapNode, 1),
+                RefCast(HeapType::Node),
+            ],
+            index,
+            value,
+            vec![ArraySet(HeapType::Node)],
+        ]
+        .concat(),
+        _ => [
+            vec![LocalGet(node)],
+            index,
+            vec![I32Const(2), I32Shl, I32Add],
+            value,
+            vec![I32Store(ENTRIES)],
+        ]
+        .concat(),
+    }
+}
+
+/// Key of the entry of the node in `node` whose first slot is in `index`
+fn key(node: usize, index: usize, memory: Memory) -> Vec<Opcodes> {
+    slot(node, vec![Opcodes::LocalGet(index)], memory)
+}
+
+/// Value or child node of the entry of the node in `node` whose first slot
+/// is in `index`
+fn value(node: usize, index: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    slot(node, vec![LocalGet(index), I32Const(1), I32Add], memory)
+}
+
+fn absent(memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![I32Const(ABSENT), RefI31],
+        _ => vec![I32Const(ABSENT)],
+    }
+}
+
+/// Whether the reference on top of the stack is `ABSENT`
+fn is_absent(memory: Memory) -> Vec<Opcodes> {
+    let compare = match memory {
+        Memory::Engine => Opcodes::RefEq,
+        _ => Opcodes::I32Eq,
+    };
+    [absent(memory), vec![compare]].concat()
+}
+
+fn null(memory: Memory) -> Opcodes {
+    match memory {
+        Memory::Engine => Opcodes::RefNull(HeapType::Eq),
+        _ => Opcodes::I32Const(NIL),
+    }
+}
+
+fn is_null(memory: Memory) -> Opcodes {
+    match memory {
+        Memory::Engine => Opcodes::RefIsNull,
+        _ => Opcodes::I32Eqz,
+    }
+}
+
+/// Bit of the bitmap of a node at the level given by `shift` for the hash in
+/// `hash`
+fn bit(hash: usize, shift: usize) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        I32Const(1),
+        LocalGet(hash),
+        LocalGet(shift),
+        I32ShrU,
+        I32Const(MASK),
+        I32And,
+        I32Shl,
+    ]
+}
+
+/// First slot of the entry of the node in `node` for the bit in `bit`, which
+/// follows the entries of the bits below it
+fn position(node: usize, bit: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    [
+        bitmap(node, memory),
+        vec![
+            LocalGet(bit),
+            I32Const(1),
+            I32Sub,
+            I32And,
+            I32Popcnt,
+            I32Const(1),
+            I32Shl,
+        ],
+    ]
+    .concat()
+}
+
+/// The level below the one given by `shift`
+fn next_level(shift: usize) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![LocalGet(shift), I32Const(BITS), I32Add]
+}
+
+/// Runs `body` with `index` going from `from` up to `to` in steps of `step`
+fn for_each_index(
+    index: usize,
+    from: Vec<Opcodes>,
+    to: Vec<Opcodes>,
+    step: i32,
+    body: Vec<Opcodes>,
+) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    [
+        from,
+        vec![
+            LocalSet(index),
+            Block(BlockType::Empty),
+            Loop(BlockType::Empty),
+            LocalGet(index),
+        ],
+        to,
+        vec![I32GeS, BrIf(1)],
+        body,
+        vec![
+            LocalGet(index),
+            I32Const(step),
+            I32Add,
+            LocalSet(index),
+            Br(0),
+            End,
+            End,
+        ],
+    ]
+    .concat()
+}
+
+/// Runs `pair` for every entry of the node in `node` holding a key and its
+/// value, and `child` for every entry holding a child node, the first slot
+/// of the entry being held in `index`
+fn for_each_entry(
+    node: usize,
+    index: usize,
+    pair: Vec<Opcodes>,
+    child: Vec<Opcodes>,
+    memory: Memory,
+) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    for_each_index(
+        index,
+        vec![I32Const(0)],
+        slot_count(node, memory),
+        2,
+        [
+            key(node, index, memory),
+            is_absent(memory),
+            vec![If(BlockType::Empty)],
+            child,
+            vec![Else],
+            pair,
+            vec![End],
+        ]
+        .concat(),
+    )
+}
+
+/// Copies the slots of the node in `from` between the indices `start` and
+/// `end` to the node in `to`, `offset` slots further
+fn copy_slots(
+    from: usize,
+    to: usize,
+    index: usize,
+    start: Vec<Opcodes>,
+    end: Vec<Opcodes>,
+    offset: i32,
+    memory: Memory,
+) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    for_each_index(
+        index,
+        start,
+        end,
+        1,
+        set_slot(
+            to,
+            vec![LocalGet(index), I32Const(offset), I32Add],
+            slot(from, vec![LocalGet(index)], memory),
+            memory,
+        ),
+    )
+}
+
+/// Returns early with `result` when the node in `node` is nil
+fn unless_null(node: usize, result: Opcodes, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(node),
+        is_null(memory),
+        If(BlockType::Empty),
+        result,
+        Return,
+        End,
+    ]
+}
+
+fn has_tag(local: usize, tag: Tag) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(local),
+        call(Runtime::TypeOf),
+        I32Const(tag as i32),
+        I32Eq,
+    ]
+}
+
+/// Raises a ClassCastException unless `local` holds a map
+fn expect_map(local: usize) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    [
+        has_tag(local, Tag::Map),
+        vec![I32Eqz, If(BlockType::Empty), call(Runtime::NotAMap), End],
+    ]
+    .concat()
+}
+
+fn call(runtime: Runtime) -> Opcodes {
+    Opcodes::Call(runtime.name().to_owned())
+}
+
+/// Calls `mark` on the references held by the object in `object` when it is
+/// a map or a node of its trie, whose tag is in `tag`
+pub fn mark_references(object: usize, tag: usize, index: usize, mark: Opcodes) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    [
+        vec![
+            LocalGet(tag),
+            I32Const(Tag::Map as i32),
+            I32Eq,
+            If(BlockType::Empty),
+        ],
+        root(object, Memory::Collected),
+        vec![
+            mark.clone(),
+            End,
+            LocalGet(tag),
+            I32Const(Tag::MapNode as i32),
+            I32Eq,
+            If(BlockType::Empty),
+        ],
+        for_each_index(
+            index,
+            vec![I32Const(0)],
+            slot_count(object, Memory::Collected),
+            1,
+            [
+                slot(object, vec![LocalGet(index)], Memory::Collected),
+                vec![mark],
+            ]
+            .concat(),
+        ),
+        vec![End],
+    ]
+    .concat()
+}
+
+/// Returns a map with the root and the count given as arguments
+pub fn make_map(name: &str, memory: Memory) -> Function {
+    const ROOT: usize = 0;
+    const COUNT: usize = 1;
+    const MAP: usize = 2;
+    use Opcodes::*;
+
+    if memory == Memory::Engine {
+        return Function {
+            name: name.to_owned(),
+            params: vec![Types::EqRef, Types::I32],
+            results: vec![Types::EqRef],
+            locals: vec![],
+            body: vec![LocalGet(ROOT), LocalGet(COUNT), StructNew(HeapType::Map)],
+        };
+    }
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32; 2],
+        results: vec![Types::I32],
+        locals: vec![Types::I32],
+        body: vec![
+            I32Const(MAP_SIZE),
+            Call(ALLOCATE.to_owned()),
+            LocalTee(MAP),
+            I32Const(Tag::Map as i32),
+            I32Store(0),
+            LocalGet(MAP),
+            LocalGet(ROOT),
+            I32Store(Field::Root.offset()),
+            LocalGet(MAP),
+            LocalGet(COUNT),
+            I32Store(Field::Count.offset()),
+            LocalGet(MAP),
+        ],
+    }
+}
+
+pub fn empty_map(name: &str, memory: Memory) -> Function {
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: vec![null(memory), I32Const(0), call(Runtime::MakeMap)],
+    }
+}
+
+/// Returns a node with the given bitmap and number of entries, whose slots
+/// all hold nil
+pub fn new_map_node(name: &str, memory: Memory) -> Function {
+    const BITMAP: usize = 0;
+    const SIZE: usize = 1;
+    const NODE: usize = 2;
+    const INDEX: usize = 3;
+    use Opcodes::*;
+
+    if memory == Memory::Engine {
+        return Function {
+            name: name.to_owned(),
+            params: vec![Types::I32; 2],
+            results: vec![Types::EqRef],
+            locals: vec![],
+            body: vec![
+                LocalGet(BITMAP),
+                LocalGet(SIZE),
+                I32Const(1),
+                I32Shl,
+                ArrayNewDefault(HeapType::Node),
+                StructNew(HeapType::MapNode),
+            ],
+        };
+    }
+    let mut body = vec![
+        LocalGet(SIZE),
+        I32Const(3),
+        I32Shl,
+        I32Const(ENTRIES as i32),
+        I32Add,
+        Call(ALLOCATE.to_owned()),
+        LocalTee(NODE),
+        I32Const(Tag::MapNode as i32),
+        I32Store(0),
+        LocalGet(NODE),
+        LocalGet(BITMAP),
+        I32Store(BITMAP_OFFSET),
+        LocalGet(NODE),
+        LocalGet(SIZE),
+        I32Store(SIZE_OFFSET),
+    ];
+    body.append(
+        for_each_index(
+            INDEX,
+            vec![I32Const(0)],
+            slot_count(NODE, memory),
+            1,
+            set_slot(NODE, vec![LocalGet(INDEX)], vec![null(memory)], memory),
+        )
+        .as_mut(),
+    );
+    body.push(LocalGet(NODE));
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32; 2],
+        results: vec![Types::I32],
+        locals: vec![Types::I32; 2],
+        body,
+    }
+}
+
+/// Returns a copy of a node with the entry at the given slot replaced by the
+/// last two arguments
+pub fn replace_entry(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const AT: usize = 1;
+    const KEY: usize = 2;
+    const VALUE: usize = 3;
+    const COPY: usize = 4;
+    const INDEX: usize = 5;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![
+            reference(memory),
+            Types::I32,
+            reference(memory),
+            reference(memory),
+        ],
+        results: vec![reference(memory)],
+        locals: vec![reference(memory), Types::I32],
+        body: [
+            bitmap(NODE, memory),
+            size(NODE, memory),
+            vec![call(Runtime::NewMapNode), LocalSet(COPY)],
+            copy_slots(
+                NODE,
+                COPY,
+                INDEX,
+                vec![I32Const(0)],
+                slot_count(NODE, memory),
+                0,
+                memory,
+            ),
+            set_slot(COPY, vec![LocalGet(AT)], vec![LocalGet(KEY)], memory),
+            set_slot(
+                COPY,
+                vec![LocalGet(AT), I32Const(1), I32Add],
+                vec![LocalGet(VALUE)],
+                memory,
+            ),
+            vec![LocalGet(COPY)],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a copy of a node with an entry for the given bit inserted at the
+/// given slot, holding the last two arguments
+pub fn insert_entry(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const AT: usize = 1;
+    const BIT: usize = 2;
+    const KEY: usize = 3;
+    const VALUE: usize = 4;
+    const COPY: usize = 5;
+    const INDEX: usize = 6;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![
+            reference(memory),
+            Types::I32,
+            Types::I32,
+            reference(memory),
+            reference(memory),
+        ],
+        results: vec![reference(memory)],
+        locals: vec![reference(memory), Types::I32],
+        body: [
+            bitmap(NODE, memory),
+            vec![LocalGet(BIT), I32Or],
+            size(NODE, memory),
+            vec![
+                I32Const(1),
+                I32Add,
+                call(Runtime::NewMapNode),
+                LocalSet(COPY),
+            ],
+            copy_slots(
+                NODE,
+                COPY,
+                INDEX,
+                vec![I32Const(0)],
+                vec![LocalGet(AT)],
+                0,
+                memory,
+            ),
+            set_slot(COPY, vec![LocalGet(AT)], vec![LocalGet(KEY)], memory),
+            set_slot(
+                COPY,
+                vec![LocalGet(AT), I32Const(1), I32Add],
+                vec![LocalGet(VALUE)],
+                memory,
+            ),
+            copy_slots(
+                NODE,
+                COPY,
+                INDEX,
+                vec![LocalGet(AT)],
+                slot_count(NODE, memory),
+                2,
+                memory,
+            ),
+            vec![LocalGet(COPY)],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a copy of a node without the entry for the given bit at the given
+/// slot
+pub fn remove_entry(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const AT: usize = 1;
+    const BIT: usize = 2;
+    const COPY: usize = 3;
+    const INDEX: usize = 4;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), Types::I32, Types::I32],
+        results: vec![reference(memory)],
+        locals: vec![reference(memory), Types::I32],
+        body: [
+            bitmap(NODE, memory),
+            vec![LocalGet(BIT), I32Xor],
+            size(NODE, memory),
+            vec![
+                I32Const(1),
+                I32Sub,
+                call(Runtime::NewMapNode),
+                LocalSet(COPY),
+            ],
+            copy_slots(
+                NODE,
+                COPY,
+                INDEX,
+                vec![I32Const(0)],
+                vec![LocalGet(AT)],
+                0,
+                memory,
+            ),
+            copy_slots(
+                NODE,
+                COPY,
+                INDEX,
+                vec![LocalGet(AT), I32Const(2), I32Add],
+                slot_count(NODE, memory),
+                -2,
+                memory,
+            ),
+            vec![LocalGet(COPY)],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a node at the given level holding two keys with their values and
+/// hashes. Keys whose bits at that level are the same go one level down,
+/// and keys with the same hash into a collision node.
+pub fn create_node(name: &str, memory: Memory) -> Function {
+    const SHIFT: usize = 0;
+    const FIRST_KEY: usize = 1;
+    const FIRST_VALUE: usize = 2;
+    const FIRST_HASH: usize = 3;
+    const SECOND_KEY: usize = 4;
+    const SECOND_VALUE: usize = 5;
+    const SECOND_HASH: usize = 6;
+    const NODE: usize = 7;
+    const FIRST_BIT: usize = 8;
+    const SECOND_BIT: usize = 9;
+    use Opcodes::*;
+
+    // the entry of the lower bit comes first
+    let first_at = vec![
+        LocalGet(FIRST_BIT),
+        LocalGet(SECOND_BIT),
+        I32GtU,
+        I32Const(1),
+        I32Shl,
+    ];
+    let second_at = vec![
+        LocalGet(SECOND_BIT),
+        LocalGet(FIRST_BIT),
+        I32GtU,
+        I32Const(1),
+        I32Shl,
+    ];
+    let both = |first: Vec<Opcodes>, second: Vec<Opcodes>| {
+        [
+            set_slot(NODE, first.clone(), vec![LocalGet(FIRST_KEY)], memory),
+            set_slot(
+                NODE,
+                [first, vec![I32Const(1), I32Add]].concat(),
+                vec![LocalGet(FIRST_VALUE)],
+                memory,
+            ),
+            set_slot(NODE, second.clone(), vec![LocalGet(SECOND_KEY)], memory),
+            set_slot(
+                NODE,
+                [second, vec![I32Const(1), I32Add]].concat(),
+                vec![LocalGet(SECOND_VALUE)],
+                memory,
+            ),
+        ]
+        .concat()
+    };
+    Function {
+        name: name.to_owned(),
+        params: vec![
+            Types::I32,
+            reference(memory),
+            reference(memory),
+            Types::I32,
+            reference(memory),
+            reference(memory),
+            Types::I32,
+        ],
+        results: vec![reference(memory)],
+        locals: vec![reference(memory), Types::I32, Types::I32],
+        body: [
+            vec![
+                LocalGet(FIRST_HASH),
+                LocalGet(SECOND_HASH),
+                I32Eq,
+                If(BlockType::Empty),
+                I32Const(0),
+                I32Const(2),
+                call(Runtime::NewMapNode),
+                LocalSet(NODE),
+            ],
+            both(vec![I32Const(0)], vec![I32Const(2)]),
+            vec![LocalGet(NODE), Return, End],
+            bit(FIRST_HASH, SHIFT),
+            vec![LocalSet(FIRST_BIT)],
+            bit(SECOND_HASH, SHIFT),
+            vec![
+                LocalSet(SECOND_BIT),
+                LocalGet(FIRST_BIT),
+                LocalGet(SECOND_BIT),
+                I32Eq,
+                If(BlockType::Empty),
+                LocalGet(FIRST_BIT),
+                I32Const(1),
+                call(Runtime::NewMapNode),
+                LocalSet(NODE),
+            ],
+            set_slot(NODE, vec![I32Const(0)], absent(memory), memory),
+            set_slot(
+                NODE,
+                vec![I32Const(1)],
+                [
+                    next_level(SHIFT),
+                    (FIRST_KEY..=SECOND_HASH).map(LocalGet).collect(),
+                    vec![Call(name.to_owned())],
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![
+                LocalGet(NODE),
+                Return,
+                End,
+                LocalGet(FIRST_BIT),
+                LocalGet(SECOND_BIT),
+                I32Or,
+                I32Const(2),
+                call(Runtime::NewMapNode),
+                LocalSet(NODE),
+            ],
+            both(first_at, second_at),
+            vec![LocalGet(NODE)],
+        ]
+        .concat(),
+    }
+}
+
+/// The value of a key with the given hash in the descendants of the node at
+/// the given level, or the last argument when they do not have the key
+pub fn node_find(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const SHIFT: usize = 1;
+    const HASH: usize = 2;
+    const KEY: usize = 3;
+    const NOT_FOUND: usize = 4;
+    const BIT: usize = 5;
+    const INDEX: usize = 6;
+    use Opcodes::*;
+
+    let found = |index: usize| {
+        [
+            key(NODE, index, memory),
+            vec![LocalGet(KEY), call(Runtime::Equals), If(BlockType::Empty)],
+            value(NODE, index, memory),
+            vec![Return, End],
+        ]
+        .concat()
+    };
+    Function {
+        name: name.to_owned(),
+        params: vec![
+            reference(memory),
+            Types::I32,
+            Types::I32,
+            reference(memory),
+            reference(memory),
+        ],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32; 2],
+        body: [
+            unless_null(NODE, LocalGet(NOT_FOUND), memory),
+            bitmap(NODE, memory),
+            vec![I32Eqz, If(BlockType::Empty)],
+            for_each_index(
+                INDEX,
+                vec![I32Const(0)],
+                slot_count(NODE, memory),
+                2,
+                found(INDEX),
+            ),
+            vec![LocalGet(NOT_FOUND), Return, End],
+            bit(HASH, SHIFT),
+            vec![LocalTee(BIT)],
+            bitmap(NODE, memory),
+            vec![
+                I32And,
+                I32Eqz,
+                If(BlockType::Empty),
+                LocalGet(NOT_FOUND),
+                Return,
+                End,
+            ],
+            position(NODE, BIT, memory),
+            vec![LocalSet(INDEX)],
+            key(NODE, INDEX, memory),
+            is_absent(memory),
+            vec![If(BlockType::Empty)],
+            value(NODE, INDEX, memory),
+            next_level(SHIFT),
+            vec![
+                LocalGet(HASH),
+                LocalGet(KEY),
+                LocalGet(NOT_FOUND),
+                Call(name.to_owned()),
+                Return,
+                End,
+            ],
+            found(INDEX),
+            vec![LocalGet(NOT_FOUND)],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a copy of the node at the given level whose descendants map a key
+/// with the given hash to the last argument, copying the path down to it. A
+/// nil node stands for an empty one.
+pub fn node_assoc(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const SHIFT: usize = 1;
+    const HASH: usize = 2;
+    const KEY: usize = 3;
+    const VALUE: usize = 4;
+    const BIT: usize = 5;
+    const INDEX: usize = 6;
+    const CHILD: usize = 7;
+    use Opcodes::*;
+
+    let replace = |index: usize| {
+        [
+            key(NODE, index, memory),
+            vec![LocalGet(KEY), call(Runtime::Equals), If(BlockType::Empty)],
+            vec![LocalGet(NODE), LocalGet(index)],
+            key(NODE, index, memory),
+            vec![LocalGet(VALUE), call(Runtime::ReplaceEntry), Return, End],
+        ]
+        .concat()
+    };
+    let first_hash = [
+        slot(NODE, vec![I32Const(0)], memory),
+        vec![call(Runtime::Hash)],
+    ]
+    .concat();
+    Function {
+        name: name.to_owned(),
+        params: vec![
+            reference(memory),
+            Types::I32,
+            Types::I32,
+            reference(memory),
+            reference(memory),
+        ],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32, Types::I32, reference(memory)],
+        body: [
+            vec![LocalGet(NODE), is_null(memory), If(BlockType::Empty)],
+            bit(HASH, SHIFT),
+            vec![I32Const(1), call(Runtime::NewMapNode), LocalSet(CHILD)],
+            set_slot(CHILD, vec![I32Const(0)], vec![LocalGet(KEY)], memory),
+            set_slot(CHILD, vec![I32Const(1)], vec![LocalGet(VALUE)], memory),
+            vec![LocalGet(CHILD), Return, End],
+            bitmap(NODE, memory),
+            vec![I32Eqz, If(BlockType::Empty)],
+            first_hash.clone(),
+            vec![LocalGet(HASH), I32Eq, If(BlockType::Empty)],
+            for_each_index(
+                INDEX,
+                vec![I32Const(0)],
+                slot_count(NODE, memory),
+                2,
+                replace(INDEX),
+            ),
+            vec![LocalGet(NODE)],
+            slot_count(NODE, memory),
+            vec![
+                I32Const(0),
+                LocalGet(KEY),
+                LocalGet(VALUE),
+                call(Runtime::InsertEntry),
+                Return,
+                End,
+            ],
+            // the collision node moves one level down, under the bit of
+            // its own hash
+            first_hash,
+            vec![LocalSet(BIT)],
+            bit(BIT, SHIFT),
+            vec![I32Const(1), call(Runtime::NewMapNode), LocalSet(CHILD)],
+            set_slot(CHILD, vec![I32Const(0)], absent(memory), memory),
+            set_slot(CHILD, vec![I32Const(1)], vec![LocalGet(NODE)], memory),
+            vec![
+                LocalGet(CHILD),
+                LocalGet(SHIFT),
+                LocalGet(HASH),
+                LocalGet(KEY),
+                LocalGet(VALUE),
+                Call(name.to_owned()),
+                Return,
+                End,
+            ],
+            bit(HASH, SHIFT),
+            vec![LocalTee(BIT)],
+            bitmap(NODE, memory),
+            vec![I32And, I32Eqz, If(BlockType::Empty), LocalGet(NODE)],
+            position(NODE, BIT, memory),
+            vec![
+                LocalGet(BIT),
+                LocalGet(KEY),
+                LocalGet(VALUE),
+                call(Runtime::InsertEntry),
+                Return,
+                End,
+            ],
+            position(NODE, BIT, memory),
+            vec![LocalSet(INDEX)],
+            key(NODE, INDEX, memory),
+            is_absent(memory),
+            vec![If(BlockType::Empty), LocalGet(NODE), LocalGet(INDEX)],
+            absent(memory),
+            value(NODE, INDEX, memory),
+            next_level(SHIFT),
+            vec![
+                LocalGet(HASH),
+                LocalGet(KEY),
+                LocalGet(VALUE),
+                Call(name.to_owned()),
+                call(Runtime::ReplaceEntry),
+                Return,
+                End,
+            ],
+            replace(INDEX),
+            // another key takes the entry, both go one level down
+            vec![LocalGet(NODE), LocalGet(INDEX)],
+            absent(memory),
+            next_level(SHIFT),
+            key(NODE, INDEX, memory),
+            value(NODE, INDEX, memory),
+            key(NODE, INDEX, memory),
+            vec![
+                call(Runtime::Hash),
+                LocalGet(KEY),
+                LocalGet(VALUE),
+                LocalGet(HASH),
+                call(Runtime::CreateNode),
+                call(Runtime::ReplaceEntry),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a copy of the node at the given level whose descendants do not
+/// have a key with the given hash, or nil when nothing is left. The node
+/// itself is returned when its descendants do not have the key.
+pub fn node_dissoc(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const SHIFT: usize = 1;
+    const HASH: usize = 2;
+    const KEY: usize = 3;
+    const BIT: usize = 4;
+    const INDEX: usize = 5;
+    const CHILD: usize = 6;
+    use Opcodes::*;
+
+    let remove = [
+        size(NODE, memory),
+        vec![
+            I32Const(1),
+            I32Eq,
+            If(BlockType::Empty),
+            null(memory),
+            Return,
+            End,
+            LocalGet(NODE),
+            LocalGet(INDEX),
+            LocalGet(BIT),
+            call(Runtime::RemoveEntry),
+        ],
+    ]
+    .concat();
+    let same = match memory {
+        Memory::Engine => RefEq,
+        _ => I32Eq,
+    };
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), Types::I32, Types::I32, reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32, Types::I32, reference(memory)],
+        body: [
+            bitmap(NODE, memory),
+            vec![I32Eqz, If(BlockType::Empty)],
+            for_each_index(
+                INDEX,
+                vec![I32Const(0)],
+                slot_count(NODE, memory),
+                2,
+                [
+                    key(NODE, INDEX, memory),
+                    vec![LocalGet(KEY), call(Runtime::Equals), If(BlockType::Empty)],
+                    remove.clone(),
+                    vec![Return, End],
+                ]
+                .concat(),
+            ),
+            vec![LocalGet(NODE), Return, End],
+            bit(HASH, SHIFT),
+            vec![LocalTee(BIT)],
+            bitmap(NODE, memory),
+            vec![
+                I32And,
+                I32Eqz,
+                If(BlockType::Empty),
+                LocalGet(NODE),
+                Return,
+                End,
+            ],
+            position(NODE, BIT, memory),
+            vec![LocalSet(INDEX)],
+            key(NODE, INDEX, memory),
+            is_absent(memory),
+            vec![If(BlockType::Empty)],
+            value(NODE, INDEX, memory),
+            next_level(SHIFT),
+            vec![
+                LocalGet(HASH),
+                LocalGet(KEY),
+                Call(name.to_owned()),
+                LocalTee(CHILD),
+            ],
+            value(NODE, INDEX, memory),
+            vec![
+                same,
+                If(BlockType::Empty),
+                LocalGet(NODE),
+                Return,
+                End,
+                LocalGet(CHILD),
+                is_null(memory),
+                I32Eqz,
+                If(BlockType::Empty),
+                LocalGet(NODE),
+                LocalGet(INDEX),
+            ],
+            absent(memory),
+            vec![
+                LocalGet(CHILD),
+                call(Runtime::ReplaceEntry),
+                Return,
+                End,
+                Else,
+            ],
+            key(NODE, INDEX, memory),
+            vec![
+                LocalGet(KEY),
+                call(Runtime::Equals),
+                I32Eqz,
+                If(BlockType::Empty),
+                LocalGet(NODE),
+                Return,
+                End,
+                End,
+            ],
+            remove,
+        ]
+        .concat(),
+    }
+}
+
+/// Adds the keys of the descendants of a node to a vector, or their values
+/// when the last argument is non zero, and returns the vector
+pub fn node_collect(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const VECTOR: usize = 1;
+    const VALUES: usize = 2;
+    const INDEX: usize = 3;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), reference(memory), Types::I32],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32],
+        body: [
+            unless_null(NODE, LocalGet(VECTOR), memory),
+            for_each_entry(
+                NODE,
+                INDEX,
+                [
+                    vec![
+                        LocalGet(VECTOR),
+                        LocalGet(VALUES),
+                        If(BlockType::Value(reference(memory))),
+                    ],
+                    value(NODE, INDEX, memory),
+                    vec![Else],
+                    key(NODE, INDEX, memory),
+                    vec![End, call(Runtime::VectorConj), LocalSet(VECTOR)],
+                ]
+                .concat(),
+                [
+                    value(NODE, INDEX, memory),
+                    vec![
+                        LocalGet(VECTOR),
+                        LocalGet(VALUES),
+                        Call(name.to_owned()),
+                        LocalSet(VECTOR),
+                    ],
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![LocalGet(VECTOR)],
+        ]
+        .concat(),
+    }
+}
+
+/// Associates the keys of the descendants of a node with their values in a
+/// map, and returns the resulting map
+pub fn node_merge(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const MAP: usize = 1;
+    const INDEX: usize = 2;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32],
+        body: [
+            unless_null(NODE, LocalGet(MAP), memory),
+            for_each_entry(
+                NODE,
+                INDEX,
+                [
+                    vec![LocalGet(MAP)],
+                    key(NODE, INDEX, memory),
+                    value(NODE, INDEX, memory),
+                    vec![call(Runtime::MapAssoc), LocalSet(MAP)],
+                ]
+                .concat(),
+                [
+                    value(NODE, INDEX, memory),
+                    vec![LocalGet(MAP), Call(name.to_owned()), LocalSet(MAP)],
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![LocalGet(MAP)],
+        ]
+        .concat(),
+    }
+}
+
+/// Whether a map has every key of the descendants of a node, with an equal
+/// value
+pub fn node_equals(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const MAP: usize = 1;
+    const INDEX: usize = 2;
+    const OTHER: usize = 3;
+    use Opcodes::*;
+
+    let unless_true = vec![I32Eqz, If(BlockType::Empty), I32Const(0), Return, End];
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![Types::I32],
+        locals: vec![Types::I32, reference(memory)],
+        body: [
+            unless_null(NODE, I32Const(1), memory),
+            for_each_entry(
+                NODE,
+                INDEX,
+                [
+                    vec![LocalGet(MAP)],
+                    key(NODE, INDEX, memory),
+                    absent(memory),
+                    vec![call(Runtime::MapGet), LocalTee(OTHER)],
+                    is_absent(memory),
+                    vec![If(BlockType::Empty), I32Const(0), Return, End],
+                    value(NODE, INDEX, memory),
+                    vec![LocalGet(OTHER), call(Runtime::Equals)],
+                    unless_true.clone(),
+                ]
+                .concat(),
+                [
+                    value(NODE, INDEX, memory),
+                    vec![LocalGet(MAP), Call(name.to_owned())],
+                    unless_true,
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![I32Const(1)],
+        ]
+        .concat(),
+    }
+}
+
+/// Sum of the hashes of the entries of the descendants of a node, which does
+/// not depend on the order of the entries. The hash of an entry combines the
+/// hashes of its key and its value, or is the hash of its key alone when the
+/// second argument is set, as the value of each entry of a set is its key.
+pub fn hash_node(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const KEYS: usize = 1;
+    const INDEX: usize = 2;
+    const HASH: usize = 3;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), Types::I32],
+        results: vec![Types::I32],
+        locals: vec![Types::I32; 2],
+        body: [
+            unless_null(NODE, I32Const(0), memory),
+            for_each_entry(
+                NODE,
+                INDEX,
+                [
+                    vec![LocalGet(HASH)],
+                    key(NODE, INDEX, memory),
+                    vec![
+                        call(Runtime::Hash),
+                        LocalGet(KEYS),
+                        If(BlockType::Value(Types::I32)),
+                        I32Const(0),
+                        Else,
+                    ],
+                    value(NODE, INDEX, memory),
+                    vec![call(Runtime::Hash), End, I32Xor, I32Add, LocalSet(HASH)],
+                ]
+                .concat(),
+                [
+                    vec![LocalGet(HASH)],
+                    value(NODE, INDEX, memory),
+                    vec![
+                        LocalGet(KEYS),
+                        Call(name.to_owned()),
+                        I32Add,
+                        LocalSet(HASH),
+                    ],
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![LocalGet(HASH)],
+        ]
+        .concat(),
+    }
+}
+
+/// Writes the entries of the descendants of a node to stdout, each key
+/// followed by its value and entries separated by commas. The second
+/// argument tells whether no entry has been written yet, which the result
+/// tells in turn once the node has been written. When the third argument is
+/// set only the keys are written, separated by spaces, as for a set.
+pub fn print_node(name: &str, data: &mut DataLayout, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const FIRST: usize = 1;
+    const KEYS: usize = 2;
+    const INDEX: usize = 3;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), Types::I32, Types::I32],
+        results: vec![Types::I32],
+        locals: vec![Types::I32],
+        body: [
+            unless_null(NODE, LocalGet(FIRST), memory),
+            for_each_entry(
+                NODE,
+                INDEX,
+                [
+                    vec![
+                        LocalGet(FIRST),
+                        I32Eqz,
+                        If(BlockType::Empty),
+                        LocalGet(KEYS),
+                        If(BlockType::Empty),
+                    ],
+                    write_literal(STDOUT, " ", data),
+                    vec![Else],
+                    write_literal(STDOUT, ", ", data),
+                    vec![End, End],
+                    key(NODE, INDEX, memory),
+                    vec![
+                        call(Runtime::PrintValue),
+                        LocalGet(KEYS),
+                        I32Eqz,
+                        If(BlockType::Empty),
+                    ],
+                    write_literal(STDOUT, " ", data),
+                    value(NODE, INDEX, memory),
+                    vec![call(Runtime::PrintValue), End, I32Const(0), LocalSet(FIRST)],
+                ]
+                .concat(),
+                [
+                    value(NODE, INDEX, memory),
+                    vec![
+                        LocalGet(FIRST),
+                        LocalGet(KEYS),
+                        Call(name.to_owned()),
+                        LocalSet(FIRST),
+                    ],
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![LocalGet(FIRST)],
+        ]
+        .concat(),
+    }
+}
+
+/// The value of a key in a map, or the last argument when it does not have
+/// the key
+pub fn map_get(name: &str, memory: Memory) -> Function {
+    const MAP: usize = 0;
+    const KEY: usize = 1;
+    const NOT_FOUND: usize = 2;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 3],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: [
+            root(MAP, memory),
+            vec![
+                I32Const(0),
+                LocalGet(KEY),
+                call(Runtime::Hash),
+                LocalGet(KEY),
+                LocalGet(NOT_FOUND),
+                call(Runtime::NodeFind),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a map with a key of the first argument mapped to the last
+/// argument, counting the key when the map does not have it yet
+pub fn map_assoc(name: &str, memory: Memory) -> Function {
+    const MAP: usize = 0;
+    const KEY: usize = 1;
+    const VALUE: usize = 2;
+    const HASH: usize = 3;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 3],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32],
+        body: [
+            vec![LocalGet(KEY), call(Runtime::Hash), LocalSet(HASH)],
+            root(MAP, memory),
+            vec![
+                I32Const(0),
+                LocalGet(HASH),
+                LocalGet(KEY),
+                LocalGet(VALUE),
+                call(Runtime::NodeAssoc),
+            ],
+            count(MAP, memory),
+            root(MAP, memory),
+            vec![I32Const(0), LocalGet(HASH), LocalGet(KEY)],
+            absent(memory),
+            vec![call(Runtime::NodeFind)],
+            is_absent(memory),
+            vec![I32Add, call(Runtime::MakeMap)],
+        ]
+        .concat(),
+    }
+}
+
+/// Associates a key with a value in a map, or in a vector when the key is an
+/// index of it or the index past its last element. Nil stands for an empty
+/// map, and other values raise a ClassCastException.
+pub fn assoc(name: &str, memory: Memory) -> Function {
+    const COLLECTION: usize = 0;
+    const KEY: usize = 1;
+    const VALUE: usize = 2;
+    const TAG: usize = 3;
+    use Opcodes::*;
+
+    let map_assoc = vec![
+        LocalGet(KEY),
+        LocalGet(VALUE),
+        call(Runtime::MapAssoc),
+        Return,
+    ];
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 3],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32],
+        body: [
+            vec![
+                LocalGet(COLLECTION),
+                call(Runtime::TypeOf),
+                LocalTee(TAG),
+                I32Const(Tag::Nil as i32),
+                I32Eq,
+                If(BlockType::Empty),
+                call(Runtime::EmptyMap),
+            ],
+            map_assoc.clone(),
+            vec![
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Map as i32),
+                I32Eq,
+                If(BlockType::Empty),
+                LocalGet(COLLECTION),
+            ],
+            map_assoc,
+            vec![
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Vector as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            has_tag(KEY, Tag::Integer),
+            vec![
+                I32Eqz,
+                If(BlockType::Empty),
+                call(Runtime::KeyNotInteger),
+                End,
+                LocalGet(COLLECTION),
+            ],
+            unbox(KEY, Types::I64, memory),
+            vec![
+                LocalGet(VALUE),
+                call(Runtime::VectorAssoc),
+                Return,
+                End,
+                call(Runtime::NotAMap),
+                Unreachable,
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a map without a key, which is the map itself when it does not
+/// have the key. Nil stays nil.
+pub fn dissoc(name: &str, memory: Memory) -> Function {
+    const MAP: usize = 0;
+    const KEY: usize = 1;
+    const HASH: usize = 2;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32],
+        body: [
+            has_tag(MAP, Tag::Nil),
+            vec![If(BlockType::Empty), LocalGet(MAP), Return, End],
+            expect_map(MAP),
+            vec![LocalGet(KEY), call(Runtime::Hash), LocalSet(HASH)],
+            root(MAP, memory),
+            vec![I32Const(0), LocalGet(HASH), LocalGet(KEY)],
+            absent(memory),
+            vec![call(Runtime::NodeFind)],
+            is_absent(memory),
+            vec![If(BlockType::Empty), LocalGet(MAP), Return, End],
+            root(MAP, memory),
+            vec![
+                I32Const(0),
+                LocalGet(HASH),
+                LocalGet(KEY),
+                call(Runtime::NodeDissoc),
+            ],
+            count(MAP, memory),
+            vec![I32Const(1), I32Sub, call(Runtime::MakeMap)],
+        ]
+        .concat(),
+    }
+}
+
+/// Whether a map or a set has a key, or whether a vector has an integer key
+/// as an index. Nil has no keys, and other values raise an
+/// IllegalArgumentException.
+pub fn contains(name: &str, memory: Memory) -> Function {
+    const COLLECTION: usize = 0;
+    const KEY: usize = 1;
+    const TAG: usize = 2;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![Types::I32],
+        locals: vec![Types::I32],
+        body: [
+            set::unwrap(COLLECTION, memory),
+            vec![
+                LocalGet(COLLECTION),
+                call(Runtime::TypeOf),
+                LocalTee(TAG),
+                I32Const(Tag::Nil as i32),
+                I32Eq,
+                If(BlockType::Empty),
+                I32Const(0),
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Map as i32),
+                I32Eq,
+                If(BlockType::Empty),
+                LocalGet(COLLECTION),
+                LocalGet(KEY),
+            ],
+            absent(memory),
+            vec![call(Runtime::MapGet)],
+            is_absent(memory),
+            vec![
+                I32Eqz,
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Vector as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            has_tag(KEY, Tag::Integer),
+            vec![
+                I32Eqz,
+                If(BlockType::Empty),
+                I32Const(0),
+                Return,
+                End,
+                LocalGet(COLLECTION),
+                call(Runtime::Count),
+            ],
+            unbox(KEY, Types::I64, memory),
+            vec![
+                I64GtU,
+                Return,
+                End,
+                call(Runtime::ContainsNotSupported),
+                Unreachable,
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns the keys of a map in a vector, or its values when `values` is
+/// set. An empty map or nil gives nil.
+pub fn entries(name: &str, values: bool, memory: Memory) -> Function {
+    const MAP: usize = 0;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: [
+            has_tag(MAP, Tag::Nil),
+            vec![If(BlockType::Empty), null(memory), Return, End],
+            expect_map(MAP),
+            count(MAP, memory),
+            vec![I32Eqz, If(BlockType::Empty), null(memory), Return, End],
+            root(MAP, memory),
+            vec![
+                call(Runtime::EmptyVector),
+                I32Const(values as i32),
+                call(Runtime::NodeCollect),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns the first map with the entries of the second one added, those of
+/// the second one winning for keys both have. Nil stands for an empty map
+/// on either side.
+pub fn merge(name: &str, memory: Memory) -> Function {
+    const LEFT: usize = 0;
+    const RIGHT: usize = 1;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: [
+            has_tag(RIGHT, Tag::Nil),
+            vec![If(BlockType::Empty), LocalGet(LEFT), Return, End],
+            expect_map(RIGHT),
+            has_tag(LEFT, Tag::Nil),
+            vec![
+                If(BlockType::Empty),
+                call(Runtime::EmptyMap),
+                LocalSet(LEFT),
+                End,
+            ],
+            expect_map(LEFT),
+            root(RIGHT, memory),
+            vec![LocalGet(LEFT), call(Runtime::NodeMerge)],
+        ]
+        .concat(),
+    }
+}
@@ -1,3 +1,13 @@
+pub mod binary;
+mod builtins;
+mod closure;
+mod data;
 pub mod emitter;
 mod environment;
 mod instructions;
+mod map;
+pub mod module;
+mod runtime;
+mod set;
+mod types;
+mod vector;
@@ -0,0 +1,207 @@
+use crate::codegen::data::DataLayout;
+use crate::codegen::instructions::{
+    Definition, Global, HeapType, OpData, Opcodes, Types, WASIImports,
+};
+use crate::codegen::runtime::Runtime;
+use std::fmt::{Display, Error, Formatter};
+
+pub const ENTRY_POINT: &str = "main";
+
+/// How the program manages the memory of its heap objects
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Memory {
+    /// Objects live in linear memory and are never freed, which suits short
+    /// lived programs
+    Arena,
+    /// Objects live in linear memory and those no function can reach anymore
+    /// are freed by a mark and sweep collector, which finds its roots on a
+    /// shadow stack
+    Collected,
+    /// Objects are structs and arrays of the GC proposal, which the engine
+    /// collects. Linear memory only holds literals and scratch space.
+    Engine,
+}
+
+pub struct Function {
+    pub name: String,
+    pub params: Vec<Types>,
+    pub results: Vec<Types>,
+    pub locals: Vec<Types>,
+    pub body: Vec<Opcodes>,
+}
+
+/// A complete WebAssembly module which can be written out either in the text
+/// format through `Display` or in the binary format through `binary::encode`.
+pub struct Module {
+    pub imports: Vec<WASIImports>,
+    pub functions: Vec<Function>,
+    pub globals: Vec<Global>,
+    pub data: Vec<OpData>,
+    /// Functions which can be called indirectly, by their index in the table
+    pub table: Vec<String>,
+    pub memory_pages: u32,
+    pub memory: Memory,
+    /// Support functions added so far, including those whose dependencies
+    /// are still being added
+    runtimes: Vec<Runtime>,
+}
+
+impl Module {
+    pub fn new(memory: Memory) -> Self {
+        Module {
+            imports: Vec::new(),
+            functions: Vec::new(),
+            globals: Vec::new(),
+            data: Vec::new(),
+            table: Vec::new(),
+            memory_pages: 1,
+            memory,
+            runtimes: Vec::new(),
+        }
+    }
+
+    pub fn add_import(&mut self, import: WASIImports) {
+        if !self.imports.contains(&import) {
+            self.imports.push(import);
+        }
+    }
+
+    /// Adds a runtime support function along with everything it depends on,
+    /// unless the module already contains it. Literals used by the runtime
+    /// are laid out in `data`. Support functions may depend on each other, as
+    /// those walking nested collections do.
+    pub fn add_runtime(&mut self, runtime: Runtime, data: &mut DataLayout) {
+        if self.runtimes.contains(&runtime)
+            || self
+                .functions
+                .iter()
+                .any(|function| function.name == runtime.name())
+        {
+            return;
+        }
+        self.runtimes.push(runtime);
+        for import in runtime.imports() {
+            self.add_import(import);
+        }
+        for dependency in runtime.dependencies(self.memory) {
+            self.add_runtime(dependency, data);
+        }
+        self.functions.push(runtime.function(data, self.memory));
+    }
+
+    /// Index of the function called `name`, imports being numbered first
+    pub fn function_index(&self, name: &str) -> Option<usize> {
+        self.imports
+            .iter()
+            .position(|import| import.name() == name)
+            .or_else(|| {
+                self.functions
+                    .iter()
+                    .position(|function| function.name == name)
+                    .map(|index| index + self.imports.len())
+            })
+    }
+
+    pub fn global_index(&self, name: &str) -> Option<usize> {
+        self.globals.iter().position(|global| global.name == name)
+    }
+
+    /// Types the module defines besides those of its functions
+    pub fn heap_types(&self) -> &'static [HeapType] {
+        match self.memory {
+            Memory::Engine => &HeapType::DEFINED,
+            _ => &[],
+        }
+    }
+
+    pub fn has_entry_point(&self) -> bool {
+        self.functions
+            .iter()
+            .any(|function| function.name == ENTRY_POINT)
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "(func ${}", self.name)?;
+        for param in &self.params {
+            write!(f, " (param {})", param)?;
+        }
+        for result in &self.results {
+            write!(f, " (result {})", result)?;
+        }
+        for local in &self.locals {
+            write!(f, "\n  (local {})", local)?;
+        }
+        let mut depth = 1;
+        for instruction in &self.body {
+            if let Opcodes::Else | Opcodes::End = instruction {
+                depth -= 1;
+            }
+            write!(f, "\n{}{}", "  ".repeat(depth), instruction)?;
+            if let Opcodes::Block(_) | Opcodes::Loop(_) | Opcodes::If(_) | Opcodes::Else =
+                instruction
+            {
+                depth += 1;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        writeln!(f, "(module")?;
+        if !self.heap_types().is_empty() {
+            writeln!(f, " (rec")?;
+        }
+        for heap_type in self.heap_types() {
+            write!(f, "  (type {} ", heap_type)?;
+            match heap_type.definition() {
+                Some(Definition::Struct(fields)) => {
+                    write!(f, "(struct")?;
+                    for field in fields {
+                        write!(f, " (field {})", field)?;
+                    }
+                    writeln!(f, "))")?;
+                }
+                Some(Definition::Array(Some(element))) => {
+                    writeln!(f, "(array (mut {})))", element)?
+                }
+                _ => writeln!(f, "(array (mut i8)))")?,
+            }
+        }
+        if !self.heap_types().is_empty() {
+            writeln!(f, " )")?;
+        }
+        for import in &self.imports {
+            writeln!(f, " {}", import)?;
+        }
+        writeln!(
+            f,
+            " (memory {}) (export \"memory\" (memory 0))",
+            self.memory_pages
+        )?;
+        for data in &self.data {
+            writeln!(f, " {}", data)?;
+        }
+        if !self.table.is_empty() {
+            writeln!(f, " (table {} funcref)", self.table.len())?;
+            write!(f, " (elem (i32.const 0) func")?;
+            for name in &self.table {
+                write!(f, " ${}", name)?;
+            }
+            writeln!(f, ")")?;
+        }
+        for global in &self.globals {
+            writeln!(f, " {}", global)?;
+        }
+        for function in &self.functions {
+            writeln!(f, " {}", function)?;
+        }
+        if self.has_entry_point() {
+            writeln!(f, " (export \"_start\" (func ${}))", ENTRY_POINT)?;
+        }
+        write!(f, ")")
+    }
+}
@@ -0,0 +1,3336 @@
+use crate::codegen::closure;
+use crate::codegen::data::DataLayout;
+use crate::codegen::instructions::{BlockType, HeapType, Opcodes, SysCalls, Types, WASIImports};
+use crate::codegen::map;
+use crate::codegen::module::{Function, Memory};
+use crate::codegen::set;
+use crate::codegen::types::Tag;
+use crate::codegen::vector;
+
+/// The first bytes of memory are scratch space for the runtime, data
+/// segments are laid out after them.
+pub const DATA_START: u32 = 64;
+const IOVEC_ADDRESS: i32 = 0;
+const NUM_WRITTEN_ADDRESS: i32 = 8;
+/// Digits are written backwards from the end of the scratch area
+const DIGITS_END: i32 = DATA_START as i32;
+/// Strings the engine manages are copied to the end of the scratch area in
+/// chunks to be written
+const STRING_BUFFER: i32 = 16;
+const STRING_BUFFER_SIZE: i32 = DIGITS_END - STRING_BUFFER;
+pub const STDOUT: i32 = 1;
+const STDERR: i32 = 2;
+/// Exit code of a program stopped by an uncaught exception
+const EXCEPTION_EXIT_CODE: i32 = 1;
+/// References to nil, false and true, see `Tag`
+pub const NIL: i32 = 0;
+pub const FALSE: i32 = 1;
+pub const TRUE: i32 = 2;
+/// Mutable global holding the address of the next heap byte never allocated
+pub const HEAP_POINTER: &str = "heap_pointer";
+/// Global holding the address of the free lists, which follow the data. The
+/// list of blocks of size class `c`, holding `1 << c` bytes, is at offset
+/// `4 * c` and links freed blocks through the first word of their payload.
+pub const FREE_LISTS: &str = "free_lists";
+pub const FREE_LISTS_SIZE: u32 = 32 * 4;
+/// When memory is collected, the free lists are followed by a shadow stack
+/// holding references to every object the running functions may still use.
+/// It grows towards the first heap block, at the address of this global.
+pub const HEAP_START: &str = "heap_start";
+/// Mutable global holding the address of the next free shadow stack slot
+pub const STACK_POINTER: &str = "stack_pointer";
+pub const SHADOW_STACK_SIZE: u32 = 64 * 1024;
+/// When the engine manages memory, mutable global holding the symbol table,
+/// an array with the interned keyword of every keyword literal once it has
+/// been evaluated
+pub const SYMBOLS: &str = "symbols";
+/// Global holding the number of keyword literals of the program
+pub const SYMBOL_COUNT: &str = "symbol_count";
+/// Every heap block starts with a header holding its size class and its
+/// state, which keeps the payload on an 8 byte boundary
+const HEADER_SIZE: i32 = 8;
+const FREE: i32 = 0;
+const ALLOCATED: i32 = 1;
+/// Allocated and reachable from the shadow stack, while collecting
+const MARKED: i32 = 2;
+/// When memory is collected, mutable global holding the grey list: the
+/// objects found reachable while collecting whose references are yet to be
+/// marked. It is threaded through the states of their block headers, each
+/// holding the address of the next object, and ends with `GREY_LIST_END`.
+pub const GREY_LIST: &str = "grey_list";
+/// Never the address of an object, and the state objects leaving the grey
+/// list are left in
+pub const GREY_LIST_END: i32 = MARKED;
+/// Size class of the smallest blocks, which have room for the header and a
+/// free list link
+const MIN_SIZE_CLASS: i32 = 4;
+const PAGE_SHIFT: i32 = 16;
+/// Size of a boxed integer or float, the number following the tag at offset 8
+const NUMBER_SIZE: i32 = 16;
+
+/// Operations on two numbers, which are lowered differently for integers,
+/// floats and values whose type is only known at runtime
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Arithmetic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Quotient,
+    Remainder,
+    Modulo,
+    Max,
+    Min,
+    UncheckedAdd,
+    UncheckedSubtract,
+    UncheckedMultiply,
+}
+
+impl Arithmetic {
+    /// Support function computing the operation on two numbers of the given
+    /// machine type, when there is no single instruction for it
+    pub fn runtime(self, number: Types) -> Option<Runtime> {
+        match (self, number) {
+            (Arithmetic::Add, Types::I64) => Some(Runtime::Add),
+            (Arithmetic::Subtract, Types::I64) => Some(Runtime::Subtract),
+            (Arithmetic::Multiply, Types::I64) => Some(Runtime::Multiply),
+            (Arithmetic::Divide, Types::I64) | (Arithmetic::Quotient, Types::I64) => {
+                Some(Runtime::Quotient)
+            }
+            (Arithmetic::Remainder, Types::I64) => Some(Runtime::Remainder),
+            (Arithmetic::Modulo, Types::I64) => Some(Runtime::Modulo),
+            (Arithmetic::Max, Types::I64) => Some(Runtime::Maximum),
+            (Arithmetic::Min, Types::I64) => Some(Runtime::Minimum),
+            (Arithmetic::Quotient, _) => Some(Runtime::FloatQuotient),
+            (Arithmetic::Remainder, _) => Some(Runtime::FloatRemainder),
+            (Arithmetic::Modulo, _) => Some(Runtime::FloatModulo),
+            _ => None,
+        }
+    }
+
+    /// Instruction replacing two numbers of the given machine type on top of
+    /// the stack with the result of the operation
+    pub fn instruction(self, number: Types) -> Opcodes {
+        if let Some(runtime) = self.runtime(number) {
+            return Opcodes::Call(runtime.name().to_owned());
+        }
+        match (self, number) {
+            (Arithmetic::UncheckedAdd, Types::I64) => Opcodes::I64Add,
+            (Arithmetic::UncheckedSubtract, Types::I64) => Opcodes::I64Sub,
+            (Arithmetic::UncheckedMultiply, Types::I64) => Opcodes::I64Mul,
+            (Arithmetic::Add, _) | (Arithmetic::UncheckedAdd, _) => Opcodes::F64Add,
+            (Arithmetic::Subtract, _) | (Arithmetic::UncheckedSubtract, _) => Opcodes::F64Sub,
+            (Arithmetic::Multiply, _) | (Arithmetic::UncheckedMultiply, _) => Opcodes::F64Mul,
+            (Arithmetic::Max, _) => Opcodes::F64Max,
+            (Arithmetic::Min, _) => Opcodes::F64Min,
+            _ => Opcodes::F64Div,
+        }
+    }
+}
+
+/// Support functions which are emitted into a module when code needs them
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Runtime {
+    PrintInteger,
+    PrintFloat,
+    PrintString,
+    DivideByZero,
+    IntegerOverflow,
+    Add,
+    Subtract,
+    Multiply,
+    Quotient,
+    Remainder,
+    Modulo,
+    FloatQuotient,
+    FloatRemainder,
+    FloatModulo,
+    Maximum,
+    Minimum,
+    OutOfMemory,
+    StackOverflow,
+    Allocate,
+    Free,
+    Root,
+    Mark,
+    Collect,
+    BoxInteger,
+    BoxFloat,
+    StringLiteral,
+    KeywordLiteral,
+    InternKeyword,
+    Truthy,
+    TypeOf,
+    ToFloat,
+    ToInteger,
+    NotANumber,
+    PrintValue,
+    Equals,
+    Hash,
+    CompareNumbers,
+    Dynamic(Arithmetic),
+    IndexOutOfBounds,
+    NotAVector,
+    PopEmpty,
+    CountNotSupported,
+    NewNode,
+    CopyNode,
+    MakeVector,
+    EmptyVector,
+    ArrayFor,
+    NewPath,
+    PushTail,
+    DoAssoc,
+    PopTail,
+    Count,
+    Nth,
+    Get,
+    VectorConj,
+    VectorAssoc,
+    Peek,
+    Pop,
+    Subvec,
+    NotAMap,
+    KeyNotInteger,
+    ContainsNotSupported,
+    MakeMap,
+    EmptyMap,
+    NewMapNode,
+    ReplaceEntry,
+    InsertEntry,
+    RemoveEntry,
+    CreateNode,
+    NodeFind,
+    NodeAssoc,
+    NodeDissoc,
+    NodeCollect,
+    NodeMerge,
+    NodeEquals,
+    HashNode,
+    PrintNode,
+    MapGet,
+    MapAssoc,
+    Assoc,
+    Dissoc,
+    Contains,
+    Keys,
+    Vals,
+    Merge,
+    NotASet,
+    NotAFunction,
+    MakeSet,
+    EmptySet,
+    SetConj,
+    Conj,
+    Disj,
+    Invoke,
+    InvokeWithKey,
+    WrongArgumentCount,
+    NoMatchingClause,
+    MakeClosure,
+    ClosureEntry,
+    Trampoline,
+}
+
+impl Runtime {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Runtime::PrintInteger => "print_integer",
+            Runtime::PrintFloat => "print_float",
+            Runtime::PrintString => "print_string",
+            Runtime::DivideByZero => "divide_by_zero",
+            Runtime::IntegerOverflow => "integer_overflow",
+            Runtime::Add => "checked_add",
+            Runtime::Subtract => "checked_subtract",
+            Runtime::Multiply => "checked_multiply",
+            Runtime::Quotient => "quotient",
+            Runtime::Remainder => "remainder",
+            Runtime::Modulo => "modulo",
+            Runtime::FloatQuotient => "float_quotient",
+            Runtime::FloatRemainder => "float_remainder",
+            Runtime::FloatModulo => "float_modulo",
+            Runtime::Maximum => "maximum",
+            Runtime::Minimum => "minimum",
+            Runtime::OutOfMemory => "out_of_memory",
+            Runtime::StackOverflow => "stack_overflow",
+            Runtime::Allocate => ALLOCATE,
+            Runtime::Free => "free",
+            Runtime::Root => "root",
+            Runtime::Mark => "mark",
+            Runtime::Collect => "collect",
+            Runtime::BoxInteger => "box_integer",
+            Runtime::BoxFloat => "box_float",
+            Runtime::StringLiteral => "string_literal",
+            Runtime::KeywordLiteral => "keyword_literal",
+            Runtime::InternKeyword => "intern_keyword",
+            Runtime::Truthy => "truthy",
+            Runtime::TypeOf => "type_of",
+            Runtime::ToFloat => "to_float",
+            Runtime::ToInteger => "to_integer",
+            Runtime::NotANumber => "not_a_number",
+            Runtime::PrintValue => "print_value",
+            Runtime::Equals => "equals",
+            Runtime::Hash => "hash",
+            Runtime::CompareNumbers => "compare_numbers",
+            Runtime::Dynamic(operation) => match operation {
+                Arithmetic::Add => "dynamic_add",
+                Arithmetic::Subtract => "dynamic_subtract",
+                Arithmetic::Multiply => "dynamic_multiply",
+                Arithmetic::Divide => "dynamic_divide",
+                Arithmetic::Quotient => "dynamic_quotient",
+                Arithmetic::Remainder => "dynamic_remainder",
+                Arithmetic::Modulo => "dynamic_modulo",
+                Arithmetic::Max => "dynamic_max",
+                Arithmetic::Min => "dynamic_min",
+                Arithmetic::UncheckedAdd => "dynamic_unchecked_add",
+                Arithmetic::UncheckedSubtract => "dynamic_unchecked_subtract",
+                Arithmetic::UncheckedMultiply => "dynamic_unchecked_multiply",
+            },
+            Runtime::IndexOutOfBounds => "index_out_of_bounds",
+            Runtime::NotAVector => "not_a_vector",
+            Runtime::PopEmpty => "pop_empty",
+            Runtime::CountNotSupported => "count_not_supported",
+            Runtime::NewNode => "new_node",
+            Runtime::CopyNode => "copy_node",
+            Runtime::MakeVector => "make_vector",
+            Runtime::EmptyVector => "empty_vector",
+            Runtime::ArrayFor => "array_for",
+            Runtime::NewPath => "new_path",
+            Runtime::PushTail => "push_tail",
+            Runtime::DoAssoc => "do_assoc",
+            Runtime::PopTail => "pop_tail",
+            Runtime::Count => "collection_count",
+            Runtime::Nth => "collection_nth",
+            Runtime::Get => "collection_get",
+            Runtime::VectorConj => "vector_conj",
+            Runtime::VectorAssoc => "vector_assoc",
+            Runtime::Peek => "vector_peek",
+            Runtime::Pop => "vector_pop",
+            Runtime::Subvec => "vector_subvec",
+            Runtime::NotAMap => "not_a_map",
+            Runtime::KeyNotInteger => "key_not_integer",
+            Runtime::ContainsNotSupported => "contains_not_supported",
+            Runtime::MakeMap => "make_map",
+            Runtime::EmptyMap => "empty_map",
+            Runtime::NewMapNode => "new_map_node",
+            Runtime::ReplaceEntry => "replace_entry",
+            Runtime::InsertEntry => "insert_entry",
+            Runtime::RemoveEntry => "remove_entry",
+            Runtime::CreateNode => "create_node",
+            Runtime::NodeFind => "node_find",
+            Runtime::NodeAssoc => "node_assoc",
+            Runtime::NodeDissoc => "node_dissoc",
+            Runtime::NodeCollect => "node_collect",
+            Runtime::NodeMerge => "node_merge",
+            Runtime::NodeEquals => "node_equals",
+            Runtime::HashNode => "hash_node",
+            Runtime::PrintNode => "print_node",
+            Runtime::MapGet => "map_get",
+            Runtime::MapAssoc => "map_assoc",
+            Runtime::Assoc => "collection_assoc",
+            Runtime::Dissoc => "map_dissoc",
+            Runtime::Contains => "collection_contains",
+            Runtime::Keys => "map_keys",
+            Runtime::Vals => "map_vals",
+            Runtime::Merge => "map_merge",
+            Runtime::NotASet => "not_a_set",
+            Runtime::NotAFunction => "not_a_function",
+            Runtime::MakeSet => "make_set",
+            Runtime::EmptySet => "empty_set",
+            Runtime::SetConj => "set_conj",
+            Runtime::Conj => "collection_conj",
+            Runtime::Disj => "set_disj",
+            Runtime::Invoke => "invoke_collection",
+            Runtime::InvokeWithKey => "invoke_with_key",
+            Runtime::WrongArgumentCount => "wrong_argument_count",
+            Runtime::NoMatchingClause => "no_matching_clause",
+            Runtime::MakeClosure => "make_closure",
+            Runtime::ClosureEntry => "closure_entry",
+            Runtime::Trampoline => "trampoline",
+        }
+    }
+
+    pub fn imports(&self) -> Vec<WASIImports> {
+        match self {
+            Runtime::PrintInteger
+            | Runtime::PrintFloat
+            | Runtime::PrintString
+            | Runtime::PrintValue
+            | Runtime::PrintNode => vec![WASIImports::FDWrite],
+            Runtime::DivideByZero
+            | Runtime::IntegerOverflow
+            | Runtime::NotANumber
+            | Runtime::OutOfMemory
+            | Runtime::StackOverflow
+            | Runtime::IndexOutOfBounds
+            | Runtime::NotAVector
+            | Runtime::PopEmpty
+            | Runtime::CountNotSupported
+            | Runtime::NotAMap
+            | Runtime::KeyNotInteger
+            | Runtime::ContainsNotSupported
+            | Runtime::NotASet
+            | Runtime::NotAFunction
+            | Runtime::WrongArgumentCount
+            | Runtime::NoMatchingClause => {
+                vec![WASIImports::FDWrite, WASIImports::ProcExit]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Other support functions called by this one
+    pub fn dependencies(&self, memory: Memory) -> Vec<Runtime> {
+        match self {
+            Runtime::PrintFloat => vec![Runtime::PrintInteger],
+            Runtime::Add | Runtime::Subtract | Runtime::Multiply => {
+                vec![Runtime::IntegerOverflow]
+            }
+            Runtime::Quotient => vec![Runtime::DivideByZero, Runtime::IntegerOverflow],
+            Runtime::Remainder
+            | Runtime::Modulo
+            | Runtime::FloatQuotient
+            | Runtime::FloatRemainder
+            | Runtime::FloatModulo => vec![Runtime::DivideByZero],
+            Runtime::Allocate if memory == Memory::Collected => {
+                vec![Runtime::OutOfMemory, Runtime::Root, Runtime::Collect]
+            }
+            Runtime::Allocate => vec![Runtime::OutOfMemory],
+            Runtime::Root => vec![Runtime::StackOverflow],
+            Runtime::Collect => vec![Runtime::Mark, Runtime::Free],
+            Runtime::ToFloat | Runtime::ToInteger => vec![Runtime::TypeOf, Runtime::NotANumber],
+            Runtime::PrintValue => vec![
+                Runtime::TypeOf,
+                Runtime::PrintInteger,
+                Runtime::PrintFloat,
+                Runtime::PrintString,
+                Runtime::ArrayFor,
+                Runtime::PrintNode,
+            ],
+            Runtime::Equals => vec![Runtime::TypeOf, Runtime::ArrayFor, Runtime::NodeEquals],
+            Runtime::Hash => vec![Runtime::TypeOf, Runtime::ArrayFor, Runtime::HashNode],
+            Runtime::KeywordLiteral => vec![Runtime::StringLiteral],
+            Runtime::InternKeyword => vec![Runtime::KeywordLiteral],
+            Runtime::CompareNumbers => vec![Runtime::TypeOf, Runtime::ToFloat],
+            Runtime::Dynamic(operation) => {
+                let mut dependencies = vec![
+                    Runtime::TypeOf,
+                    Runtime::ToFloat,
+                    Runtime::BoxInteger,
+                    Runtime::BoxFloat,
+                ];
+                dependencies.extend(operation.runtime(Types::I64));
+                dependencies.extend(operation.runtime(Types::F64));
+                if *operation == Arithmetic::Divide {
+                    dependencies.push(Runtime::Remainder);
+                }
+                dependencies
+            }
+            Runtime::CopyNode | Runtime::NewPath => vec![Runtime::NewNode],
+            Runtime::EmptyVector => vec![Runtime::NewNode, Runtime::MakeVector],
+            Runtime::PushTail => vec![Runtime::CopyNode, Runtime::NewPath],
+            Runtime::DoAssoc | Runtime::PopTail => vec![Runtime::CopyNode],
+            Runtime::Count => vec![Runtime::TypeOf, Runtime::CountNotSupported],
+            Runtime::Nth => vec![
+                Runtime::TypeOf,
+                Runtime::NotAVector,
+                Runtime::IndexOutOfBounds,
+                Runtime::ArrayFor,
+            ],
+            Runtime::Get => vec![Runtime::TypeOf, Runtime::ArrayFor, Runtime::MapGet],
+            Runtime::VectorConj => vec![
+                Runtime::TypeOf,
+                Runtime::NotAVector,
+                Runtime::CopyNode,
+                Runtime::NewNode,
+                Runtime::NewPath,
+                Runtime::PushTail,
+                Runtime::MakeVector,
+            ],
+            Runtime::VectorAssoc => vec![
+                Runtime::TypeOf,
+                Runtime::NotAVector,
+                Runtime::IndexOutOfBounds,
+                Runtime::VectorConj,
+                Runtime::CopyNode,
+                Runtime::DoAssoc,
+                Runtime::MakeVector,
+            ],
+            Runtime::Peek => vec![Runtime::TypeOf, Runtime::NotAVector, Runtime::ArrayFor],
+            Runtime::Pop => vec![
+                Runtime::TypeOf,
+                Runtime::NotAVector,
+                Runtime::PopEmpty,
+                Runtime::EmptyVector,
+                Runtime::ArrayFor,
+                Runtime::PopTail,
+                Runtime::NewNode,
+                Runtime::MakeVector,
+            ],
+            Runtime::Subvec => vec![
+                Runtime::TypeOf,
+                Runtime::NotAVector,
+                Runtime::IndexOutOfBounds,
+                Runtime::EmptyVector,
+                Runtime::ArrayFor,
+                Runtime::MakeVector,
+            ],
+            Runtime::EmptyMap => vec![Runtime::MakeMap],
+            Runtime::ReplaceEntry
+            | Runtime::InsertEntry
+            | Runtime::RemoveEntry
+            | Runtime::CreateNode => vec![Runtime::NewMapNode],
+            Runtime::NodeFind => vec![Runtime::Equals],
+            Runtime::NodeAssoc => vec![
+                Runtime::Hash,
+                Runtime::Equals,
+                Runtime::NewMapNode,
+                Runtime::ReplaceEntry,
+                Runtime::InsertEntry,
+                Runtime::CreateNode,
+            ],
+            Runtime::NodeDissoc => {
+                vec![Runtime::Equals, Runtime::ReplaceEntry, Runtime::RemoveEntry]
+            }
+            Runtime::NodeCollect => vec![Runtime::VectorConj],
+            Runtime::NodeMerge => vec![Runtime::MapAssoc],
+            Runtime::NodeEquals => vec![Runtime::MapGet, Runtime::Equals],
+            Runtime::HashNode => vec![Runtime::Hash],
+            Runtime::PrintNode => vec![Runtime::PrintValue],
+            Runtime::MapGet => vec![Runtime::Hash, Runtime::NodeFind],
+            Runtime::MapAssoc => vec![
+                Runtime::Hash,
+                Runtime::NodeAssoc,
+                Runtime::NodeFind,
+                Runtime::MakeMap,
+            ],
+            Runtime::Assoc => vec![
+                Runtime::TypeOf,
+                Runtime::EmptyMap,
+                Runtime::MapAssoc,
+                Runtime::KeyNotInteger,
+                Runtime::VectorAssoc,
+                Runtime::NotAMap,
+            ],
+            Runtime::Dissoc => vec![
+                Runtime::TypeOf,
+                Runtime::NotAMap,
+                Runtime::Hash,
+                Runtime::NodeFind,
+                Runtime::NodeDissoc,
+                Runtime::MakeMap,
+            ],
+            Runtime::Contains => vec![
+                Runtime::TypeOf,
+                Runtime::MapGet,
+                Runtime::Count,
+                Runtime::ContainsNotSupported,
+            ],
+            Runtime::Keys | Runtime::Vals => vec![
+                Runtime::TypeOf,
+                Runtime::NotAMap,
+                Runtime::EmptyVector,
+                Runtime::NodeCollect,
+            ],
+            Runtime::Merge => vec![
+                Runtime::TypeOf,
+                Runtime::NotAMap,
+                Runtime::EmptyMap,
+                Runtime::NodeMerge,
+            ],
+            Runtime::EmptySet => vec![Runtime::EmptyMap, Runtime::MakeSet],
+            Runtime::SetConj => vec![Runtime::MapAssoc, Runtime::MakeSet],
+            Runtime::Conj => vec![Runtime::TypeOf, Runtime::SetConj, Runtime::VectorConj],
+            Runtime::Disj => vec![
+                Runtime::TypeOf,
+                Runtime::NotASet,
+                Runtime::Dissoc,
+                Runtime::MakeSet,
+            ],
+            Runtime::Invoke => vec![Runtime::TypeOf, Runtime::NotAFunction, Runtime::Get],
+            Runtime::InvokeWithKey => vec![Runtime::Invoke],
+            Runtime::ClosureEntry => vec![
+                Runtime::TypeOf,
+                Runtime::WrongArgumentCount,
+                Runtime::NotAFunction,
+            ],
+            Runtime::Trampoline if memory == Memory::Collected => {
+                vec![Runtime::TypeOf, Runtime::Root]
+            }
+            Runtime::Trampoline => vec![Runtime::TypeOf],
+            _ => vec![],
+        }
+    }
+
+    /// The support function for a module managing its memory as given
+    pub fn function(&self, data: &mut DataLayout, memory: Memory) -> Function {
+        match self {
+            Runtime::PrintInteger => print_integer(self.name()),
+            Runtime::PrintFloat => print_float(self.name(), data),
+            Runtime::PrintString => print_string(self.name(), memory),
+            Runtime::DivideByZero => {
+                exception(self.name(), "ArithmeticException: Divide by zero\n", data)
+            }
+            Runtime::IntegerOverflow => {
+                exception(self.name(), "ArithmeticException: integer overflow\n", data)
+            }
+            Runtime::Add => overflow_checked(self.name(), Opcodes::I64Add, add_overflowed()),
+            Runtime::Subtract => {
+                overflow_checked(self.name(), Opcodes::I64Sub, subtract_overflowed())
+            }
+            Runtime::Multiply => {
+                overflow_checked(self.name(), Opcodes::I64Mul, multiply_overflowed())
+            }
+            Runtime::Quotient => checked_division(self.name(), Types::I64, quotient()),
+            Runtime::Remainder => checked_division(self.name(), Types::I64, vec![Opcodes::I64RemS]),
+            Runtime::Modulo => checked_division(self.name(), Types::I64, modulo()),
+            Runtime::FloatQuotient => checked_division(
+                self.name(),
+                Types::F64,
+                vec![Opcodes::F64Div, Opcodes::F64Trunc],
+            ),
+            Runtime::FloatRemainder => checked_division(self.name(), Types::F64, float_remainder()),
+            Runtime::FloatModulo => checked_division(
+                self.name(),
+                Types::F64,
+                [float_remainder(), float_modulo()].concat(),
+            ),
+            Runtime::Maximum => extremum(self.name(), Opcodes::I64GtS),
+            Runtime::Minimum => extremum(self.name(), Opcodes::I64LtS),
+            Runtime::OutOfMemory => exception(
+                self.name(),
+                "OutOfMemoryError: heap space exhausted\n",
+                data,
+            ),
+            Runtime::StackOverflow => exception(
+                self.name(),
+                "StackOverflowError: too many live references\n",
+                data,
+            ),
+            Runtime::Allocate => allocate(self.name(), memory),
+            Runtime::Free => free(self.name()),
+            Runtime::Root => root(self.name()),
+            Runtime::Mark => mark(self.name()),
+            Runtime::Collect => collect(self.name()),
+            Runtime::BoxInteger => box_number(self.name(), Tag::Integer, memory),
+            Runtime::BoxFloat => box_number(self.name(), Tag::Float, memory),
+            Runtime::StringLiteral => string_literal(self.name(), memory),
+            Runtime::KeywordLiteral => keyword_literal(self.name(), memory),
+            Runtime::InternKeyword => intern_keyword(self.name()),
+            Runtime::Truthy => truthy(self.name(), memory),
+            Runtime::TypeOf => type_of(self.name(), memory),
+            Runtime::ToFloat => to_float(self.name(), memory),
+            Runtime::ToInteger => to_integer(self.name(), memory),
+            Runtime::NotANumber => exception(
+                self.name(),
+                "ClassCastException: value cannot be cast to a number\n",
+                data,
+            ),
+            Runtime::PrintValue => print_value(self.name(), data, memory),
+            Runtime::Equals => equals(self.name(), memory),
+            Runtime::Hash => hash(self.name(), memory),
+            Runtime::CompareNumbers => compare_numbers(self.name(), memory),
+            Runtime::Dynamic(operation) => dynamic_arithmetic(self.name(), *operation, memory),
+            Runtime::IndexOutOfBounds => {
+                exception(self.name(), "IndexOutOfBoundsException\n", data)
+            }
+            Runtime::NotAVector => exception(
+                self.name(),
+                "ClassCastException: value cannot be cast to a vector\n",
+                data,
+            ),
+            Runtime::PopEmpty => exception(
+                self.name(),
+                "IllegalStateException: Can't pop empty vector\n",
+                data,
+            ),
+            Runtime::CountNotSupported => exception(
+                self.name(),
+                "UnsupportedOperationException: count not supported on this type\n",
+                data,
+            ),
+            Runtime::NewNode => vector::new_node(self.name(), memory),
+            Runtime::CopyNode => vector::copy_node(self.name(), memory),
+            Runtime::MakeVector => vector::make_vector(self.name(), memory),
+            Runtime::EmptyVector => vector::empty_vector(self.name(), memory),
+            Runtime::ArrayFor => vector::array_for(self.name(), memory),
+            Runtime::NewPath => vector::new_path(self.name(), memory),
+            Runtime::PushTail => vector::push_tail(self.name(), memory),
+            Runtime::DoAssoc => vector::do_assoc(self.name(), memory),
+            Runtime::PopTail => vector::pop_tail(self.name(), memory),
+            Runtime::Count => vector::count_items(self.name(), memory),
+            Runtime::Nth => vector::nth(self.name(), memory),
+            Runtime::Get => vector::get(self.name(), memory),
+            Runtime::VectorConj => vector::conj(self.name(), memory),
+            Runtime::VectorAssoc => vector::assoc(self.name(), memory),
+            Runtime::Peek => vector::peek(self.name(), memory),
+            Runtime::Pop => vector::pop(self.name(), memory),
+            Runtime::Subvec => vector::subvec(self.name(), memory),
+            Runtime::NotAMap => exception(
+                self.name(),
+                "ClassCastException: value cannot be cast to a map\n",
+                data,
+            ),
+            Runtime::KeyNotInteger => exception(
+                self.name(),
+                "IllegalArgumentException: Key must be integer\n",
+                data,
+            ),
+            Runtime::ContainsNotSupported => exception(
+                self.name(),
+                "IllegalArgumentException: contains? not supported on this type\n",
+                data,
+            ),
+            Runtime::MakeMap => map::make_map(self.name(), memory),
+            Runtime::EmptyMap => map::empty_map(self.name(), memory),
+            Runtime::NewMapNode => map::new_map_node(self.name(), memory),
+            Runtime::ReplaceEntry => map::replace_entry(self.name(), memory),
+            Runtime::InsertEntry => map::insert_entry(self.name(), memory),
+            Runtime::RemoveEntry => map::remove_entry(self.name(), memory),
+            Runtime::CreateNode => map::create_node(self.name(), memory),
+            Runtime::NodeFind => map::node_find(self.name(), memory),
+            Runtime::NodeAssoc => map::node_assoc(self.name(), memory),
+            Runtime::NodeDissoc => map::node_dissoc(self.name(), memory),
+            Runtime::NodeCollect => map::node_collect(self.name(), memory),
+            Runtime::NodeMerge => map::node_merge(self.name(), memory),
+            Runtime::NodeEquals => map::node_equals(self.name(), memory),
+            Runtime::HashNode => map::hash_node(self.name(), memory),
+            Runtime::PrintNode => map::print_node(self.name(), data, memory),
+            Runtime::MapGet => map::map_get(self.name(), memory),
+            Runtime::MapAssoc => map::map_assoc(self.name(), memory),
+            Runtime::Assoc => map::assoc(self.name(), memory),
+            Runtime::Dissoc => map::dissoc(self.name(), memory),
+            Runtime::Contains => map::contains(self.name(), memory),
+            Runtime::Keys => map::entries(self.name(), false, memory),
+            Runtime::Vals => map::entries(self.name(), true, memory),
+            Runtime::Merge => map::merge(self.name(), memory),
+            Runtime::NotASet => exception(
+                self.name(),
+                "ClassCastException: value cannot be cast to a set\n",
+                data,
+            ),
+            Runtime::NotAFunction => exception(
+                self.name(),
+                "ClassCastException: value cannot be cast to a function\n",
+                data,
+            ),
+            Runtime::MakeSet => set::make_set(self.name(), memory),
+            Runtime::EmptySet => set::empty_set(self.name(), memory),
+            Runtime::SetConj => set::set_conj(self.name(), memory),
+            Runtime::Conj => set::conj(self.name(), memory),
+            Runtime::Disj => set::disj(self.name(), memory),
+            Runtime::Invoke => set::invoke(self.name(), memory),
+            Runtime::InvokeWithKey => set::invoke_with_key(self.name(), memory),
+            Runtime::WrongArgumentCount => exception(
+                self.name(),
+                "ArityException: Wrong number of args passed to function\n",
+                data,
+            ),
+            Runtime::NoMatchingClause => exception(
+                self.name(),
+                "IllegalArgumentException: No matching clause\n",
+                data,
+            ),
+            Runtime::MakeClosure => closure::make_closure(self.name(), memory),
+            Runtime::ClosureEntry => closure::entry(self.name(), memory),
+            Runtime::Trampoline => closure::trampoline(self.name(), memory),
+        }
+    }
+}
+
+/// Writes `length` bytes starting at `address` to the given file
+fn write_bytes(file_descriptor: i32, address: Opcodes, mut length: Vec<Opcodes>) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    let mut body = vec![I32Const(IOVEC_ADDRESS), address, I32Store(0)];
+    body.push(I32Const(IOVEC_ADDRESS));
+    body.append(length.as_mut());
+    body.push(I32Store(4));
+    body.append(
+        SysCalls::Write(
+            I32Const(file_descriptor),
+            I32Const(IOVEC_ADDRESS),
+            I32Const(1),
+            I32Const(NUM_WRITTEN_ADDRESS),
+        )
+        .instructions()
+        .as_mut(),
+    );
+    body.push(Drop);
+    body
+}
+
+/// Writes a string known at compile time to the given file
+pub fn write_literal(file_descriptor: i32, text: &str, data: &mut DataLayout) -> Vec<Opcodes> {
+    let location = data.add_string(text);
+    write_bytes(
+        file_descriptor,
+        Opcodes::I32Const(location.address + 8),
+        vec![Opcodes::I32Const(location.length)],
+    )
+}
+
+/// Writes the decimal representation of its i64 argument to stdout. The
+/// magnitude is handled as an unsigned number so that negating `i64::MIN`
+/// still yields the right digits.
+fn print_integer(name: &str) -> Function {
+    const VALUE: usize = 0;
+    const POSITION: usize = 1;
+    const MAGNITUDE: usize = 2;
+    use Opcodes::*;
+
+    let mut body = vec![
+        I32Const(DIGITS_END),
+        LocalSet(POSITION),
+        // magnitude = value < 0 ? 0 - value : value
+        LocalGet(VALUE),
+        LocalSet(MAGNITUDE),
+        LocalGet(VALUE),
+        I64Const(0),
+        I64LtS,
+        If(BlockType::Empty),
+        I64Const(0),
+        LocalGet(VALUE),
+        I64Sub,
+        LocalSet(MAGNITUDE),
+        End,
+        // write digits from the least significant one
+        Loop(BlockType::Empty),
+        LocalGet(POSITION),
+        I32Const(1),
+        I32Sub,
+        LocalTee(POSITION),
+        LocalGet(MAGNITUDE),
+        I64Const(10),
+        I64RemU,
+        I32WrapI64,
+        I32Const('0' as i32),
+        I32Add,
+        I32Store8(0),
+        LocalGet(MAGNITUDE),
+        I64Const(10),
+        I64DivU,
+        LocalTee(MAGNITUDE),
+        I64Eqz,
+        I32Eqz,
+        BrIf(0),
+        End,
+        // sign
+        LocalGet(VALUE),
+        I64Const(0),
+        I64LtS,
+        If(BlockType::Empty),
+        LocalGet(POSITION),
+        I32Const(1),
+        I32Sub,
+        LocalTee(POSITION),
+        I32Const('-' as i32),
+        I32Store8(0),
+        End,
+    ];
+    body.append(
+        write_bytes(
+            STDOUT,
+            LocalGet(POSITION),
+            vec![I32Const(DIGITS_END), LocalGet(POSITION), I32Sub],
+        )
+        .as_mut(),
+    );
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I64],
+        results: vec![],
+        locals: vec![Types::I32, Types::I64],
+        body,
+    }
+}
+
+/// Limbs of the numbers `print_float` works with, each holding 32 bits in an
+/// i64 so that products and carries fit. They are big enough for the
+/// numerators and denominators of the smallest and largest doubles.
+const LIMBS: i32 = 40;
+const NUMBER_BYTES: i32 = LIMBS * 8;
+
+/// Writes its f64 argument to stdout the way Clojure prints doubles: with the
+/// fewest digits which read back as the same double, at least one of them
+/// fractional, and in scientific notation outside of [1e-3, 1e7). The digits
+/// are generated exactly from integers standing for the value and the
+/// distances to its neighbours, following Burger and Dybvig's free-format
+/// algorithm.
+fn print_float(name: &str, data: &mut DataLayout) -> Function {
+    const VALUE: usize = 0;
+    const BITS: usize = 1;
+    const MANTISSA: usize = 2;
+    const CARRY: usize = 3;
+    const FACTOR: usize = 4;
+    const EXPONENT: usize = 5;
+    const UNEVEN_GAP: usize = 6;
+    const DECIMAL_EXPONENT: usize = 7;
+    const INDEX: usize = 8;
+    const COUNT: usize = 9;
+    const DIGIT: usize = 10;
+    const LOW: usize = 11;
+    const HIGH: usize = 12;
+    const POWER: usize = 13;
+    const POSITION: usize = 14;
+    const EVEN: usize = 15;
+    const ORDER: usize = 16;
+    const POINT: usize = 17;
+    const SCIENTIFIC: usize = 18;
+    use Opcodes::*;
+
+    // value = r / s, its neighbours being m- below and m+ above it
+    let r = data.reserve(NUMBER_BYTES as u32 * 5 + 64) as u32;
+    let s = r + NUMBER_BYTES as u32;
+    let plus = s + NUMBER_BYTES as u32;
+    let minus = plus + NUMBER_BYTES as u32;
+    let sum = minus + NUMBER_BYTES as u32;
+    let digits = sum + NUMBER_BYTES as u32;
+    let output = digits + 32;
+
+    let limb = || vec![LocalGet(INDEX), I32Const(3), I32Shl];
+    let each_limb = |mut body: Vec<Opcodes>| {
+        let mut limbs = vec![I32Const(0), LocalSet(INDEX), Loop(BlockType::Empty)];
+        limbs.append(body.as_mut());
+        limbs.append(
+            vec![
+                LocalGet(INDEX),
+                I32Const(1),
+                I32Add,
+                LocalTee(INDEX),
+                I32Const(LIMBS),
+                I32LtS,
+                BrIf(0),
+                End,
+            ]
+            .as_mut(),
+        );
+        limbs
+    };
+    // stores the low 32 bits of the carry in a limb and keeps the others
+    let store_carry = |number: u32| {
+        vec![
+            I64Const(0xffff_ffff),
+            I64And,
+            I64Store(number),
+            LocalGet(CARRY),
+            I64Const(32),
+            I64ShrU,
+            LocalSet(CARRY),
+        ]
+    };
+    let multiply = |number: u32| {
+        let mut body = vec![I64Const(0), LocalSet(CARRY)];
+        body.append(
+            each_limb(
+                [
+                    limb(),
+                    limb(),
+                    vec![
+                        I64Load(number),
+                        LocalGet(FACTOR),
+                        I64Mul,
+                        LocalGet(CARRY),
+                        I64Add,
+                        LocalTee(CARRY),
+                    ],
+                    store_carry(number),
+                ]
+                .concat(),
+            )
+            .as_mut(),
+        );
+        body
+    };
+    let add = |result: u32, left: u32, right: u32| {
+        let mut body = vec![I64Const(0), LocalSet(CARRY)];
+        body.append(
+            each_limb(
+                [
+                    limb(),
+                    limb(),
+                    vec![I64Load(left)],
+                    limb(),
+                    vec![
+                        I64Load(right),
+                        I64Add,
+                        LocalGet(CARRY),
+                        I64Add,
+                        LocalTee(CARRY),
+                    ],
+                    store_carry(result),
+                ]
+                .concat(),
+            )
+            .as_mut(),
+        );
+        body
+    };
+    // the borrow is the sign bit of a limb which went below zero
+    let subtract = |number: u32, other: u32| {
+        let mut body = vec![I64Const(0), LocalSet(CARRY)];
+        body.append(
+            each_limb(
+                [
+                    limb(),
+                    limb(),
+                    vec![I64Load(number)],
+                    limb(),
+                    vec![
+                        I64Load(other),
+                        I64Sub,
+                        LocalGet(CARRY),
+                        I64Sub,
+                        LocalTee(CARRY),
+                        I64Const(0xffff_ffff),
+                        I64And,
+                        I64Store(number),
+                        LocalGet(CARRY),
+                        I64Const(63),
+                        I64ShrU,
+                        LocalSet(CARRY),
+                    ],
+                ]
+                .concat(),
+            )
+            .as_mut(),
+        );
+        body
+    };
+    // order = -1, 0 or 1 as left is below, equal to or above right
+    let compare = |left: u32, right: u32| {
+        [
+            vec![
+                I32Const(0),
+                LocalSet(ORDER),
+                I32Const(LIMBS),
+                LocalSet(INDEX),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(INDEX),
+                I32Eqz,
+                BrIf(1),
+                LocalGet(INDEX),
+                I32Const(1),
+                I32Sub,
+                LocalSet(INDEX),
+            ],
+            limb(),
+            vec![I64Load(left)],
+            limb(),
+            vec![I64Load(right), I64Ne, If(BlockType::Empty)],
+            limb(),
+            vec![I64Load(left)],
+            limb(),
+            vec![
+                I64Load(right),
+                I64GtU,
+                I32Const(1),
+                I32Shl,
+                I32Const(1),
+                I32Sub,
+                LocalSet(ORDER),
+                Br(2),
+                End,
+                Br(0),
+                End,
+                End,
+            ],
+        ]
+        .concat()
+    };
+    // multiplies a number by 2^power, 30 bits at a time
+    let shift = |number: u32, mut power: Vec<Opcodes>| {
+        let mut body = vec![];
+        body.append(power.as_mut());
+        body.append(
+            vec![
+                LocalSet(POWER),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(POWER),
+                I32Eqz,
+                BrIf(1),
+                I32Const(30),
+                LocalGet(POWER),
+                LocalGet(POWER),
+                I32Const(30),
+                I32GtS,
+                Select,
+                LocalSet(DIGIT),
+                I32Const(1),
+                LocalGet(DIGIT),
+                I32Shl,
+                I64ExtendI32S,
+                LocalSet(FACTOR),
+                LocalGet(POWER),
+                LocalGet(DIGIT),
+                I32Sub,
+                LocalSet(POWER),
+            ]
+            .as_mut(),
+        );
+        body.append(multiply(number).as_mut());
+        body.append(vec![Br(0), End, End].as_mut());
+        body
+    };
+    // multiplies numbers by 10^power
+    let scale = |numbers: Vec<u32>, mut power: Vec<Opcodes>| {
+        let mut body = vec![I64Const(10), LocalSet(FACTOR)];
+        body.append(power.as_mut());
+        body.append(
+            vec![
+                LocalSet(POWER),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(POWER),
+                I32Const(0),
+                I32LeS,
+                BrIf(1),
+            ]
+            .as_mut(),
+        );
+        for number in numbers {
+            body.append(multiply(number).as_mut());
+        }
+        body.append(
+            vec![
+                LocalGet(POWER),
+                I32Const(1),
+                I32Sub,
+                LocalSet(POWER),
+                Br(0),
+                End,
+                End,
+            ]
+            .as_mut(),
+        );
+        body
+    };
+    // whether r + m+ reaches s, which is inclusive when the mantissa is even
+    // since the value then wins ties when reading
+    let high = || {
+        let mut body = add(sum, r, plus);
+        body.append(compare(sum, s).as_mut());
+        body.append(vec![LocalGet(ORDER), LocalGet(EVEN), I32Add, I32Const(0), I32GtS].as_mut());
+        body
+    };
+    let put = |mut byte: Vec<Opcodes>| {
+        let mut body = vec![LocalGet(POSITION)];
+        body.append(byte.as_mut());
+        body.append(
+            vec![
+                I32Store8(0),
+                LocalGet(POSITION),
+                I32Const(1),
+                I32Add,
+                LocalSet(POSITION),
+            ]
+            .as_mut(),
+        );
+        body
+    };
+    // copies the digits from the index `from` up to the index `to`
+    let copy = |mut from: Vec<Opcodes>, mut to: Vec<Opcodes>| {
+        let mut body = vec![];
+        body.append(from.as_mut());
+        body.append(
+            vec![
+                LocalSet(INDEX),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(INDEX),
+            ]
+            .as_mut(),
+        );
+        body.append(to.as_mut());
+        body.push(I32GeS);
+        body.push(BrIf(1));
+        body.append(put(vec![LocalGet(INDEX), I32Load8U(digits)]).as_mut());
+        body.append(
+            vec![
+                LocalGet(INDEX),
+                I32Const(1),
+                I32Add,
+                LocalSet(INDEX),
+                Br(0),
+                End,
+                End,
+            ]
+            .as_mut(),
+        );
+        body
+    };
+    let zeros = |mut count: Vec<Opcodes>| {
+        let mut body = vec![];
+        body.append(count.as_mut());
+        body.append(
+            vec![
+                LocalSet(POWER),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(POWER),
+                I32Const(0),
+                I32LeS,
+                BrIf(1),
+            ]
+            .as_mut(),
+        );
+        body.append(put(vec![I32Const('0' as i32)]).as_mut());
+        body.append(
+            vec![
+                LocalGet(POWER),
+                I32Const(1),
+                I32Sub,
+                LocalSet(POWER),
+                Br(0),
+                End,
+                End,
+            ]
+            .as_mut(),
+        );
+        body
+    };
+
+    let mut body = vec![
+        LocalGet(VALUE),
+        LocalGet(VALUE),
+        F64Ne,
+        If(BlockType::Empty),
+    ];
+    body.append(write_literal(STDOUT, "NaN", data).as_mut());
+    body.append(
+        vec![
+            Return,
+            End,
+            // the sign bit tells -0.0 apart from 0.0
+            LocalGet(VALUE),
+            I64ReinterpretF64,
+            I64Const(0),
+            I64LtS,
+            If(BlockType::Empty),
+        ]
+        .as_mut(),
+    );
+    body.append(write_literal(STDOUT, "-", data).as_mut());
+    body.append(
+        vec![
+            LocalGet(VALUE),
+            F64Neg,
+            LocalSet(VALUE),
+            End,
+            LocalGet(VALUE),
+            F64Const(f64::INFINITY),
+            F64Eq,
+            If(BlockType::Empty),
+        ]
+        .as_mut(),
+    );
+    body.append(write_literal(STDOUT, "Infinity", data).as_mut());
+    body.append(
+        vec![
+            Return,
+            End,
+            LocalGet(VALUE),
+            F64Const(0.0),
+            F64Eq,
+            If(BlockType::Empty),
+        ]
+        .as_mut(),
+    );
+    body.append(write_literal(STDOUT, "0.0", data).as_mut());
+    body.append(
+        vec![
+            Return,
+            End,
+            // value = mantissa * 2^exponent, subnormals sharing the exponent
+            // of the smallest normal doubles
+            LocalGet(VALUE),
+            I64ReinterpretF64,
+            LocalTee(BITS),
+            I64Const(52),
+            I64ShrU,
+            I32WrapI64,
+            LocalSet(EXPONENT),
+            LocalGet(BITS),
+            I64Const((1 << 52) - 1),
+            I64And,
+            LocalSet(MANTISSA),
+            LocalGet(EXPONENT),
+            If(BlockType::Empty),
+            LocalGet(MANTISSA),
+            I64Const(1 << 52),
+            I64Add,
+            LocalSet(MANTISSA),
+            Else,
+            I32Const(1),
+            LocalSet(EXPONENT),
+            End,
+            LocalGet(EXPONENT),
+            I32Const(1075),
+            I32Sub,
+            LocalSet(EXPONENT),
+            LocalGet(MANTISSA),
+            I64Const(1),
+            I64And,
+            I64Eqz,
+            LocalSet(EVEN),
+            // the double below a power of two is nearer than the one above
+            LocalGet(MANTISSA),
+            I64Const(1 << 52),
+            I64Eq,
+            LocalGet(EXPONENT),
+            I32Const(-1074),
+            I32GtS,
+            I32And,
+            LocalSet(UNEVEN_GAP),
+            // r = mantissa, and s, m+ and m- are 1
+            I32Const(0),
+            LocalSet(INDEX),
+            Loop(BlockType::Empty),
+            LocalGet(INDEX),
+            I32Const(3),
+            I32Shl,
+            I64Const(0),
+            I64Store(r),
+            LocalGet(INDEX),
+            I32Const(1),
+            I32Add,
+            LocalTee(INDEX),
+            I32Const(LIMBS * 5),
+            I32LtS,
+            BrIf(0),
+            End,
+            I32Const(0),
+            LocalGet(MANTISSA),
+            I64Const(0xffff_ffff),
+            I64And,
+            I64Store(r),
+            I32Const(0),
+            LocalGet(MANTISSA),
+            I64Const(32),
+            I64ShrU,
+            I64Store(r + 8),
+            I32Const(0),
+            I64Const(1),
+            I64Store(s),
+            I32Const(0),
+            I64Const(1),
+            I64Store(plus),
+            I32Const(0),
+            I64Const(1),
+            I64Store(minus),
+        ]
+        .as_mut(),
+    );
+    // with p = max(exponent, 0), q = max(-exponent, 0) and g = 1 for an
+    // uneven gap: r = mantissa * 2^(p + g + 1), s = 2^(q + g + 1),
+    // m+ = 2^(p + g) and m- = 2^p
+    let positive = || {
+        vec![
+            LocalGet(EXPONENT),
+            I32Const(0),
+            LocalGet(EXPONENT),
+            I32Const(0),
+            I32GtS,
+            Select,
+        ]
+    };
+    let negative = || {
+        vec![
+            I32Const(0),
+            LocalGet(EXPONENT),
+            I32Sub,
+            I32Const(0),
+            LocalGet(EXPONENT),
+            I32Const(0),
+            I32LtS,
+            Select,
+        ]
+    };
+    let gap = |mut power: Vec<Opcodes>, extra: i32| {
+        power.append(vec![LocalGet(UNEVEN_GAP), I32Add, I32Const(extra), I32Add].as_mut());
+        power
+    };
+    body.append(shift(r, gap(positive(), 1)).as_mut());
+    body.append(shift(s, gap(negative(), 1)).as_mut());
+    body.append(shift(plus, gap(positive(), 0)).as_mut());
+    body.append(shift(minus, positive()).as_mut());
+    // the decimal exponent k, for which value < 10^k, is estimated from the
+    // binary one, never above k, and raised until it is right. Values below
+    // 1 are scaled by 2^64 first, which makes subnormals normal.
+    body.append(
+        vec![
+            LocalGet(VALUE),
+            F64Const(18446744073709551616.0),
+            F64Mul,
+            LocalGet(VALUE),
+            LocalGet(VALUE),
+            F64Const(1.0),
+            F64Lt,
+            Select,
+            I64ReinterpretF64,
+            I64Const(52),
+            I64ShrU,
+            I32WrapI64,
+            I32Const(1087),
+            I32Const(1023),
+            LocalGet(VALUE),
+            F64Const(1.0),
+            F64Lt,
+            Select,
+            I32Sub,
+            I64ExtendI32S,
+            F64ConvertI64S,
+            F64Const(std::f64::consts::LOG10_2),
+            F64Mul,
+            F64Const(1e-10),
+            F64Sub,
+            F64Neg,
+            F64Floor,
+            F64Neg,
+            I32TruncF64S,
+            LocalTee(DECIMAL_EXPONENT),
+            I32Const(0),
+            I32GeS,
+            If(BlockType::Empty),
+        ]
+        .as_mut(),
+    );
+    body.append(scale(vec![s], vec![LocalGet(DECIMAL_EXPONENT)]).as_mut());
+    body.push(Else);
+    body.append(
+        scale(
+            vec![r, plus, minus],
+            vec![I32Const(0), LocalGet(DECIMAL_EXPONENT), I32Sub],
+        )
+        .as_mut(),
+    );
+    body.append(vec![End, Loop(BlockType::Empty)].as_mut());
+    body.append(high().as_mut());
+    body.push(If(BlockType::Empty));
+    body.append(scale(vec![s], vec![I32Const(1)]).as_mut());
+    body.append(
+        vec![
+            LocalGet(DECIMAL_EXPONENT),
+            I32Const(1),
+            I32Add,
+            LocalSet(DECIMAL_EXPONENT),
+            Br(1),
+            End,
+            End,
+            // each digit is the integral part of 10r / s, until the digits
+            // so far or the next one up are within the neighbours' reach
+            I32Const(0),
+            LocalSet(COUNT),
+            Loop(BlockType::Empty),
+        ]
+        .as_mut(),
+    );
+    body.append(scale(vec![r, plus, minus], vec![I32Const(1)]).as_mut());
+    body.append(
+        vec![
+            I32Const(0),
+            LocalSet(DIGIT),
+            Block(BlockType::Empty),
+            Loop(BlockType::Empty),
+        ]
+        .as_mut(),
+    );
+    body.append(compare(r, s).as_mut());
+    body.append(vec![LocalGet(ORDER), I32Const(0), I32LtS, BrIf(1)].as_mut());
+    body.append(subtract(r, s).as_mut());
+    body.append(
+        vec![
+            LocalGet(DIGIT),
+            I32Const(1),
+            I32Add,
+            LocalSet(DIGIT),
+            Br(0),
+            End,
+            End,
+        ]
+        .as_mut(),
+    );
+    body.append(compare(r, minus).as_mut());
+    body.append(
+        vec![
+            LocalGet(ORDER),
+            LocalGet(EVEN),
+            I32Sub,
+            I32Const(0),
+            I32LtS,
+            LocalSet(LOW),
+        ]
+        .as_mut(),
+    );
+    body.append(high().as_mut());
+    body.append(
+        vec![
+            LocalTee(HIGH),
+            LocalGet(LOW),
+            I32Or,
+            I32Eqz,
+            If(BlockType::Empty),
+            LocalGet(COUNT),
+            LocalGet(DIGIT),
+            I32Const('0' as i32),
+            I32Add,
+            I32Store8(digits),
+            LocalGet(COUNT),
+            I32Const(1),
+            I32Add,
+            LocalSet(COUNT),
+            Br(1),
+            End,
+            // when both digits are in reach, the nearest one wins, or the
+            // even one when they are as near
+            LocalGet(LOW),
+            LocalGet(HIGH),
+            I32And,
+            If(BlockType::Empty),
+        ]
+        .as_mut(),
+    );
+    body.append(add(sum, r, r).as_mut());
+    body.append(compare(sum, s).as_mut());
+    body.append(
+        vec![
+            LocalGet(ORDER),
+            LocalGet(DIGIT),
+            I32Const(1),
+            I32And,
+            I32Add,
+            I32Const(0),
+            I32GtS,
+            LocalSet(HIGH),
+            End,
+            LocalGet(COUNT),
+            LocalGet(DIGIT),
+            LocalGet(HIGH),
+            I32Add,
+            I32Const('0' as i32),
+            I32Add,
+            I32Store8(digits),
+            LocalGet(COUNT),
+            I32Const(1),
+            I32Add,
+            LocalSet(COUNT),
+            End,
+            // value = 0.d1d2... * 10^k, whose point goes after the first
+            // digit in scientific notation
+            LocalGet(DECIMAL_EXPONENT),
+            I32Const(2),
+            I32Add,
+            I32Const(9),
+            I32GtU,
+            LocalTee(SCIENTIFIC),
+            If(BlockType::Empty),
+            I32Const(1),
+            LocalSet(POINT),
+            Else,
+            LocalGet(DECIMAL_EXPONENT),
+            LocalSet(POINT),
+            End,
+            I32Const(output as i32),
+            LocalSet(POSITION),
+            LocalGet(POINT),
+            I32Const(0),
+            I32LeS,
+            If(BlockType::Empty),
+        ]
+        .as_mut(),
+    );
+    body.append(put(vec![I32Const('0' as i32)]).as_mut());
+    body.append(put(vec![I32Const('.' as i32)]).as_mut());
+    body.append(zeros(vec![I32Const(0), LocalGet(POINT), I32Sub]).as_mut());
+    body.append(copy(vec![I32Const(0)], vec![LocalGet(COUNT)]).as_mut());
+    body.push(Else);
+    body.append(
+        copy(
+            vec![I32Const(0)],
+            vec![
+                LocalGet(POINT),
+                LocalGet(COUNT),
+                LocalGet(POINT),
+                LocalGet(COUNT),
+                I32LtS,
+                Select,
+            ],
+        )
+        .as_mut(),
+    );
+    body.append(zeros(vec![LocalGet(POINT), LocalGet(COUNT), I32Sub]).as_mut());
+    body.append(put(vec![I32Const('.' as i32)]).as_mut());
+    body.append(copy(vec![LocalGet(POINT)], vec![LocalGet(COUNT)]).as_mut());
+    body.append(
+        vec![
+            LocalGet(COUNT),
+            LocalGet(POINT),
+            I32LeS,
+            If(BlockType::Empty),
+        ]
+        .as_mut(),
+    );
+    body.append(put(vec![I32Const('0' as i32)]).as_mut());
+    body.append(vec![End, End].as_mut());
+    body.append(
+        write_bytes(
+            STDOUT,
+            I32Const(output as i32),
+            vec![LocalGet(POSITION), I32Const(output as i32), I32Sub],
+        )
+        .as_mut(),
+    );
+    body.append(vec![LocalGet(SCIENTIFIC), If(BlockType::Empty)].as_mut());
+    body.append(write_literal(STDOUT, "E", data).as_mut());
+    body.append(
+        vec![
+            LocalGet(DECIMAL_EXPONENT),
+            I32Const(1),
+            I32Sub,
+            I64ExtendI32S,
+            Call(Runtime::PrintInteger.name().to_owned()),
+            End,
+        ]
+        .as_mut(),
+    );
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::F64],
+        results: vec![],
+        locals: [vec![Types::I64; 4], vec![Types::I32; 14]].concat(),
+        body,
+    }
+}
+
+/// Writes the bytes of its string argument to stdout. Strings the engine
+/// manages are copied to linear memory a chunk at a time.
+fn print_string(name: &str, memory: Memory) -> Function {
+    const STRING: usize = 0;
+    const BYTES: usize = 1;
+    const LENGTH: usize = 1;
+    const OFFSET: usize = 2;
+    const COUNT: usize = 3;
+    const INDEX: usize = 4;
+    use Opcodes::*;
+
+    if memory != Memory::Engine {
+        let mut body = vec![LocalGet(STRING), I32Const(8), I32Add, LocalSet(BYTES)];
+        body.append(
+            write_bytes(STDOUT, LocalGet(BYTES), vec![LocalGet(STRING), I32Load(4)]).as_mut(),
+        );
+        return Function {
+            name: name.to_owned(),
+            params: vec![Types::I32],
+            results: vec![],
+            locals: vec![Types::I32],
+            body,
+        };
+    }
+
+    let mut body = vec![
+        LocalGet(STRING),
+        RefCast(HeapType::String),
+        ArrayLen,
+        LocalSet(LENGTH),
+        Block(BlockType::Empty),
+        Loop(BlockType::Empty),
+        LocalGet(OFFSET),
+        LocalGet(LENGTH),
+        I32GeU,
+        BrIf(1),
+        // count = min(length - offset, STRING_BUFFER_SIZE)
+        LocalGet(LENGTH),
+        LocalGet(OFFSET),
+        I32Sub,
+        LocalTee(COUNT),
+        I32Const(STRING_BUFFER_SIZE),
+        LocalGet(COUNT),
+        I32Const(STRING_BUFFER_SIZE),
+        I32LtS,
+        Select,
+        LocalSet(COUNT),
+        I32Const(0),
+        LocalSet(INDEX),
+        Block(BlockType::Empty),
+        Loop(BlockType::Empty),
+        LocalGet(INDEX),
+        LocalGet(COUNT),
+        I32Eq,
+        BrIf(1),
+        LocalGet(INDEX),
+        LocalGet(STRING),
+        RefCast(HeapType::String),
+        LocalGet(OFFSET),
+        LocalGet(INDEX),
+        I32Add,
+        ArrayGetU(HeapType::String),
+        I32Store8(STRING_BUFFER as u32),
+        LocalGet(INDEX),
+        I32Const(1),
+        I32Add,
+        LocalSet(INDEX),
+        Br(0),
+        End,
+        End,
+    ];
+    body.append(write_bytes(STDOUT, I32Const(STRING_BUFFER), vec![LocalGet(COUNT)]).as_mut());
+    body.append(
+        vec![
+            LocalGet(OFFSET),
+            LocalGet(COUNT),
+            I32Add,
+            LocalSet(OFFSET),
+            Br(0),
+            End,
+            End,
+        ]
+        .as_mut(),
+    );
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::EqRef],
+        results: vec![],
+        locals: vec![Types::I32; 4],
+        body,
+    }
+}
+
+/// Reports an exception with the given message on stderr and stops the
+/// program
+fn exception(name: &str, message: &str, data: &mut DataLayout) -> Function {
+    let mut body = write_literal(STDERR, message, data);
+    body.append(
+        SysCalls::Exit(Opcodes::I32Const(EXCEPTION_EXIT_CODE))
+            .instructions()
+            .as_mut(),
+    );
+    body.push(Opcodes::Unreachable);
+
+    Function {
+        name: name.to_owned(),
+        params: vec![],
+        results: vec![],
+        locals: vec![],
+        body,
+    }
+}
+
+/// Applies `operation` to its two i64 arguments and reports an
+/// ArithmeticException when `overflowed` holds, which may use the third local
+/// holding the result.
+fn overflow_checked(name: &str, operation: Opcodes, mut overflowed: Vec<Opcodes>) -> Function {
+    const RESULT: usize = 2;
+    use Opcodes::*;
+
+    let mut body = vec![LocalGet(0), LocalGet(1), operation, LocalSet(RESULT)];
+    body.append(overflowed.as_mut());
+    body.append(
+        vec![
+            If(BlockType::Empty),
+            Call(Runtime::IntegerOverflow.name().to_owned()),
+            End,
+            LocalGet(RESULT),
+        ]
+        .as_mut(),
+    );
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I64; 2],
+        results: vec![Types::I64],
+        locals: vec![Types::I64],
+        body,
+    }
+}
+
+/// A sum overflows when both operands have a different sign than the result
+fn add_overflowed() -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(0),
+        LocalGet(2),
+        I64Xor,
+        LocalGet(1),
+        LocalGet(2),
+        I64Xor,
+        I64And,
+        I64Const(0),
+        I64LtS,
+    ]
+}
+
+/// A difference overflows when the operands have different signs and the
+/// result does not have the sign of the minuend
+fn subtract_overflowed() -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(0),
+        LocalGet(1),
+        I64Xor,
+        LocalGet(0),
+        LocalGet(2),
+        I64Xor,
+        I64And,
+        I64Const(0),
+        I64LtS,
+    ]
+}
+
+/// A product overflows when dividing it by one operand does not give back the
+/// other one. That division would trap for `i64::MIN * -1`, which is checked
+/// on its own.
+fn multiply_overflowed() -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(0),
+        I64Const(-1),
+        I64Eq,
+        LocalGet(1),
+        I64Const(i64::MIN),
+        I64Eq,
+        I32And,
+        If(BlockType::Value(Types::I32)),
+        I32Const(1),
+        Else,
+        LocalGet(0),
+        I64Eqz,
+        If(BlockType::Value(Types::I32)),
+        I32Const(0),
+        Else,
+        LocalGet(2),
+        LocalGet(0),
+        I64DivS,
+        LocalGet(1),
+        I64Ne,
+        End,
+        End,
+    ]
+}
+
+/// Applies `operation` to its two arguments of the given type, after making
+/// sure that the divisor is not zero. The operation may use the third local as
+/// scratch.
+fn checked_division(name: &str, value_type: Types, mut operation: Vec<Opcodes>) -> Function {
+    const DIVISOR: usize = 1;
+    use Opcodes::*;
+
+    let mut body = match value_type {
+        Types::I64 => vec![LocalGet(DIVISOR), I64Eqz],
+        Types::F64 => vec![LocalGet(DIVISOR), F64Const(0.0), F64Eq],
+        _ => vec![LocalGet(DIVISOR), I32Eqz],
+    };
+    body.append(
+        vec![
+            If(BlockType::Empty),
+            Call(Runtime::DivideByZero.name().to_owned()),
+            End,
+            LocalGet(0),
+            LocalGet(DIVISOR),
+        ]
+        .as_mut(),
+    );
+    body.append(operation.as_mut());
+
+    Function {
+        name: name.to_owned(),
+        params: vec![value_type; 2],
+        results: vec![value_type],
+        locals: vec![value_type],
+        body,
+    }
+}
+
+/// Truncated quotient, which overflows, rather than trapping, for
+/// `i64::MIN / -1`
+fn quotient() -> Vec<Opcodes> {
+    const DIVISOR: usize = 1;
+    use Opcodes::*;
+
+    vec![
+        LocalGet(0),
+        I64Const(i64::MIN),
+        I64Eq,
+        LocalGet(DIVISOR),
+        I64Const(-1),
+        I64Eq,
+        I32And,
+        If(BlockType::Empty),
+        Call(Runtime::IntegerOverflow.name().to_owned()),
+        End,
+        I64DivS,
+    ]
+}
+
+/// Remainder rounded towards negative infinity, which takes the sign of the
+/// divisor instead of the dividend
+fn modulo() -> Vec<Opcodes> {
+    const DIVISOR: usize = 1;
+    const REMAINDER: usize = 2;
+    use Opcodes::*;
+
+    vec![
+        I64RemS,
+        LocalSet(REMAINDER),
+        // remainder + divisor when the signs differ, remainder otherwise
+        LocalGet(REMAINDER),
+        LocalGet(DIVISOR),
+        I64Add,
+        LocalGet(REMAINDER),
+        LocalGet(REMAINDER),
+        LocalGet(DIVISOR),
+        I64Xor,
+        I64Const(0),
+        I64LtS,
+        LocalGet(REMAINDER),
+        I64Const(0),
+        I64Ne,
+        I32And,
+        Select,
+    ]
+}
+
+/// Remainder of two f64 values with the sign of the dividend, like `rem_s`
+fn float_remainder() -> Vec<Opcodes> {
+    const DIVIDEND: usize = 0;
+    const DIVISOR: usize = 1;
+    const REMAINDER: usize = 2;
+    use Opcodes::*;
+
+    // dividend - trunc(dividend / divisor) * divisor
+    vec![
+        F64Div,
+        F64Trunc,
+        LocalGet(DIVISOR),
+        F64Mul,
+        LocalSet(REMAINDER),
+        LocalGet(DIVIDEND),
+        LocalGet(REMAINDER),
+        F64Sub,
+    ]
+}
+
+/// Turns the f64 remainder on the stack into the modulo, the same way
+/// `modulo` does for i64 values
+fn float_modulo() -> Vec<Opcodes> {
+    const DIVISOR: usize = 1;
+    const REMAINDER: usize = 2;
+    use Opcodes::*;
+
+    vec![
+        LocalSet(REMAINDER),
+        LocalGet(REMAINDER),
+        LocalGet(DIVISOR),
+        F64Add,
+        LocalGet(REMAINDER),
+        LocalGet(REMAINDER),
+        F64Const(0.0),
+        F64Lt,
+        LocalGet(DIVISOR),
+        F64Const(0.0),
+        F64Lt,
+        I32Ne,
+        LocalGet(REMAINDER),
+        F64Const(0.0),
+        F64Ne,
+        I32And,
+        Select,
+    ]
+}
+
+/// Keeps the first of two i64 values when `keep_first` holds for them and the
+/// second one otherwise
+fn extremum(name: &str, keep_first: Opcodes) -> Function {
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I64; 2],
+        results: vec![Types::I64],
+        locals: vec![],
+        body: vec![
+            LocalGet(0),
+            LocalGet(1),
+            LocalGet(0),
+            LocalGet(1),
+            keep_first,
+            Select,
+        ],
+    }
+}
+
+/// Name of the allocator every module contains, whichever way its memory is
+/// managed
+pub const ALLOCATE: &str = "allocate";
+
+/// Returns the address of a block of at least as many bytes as its argument.
+/// Blocks are rounded up to a power of two and taken from the free list of
+/// their size class, or else from the end of the heap. Memory doubles when
+/// the heap outgrows it, an OutOfMemoryError being raised when it cannot.
+/// Collected memory is collected before it grows, and new objects are rooted
+/// with a nil tag until the caller fills them in.
+fn allocate(name: &str, memory: Memory) -> Function {
+    const SIZE: usize = 0;
+    const CLASS: usize = 1;
+    const LIST: usize = 2;
+    const BLOCK: usize = 3;
+    const END: usize = 4;
+    const PAGES: usize = 5;
+    use Opcodes::*;
+
+    let memory_end = vec![MemorySize, I32Const(PAGE_SHIFT), I32Shl];
+    let mut body = vec![
+        // class = max(ceil(log2(size + header)), MIN_SIZE_CLASS)
+        I32Const(32),
+        LocalGet(SIZE),
+        I32Const(HEADER_SIZE - 1),
+        I32Add,
+        I32Clz,
+        I32Sub,
+        LocalTee(CLASS),
+        I32Const(MIN_SIZE_CLASS),
+        LocalGet(CLASS),
+        I32Const(MIN_SIZE_CLASS),
+        I32GtU,
+        Select,
+        LocalSet(CLASS),
+        GlobalGet(FREE_LISTS.to_owned()),
+        LocalGet(CLASS),
+        I32Const(2),
+        I32Shl,
+        I32Add,
+        LocalSet(LIST),
+    ];
+    if memory == Memory::Collected {
+        body.append(
+            [
+                vec![
+                    LocalGet(LIST),
+                    I32Load(0),
+                    I32Eqz,
+                    GlobalGet(HEAP_POINTER.to_owned()),
+                    I32Const(1),
+                    LocalGet(CLASS),
+                    I32Shl,
+                    I32Add,
+                ],
+                memory_end.clone(),
+                vec![
+                    I32GtU,
+                    I32And,
+                    If(BlockType::Empty),
+                    Call(Runtime::Collect.name().to_owned()),
+                    End,
+                ],
+            ]
+            .concat()
+            .as_mut(),
+        );
+    }
+    body.append(
+        [
+            vec![
+                LocalGet(LIST),
+                I32Load(0),
+                LocalTee(BLOCK),
+                If(BlockType::Empty),
+                // unlink the first free block
+                LocalGet(LIST),
+                LocalGet(BLOCK),
+                I32Load(HEADER_SIZE as u32),
+                I32Store(0),
+                Else,
+                GlobalGet(HEAP_POINTER.to_owned()),
+                LocalTee(BLOCK),
+                I32Const(1),
+                LocalGet(CLASS),
+                I32Shl,
+                I32Add,
+                LocalTee(END),
+            ],
+            memory_end.clone(),
+            vec![
+                I32GtU,
+                If(BlockType::Empty),
+                // pages = max(pages the block does not fit in, memory size)
+                LocalGet(END),
+            ],
+            memory_end,
+            vec![
+                I32Sub,
+                I32Const((1 << PAGE_SHIFT) - 1),
+                I32Add,
+                I32Const(PAGE_SHIFT),
+                I32ShrU,
+                LocalTee(PAGES),
+                MemorySize,
+                LocalGet(PAGES),
+                MemorySize,
+                I32GtU,
+                Select,
+                MemoryGrow,
+                I32Const(-1),
+                I32Eq,
+                If(BlockType::Empty),
+                Call(Runtime::OutOfMemory.name().to_owned()),
+                End,
+                End,
+                LocalGet(END),
+                GlobalSet(HEAP_POINTER.to_owned()),
+                End,
+                LocalGet(BLOCK),
+                LocalGet(CLASS),
+                I32Store(0),
+                LocalGet(BLOCK),
+                I32Const(ALLOCATED),
+                I32Store(4),
+            ],
+        ]
+        .concat()
+        .as_mut(),
+    );
+    if memory == Memory::Collected {
+        body.append(
+            vec![
+                LocalGet(BLOCK),
+                I32Const(Tag::Nil as i32),
+                I32Store(HEADER_SIZE as u32),
+            ]
+            .as_mut(),
+        );
+    }
+    body.append(vec![LocalGet(BLOCK), I32Const(HEADER_SIZE), I32Add].as_mut());
+    if memory == Memory::Collected {
+        body.push(Call(Runtime::Root.name().to_owned()));
+    }
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32],
+        results: vec![Types::I32],
+        locals: vec![Types::I32; 5],
+        body,
+    }
+}
+
+/// Returns a block given out by `allocate` to the free list of its size
+/// class, so that it is handed out again by the next allocation of that size
+fn free(name: &str) -> Function {
+    const ADDRESS: usize = 0;
+    const BLOCK: usize = 1;
+    const LIST: usize = 2;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32],
+        results: vec![],
+        locals: vec![Types::I32; 2],
+        body: vec![
+            LocalGet(ADDRESS),
+            I32Const(HEADER_SIZE),
+            I32Sub,
+            LocalTee(BLOCK),
+            I32Const(FREE),
+            I32Store(4),
+            GlobalGet(FREE_LISTS.to_owned()),
+            LocalGet(BLOCK),
+            I32Load(0),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            LocalSet(LIST),
+            LocalGet(ADDRESS),
+            LocalGet(LIST),
+            I32Load(0),
+            I32Store(0),
+            LocalGet(LIST),
+            LocalGet(BLOCK),
+            I32Store(0),
+        ],
+    }
+}
+
+/// Pushes its reference argument on the shadow stack and returns it
+fn root(name: &str) -> Function {
+    const VALUE: usize = 0;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32],
+        results: vec![Types::I32],
+        locals: vec![],
+        body: vec![
+            GlobalGet(STACK_POINTER.to_owned()),
+            GlobalGet(HEAP_START.to_owned()),
+            I32Eq,
+            If(BlockType::Empty),
+            Call(Runtime::StackOverflow.name().to_owned()),
+            End,
+            GlobalGet(STACK_POINTER.to_owned()),
+            LocalGet(VALUE),
+            I32Store(0),
+            GlobalGet(STACK_POINTER.to_owned()),
+            I32Const(4),
+            I32Add,
+            GlobalSet(STACK_POINTER.to_owned()),
+            LocalGet(VALUE),
+        ],
+    }
+}
+
+/// Marks the heap object its reference argument refers to by pushing it onto
+/// the grey list, for `collect` to mark the objects it refers to in turn.
+/// Nil, booleans and literals are not on the heap, and objects already marked
+/// are not pushed again.
+fn mark(name: &str) -> Function {
+    const VALUE: usize = 0;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32],
+        results: vec![],
+        locals: vec![],
+        body: vec![
+            LocalGet(VALUE),
+            GlobalGet(HEAP_START.to_owned()),
+            I32GeU,
+            I32Eqz,
+            If(BlockType::Empty),
+            Return,
+            End,
+            LocalGet(VALUE),
+            I32Const(HEADER_SIZE),
+            I32Sub,
+            I32Load(4),
+            I32Const(ALLOCATED),
+            I32Ne,
+            If(BlockType::Empty),
+            Return,
+            End,
+            LocalGet(VALUE),
+            I32Const(HEADER_SIZE),
+            I32Sub,
+            GlobalGet(GREY_LIST.to_owned()),
+            I32Store(4),
+            LocalGet(VALUE),
+            GlobalSet(GREY_LIST.to_owned()),
+        ],
+    }
+}
+
+/// Frees every heap object which is not reachable from the shadow stack, by
+/// marking the reachable ones and then sweeping over all the blocks. Objects
+/// are taken off the grey list until it is empty rather than marked
+/// recursively, so that long chains of objects do not overflow the stack.
+fn collect(name: &str) -> Function {
+    const SLOT: usize = 0;
+    const BLOCK: usize = 1;
+    const OBJECT: usize = 2;
+    const TAG: usize = 3;
+    const INDEX: usize = 4;
+    use Opcodes::*;
+
+    let mark = || Call(Runtime::Mark.name().to_owned());
+    Function {
+        name: name.to_owned(),
+        params: vec![],
+        results: vec![],
+        locals: vec![Types::I32; 5],
+        body: [
+            vec![
+                GlobalGet(FREE_LISTS.to_owned()),
+                I32Const(FREE_LISTS_SIZE as i32),
+                I32Add,
+                LocalSet(SLOT),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(SLOT),
+                GlobalGet(STACK_POINTER.to_owned()),
+                I32Eq,
+                BrIf(1),
+                LocalGet(SLOT),
+                I32Load(0),
+                Call(Runtime::Mark.name().to_owned()),
+                LocalGet(SLOT),
+                I32Const(4),
+                I32Add,
+                LocalSet(SLOT),
+                Br(0),
+                End,
+                End,
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                GlobalGet(GREY_LIST.to_owned()),
+                LocalTee(OBJECT),
+                I32Const(GREY_LIST_END),
+                I32Eq,
+                BrIf(1),
+                LocalGet(OBJECT),
+                I32Const(HEADER_SIZE),
+                I32Sub,
+                I32Load(4),
+                GlobalSet(GREY_LIST.to_owned()),
+                LocalGet(OBJECT),
+                I32Const(HEADER_SIZE),
+                I32Sub,
+                I32Const(MARKED),
+                I32Store(4),
+                LocalGet(OBJECT),
+                I32Load(0),
+                LocalSet(TAG),
+            ],
+            vector::mark_references(OBJECT, TAG, INDEX, mark()),
+            map::mark_references(OBJECT, TAG, INDEX, mark()),
+            set::mark_references(OBJECT, TAG, mark()),
+            closure::mark_references(OBJECT, TAG, INDEX, mark()),
+            vec![
+                Br(0),
+                End,
+                End,
+                GlobalGet(HEAP_START.to_owned()),
+                LocalSet(BLOCK),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(BLOCK),
+                GlobalGet(HEAP_POINTER.to_owned()),
+                I32Eq,
+                BrIf(1),
+                LocalGet(BLOCK),
+                I32Load(4),
+                I32Const(MARKED),
+                I32Eq,
+                If(BlockType::Empty),
+                LocalGet(BLOCK),
+                I32Const(ALLOCATED),
+                I32Store(4),
+                Else,
+                LocalGet(BLOCK),
+                I32Load(4),
+                I32Const(ALLOCATED),
+                I32Eq,
+                If(BlockType::Empty),
+                LocalGet(BLOCK),
+                I32Const(HEADER_SIZE),
+                I32Add,
+                Call(Runtime::Free.name().to_owned()),
+                End,
+                End,
+                // the next block follows this one
+                LocalGet(BLOCK),
+                I32Const(1),
+                LocalGet(BLOCK),
+                I32Load(0),
+                I32Shl,
+                I32Add,
+                LocalSet(BLOCK),
+                Br(0),
+                End,
+                End,
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Copies an i64 or f64 argument, as given by `tag`, into a new heap object
+/// and returns its reference
+fn box_number(name: &str, tag: Tag, memory: Memory) -> Function {
+    const VALUE: usize = 0;
+    const OBJECT: usize = 1;
+    use Opcodes::*;
+
+    let (value_type, store, heap_type) = match tag {
+        Tag::Float => (Types::F64, F64Store(8), HeapType::Float),
+        _ => (Types::I64, I64Store(8), HeapType::Integer),
+    };
+    if memory == Memory::Engine {
+        return Function {
+            name: name.to_owned(),
+            params: vec![value_type],
+            results: vec![Types::EqRef],
+            locals: vec![],
+            body: vec![LocalGet(VALUE), StructNew(heap_type)],
+        };
+    }
+    Function {
+        name: name.to_owned(),
+        params: vec![value_type],
+        results: vec![Types::I32],
+        locals: vec![Types::I32],
+        body: vec![
+            I32Const(NUMBER_SIZE),
+            Call(ALLOCATE.to_owned()),
+            LocalTee(OBJECT),
+            I32Const(tag as i32),
+            I32Store(0),
+            LocalGet(OBJECT),
+            LocalGet(VALUE),
+            store,
+            LocalGet(OBJECT),
+        ],
+    }
+}
+
+/// Turns the address of a string literal into a reference to it. Literals
+/// stay in linear memory unless the engine manages memory, in which case
+/// they are copied into a new array.
+fn string_literal(name: &str, memory: Memory) -> Function {
+    const ADDRESS: usize = 0;
+    const STRING: usize = 1;
+    const INDEX: usize = 2;
+    use Opcodes::*;
+
+    if memory != Memory::Engine {
+        return Function {
+            name: name.to_owned(),
+            params: vec![Types::I32],
+            results: vec![Types::I32],
+            locals: vec![],
+            body: vec![LocalGet(ADDRESS)],
+        };
+    }
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32],
+        results: vec![Types::EqRef],
+        locals: vec![Types::EqRef, Types::I32],
+        body: vec![
+            LocalGet(ADDRESS),
+            I32Load(4),
+            ArrayNewDefault(HeapType::String),
+            LocalSet(STRING),
+            Block(BlockType::Empty),
+            Loop(BlockType::Empty),
+            LocalGet(INDEX),
+            LocalGet(ADDRESS),
+            I32Load(4),
+            I32Eq,
+            BrIf(1),
+            LocalGet(STRING),
+            RefCast(HeapType::String),
+            LocalGet(INDEX),
+            LocalGet(ADDRESS),
+            LocalGet(INDEX),
+            I32Add,
+            I32Load8U(8),
+            ArraySet(HeapType::String),
+            LocalGet(INDEX),
+            I32Const(1),
+            I32Add,
+            LocalSet(INDEX),
+            Br(0),
+            End,
+            End,
+            LocalGet(STRING),
+        ],
+    }
+}
+
+/// Turns the address of a keyword literal into a reference to it. Literals
+/// with the same name are the same object in linear memory, while the engine
+/// holds the name of a new keyword in a new string, which `intern_keyword`
+/// only does once for every name.
+fn keyword_literal(name: &str, memory: Memory) -> Function {
+    const ADDRESS: usize = 0;
+    use Opcodes::*;
+
+    let mut body = vec![LocalGet(ADDRESS)];
+    if memory == Memory::Engine {
+        body.append(
+            vec![
+                Call(Runtime::StringLiteral.name().to_owned()),
+                StructNew(HeapType::Keyword),
+            ]
+            .as_mut(),
+        );
+    }
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body,
+    }
+}
+
+/// The keyword of the symbol table index given as first argument, made from
+/// the keyword literal at the address given as second argument the first
+/// time it is evaluated. The symbol table itself is made on first use.
+fn intern_keyword(name: &str) -> Function {
+    const SYMBOL: usize = 0;
+    const ADDRESS: usize = 1;
+    const KEYWORD: usize = 2;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32; 2],
+        results: vec![Types::EqRef],
+        locals: vec![Types::EqRef],
+        body: vec![
+            GlobalGet(SYMBOLS.to_owned()),
+            RefIsNull,
+            If(BlockType::Empty),
+            GlobalGet(SYMBOL_COUNT.to_owned()),
+            ArrayNewDefault(HeapType::Node),
+            GlobalSet(SYMBOLS.to_owned()),
+            End,
+            GlobalGet(SYMBOLS.to_owned()),
+            RefCast(HeapType::Node),
+            LocalGet(SYMBOL),
+            ArrayGet(HeapType::Node),
+            LocalTee(KEYWORD),
+            RefIsNull,
+            If(BlockType::Empty),
+            GlobalGet(SYMBOLS.to_owned()),
+            RefCast(HeapType::Node),
+            LocalGet(SYMBOL),
+            LocalGet(ADDRESS),
+            Call(Runtime::KeywordLiteral.name().to_owned()),
+            LocalTee(KEYWORD),
+            ArraySet(HeapType::Node),
+            End,
+            LocalGet(KEYWORD),
+        ],
+    }
+}
+
+/// Whether its reference argument stands for neither nil nor false
+fn truthy(name: &str, memory: Memory) -> Function {
+    const VALUE: usize = 0;
+    use Opcodes::*;
+
+    let body = match memory {
+        Memory::Engine => vec![
+            LocalGet(VALUE),
+            RefIsNull,
+            If(BlockType::Value(Types::I32)),
+            I32Const(0),
+            Else,
+            LocalGet(VALUE),
+            RefTest(HeapType::I31),
+            If(BlockType::Value(Types::I32)),
+            LocalGet(VALUE),
+            RefCast(HeapType::I31),
+            I31GetU,
+            Else,
+            I32Const(1),
+            End,
+            End,
+        ],
+        _ => vec![LocalGet(VALUE), I32Const(FALSE), I32GtU],
+    };
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![Types::I32],
+        locals: vec![],
+        body,
+    }
+}
+
+/// Tag of the value a reference stands for. Objects the engine manages are
+/// told apart by their heap type instead of a tag.
+fn type_of(name: &str, memory: Memory) -> Function {
+    const VALUE: usize = 0;
+    use Opcodes::*;
+
+    let body = match memory {
+        Memory::Engine => {
+            let mut body = vec![LocalGet(VALUE), RefIsNull];
+            let cases = [
+                (HeapType::I31, Tag::Boolean),
+                (HeapType::Integer, Tag::Integer),
+                (HeapType::Float, Tag::Float),
+                (HeapType::Vector, Tag::Vector),
+                (HeapType::Keyword, Tag::Keyword),
+                (HeapType::Map, Tag::Map),
+                (HeapType::Set, Tag::Set),
+                (HeapType::Closure, Tag::Closure),
+            ];
+            body.append(vec![If(BlockType::Value(Types::I32)), I32Const(Tag::Nil as i32)].as_mut());
+            for (heap_type, tag) in cases.iter() {
+                body.append(
+                    vec![
+                        Else,
+                        LocalGet(VALUE),
+                        RefTest(*heap_type),
+                        If(BlockType::Value(Types::I32)),
+                        I32Const(*tag as i32),
+                    ]
+                    .as_mut(),
+                );
+            }
+            body.append(vec![Else, I32Const(Tag::String as i32)].as_mut());
+            body.append(vec![End; cases.len() + 1].as_mut());
+            body
+        }
+        _ => vec![
+            LocalGet(VALUE),
+            I32Const(TRUE),
+            I32GtU,
+            If(BlockType::Value(Types::I32)),
+            LocalGet(VALUE),
+            I32Load(0),
+            Else,
+            // nil is 0 like its tag, both booleans are tagged 1
+            LocalGet(VALUE),
+            I32Const(NIL),
+            I32Ne,
+            End,
+        ],
+    };
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![Types::I32],
+        locals: vec![],
+        body,
+    }
+}
+
+/// The number a reference stands for as an f64, raising a ClassCastException
+/// for values which are not numbers
+fn to_float(name: &str, memory: Memory) -> Function {
+    const VALUE: usize = 0;
+    const TAG: usize = 1;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![Types::F64],
+        locals: vec![Types::I32],
+        body: [
+            vec![
+                LocalGet(VALUE),
+                Call(Runtime::TypeOf.name().to_owned()),
+                LocalTee(TAG),
+                I32Const(Tag::Float as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            unbox(VALUE, Types::F64, memory),
+            vec![
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Integer as i32),
+                I32Ne,
+                If(BlockType::Empty),
+                Call(Runtime::NotANumber.name().to_owned()),
+                End,
+            ],
+            unbox(VALUE, Types::I64, memory),
+            vec![F64ConvertI64S],
+        ]
+        .concat(),
+    }
+}
+
+/// The number a reference stands for as an i64, truncating floats and
+/// raising a ClassCastException for values which are not numbers
+fn to_integer(name: &str, memory: Memory) -> Function {
+    const VALUE: usize = 0;
+    const TAG: usize = 1;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![Types::I64],
+        locals: vec![Types::I32],
+        body: [
+            vec![
+                LocalGet(VALUE),
+                Call(Runtime::TypeOf.name().to_owned()),
+                LocalTee(TAG),
+                I32Const(Tag::Integer as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            unbox(VALUE, Types::I64, memory),
+            vec![
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Float as i32),
+                I32Ne,
+                If(BlockType::Empty),
+                Call(Runtime::NotANumber.name().to_owned()),
+                End,
+            ],
+            unbox(VALUE, Types::F64, memory),
+            vec![I64TruncF64S],
+        ]
+        .concat(),
+    }
+}
+
+/// Writes the value a reference stands for to stdout, according to its tag.
+/// The elements of a vector are printed in turn between brackets, the
+/// entries of a map between braces and the elements of a set after `#{`.
+/// Closures are all printed alike.
+fn print_value(name: &str, data: &mut DataLayout, memory: Memory) -> Function {
+    const VALUE: usize = 0;
+    const TAG: usize = 1;
+    const POSITION: usize = 2;
+    use Opcodes::*;
+
+    let mut body = vec![
+        LocalGet(VALUE),
+        Call(Runtime::TypeOf.name().to_owned()),
+        LocalSet(TAG),
+    ];
+    let cases = vec![
+        (Tag::Nil, write_literal(STDOUT, "nil", data)),
+        (
+            Tag::Boolean,
+            [
+                is_true(VALUE, memory),
+                vec![If(BlockType::Empty)],
+                write_literal(STDOUT, "true", data),
+                vec![Else],
+                write_literal(STDOUT, "false", data),
+                vec![End],
+            ]
+            .concat(),
+        ),
+        (
+            Tag::Integer,
+            [
+                unbox(VALUE, Types::I64, memory),
+                vec![Call(Runtime::PrintInteger.name().to_owned())],
+            ]
+            .concat(),
+        ),
+        (
+            Tag::Float,
+            [
+                unbox(VALUE, Types::F64, memory),
+                vec![Call(Runtime::PrintFloat.name().to_owned())],
+            ]
+            .concat(),
+        ),
+        (
+            Tag::String,
+            vec![
+                LocalGet(VALUE),
+                Call(Runtime::PrintString.name().to_owned()),
+            ],
+        ),
+        (
+            Tag::Vector,
+            [
+                write_literal(STDOUT, "[", data),
+                vector::for_each_element(
+                    VALUE,
+                    POSITION,
+                    [
+                        vector::is_first(VALUE, POSITION, memory),
+                        vec![I32Eqz, If(BlockType::Empty)],
+                        write_literal(STDOUT, " ", data),
+                        vec![End],
+                    ]
+                    .concat(),
+                    vec![Call(name.to_owned())],
+                    memory,
+                ),
+                write_literal(STDOUT, "]", data),
+            ]
+            .concat(),
+        ),
+        (
+            Tag::Keyword,
+            [
+                write_literal(STDOUT, ":", data),
+                keyword_name(VALUE, memory),
+                vec![Call(Runtime::PrintString.name().to_owned())],
+            ]
+            .concat(),
+        ),
+        (
+            Tag::Map,
+            [
+                write_literal(STDOUT, "{", data),
+                map::root(VALUE, memory),
+                vec![
+                    I32Const(1),
+                    I32Const(0),
+                    Call(Runtime::PrintNode.name().to_owned()),
+                    Drop,
+                ],
+                write_literal(STDOUT, "}", data),
+            ]
+            .concat(),
+        ),
+        (
+            Tag::Set,
+            [
+                write_literal(STDOUT, "#{", data),
+                set::unwrap(VALUE, memory),
+                map::root(VALUE, memory),
+                vec![
+                    I32Const(1),
+                    I32Const(1),
+                    Call(Runtime::PrintNode.name().to_owned()),
+                    Drop,
+                ],
+                write_literal(STDOUT, "}", data),
+            ]
+            .concat(),
+        ),
+        (Tag::Closure, write_literal(STDOUT, "#function", data)),
+    ];
+    for (tag, mut print) in cases {
+        body.append(
+            vec![
+                LocalGet(TAG),
+                I32Const(tag as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ]
+            .as_mut(),
+        );
+        body.append(print.as_mut());
+        body.append(vec![Return, End].as_mut());
+    }
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![],
+        locals: vec![Types::I32; 2],
+        body,
+    }
+}
+
+/// Whether two references stand for equal values. Values of different types
+/// are never equal, strings are equal when they have the same bytes, vectors
+/// when they have equal elements, maps when they have equal values for the
+/// same keys and sets when they have equal elements. Keywords are interned,
+/// so that they are only equal when they are the same object, and closures
+/// are equal when they are the same function.
+fn equals(name: &str, memory: Memory) -> Function {
+    const LEFT: usize = 0;
+    const RIGHT: usize = 1;
+    const TAG: usize = 2;
+    const INDEX: usize = 3;
+    const POSITION: usize = 4;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![Types::I32],
+        locals: vec![Types::I32; 3],
+        body: [
+            // the same reference, which covers nil and booleans
+            same_reference(LEFT, RIGHT, memory),
+            vec![
+                If(BlockType::Empty),
+                I32Const(1),
+                Return,
+                End,
+                LocalGet(LEFT),
+                Call(Runtime::TypeOf.name().to_owned()),
+                LocalTee(TAG),
+                LocalGet(RIGHT),
+                Call(Runtime::TypeOf.name().to_owned()),
+                I32Ne,
+                If(BlockType::Empty),
+                I32Const(0),
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Integer as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            unbox(LEFT, Types::I64, memory),
+            unbox(RIGHT, Types::I64, memory),
+            vec![
+                I64Eq,
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Float as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            unbox(LEFT, Types::F64, memory),
+            unbox(RIGHT, Types::F64, memory),
+            vec![
+                F64Eq,
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Vector as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            vector::same_count(LEFT, RIGHT, memory),
+            vec![I32Eqz, If(BlockType::Empty), I32Const(0), Return, End],
+            vector::for_each_element(
+                LEFT,
+                POSITION,
+                vec![],
+                [
+                    vector::matching_element(LEFT, RIGHT, POSITION, memory),
+                    vec![
+                        Call(name.to_owned()),
+                        I32Eqz,
+                        If(BlockType::Empty),
+                        I32Const(0),
+                        Return,
+                        End,
+                    ],
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![
+                I32Const(1),
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Set as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            set::elements(LEFT, memory),
+            set::elements(RIGHT, memory),
+            vec![
+                Call(name.to_owned()),
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Closure as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            closure::same_function(LEFT, RIGHT, memory),
+            vec![
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Map as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            map::same_count(LEFT, RIGHT, memory),
+            vec![I32Eqz, If(BlockType::Empty), I32Const(0), Return, End],
+            map::root(LEFT, memory),
+            vec![
+                LocalGet(RIGHT),
+                Call(Runtime::NodeEquals.name().to_owned()),
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::String as i32),
+                I32Ne,
+                If(BlockType::Empty),
+                I32Const(0),
+                Return,
+                End,
+            ],
+            string_length(LEFT, memory),
+            string_length(RIGHT, memory),
+            vec![I32Ne, If(BlockType::Empty), I32Const(0), Return, End],
+            // compare the bytes from the last one
+            string_length(LEFT, memory),
+            vec![
+                LocalSet(INDEX),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(INDEX),
+                I32Eqz,
+                BrIf(1),
+                LocalGet(INDEX),
+                I32Const(1),
+                I32Sub,
+                LocalSet(INDEX),
+            ],
+            string_byte(LEFT, INDEX, memory),
+            string_byte(RIGHT, INDEX, memory),
+            vec![I32Eq, BrIf(0), I32Const(0), Return, End, End, I32Const(1)],
+        ]
+        .concat(),
+    }
+}
+
+/// Hash of the value a reference stands for, which is the same for equal
+/// values. Numbers hash their bits, strings and vectors combine the hashes
+/// of their bytes or elements in order, maps and sets those of their entries
+/// or elements in any order, and keywords hash their name apart from the
+/// string of the same name. Closures, which are only equal to themselves,
+/// hash the index of their function.
+fn hash(name: &str, memory: Memory) -> Function {
+    const VALUE: usize = 0;
+    const TAG: usize = 1;
+    const HASH: usize = 2;
+    const INDEX: usize = 3;
+    const LENGTH: usize = 4;
+    const BITS: usize = 5;
+    /// Added to the hash of the name of a keyword
+    const KEYWORD_HASH: i32 = 0x9e37_79b9_u32 as i32;
+    use Opcodes::*;
+
+    let fold_bits = vec![
+        LocalTee(BITS),
+        LocalGet(BITS),
+        I64Const(32),
+        I64ShrU,
+        I64Xor,
+        I32WrapI64,
+        Return,
+        End,
+    ];
+    let is_tag = |tag: Tag| vec![LocalGet(TAG), I32Const(tag as i32), I32Eq];
+    let mut body = [
+        vec![
+            LocalGet(VALUE),
+            Call(Runtime::TypeOf.name().to_owned()),
+            LocalSet(TAG),
+        ],
+        is_tag(Tag::Nil),
+        vec![If(BlockType::Empty), I32Const(0), Return, End],
+        is_tag(Tag::Boolean),
+        vec![If(BlockType::Empty), I32Const(1231), I32Const(1237)],
+        is_true(VALUE, memory),
+        vec![Select, Return, End],
+        is_tag(Tag::Integer),
+        vec![If(BlockType::Empty)],
+        unbox(VALUE, Types::I64, memory),
+        fold_bits.clone(),
+        // 0.0 and -0.0 are equal, adding 0.0 gives both the same bits
+        is_tag(Tag::Float),
+        vec![If(BlockType::Empty)],
+        unbox(VALUE, Types::F64, memory),
+        vec![F64Const(0.0), F64Add, I64ReinterpretF64],
+        fold_bits,
+        is_tag(Tag::Vector),
+        vec![If(BlockType::Empty), I32Const(1), LocalSet(HASH)],
+        vector::for_each_element(
+            VALUE,
+            INDEX,
+            vec![LocalGet(HASH), I32Const(31), I32Mul],
+            vec![Call(name.to_owned()), I32Add, LocalSet(HASH)],
+            memory,
+        ),
+        vec![LocalGet(HASH), Return, End],
+        is_tag(Tag::Map),
+        vec![If(BlockType::Empty)],
+        map::root(VALUE, memory),
+        vec![
+            I32Const(0),
+            Call(Runtime::HashNode.name().to_owned()),
+            Return,
+            End,
+        ],
+        is_tag(Tag::Set),
+        vec![If(BlockType::Empty)],
+        set::unwrap(VALUE, memory),
+        map::root(VALUE, memory),
+        vec![
+            I32Const(1),
+            Call(Runtime::HashNode.name().to_owned()),
+            Return,
+            End,
+        ],
+        is_tag(Tag::Closure),
+        vec![If(BlockType::Empty)],
+        closure::index(VALUE, memory),
+        vec![Return, End],
+    ]
+    .concat();
+    if memory == Memory::Engine {
+        body.append(
+            [
+                is_tag(Tag::Keyword),
+                vec![If(BlockType::Empty)],
+                keyword_name(VALUE, memory),
+                vec![LocalSet(VALUE), End],
+            ]
+            .concat()
+            .as_mut(),
+        );
+    }
+    // strings and keywords hash the bytes of their text or name
+    body.append(
+        [
+            string_length(VALUE, memory),
+            vec![
+                LocalSet(LENGTH),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(INDEX),
+                LocalGet(LENGTH),
+                I32Eq,
+                BrIf(1),
+                LocalGet(HASH),
+                I32Const(31),
+                I32Mul,
+            ],
+            string_byte(VALUE, INDEX, memory),
+            vec![
+                I32Add,
+                LocalSet(HASH),
+                LocalGet(INDEX),
+                I32Const(1),
+                I32Add,
+                LocalSet(INDEX),
+                Br(0),
+                End,
+                End,
+                LocalGet(HASH),
+                I32Const(KEYWORD_HASH),
+                I32Add,
+                LocalGet(HASH),
+            ],
+            is_tag(Tag::Keyword),
+            vec![Select],
+        ]
+        .concat()
+        .as_mut(),
+    );
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![Types::I32],
+        locals: [vec![Types::I32; 4], vec![Types::I64]].concat(),
+        body,
+    }
+}
+
+/// Compares two references to numbers, returning -1, 0 or 1 when the first
+/// one is less than, equal to or greater than the second one. Integers are
+/// compared exactly, any other pair as floats.
+fn compare_numbers(name: &str, memory: Memory) -> Function {
+    const LEFT: usize = 0;
+    const RIGHT: usize = 1;
+    const LEFT_FLOAT: usize = 2;
+    const RIGHT_FLOAT: usize = 3;
+    use Opcodes::*;
+
+    let mut body = both_integers(LEFT, RIGHT);
+    body.append(
+        [
+            vec![If(BlockType::Empty)],
+            unbox(LEFT, Types::I64, memory),
+            unbox(RIGHT, Types::I64, memory),
+            vec![I64GtS],
+            unbox(LEFT, Types::I64, memory),
+            unbox(RIGHT, Types::I64, memory),
+            vec![
+                I64LtS,
+                I32Sub,
+                Return,
+                End,
+                LocalGet(LEFT),
+                Call(Runtime::ToFloat.name().to_owned()),
+                LocalSet(LEFT_FLOAT),
+                LocalGet(RIGHT),
+                Call(Runtime::ToFloat.name().to_owned()),
+                LocalSet(RIGHT_FLOAT),
+                LocalGet(LEFT_FLOAT),
+                LocalGet(RIGHT_FLOAT),
+                F64Gt,
+                LocalGet(LEFT_FLOAT),
+                LocalGet(RIGHT_FLOAT),
+                F64Lt,
+                I32Sub,
+            ],
+        ]
+        .concat()
+        .as_mut(),
+    );
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![Types::I32],
+        locals: vec![Types::F64; 2],
+        body,
+    }
+}
+
+/// Applies `operation` to two references to numbers, as integers when both
+/// of them are integers and as floats otherwise, and returns a reference to
+/// the result. Integers which don't divide exactly divide as floats, there
+/// being no ratios.
+fn dynamic_arithmetic(name: &str, operation: Arithmetic, memory: Memory) -> Function {
+    const LEFT: usize = 0;
+    const RIGHT: usize = 1;
+    use Opcodes::*;
+
+    let mut integers = [
+        unbox(LEFT, Types::I64, memory),
+        unbox(RIGHT, Types::I64, memory),
+        vec![
+            operation.instruction(Types::I64),
+            Call(Runtime::BoxInteger.name().to_owned()),
+            Return,
+        ],
+    ]
+    .concat();
+    if operation == Arithmetic::Divide {
+        integers = [
+            unbox(LEFT, Types::I64, memory),
+            unbox(RIGHT, Types::I64, memory),
+            vec![
+                Call(Runtime::Remainder.name().to_owned()),
+                I64Eqz,
+                If(BlockType::Empty),
+            ],
+            integers,
+            vec![End],
+        ]
+        .concat();
+    }
+    let mut body = both_integers(LEFT, RIGHT);
+    body.append(
+        [
+            vec![If(BlockType::Empty)],
+            integers,
+            vec![
+                End,
+                LocalGet(LEFT),
+                Call(Runtime::ToFloat.name().to_owned()),
+                LocalGet(RIGHT),
+                Call(Runtime::ToFloat.name().to_owned()),
+                operation.instruction(Types::F64),
+                Call(Runtime::BoxFloat.name().to_owned()),
+            ],
+        ]
+        .concat()
+        .as_mut(),
+    );
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body,
+    }
+}
+
+/// Whether the two references in the given locals both stand for integers
+fn both_integers(left: usize, right: usize) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(left),
+        Call(Runtime::TypeOf.name().to_owned()),
+        I32Const(Tag::Integer as i32),
+        I32Eq,
+        LocalGet(right),
+        Call(Runtime::TypeOf.name().to_owned()),
+        I32Const(Tag::Integer as i32),
+        I32Eq,
+        I32And,
+    ]
+}
+
+/// Type of the references to values, which are addresses in linear memory
+/// unless the engine manages memory
+pub fn reference(memory: Memory) -> Types {
+    match memory {
+        Memory::Engine => Types::EqRef,
+        _ => Types::I32,
+    }
+}
+
+/// The i64 or f64 held by the boxed number the reference in `local` refers to
+pub fn unbox(local: usize, number: Types, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match (memory, number) {
+        (Memory::Engine, Types::F64) => vec![
+            LocalGet(local),
+            RefCast(HeapType::Float),
+            StructGet(HeapType::Float, 0),
+        ],
+        (Memory::Engine, _) => vec![
+            LocalGet(local),
+            RefCast(HeapType::Integer),
+            StructGet(HeapType::Integer, 0),
+        ],
+        (_, Types::F64) => vec![LocalGet(local), F64Load(8)],
+        _ => vec![LocalGet(local), I64Load(8)],
+    }
+}
+
+/// Whether the references in two locals are the same
+fn same_reference(left: usize, right: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    let compare = match memory {
+        Memory::Engine => RefEq,
+        _ => I32Eq,
+    };
+    vec![LocalGet(left), LocalGet(right), compare]
+}
+
+/// Whether the reference to a boolean in `local` stands for true
+fn is_true(local: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![LocalGet(local), RefCast(HeapType::I31), I31GetU],
+        _ => vec![LocalGet(local), I32Const(TRUE), I32Eq],
+    }
+}
+
+/// Name of the keyword the reference in `local` refers to, as a string. In
+/// linear memory a keyword is laid out like the string of its name.
+fn keyword_name(local: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![
+            LocalGet(local),
+            RefCast(HeapType::Keyword),
+            StructGet(HeapType::Keyword, 0),
+        ],
+        _ => vec![LocalGet(local)],
+    }
+}
+
+/// Byte length of the string the reference in `local` refers to
+pub fn string_length(local: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![LocalGet(local), RefCast(HeapType::String), ArrayLen],
+        _ => vec![LocalGet(local), I32Load(4)],
+    }
+}
+
+/// Byte at the offset in `index` of the string the reference in `local`
+/// refers to
+fn string_byte(local: usize, index: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![
+            LocalGet(local),
+            RefCast(HeapType::String),
+            LocalGet(index),
+            ArrayGetU(HeapType::String),
+        ],
+        _ => vec![LocalGet(local), LocalGet(index), I32Add, I32Load8U(8)],
+    }
+}
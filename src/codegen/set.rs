@@ -0,0 +1,268 @@
+use crate::codegen::instructions::{BlockType, HeapType, Opcodes, Types};
+use crate::codegen::module::{Function, Memory};
+use crate::codegen::runtime::{reference, Runtime, ALLOCATE, NIL};
+use crate::codegen::types::Tag;
+
+/// In linear memory, the map of the elements of a set follows its tag
+const SET_SIZE: i32 = 8;
+const ELEMENTS_OFFSET: u32 = 4;
+
+/// The map of the elements of the set in `set`. A set wraps a map from each
+/// of its elements to itself, so that looking an element up in the map
+/// gives back the element as calling the set does.
+pub fn elements(set: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![
+            LocalGet(set),
+            RefCast(HeapType::Set),
+            StructGet(HeapType::Set, 0),
+        ],
+        _ => vec![LocalGet(set), I32Load(ELEMENTS_OFFSET)],
+    }
+}
+
+/// Replaces the set in `local` by the map of its elements, leaving other
+/// values alone, so that sets can be handled like the maps they wrap
+pub fn unwrap(local: usize, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    [
+        has_tag(local, Tag::Set),
+        vec![If(BlockType::Empty)],
+        elements(local, memory),
+        vec![LocalSet(local), End],
+    ]
+    .concat()
+}
+
+fn has_tag(local: usize, tag: Tag) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(local),
+        call(Runtime::TypeOf),
+        I32Const(tag as i32),
+        I32Eq,
+    ]
+}
+
+fn call(runtime: Runtime) -> Opcodes {
+    Opcodes::Call(runtime.name().to_owned())
+}
+
+fn null(memory: Memory) -> Opcodes {
+    match memory {
+        Memory::Engine => Opcodes::RefNull(HeapType::Eq),
+        _ => Opcodes::I32Const(NIL),
+    }
+}
+
+/// Calls `mark` on the map of the object in `object` when it is a set, whose
+/// tag is in `tag`
+pub fn mark_references(object: usize, tag: usize, mark: Opcodes) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    [
+        vec![
+            LocalGet(tag),
+            I32Const(Tag::Set as i32),
+            I32Eq,
+            If(BlockType::Empty),
+        ],
+        elements(object, Memory::Collected),
+        vec![mark, End],
+    ]
+    .concat()
+}
+
+/// Returns a set wrapping the map given as argument
+pub fn make_set(name: &str, memory: Memory) -> Function {
+    const ELEMENTS: usize = 0;
+    const SET: usize = 1;
+    use Opcodes::*;
+
+    if memory == Memory::Engine {
+        return Function {
+            name: name.to_owned(),
+            params: vec![Types::EqRef],
+            results: vec![Types::EqRef],
+            locals: vec![],
+            body: vec![LocalGet(ELEMENTS), StructNew(HeapType::Set)],
+        };
+    }
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32],
+        results: vec![Types::I32],
+        locals: vec![Types::I32],
+        body: vec![
+            I32Const(SET_SIZE),
+            Call(ALLOCATE.to_owned()),
+            LocalTee(SET),
+            I32Const(Tag::Set as i32),
+            I32Store(0),
+            LocalGet(SET),
+            LocalGet(ELEMENTS),
+            I32Store(ELEMENTS_OFFSET),
+            LocalGet(SET),
+        ],
+    }
+}
+
+pub fn empty_set(name: &str, memory: Memory) -> Function {
+    Function {
+        name: name.to_owned(),
+        params: vec![],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: vec![call(Runtime::EmptyMap), call(Runtime::MakeSet)],
+    }
+}
+
+/// Returns a set with the second argument added to the first one
+pub fn set_conj(name: &str, memory: Memory) -> Function {
+    const SET: usize = 0;
+    const VALUE: usize = 1;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: [
+            elements(SET, memory),
+            vec![
+                LocalGet(VALUE),
+                LocalGet(VALUE),
+                call(Runtime::MapAssoc),
+                call(Runtime::MakeSet),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a collection with the second argument added to the first one,
+/// which is a set or a vector
+pub fn conj(name: &str, memory: Memory) -> Function {
+    const COLLECTION: usize = 0;
+    const VALUE: usize = 1;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: [
+            has_tag(COLLECTION, Tag::Set),
+            vec![
+                If(BlockType::Value(reference(memory))),
+                LocalGet(COLLECTION),
+                LocalGet(VALUE),
+                call(Runtime::SetConj),
+                Else,
+                LocalGet(COLLECTION),
+                LocalGet(VALUE),
+                call(Runtime::VectorConj),
+                End,
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a set without the second argument. Nil stays nil, and other values
+/// raise a ClassCastException.
+pub fn disj(name: &str, memory: Memory) -> Function {
+    const SET: usize = 0;
+    const KEY: usize = 1;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: [
+            has_tag(SET, Tag::Nil),
+            vec![If(BlockType::Empty), LocalGet(SET), Return, End],
+            has_tag(SET, Tag::Set),
+            vec![I32Eqz, If(BlockType::Empty), call(Runtime::NotASet), End],
+            elements(SET, memory),
+            vec![LocalGet(KEY), call(Runtime::Dissoc), call(Runtime::MakeSet)],
+        ]
+        .concat(),
+    }
+}
+
+/// Calls a set, a map or a vector as a function of a key, which looks the key
+/// up in it like `get` does, or a keyword as a function of a collection,
+/// which looks the keyword up in the collection. Only maps and keywords take
+/// a value for when nothing is found, `closure_entry` calling sets and
+/// vectors with the key alone. Other values raise a ClassCastException.
+pub fn invoke(name: &str, memory: Memory) -> Function {
+    const COLLECTION: usize = 0;
+    const KEY: usize = 1;
+    const NOT_FOUND: usize = 2;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 3],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: [
+            has_tag(COLLECTION, Tag::Keyword),
+            vec![
+                If(BlockType::Empty),
+                LocalGet(KEY),
+                LocalGet(COLLECTION),
+                LocalGet(NOT_FOUND),
+                call(Runtime::Get),
+                Return,
+                End,
+            ],
+            has_tag(COLLECTION, Tag::Set),
+            has_tag(COLLECTION, Tag::Map),
+            vec![I32Or],
+            has_tag(COLLECTION, Tag::Vector),
+            vec![
+                I32Or,
+                I32Eqz,
+                If(BlockType::Empty),
+                call(Runtime::NotAFunction),
+                End,
+                LocalGet(COLLECTION),
+                LocalGet(KEY),
+                LocalGet(NOT_FOUND),
+                call(Runtime::Get),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Calls a set, a map, a vector or a keyword with a single argument, like
+/// `invoke` does with nil as the value when nothing is found
+pub fn invoke_with_key(name: &str, memory: Memory) -> Function {
+    const COLLECTION: usize = 0;
+    const KEY: usize = 1;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: vec![
+            LocalGet(COLLECTION),
+            LocalGet(KEY),
+            null(memory),
+            call(Runtime::Invoke),
+        ],
+    }
+}
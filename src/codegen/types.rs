@@ -0,0 +1,125 @@
+use crate::codegen::instructions::Types;
+use crate::codegen::module::Memory;
+
+/// Type of an expression as far as it is known while compiling. Integers are
+/// i64 values and floats f64 values at runtime, every other type is
+/// represented by an i32: booleans by 0 or 1, strings and keywords by the
+/// address of their data, nil by 0 and collections, functions as well as
+/// values of any type by a reference as described by `Tag`. When the engine
+/// manages memory, strings, keywords, collections, functions and values of
+/// any type are `eqref` references instead. An expression of type `Never`,
+/// such as `recur`, jumps elsewhere instead of leaving a value.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ValueType {
+    Integer,
+    Float,
+    Boolean,
+    String,
+    Keyword,
+    Vector,
+    Map,
+    Set,
+    Function,
+    Nil,
+    Any,
+    Never,
+}
+
+/// Type of a value as recorded at runtime, for values whose type is not known
+/// while compiling. Such values are i32 references: nil is 0, false and true
+/// are 1 and 2, and every other value is the address of a heap object whose
+/// first word is its tag. Integers and floats keep their number at offset 8,
+/// strings and keywords their byte length at offset 4 followed by the bytes
+/// of their text or name. Vectors, maps and the nodes of their tries are laid
+/// out as described in `vector` and `map`, sets as described in `set` and
+/// closures as described in `closure`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Tag {
+    Nil = 0,
+    Boolean = 1,
+    Integer = 2,
+    Float = 3,
+    String = 4,
+    Vector = 5,
+    /// Never the tag of a value, only of the nodes inside a vector
+    Node = 6,
+    Keyword = 7,
+    Map = 8,
+    /// Never the tag of a value, only of the nodes inside a map
+    MapNode = 9,
+    Set = 10,
+    Closure = 11,
+}
+
+impl ValueType {
+    /// Type of an expression which evaluates to either `self` or `other`,
+    /// values of different types only being told apart at runtime
+    pub fn unify(self, other: ValueType) -> ValueType {
+        if self == other || other == ValueType::Never {
+            self
+        } else if self == ValueType::Never {
+            other
+        } else {
+            ValueType::Any
+        }
+    }
+
+    pub fn is_number(self) -> bool {
+        match self {
+            ValueType::Integer | ValueType::Float => true,
+            _ => false,
+        }
+    }
+
+    /// Whether values of this type are references to objects which may be on
+    /// the heap
+    pub fn is_reference(self) -> bool {
+        match self {
+            ValueType::String
+            | ValueType::Keyword
+            | ValueType::Vector
+            | ValueType::Map
+            | ValueType::Set
+            | ValueType::Function
+            | ValueType::Any => true,
+            _ => false,
+        }
+    }
+
+    pub fn machine_type(self, memory: Memory) -> Types {
+        match self {
+            ValueType::Integer => Types::I64,
+            ValueType::Float => Types::F64,
+            ValueType::String
+            | ValueType::Keyword
+            | ValueType::Vector
+            | ValueType::Map
+            | ValueType::Set
+            | ValueType::Function
+            | ValueType::Any
+                if memory == Memory::Engine =>
+            {
+                Types::EqRef
+            }
+            _ => Types::I32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::types::ValueType;
+
+    #[test]
+    fn unify_keeps_a_single_type() {
+        assert_eq!(
+            ValueType::String.unify(ValueType::String),
+            ValueType::String
+        );
+        assert_eq!(ValueType::Nil.unify(ValueType::String), ValueType::Any);
+        assert_eq!(ValueType::Integer.unify(ValueType::Float), ValueType::Any);
+        assert_eq!(ValueType::Any.unify(ValueType::Float), ValueType::Any);
+        assert_eq!(ValueType::Never.unify(ValueType::Float), ValueType::Float);
+        assert_eq!(ValueType::Nil.unify(ValueType::Never), ValueType::Nil);
+    }
+}
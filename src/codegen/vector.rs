@@ -0,0 +1,1347 @@
+use crate::codegen::instructions::{BlockType, HeapType, Opcodes, Types};
+use crate::codegen::map;
+use crate::codegen::module::{Function, Memory};
+use crate::codegen::runtime::{reference, string_length, unbox, Runtime, ALLOCATE, NIL};
+use crate::codegen::set;
+use crate::codegen::types::Tag;
+
+/// Every node of a trie has 32 slots, each level of the trie being indexed by
+/// the next 5 bits of an index
+const BITS: i32 = 5;
+const BRANCHING: i32 = 1 << BITS;
+const MASK: i32 = BRANCHING - 1;
+/// In linear memory, the slots of a node follow its tag at offset 8
+const SLOTS: u32 = 8;
+const NODE_SIZE: i32 = SLOTS as i32 + 4 * BRANCHING;
+/// In linear memory, the fields of a vector follow its tag
+const VECTOR_SIZE: i32 = 24;
+
+/// Fields of a vector, which is a persistent trie of 32-way nodes whose last
+/// elements are kept in a tail node outside of it, as in Clojure. Vectors
+/// returned by `subvec` share the trie and the tail of the vector they are
+/// taken from, so the elements of a vector are those from `Start` up to
+/// `Size` in its trie, and the slots past them are never read.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Field {
+    /// Index past the last element in the trie and its tail
+    Size,
+    /// Index in the trie of the first element
+    Start,
+    /// Bits an index is shifted right by to get its slot in the root
+    Shift,
+    Root,
+    Tail,
+}
+
+impl Field {
+    /// Offset of the field in linear memory, past the tag
+    fn offset(self) -> u32 {
+        4 + 4 * self as u32
+    }
+}
+
+fn field(vector: usize, field: Field, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => vec![
+            LocalGet(vector),
+            RefCast(HeapType::Vector),
+            StructGet(HeapType::Vector, field as u32),
+        ],
+        _ => vec![LocalGet(vector), I32Load(field.offset())],
+    }
+}
+
+/// Number of elements of the vector in `vector`
+fn count(vector: usize, memory: Memory) -> Vec<Opcodes> {
+    [
+        field(vector, Field::Size, memory),
+        field(vector, Field::Start, memory),
+        vec![Opcodes::I32Sub],
+    ]
+    .concat()
+}
+
+/// The reference in the slot at `index` of `node`
+fn slot(node: Vec<Opcodes>, index: Vec<Opcodes>, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => [
+            node,
+            vec![RefCast(HeapType::Node)],
+            index,
+            vec![ArrayGet(HeapType::Node)],
+        ]
+        .concat(),
+        _ => [
+            node,
+            index,
+            vec![I32Const(2), I32Shl, I32Add, I32Load(SLOTS)],
+        ]
+        .concat(),
+    }
+}
+
+fn set_slot(
+    node: Vec<Opcodes>,
+    index: Vec<Opcodes>,
+    value: Vec<Opcodes>,
+    memory: Memory,
+) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    match memory {
+        Memory::Engine => [
+            node,
+            vec![RefCast(HeapType::Node)],
+            index,
+            value,
+            vec![ArraySet(HeapType::Node)],
+        ]
+        .concat(),
+        _ => [
+            node,
+            index,
+            vec![I32Const(2), I32Shl, I32Add],
+            value,
+            vec![I32Store(SLOTS)],
+        ]
+        .concat(),
+    }
+}
+
+/// Slot of a node at `level` on the path to the element at `index`
+fn slot_index(index: Vec<Opcodes>, level: Vec<Opcodes>) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    [index, level, vec![I32ShrU, I32Const(MASK), I32And]].concat()
+}
+
+/// The element at the index in the trie of `vector` given by `position`
+fn element(vector: usize, position: Vec<Opcodes>, memory: Memory) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    slot(
+        [
+            vec![LocalGet(vector)],
+            position.clone(),
+            vec![call(Runtime::ArrayFor)],
+        ]
+        .concat(),
+        [position, vec![I32Const(MASK), I32And]].concat(),
+        memory,
+    )
+}
+
+/// Empty slots hold nil, which is a null reference when the engine manages
+/// memory
+fn null(memory: Memory) -> Opcodes {
+    match memory {
+        Memory::Engine => Opcodes::RefNull(HeapType::Eq),
+        _ => Opcodes::I32Const(NIL),
+    }
+}
+
+fn is_null(memory: Memory) -> Opcodes {
+    match memory {
+        Memory::Engine => Opcodes::RefIsNull,
+        _ => Opcodes::I32Eqz,
+    }
+}
+
+/// Index in the trie of the first element in the tail, for a vector of the
+/// size in `size`. It is the index of the last element with its slot bits
+/// cleared, or 0 for an empty vector.
+fn tail_offset(size: usize) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(size),
+        I32Const(1),
+        I32Sub,
+        I32Const(BITS),
+        I32ShrU,
+        I32Const(BITS),
+        I32Shl,
+        I32Const(0),
+        LocalGet(size),
+        Select,
+    ]
+}
+
+/// Runs `body` for every slot index of a node, which is held in `index`
+fn for_each_slot(index: usize, mut body: Vec<Opcodes>) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    let mut instructions = vec![
+        I32Const(0),
+        LocalSet(index),
+        Block(BlockType::Empty),
+        Loop(BlockType::Empty),
+        LocalGet(index),
+        I32Const(BRANCHING),
+        I32Eq,
+        BrIf(1),
+    ];
+    instructions.append(body.as_mut());
+    instructions.append(
+        vec![
+            LocalGet(index),
+            I32Const(1),
+            I32Add,
+            LocalSet(index),
+            Br(0),
+            End,
+            End,
+        ]
+        .as_mut(),
+    );
+    instructions
+}
+
+/// Runs `before` and then `after` for every element of the vector in
+/// `vector`, whose index in the trie is held in `position`. The element is
+/// on the stack between the two, which `after` consumes.
+pub fn for_each_element(
+    vector: usize,
+    position: usize,
+    before: Vec<Opcodes>,
+    after: Vec<Opcodes>,
+    memory: Memory,
+) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    [
+        field(vector, Field::Start, memory),
+        vec![
+            LocalSet(position),
+            Block(BlockType::Empty),
+            Loop(BlockType::Empty),
+            LocalGet(position),
+        ],
+        field(vector, Field::Size, memory),
+        vec![I32Eq, BrIf(1)],
+        before,
+        element(vector, vec![LocalGet(position)], memory),
+        after,
+        vec![
+            LocalGet(position),
+            I32Const(1),
+            I32Add,
+            LocalSet(position),
+            Br(0),
+            End,
+            End,
+        ],
+    ]
+    .concat()
+}
+
+/// Whether the vectors in `left` and `right` have as many elements
+pub fn same_count(left: usize, right: usize, memory: Memory) -> Vec<Opcodes> {
+    [
+        count(left, memory),
+        count(right, memory),
+        vec![Opcodes::I32Eq],
+    ]
+    .concat()
+}
+
+/// Whether the index in the trie of the vector in `vector` held in
+/// `position` is that of its first element
+pub fn is_first(vector: usize, position: usize, memory: Memory) -> Vec<Opcodes> {
+    [
+        vec![Opcodes::LocalGet(position)],
+        field(vector, Field::Start, memory),
+        vec![Opcodes::I32Eq],
+    ]
+    .concat()
+}
+
+/// The element of the vector in `right` matching the one of the vector in
+/// `left` at the index in its trie held in `position`, both vectors having as
+/// many elements
+pub fn matching_element(
+    left: usize,
+    right: usize,
+    position: usize,
+    memory: Memory,
+) -> Vec<Opcodes> {
+    let position = [
+        vec![Opcodes::LocalGet(position)],
+        field(left, Field::Start, memory),
+        vec![Opcodes::I32Sub],
+        field(right, Field::Start, memory),
+        vec![Opcodes::I32Add],
+    ]
+    .concat();
+    element(right, position, memory)
+}
+
+/// Calls `mark` on the references held by the object in `object` when it is
+/// a vector or a node, whose tag is in `tag`. Every slot of a node is marked,
+/// including those past the elements of the vectors sharing it, so that they
+/// never refer to freed blocks.
+pub fn mark_references(object: usize, tag: usize, index: usize, mark: Opcodes) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    [
+        vec![
+            LocalGet(tag),
+            I32Const(Tag::Vector as i32),
+            I32Eq,
+            If(BlockType::Empty),
+        ],
+        field(object, Field::Root, Memory::Collected),
+        vec![mark.clone()],
+        field(object, Field::Tail, Memory::Collected),
+        vec![
+            mark.clone(),
+            End,
+            LocalGet(tag),
+            I32Const(Tag::Node as i32),
+            I32Eq,
+            If(BlockType::Empty),
+        ],
+        for_each_slot(
+            index,
+            [
+                slot(
+                    vec![LocalGet(object)],
+                    vec![LocalGet(index)],
+                    Memory::Collected,
+                ),
+                vec![mark],
+            ]
+            .concat(),
+        ),
+        vec![End],
+    ]
+    .concat()
+}
+
+/// Raises a ClassCastException unless `local` holds a vector
+fn expect_vector(local: usize) -> Vec<Opcodes> {
+    use Opcodes::*;
+
+    vec![
+        LocalGet(local),
+        call(Runtime::TypeOf),
+        I32Const(Tag::Vector as i32),
+        I32Ne,
+        If(BlockType::Empty),
+        call(Runtime::NotAVector),
+        End,
+    ]
+}
+
+fn call(runtime: Runtime) -> Opcodes {
+    Opcodes::Call(runtime.name().to_owned())
+}
+
+/// Returns a node whose slots all hold nil
+pub fn new_node(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const INDEX: usize = 1;
+    use Opcodes::*;
+
+    if memory == Memory::Engine {
+        return Function {
+            name: name.to_owned(),
+            params: vec![],
+            results: vec![Types::EqRef],
+            locals: vec![],
+            body: vec![I32Const(BRANCHING), ArrayNewDefault(HeapType::Node)],
+        };
+    }
+    let mut body = vec![
+        I32Const(NODE_SIZE),
+        Call(ALLOCATE.to_owned()),
+        LocalTee(NODE),
+        I32Const(Tag::Node as i32),
+        I32Store(0),
+    ];
+    body.append(
+        for_each_slot(
+            INDEX,
+            set_slot(
+                vec![LocalGet(NODE)],
+                vec![LocalGet(INDEX)],
+                vec![null(memory)],
+                memory,
+            ),
+        )
+        .as_mut(),
+    );
+    body.push(LocalGet(NODE));
+
+    Function {
+        name: name.to_owned(),
+        params: vec![],
+        results: vec![Types::I32],
+        locals: vec![Types::I32; 2],
+        body,
+    }
+}
+
+/// Returns a new node holding the same references as its argument
+pub fn copy_node(name: &str, memory: Memory) -> Function {
+    const NODE: usize = 0;
+    const COPY: usize = 1;
+    const INDEX: usize = 2;
+    use Opcodes::*;
+
+    let mut body = vec![call(Runtime::NewNode), LocalSet(COPY)];
+    body.append(
+        for_each_slot(
+            INDEX,
+            set_slot(
+                vec![LocalGet(COPY)],
+                vec![LocalGet(INDEX)],
+                slot(vec![LocalGet(NODE)], vec![LocalGet(INDEX)], memory),
+                memory,
+            ),
+        )
+        .as_mut(),
+    );
+    body.push(LocalGet(COPY));
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![reference(memory), Types::I32],
+        body,
+    }
+}
+
+/// Returns a vector with the fields given as arguments, in the order of
+/// `Field`
+pub fn make_vector(name: &str, memory: Memory) -> Function {
+    const VECTOR: usize = 5;
+    use Opcodes::*;
+
+    let fields = [
+        Field::Size,
+        Field::Start,
+        Field::Shift,
+        Field::Root,
+        Field::Tail,
+    ];
+    let params = vec![
+        Types::I32,
+        Types::I32,
+        Types::I32,
+        reference(memory),
+        reference(memory),
+    ];
+    if memory == Memory::Engine {
+        let mut body: Vec<Opcodes> = (0..fields.len()).map(LocalGet).collect();
+        body.push(StructNew(HeapType::Vector));
+        return Function {
+            name: name.to_owned(),
+            params,
+            results: vec![Types::EqRef],
+            locals: vec![],
+            body,
+        };
+    }
+    let mut body = vec![
+        I32Const(VECTOR_SIZE),
+        Call(ALLOCATE.to_owned()),
+        LocalTee(VECTOR),
+        I32Const(Tag::Vector as i32),
+        I32Store(0),
+    ];
+    for (index, field) in fields.iter().enumerate() {
+        body.append(vec![LocalGet(VECTOR), LocalGet(index), I32Store(field.offset())].as_mut());
+    }
+    body.push(LocalGet(VECTOR));
+
+    Function {
+        name: name.to_owned(),
+        params,
+        results: vec![Types::I32],
+        locals: vec![Types::I32],
+        body,
+    }
+}
+
+pub fn empty_vector(name: &str, memory: Memory) -> Function {
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![],
+        results: vec![reference(memory)],
+        locals: vec![],
+        body: vec![
+            I32Const(0),
+            I32Const(0),
+            I32Const(BITS),
+            call(Runtime::NewNode),
+            call(Runtime::NewNode),
+            call(Runtime::MakeVector),
+        ],
+    }
+}
+
+/// Returns the node holding the element at the given index in the trie of a
+/// vector, which is either its tail or a leaf of its trie
+pub fn array_for(name: &str, memory: Memory) -> Function {
+    const VECTOR: usize = 0;
+    const POSITION: usize = 1;
+    const SIZE: usize = 2;
+    const NODE: usize = 3;
+    const LEVEL: usize = 4;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), Types::I32],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32, reference(memory), Types::I32],
+        body: [
+            field(VECTOR, Field::Size, memory),
+            vec![LocalSet(SIZE), LocalGet(POSITION)],
+            tail_offset(SIZE),
+            vec![I32GeU, If(BlockType::Empty)],
+            field(VECTOR, Field::Tail, memory),
+            vec![Return, End],
+            field(VECTOR, Field::Root, memory),
+            vec![LocalSet(NODE)],
+            field(VECTOR, Field::Shift, memory),
+            vec![
+                LocalSet(LEVEL),
+                Block(BlockType::Empty),
+                Loop(BlockType::Empty),
+                LocalGet(LEVEL),
+                I32Eqz,
+                BrIf(1),
+            ],
+            slot(
+                vec![LocalGet(NODE)],
+                slot_index(vec![LocalGet(POSITION)], vec![LocalGet(LEVEL)]),
+                memory,
+            ),
+            vec![
+                LocalSet(NODE),
+                LocalGet(LEVEL),
+                I32Const(BITS),
+                I32Sub,
+                LocalSet(LEVEL),
+                Br(0),
+                End,
+                End,
+                LocalGet(NODE),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Number of elements of nil, a string, a vector, a map or a set as an i64,
+/// raising an UnsupportedOperationException for other values
+pub fn count_items(name: &str, memory: Memory) -> Function {
+    const COLLECTION: usize = 0;
+    const TAG: usize = 1;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![Types::I64],
+        locals: vec![Types::I32],
+        body: [
+            set::unwrap(COLLECTION, memory),
+            vec![
+                LocalGet(COLLECTION),
+                call(Runtime::TypeOf),
+                LocalTee(TAG),
+                I32Const(Tag::Nil as i32),
+                I32Eq,
+                If(BlockType::Empty),
+                I64Const(0),
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::String as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            string_length(COLLECTION, memory),
+            vec![
+                I64ExtendI32S,
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Vector as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            count(COLLECTION, memory),
+            vec![
+                I64ExtendI32S,
+                Return,
+                End,
+                LocalGet(TAG),
+                I32Const(Tag::Map as i32),
+                I32Eq,
+                If(BlockType::Empty),
+            ],
+            map::count(COLLECTION, memory),
+            vec![
+                I64ExtendI32S,
+                Return,
+                End,
+                call(Runtime::CountNotSupported),
+                Unreachable,
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// The element of a vector at an i64 index. Out of range indices give the
+/// third argument when the fourth one is non zero, and raise an
+/// IndexOutOfBoundsException otherwise. Nil has no elements.
+pub fn nth(name: &str, memory: Memory) -> Function {
+    const VECTOR: usize = 0;
+    const INDEX: usize = 1;
+    const NOT_FOUND: usize = 2;
+    const DEFAULTED: usize = 3;
+    const POSITION: usize = 4;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), Types::I64, reference(memory), Types::I32],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32],
+        body: [
+            vec![
+                LocalGet(VECTOR),
+                call(Runtime::TypeOf),
+                I32Const(Tag::Nil as i32),
+                I32Eq,
+                If(BlockType::Empty),
+                LocalGet(NOT_FOUND),
+                Return,
+                End,
+            ],
+            expect_vector(VECTOR),
+            vec![LocalGet(INDEX)],
+            count(VECTOR, memory),
+            vec![
+                I64ExtendI32S,
+                I64GeU,
+                If(BlockType::Empty),
+                LocalGet(DEFAULTED),
+                If(BlockType::Empty),
+                LocalGet(NOT_FOUND),
+                Return,
+                End,
+                call(Runtime::IndexOutOfBounds),
+                End,
+            ],
+            field(VECTOR, Field::Start, memory),
+            vec![LocalGet(INDEX), I32WrapI64, I32Add, LocalSet(POSITION)],
+            element(VECTOR, vec![LocalGet(POSITION)], memory),
+        ]
+        .concat(),
+    }
+}
+
+/// The value of a map at a key, the element of a set equal to a key or the
+/// element of a vector at an integer key, or the third argument when the
+/// first one is none of them or does not have the key
+pub fn get(name: &str, memory: Memory) -> Function {
+    const VECTOR: usize = 0;
+    const KEY: usize = 1;
+    const NOT_FOUND: usize = 2;
+    const POSITION: usize = 3;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 3],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32],
+        body: [
+            set::unwrap(VECTOR, memory),
+            vec![
+                LocalGet(VECTOR),
+                call(Runtime::TypeOf),
+                I32Const(Tag::Map as i32),
+                I32Eq,
+                If(BlockType::Empty),
+                LocalGet(VECTOR),
+                LocalGet(KEY),
+                LocalGet(NOT_FOUND),
+                call(Runtime::MapGet),
+                Return,
+                End,
+                LocalGet(VECTOR),
+                call(Runtime::TypeOf),
+                I32Const(Tag::Vector as i32),
+                I32Eq,
+                LocalGet(KEY),
+                call(Runtime::TypeOf),
+                I32Const(Tag::Integer as i32),
+                I32Eq,
+                I32And,
+                I32Eqz,
+                If(BlockType::Empty),
+                LocalGet(NOT_FOUND),
+                Return,
+                End,
+            ],
+            unbox(KEY, Types::I64, memory),
+            count(VECTOR, memory),
+            vec![
+                I64ExtendI32S,
+                I64GeU,
+                If(BlockType::Empty),
+                LocalGet(NOT_FOUND),
+                Return,
+                End,
+            ],
+            field(VECTOR, Field::Start, memory),
+            unbox(KEY, Types::I64, memory),
+            vec![I32WrapI64, I32Add, LocalSet(POSITION)],
+            element(VECTOR, vec![LocalGet(POSITION)], memory),
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a vector with the second argument added at the end of the first
+/// one. Only the tail is copied while it has room, otherwise it moves into
+/// the trie along the path copied by `push_tail`, and the root moves one
+/// level down when the trie is full.
+pub fn conj(name: &str, memory: Memory) -> Function {
+    const VECTOR: usize = 0;
+    const VALUE: usize = 1;
+    const SIZE: usize = 2;
+    const SHIFT: usize = 3;
+    const ROOT: usize = 4;
+    const TAIL: usize = 5;
+    const NODE: usize = 6;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory); 2],
+        results: vec![reference(memory)],
+        locals: vec![
+            Types::I32,
+            Types::I32,
+            reference(memory),
+            reference(memory),
+            reference(memory),
+        ],
+        body: [
+            expect_vector(VECTOR),
+            field(VECTOR, Field::Size, memory),
+            vec![LocalSet(SIZE)],
+            field(VECTOR, Field::Shift, memory),
+            vec![LocalSet(SHIFT)],
+            field(VECTOR, Field::Root, memory),
+            vec![LocalSet(ROOT), LocalGet(SIZE)],
+            tail_offset(SIZE),
+            vec![I32Sub, I32Const(BRANCHING), I32LtS, If(BlockType::Empty)],
+            field(VECTOR, Field::Tail, memory),
+            vec![call(Runtime::CopyNode), LocalSet(TAIL)],
+            set_slot(
+                vec![LocalGet(TAIL)],
+                vec![LocalGet(SIZE), I32Const(MASK), I32And],
+                vec![LocalGet(VALUE)],
+                memory,
+            ),
+            vec![
+                Else,
+                LocalGet(SIZE),
+                I32Const(BITS),
+                I32ShrU,
+                I32Const(1),
+                LocalGet(SHIFT),
+                I32Shl,
+                I32GtU,
+                If(BlockType::Empty),
+                call(Runtime::NewNode),
+                LocalSet(NODE),
+            ],
+            set_slot(
+                vec![LocalGet(NODE)],
+                vec![I32Const(0)],
+                vec![LocalGet(ROOT)],
+                memory,
+            ),
+            set_slot(
+                vec![LocalGet(NODE)],
+                vec![I32Const(1)],
+                [
+                    vec![LocalGet(SHIFT)],
+                    field(VECTOR, Field::Tail, memory),
+                    vec![call(Runtime::NewPath)],
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![
+                LocalGet(NODE),
+                LocalSet(ROOT),
+                LocalGet(SHIFT),
+                I32Const(BITS),
+                I32Add,
+                LocalSet(SHIFT),
+                Else,
+                LocalGet(SIZE),
+                LocalGet(SHIFT),
+                LocalGet(ROOT),
+            ],
+            field(VECTOR, Field::Tail, memory),
+            vec![
+                call(Runtime::PushTail),
+                LocalSet(ROOT),
+                End,
+                call(Runtime::NewNode),
+                LocalSet(TAIL),
+            ],
+            set_slot(
+                vec![LocalGet(TAIL)],
+                vec![I32Const(0)],
+                vec![LocalGet(VALUE)],
+                memory,
+            ),
+            vec![End, LocalGet(SIZE), I32Const(1), I32Add],
+            field(VECTOR, Field::Start, memory),
+            vec![
+                LocalGet(SHIFT),
+                LocalGet(ROOT),
+                LocalGet(TAIL),
+                call(Runtime::MakeVector),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a node holding the second argument at the end of a path of new
+/// nodes as deep as the given level
+pub fn new_path(name: &str, memory: Memory) -> Function {
+    const LEVEL: usize = 0;
+    const NODE: usize = 1;
+    const PATH: usize = 2;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32, reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![reference(memory)],
+        body: [
+            vec![
+                LocalGet(LEVEL),
+                I32Eqz,
+                If(BlockType::Empty),
+                LocalGet(NODE),
+                Return,
+                End,
+                call(Runtime::NewNode),
+                LocalSet(PATH),
+            ],
+            set_slot(
+                vec![LocalGet(PATH)],
+                vec![I32Const(0)],
+                vec![
+                    LocalGet(LEVEL),
+                    I32Const(BITS),
+                    I32Sub,
+                    LocalGet(NODE),
+                    Call(name.to_owned()),
+                ],
+                memory,
+            ),
+            vec![LocalGet(PATH)],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a copy of the node at the given level whose descendants hold the
+/// full tail of a vector of the given size as their last leaf, copying the
+/// path down to it
+pub fn push_tail(name: &str, memory: Memory) -> Function {
+    const SIZE: usize = 0;
+    const LEVEL: usize = 1;
+    const PARENT: usize = 2;
+    const TAIL: usize = 3;
+    const INDEX: usize = 4;
+    const COPY: usize = 5;
+    const CHILD: usize = 6;
+    use Opcodes::*;
+
+    let child_level = vec![LocalGet(LEVEL), I32Const(BITS), I32Sub];
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32, Types::I32, reference(memory), reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32, reference(memory), reference(memory)],
+        body: [
+            slot_index(
+                vec![LocalGet(SIZE), I32Const(1), I32Sub],
+                vec![LocalGet(LEVEL)],
+            ),
+            vec![
+                LocalSet(INDEX),
+                LocalGet(PARENT),
+                call(Runtime::CopyNode),
+                LocalSet(COPY),
+            ],
+            set_slot(
+                vec![LocalGet(COPY)],
+                vec![LocalGet(INDEX)],
+                [
+                    vec![
+                        LocalGet(LEVEL),
+                        I32Const(BITS),
+                        I32Eq,
+                        If(BlockType::Value(reference(memory))),
+                        LocalGet(TAIL),
+                        Else,
+                    ],
+                    slot(vec![LocalGet(PARENT)], vec![LocalGet(INDEX)], memory),
+                    vec![
+                        LocalTee(CHILD),
+                        is_null(memory),
+                        If(BlockType::Value(reference(memory))),
+                    ],
+                    child_level.clone(),
+                    vec![LocalGet(TAIL), call(Runtime::NewPath), Else, LocalGet(SIZE)],
+                    child_level,
+                    vec![
+                        LocalGet(CHILD),
+                        LocalGet(TAIL),
+                        Call(name.to_owned()),
+                        End,
+                        End,
+                    ],
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![LocalGet(COPY)],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a vector with the element at an i64 index replaced by the third
+/// argument, copying the path down to it. The index past the last element
+/// adds the argument like `conj` does.
+pub fn assoc(name: &str, memory: Memory) -> Function {
+    const VECTOR: usize = 0;
+    const INDEX: usize = 1;
+    const VALUE: usize = 2;
+    const SIZE: usize = 3;
+    const POSITION: usize = 4;
+    const TAIL: usize = 5;
+    use Opcodes::*;
+
+    let count = [count(VECTOR, memory), vec![I64ExtendI32S]].concat();
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), Types::I64, reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32, Types::I32, reference(memory)],
+        body: [
+            expect_vector(VECTOR),
+            vec![LocalGet(INDEX)],
+            count.clone(),
+            vec![
+                I64Eq,
+                If(BlockType::Empty),
+                LocalGet(VECTOR),
+                LocalGet(VALUE),
+                call(Runtime::VectorConj),
+                Return,
+                End,
+                LocalGet(INDEX),
+            ],
+            count,
+            vec![
+                I64GeU,
+                If(BlockType::Empty),
+                call(Runtime::IndexOutOfBounds),
+                End,
+            ],
+            field(VECTOR, Field::Size, memory),
+            vec![LocalSet(SIZE)],
+            field(VECTOR, Field::Start, memory),
+            vec![LocalGet(INDEX), I32WrapI64, I32Add, LocalTee(POSITION)],
+            tail_offset(SIZE),
+            vec![I32GeU, If(BlockType::Empty)],
+            field(VECTOR, Field::Tail, memory),
+            vec![call(Runtime::CopyNode), LocalSet(TAIL)],
+            set_slot(
+                vec![LocalGet(TAIL)],
+                vec![LocalGet(POSITION), I32Const(MASK), I32And],
+                vec![LocalGet(VALUE)],
+                memory,
+            ),
+            vec![LocalGet(SIZE)],
+            field(VECTOR, Field::Start, memory),
+            field(VECTOR, Field::Shift, memory),
+            field(VECTOR, Field::Root, memory),
+            vec![
+                LocalGet(TAIL),
+                call(Runtime::MakeVector),
+                Return,
+                End,
+                LocalGet(SIZE),
+            ],
+            field(VECTOR, Field::Start, memory),
+            field(VECTOR, Field::Shift, memory),
+            field(VECTOR, Field::Shift, memory),
+            field(VECTOR, Field::Root, memory),
+            vec![LocalGet(POSITION), LocalGet(VALUE), call(Runtime::DoAssoc)],
+            field(VECTOR, Field::Tail, memory),
+            vec![call(Runtime::MakeVector)],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a copy of the node at the given level with the element at the
+/// given index in its descendants replaced by the last argument
+pub fn do_assoc(name: &str, memory: Memory) -> Function {
+    const LEVEL: usize = 0;
+    const NODE: usize = 1;
+    const POSITION: usize = 2;
+    const VALUE: usize = 3;
+    const COPY: usize = 4;
+    const INDEX: usize = 5;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32, reference(memory), Types::I32, reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![reference(memory), Types::I32],
+        body: [
+            vec![
+                LocalGet(NODE),
+                call(Runtime::CopyNode),
+                LocalSet(COPY),
+                LocalGet(LEVEL),
+                I32Eqz,
+                If(BlockType::Empty),
+            ],
+            set_slot(
+                vec![LocalGet(COPY)],
+                vec![LocalGet(POSITION), I32Const(MASK), I32And],
+                vec![LocalGet(VALUE)],
+                memory,
+            ),
+            vec![Else],
+            slot_index(vec![LocalGet(POSITION)], vec![LocalGet(LEVEL)]),
+            vec![LocalSet(INDEX)],
+            set_slot(
+                vec![LocalGet(COPY)],
+                vec![LocalGet(INDEX)],
+                [
+                    vec![LocalGet(LEVEL), I32Const(BITS), I32Sub],
+                    slot(vec![LocalGet(NODE)], vec![LocalGet(INDEX)], memory),
+                    vec![LocalGet(POSITION), LocalGet(VALUE), Call(name.to_owned())],
+                ]
+                .concat(),
+                memory,
+            ),
+            vec![End, LocalGet(COPY)],
+        ]
+        .concat(),
+    }
+}
+
+/// The last element of a vector, or nil when it is empty or nil itself
+pub fn peek(name: &str, memory: Memory) -> Function {
+    const VECTOR: usize = 0;
+    const POSITION: usize = 1;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32],
+        body: [
+            vec![
+                LocalGet(VECTOR),
+                call(Runtime::TypeOf),
+                I32Const(Tag::Nil as i32),
+                I32Eq,
+                If(BlockType::Empty),
+                null(memory),
+                Return,
+                End,
+            ],
+            expect_vector(VECTOR),
+            count(VECTOR, memory),
+            vec![I32Eqz, If(BlockType::Empty), null(memory), Return, End],
+            field(VECTOR, Field::Size, memory),
+            vec![I32Const(1), I32Sub, LocalSet(POSITION)],
+            element(VECTOR, vec![LocalGet(POSITION)], memory),
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a vector without the last element of its argument, raising an
+/// IllegalStateException when it is empty. The tail is shared while it holds
+/// other elements, otherwise the last leaf of the trie becomes the tail and
+/// the root moves one level up when it is left with a single child.
+pub fn pop(name: &str, memory: Memory) -> Function {
+    const VECTOR: usize = 0;
+    const SIZE: usize = 1;
+    const SHIFT: usize = 2;
+    const ROOT: usize = 3;
+    const TAIL: usize = 4;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32, Types::I32, reference(memory), reference(memory)],
+        body: [
+            expect_vector(VECTOR),
+            field(VECTOR, Field::Size, memory),
+            vec![LocalSet(SIZE)],
+            field(VECTOR, Field::Shift, memory),
+            vec![LocalSet(SHIFT)],
+            count(VECTOR, memory),
+            vec![I32Eqz, If(BlockType::Empty), call(Runtime::PopEmpty), End],
+            count(VECTOR, memory),
+            vec![
+                I32Const(1),
+                I32Eq,
+                If(BlockType::Empty),
+                call(Runtime::EmptyVector),
+                Return,
+                End,
+                LocalGet(SIZE),
+            ],
+            tail_offset(SIZE),
+            vec![
+                I32Sub,
+                I32Const(1),
+                I32GtS,
+                If(BlockType::Empty),
+                LocalGet(SIZE),
+                I32Const(1),
+                I32Sub,
+            ],
+            field(VECTOR, Field::Start, memory),
+            vec![LocalGet(SHIFT)],
+            field(VECTOR, Field::Root, memory),
+            field(VECTOR, Field::Tail, memory),
+            vec![
+                call(Runtime::MakeVector),
+                Return,
+                End,
+                LocalGet(VECTOR),
+                LocalGet(SIZE),
+                I32Const(2),
+                I32Sub,
+                call(Runtime::ArrayFor),
+                LocalSet(TAIL),
+                LocalGet(SIZE),
+                LocalGet(SHIFT),
+            ],
+            field(VECTOR, Field::Root, memory),
+            vec![
+                call(Runtime::PopTail),
+                LocalTee(ROOT),
+                is_null(memory),
+                If(BlockType::Empty),
+                call(Runtime::NewNode),
+                LocalSet(ROOT),
+                End,
+                LocalGet(SHIFT),
+                I32Const(BITS),
+                I32GtU,
+            ],
+            slot(vec![LocalGet(ROOT)], vec![I32Const(1)], memory),
+            vec![is_null(memory), I32And, If(BlockType::Empty)],
+            slot(vec![LocalGet(ROOT)], vec![I32Const(0)], memory),
+            vec![
+                LocalSet(ROOT),
+                LocalGet(SHIFT),
+                I32Const(BITS),
+                I32Sub,
+                LocalSet(SHIFT),
+                End,
+                LocalGet(SIZE),
+                I32Const(1),
+                I32Sub,
+            ],
+            field(VECTOR, Field::Start, memory),
+            vec![
+                LocalGet(SHIFT),
+                LocalGet(ROOT),
+                LocalGet(TAIL),
+                call(Runtime::MakeVector),
+            ],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns a copy of the node at the given level without the last leaf of a
+/// vector of the given size in its descendants, or nil when nothing is left
+pub fn pop_tail(name: &str, memory: Memory) -> Function {
+    const SIZE: usize = 0;
+    const LEVEL: usize = 1;
+    const NODE: usize = 2;
+    const INDEX: usize = 3;
+    const CHILD: usize = 4;
+    const COPY: usize = 5;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![Types::I32, Types::I32, reference(memory)],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32, reference(memory), reference(memory)],
+        body: [
+            slot_index(
+                vec![LocalGet(SIZE), I32Const(2), I32Sub],
+                vec![LocalGet(LEVEL)],
+            ),
+            vec![
+                LocalSet(INDEX),
+                LocalGet(LEVEL),
+                I32Const(BITS),
+                I32GtU,
+                If(BlockType::Empty),
+                LocalGet(SIZE),
+                LocalGet(LEVEL),
+                I32Const(BITS),
+                I32Sub,
+            ],
+            slot(vec![LocalGet(NODE)], vec![LocalGet(INDEX)], memory),
+            vec![
+                Call(name.to_owned()),
+                LocalTee(CHILD),
+                is_null(memory),
+                LocalGet(INDEX),
+                I32Eqz,
+                I32And,
+                If(BlockType::Empty),
+                null(memory),
+                Return,
+                End,
+                LocalGet(NODE),
+                call(Runtime::CopyNode),
+                LocalSet(COPY),
+            ],
+            set_slot(
+                vec![LocalGet(COPY)],
+                vec![LocalGet(INDEX)],
+                vec![LocalGet(CHILD)],
+                memory,
+            ),
+            vec![
+                LocalGet(COPY),
+                Return,
+                End,
+                LocalGet(INDEX),
+                I32Eqz,
+                If(BlockType::Empty),
+                null(memory),
+                Return,
+                End,
+                LocalGet(NODE),
+                call(Runtime::CopyNode),
+                LocalSet(COPY),
+            ],
+            set_slot(
+                vec![LocalGet(COPY)],
+                vec![LocalGet(INDEX)],
+                vec![null(memory)],
+                memory,
+            ),
+            vec![LocalGet(COPY)],
+        ]
+        .concat(),
+    }
+}
+
+/// Returns the elements of a vector from the first i64 index up to the
+/// second one, raising an IndexOutOfBoundsException unless both are in
+/// range. The result shares the trie and the tail of its argument, and its
+/// tail is the leaf holding its last element when that is in the trie.
+pub fn subvec(name: &str, memory: Memory) -> Function {
+    const VECTOR: usize = 0;
+    const FROM: usize = 1;
+    const TO: usize = 2;
+    const SIZE: usize = 3;
+    const END: usize = 4;
+    use Opcodes::*;
+
+    Function {
+        name: name.to_owned(),
+        params: vec![reference(memory), Types::I64, Types::I64],
+        results: vec![reference(memory)],
+        locals: vec![Types::I32; 2],
+        body: [
+            expect_vector(VECTOR),
+            vec![LocalGet(TO)],
+            count(VECTOR, memory),
+            vec![
+                I64ExtendI32S,
+                I64GtU,
+                LocalGet(FROM),
+                LocalGet(TO),
+                I64GtU,
+                I32Add,
+                If(BlockType::Empty),
+                call(Runtime::IndexOutOfBounds),
+                End,
+                LocalGet(FROM),
+                LocalGet(TO),
+                I64Eq,
+                If(BlockType::Empty),
+                call(Runtime::EmptyVector),
+                Return,
+                End,
+            ],
+            field(VECTOR, Field::Size, memory),
+            vec![LocalSet(SIZE)],
+            field(VECTOR, Field::Start, memory),
+            vec![LocalGet(TO), I32WrapI64, I32Add, LocalTee(END)],
+            field(VECTOR, Field::Start, memory),
+            vec![LocalGet(FROM), I32WrapI64, I32Add],
+            field(VECTOR, Field::Shift, memory),
+            field(VECTOR, Field::Root, memory),
+            vec![LocalGet(END)],
+            tail_offset(SIZE),
+            vec![I32GtU, If(BlockType::Value(reference(memory)))],
+            field(VECTOR, Field::Tail, memory),
+            vec![
+                Else,
+                LocalGet(VECTOR),
+                LocalGet(END),
+                I32Const(1),
+                I32Sub,
+                call(Runtime::ArrayFor),
+                End,
+                call(Runtime::MakeVector),
+            ],
+        ]
+        .concat(),
+    }
+}
@@ -0,0 +1,375 @@
+use crate::frontend::ast::{
+    CaseDetails, CondpDetails, FunctionDetails, IfDetails, KeywordDetails, LambdaDetails,
+    LetDetails, ListDetails, MainDetails, Node,
+};
+use crate::frontend::scanner::{Lexeme, Position};
+
+/// Names the body of an anonymous function refers to without binding them,
+/// in the order they first appear. These are the variables a closure
+/// captures from the function it is created in.
+pub fn free_variables(lambda: &LambdaDetails) -> Vec<String> {
+    let mut bound = lambda_bindings(lambda);
+    let mut free = Vec::new();
+    for node in &lambda.body {
+        collect_free(node, &mut bound, &mut free);
+    }
+    free
+}
+
+/// Highest `%` argument an anonymous function literal refers to, not counting
+/// those of the functions nested in it
+pub fn highest_argument(node: &Node) -> usize {
+    match node {
+        Node::Variable(name, _) if name.starts_with('%') => name[1..].parse().unwrap_or(0),
+        Node::Lambda(_) => 0,
+        _ => children(node)
+            .into_iter()
+            .map(highest_argument)
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+/// Position of the first `recur` which is not in tail position of the loop or
+/// function it binds anew, and so cannot jump back to its start
+pub fn misplaced_recur(node: &Node) -> Option<Position> {
+    let mut misplaced = None;
+    visit_tail_positions(node, NOT_TAIL, &mut |node, tail| match node {
+        Node::Recur(_, position) if !tail.recur && misplaced.is_none() => {
+            misplaced = Some(*position)
+        }
+        _ => {}
+    });
+    misplaced
+}
+
+/// Positions of the names called by the calls in tail position of the
+/// functions in `node`, whose values are those of the functions themselves
+pub fn tail_calls(node: &Node) -> Vec<Position> {
+    let mut calls = Vec::new();
+    visit_tail_positions(node, NOT_TAIL, &mut |node, tail| match node {
+        Node::List(ListDetails {
+            head: box Node::Variable(_, position),
+            ..
+        }) if tail.function => calls.push(*position),
+        _ => {}
+    });
+    calls
+}
+
+/// Whether an expression is the last one evaluated by the function it is in,
+/// and by the loop or function `recur` would start over
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct TailPosition {
+    function: bool,
+    recur: bool,
+}
+
+const NOT_TAIL: TailPosition = TailPosition {
+    function: false,
+    recur: false,
+};
+
+/// Calls `visit` on `node` and on every expression nested in it, in the order
+/// they appear, along with their tail position. The bodies of nested
+/// functions end in tail position of their own.
+fn visit_tail_positions(
+    node: &Node,
+    tail: TailPosition,
+    visit: &mut dyn FnMut(&Node, TailPosition),
+) {
+    visit(node, tail);
+    match node {
+        Node::If(IfDetails {
+            test,
+            then,
+            otherwise,
+        }) => {
+            visit_tail_positions(test, NOT_TAIL, visit);
+            visit_tail_positions(then, tail, visit);
+            if let Some(otherwise) = otherwise {
+                visit_tail_positions(otherwise, tail, visit);
+            }
+        }
+        Node::Do(body) => visit_body(body, tail, visit),
+        // the last argument of `and` or `or` is evaluated last, when it is
+        Node::List(ListDetails {
+            head: head @ box Node::Keyword(KeywordDetails { token, .. }),
+            rest,
+            ..
+        }) if *token == Lexeme::And || *token == Lexeme::Or => {
+            visit_tail_positions(head, NOT_TAIL, visit);
+            visit_body(rest, tail, visit)
+        }
+        Node::Cond(clauses) => {
+            for clause in clauses {
+                visit_tail_positions(&clause.test, NOT_TAIL, visit);
+                visit_tail_positions(&clause.value, tail, visit);
+            }
+        }
+        Node::Condp(CondpDetails {
+            predicate,
+            expression,
+            clauses,
+            default,
+        }) => {
+            visit_tail_positions(predicate, NOT_TAIL, visit);
+            visit_tail_positions(expression, NOT_TAIL, visit);
+            for clause in clauses {
+                visit_tail_positions(&clause.test, NOT_TAIL, visit);
+                visit_tail_positions(&clause.value, tail, visit);
+            }
+            if let Some(default) = default {
+                visit_tail_positions(default, tail, visit);
+            }
+        }
+        Node::Case(CaseDetails {
+            expression,
+            clauses,
+            default,
+        }) => {
+            visit_tail_positions(expression, NOT_TAIL, visit);
+            for clause in clauses {
+                visit_tail_positions(&clause.value, tail, visit);
+            }
+            if let Some(default) = default {
+                visit_tail_positions(default, tail, visit);
+            }
+        }
+        Node::Let(details) | Node::Loop(details, _) => {
+            for binding in &details.bindings {
+                visit_tail_positions(&binding.value, NOT_TAIL, visit);
+            }
+            let tail = match node {
+                Node::Loop(..) => TailPosition {
+                    function: tail.function,
+                    recur: true,
+                },
+                _ => tail,
+            };
+            visit_body(&details.body, tail, visit);
+        }
+        Node::Function(FunctionDetails { body, .. })
+        | Node::Main(MainDetails { body, .. })
+        | Node::Lambda(LambdaDetails { body, .. }) => {
+            let tail = TailPosition {
+                function: true,
+                recur: true,
+            };
+            visit_body(body, tail, visit)
+        }
+        Node::Def(definition) => visit_tail_positions(&definition.value, NOT_TAIL, visit),
+        _ => {
+            for child in children(node) {
+                visit_tail_positions(child, NOT_TAIL, visit);
+            }
+        }
+    }
+}
+
+/// Only the last expression of a body is in the tail position of the body
+fn visit_body(body: &[Node], tail: TailPosition, visit: &mut dyn FnMut(&Node, TailPosition)) {
+    for (index, node) in body.iter().enumerate() {
+        let last = index + 1 == body.len();
+        visit_tail_positions(node, if last { tail } else { NOT_TAIL }, visit);
+    }
+}
+
+fn lambda_bindings(lambda: &LambdaDetails) -> Vec<String> {
+    let mut bound: Vec<String> = lambda.name.iter().cloned().collect();
+    for arg in &lambda.args {
+        if let Node::Variable(name, _) = arg {
+            bound.push(name.to_owned());
+        }
+    }
+    bound
+}
+
+fn collect_free(node: &Node, bound: &mut Vec<String>, free: &mut Vec<String>) {
+    match node {
+        Node::Variable(name, _) => {
+            if !bound.contains(name) && !free.contains(name) {
+                free.push(name.to_owned());
+            }
+        }
+        Node::Let(LetDetails { bindings, body }) | Node::Loop(LetDetails { bindings, body }, _) => {
+            let depth = bound.len();
+            for binding in bindings {
+                collect_free(&binding.value, bound, free);
+                if let box Node::Variable(name, _) = &binding.name {
+                    bound.push(name.to_owned());
+                }
+            }
+            for node in body {
+                collect_free(node, bound, free);
+            }
+            bound.truncate(depth);
+        }
+        Node::Lambda(lambda) => {
+            let depth = bound.len();
+            bound.extend(lambda_bindings(lambda));
+            for node in &lambda.body {
+                collect_free(node, bound, free);
+            }
+            bound.truncate(depth);
+        }
+        _ => {
+            for child in children(node) {
+                collect_free(child, bound, free);
+            }
+        }
+    }
+}
+
+/// Expressions directly nested in a form which does not bind names
+fn children(node: &Node) -> Vec<&Node> {
+    match node {
+        Node::List(list) => {
+            let mut children = vec![list.head.as_ref()];
+            children.extend(&list.rest);
+            children
+        }
+        Node::If(IfDetails {
+            test,
+            then,
+            otherwise,
+        }) => {
+            let mut children = vec![test.as_ref(), then.as_ref()];
+            children.extend(otherwise.as_deref());
+            children
+        }
+        Node::Let(LetDetails { bindings, body }) | Node::Loop(LetDetails { bindings, body }, _) => {
+            bindings
+                .iter()
+                .map(|binding| binding.value.as_ref())
+                .chain(body)
+                .collect()
+        }
+        Node::Recur(args, _) | Node::Do(args) => args.iter().collect(),
+        Node::Cond(clauses) => clauses
+            .iter()
+            .flat_map(|clause| vec![&clause.test, &clause.value])
+            .collect(),
+        Node::Condp(details) => {
+            let mut children = vec![details.predicate.as_ref(), details.expression.as_ref()];
+            for clause in &details.clauses {
+                children.push(&clause.test);
+                children.push(&clause.value);
+            }
+            children.extend(details.default.as_deref());
+            children
+        }
+        Node::Case(details) => {
+            // keys are constants, which are not evaluated
+            let mut children = vec![details.expression.as_ref()];
+            children.extend(details.clauses.iter().map(|clause| &clause.value));
+            children.extend(details.default.as_deref());
+            children
+        }
+        Node::Vector(elements) | Node::Set(elements) => elements.iter().collect(),
+        Node::Map(items) => items
+            .iter()
+            .flat_map(|item| vec![&item.key, &item.value])
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frontend::analysis::{
+        free_variables, highest_argument, misplaced_recur, tail_calls,
+    };
+    use crate::frontend::ast::{ConstantLiteral, LambdaDetails, LetDetails, Node};
+    use crate::frontend::parser::Parser;
+    use crate::frontend::scanner::Position;
+
+    fn parse(text: &str) -> Node {
+        Parser::new(text).parse().unwrap().remove(0)
+    }
+
+    #[test]
+    fn free_variables_leave_out_bound_names() {
+        let lambda = match parse("(fn f [x] (let [y x z w] (f x y z v [w] (fn [v] u))))") {
+            Node::Lambda(lambda) => lambda,
+            _ => panic!(),
+        };
+
+        assert_eq!(free_variables(&lambda), vec!["w", "v", "u"]);
+    }
+
+    #[test]
+    fn highest_argument_skips_nested_functions() {
+        assert_eq!(highest_argument(&parse("(+ % 1)")), 1);
+        assert_eq!(highest_argument(&parse("(+ %3 (if a %2))")), 3);
+        assert_eq!(highest_argument(&parse("(+ 1 (fn [] %4))")), 0);
+    }
+
+    #[test]
+    fn recur_binds_the_nearest_loop_or_function() {
+        let at = Position { line: 1, column: 1 };
+        let recur = Node::Recur(vec![], at);
+
+        assert_eq!(
+            misplaced_recur(&parse("(loop [x 1] (fn [] (recur)))")),
+            None
+        );
+        assert_eq!(
+            misplaced_recur(&parse("(loop [x 1] (let [y x] (recur y)))")),
+            None
+        );
+        assert_eq!(
+            misplaced_recur(&Node::Lambda(LambdaDetails {
+                name: None,
+                args: vec![],
+                body: vec![Node::Loop(
+                    LetDetails {
+                        bindings: vec![],
+                        body: vec![recur.clone(), Node::Constant(ConstantLiteral::NilLiteral)],
+                    },
+                    at
+                )],
+            })),
+            Some(at)
+        );
+        assert_eq!(
+            misplaced_recur(&Node::Let(LetDetails {
+                bindings: vec![],
+                body: vec![recur],
+            })),
+            Some(at)
+        );
+    }
+
+    #[test]
+    fn tail_calls_are_the_last_calls_of_their_function() {
+        let calls = |text: &str| -> Vec<usize> {
+            tail_calls(&parse(text))
+                .iter()
+                .map(|position| position.column)
+                .collect()
+        };
+
+        assert_eq!(
+            calls("(defn f [x] (g x) (if (h x) (g x) (let [y x] (f y))))"),
+            vec![30, 47]
+        );
+        assert_eq!(
+            calls("(defn f [x] (loop [y x] (if y (g y) (recur y))))"),
+            vec![32]
+        );
+        assert_eq!(
+            calls("(defn f [x] (g (h x)) (fn [] (g x) (h x)))"),
+            vec![37]
+        );
+        assert_eq!(
+            calls("(defn f [x] (cond (g x) (h x) :else (case x 1 (f x) (g x))))"),
+            vec![26, 48, 54]
+        );
+        assert_eq!(calls("(defn f [x] (or (g x) (and x (h x))))"), vec![31]);
+        assert_eq!(
+            calls("(defn f [x] (let [y (g x)] (+ y (h y))))"),
+            Vec::<usize>::new()
+        );
+    }
+}
@@ -1,59 +1,148 @@
-use crate::frontend::scanner::Lexeme;
+use crate::frontend::scanner::{Lexeme, Position};
 
 type VariableName = String;
+type KeywordName = String;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ConstantLiteral {
-    IntegerLiteral(i32),
+    IntegerLiteral(i64),
+    FloatLiteral(f64),
     StringLiteral(String),
+    BooleanLiteral(bool),
+    NilLiteral,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct KeywordDetails {
     pub token: Lexeme,
+    pub position: Position,
 }
 
-#[derive(Debug, PartialEq)]
+/// A call, positioned at the start of its head
+#[derive(Debug, Clone, PartialEq)]
 pub struct ListDetails {
     pub head: Box<Node>,
     pub rest: Vec<Node>,
+    pub position: Position,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FunctionDetails {
     pub name: Box<Node>,
     pub args: Vec<Node>,
     pub body: Vec<Node>,
 }
 
-#[derive(Debug, PartialEq)]
+/// An anonymous function, which evaluates to a closure over the variables
+/// of its enclosing function that its body refers to. A name binds the
+/// closure itself within the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LambdaDetails {
+    pub name: Option<VariableName>,
+    pub args: Vec<Node>,
+    pub body: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct MainDetails {
     pub args: Vec<Node>,
     pub body: Vec<Node>,
+    pub position: Position,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct VariableInformation {
     pub name: Box<Node>,
     pub value: Box<Node>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfDetails {
+    pub test: Box<Node>,
+    pub then: Box<Node>,
+    pub otherwise: Option<Box<Node>>,
+}
+
+/// Bindings are evaluated in order and each one is visible to the bindings
+/// after it as well as to the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LetDetails {
+    pub bindings: Vec<VariableInformation>,
+    pub body: Vec<Node>,
+}
+
+/// A test along with the expression evaluated when it is the first test of
+/// its form to hold
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clause {
+    pub test: Node,
+    pub value: Node,
+}
+
+/// `(condp pred expr ...)` evaluates `pred` and `expr` once, and the value of
+/// the first clause for which `(pred test expr)` is truthy. Without a default
+/// an expression no clause matches is an error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CondpDetails {
+    pub predicate: Box<Node>,
+    pub expression: Box<Node>,
+    pub clauses: Vec<Clause>,
+    pub default: Option<Box<Node>>,
+}
+
+/// Constants a clause of `case` matches, which are not evaluated, along with
+/// its value
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseClause {
+    pub keys: Vec<Node>,
+    pub value: Node,
+}
+
+/// `(case expr ...)` evaluates to the value of the clause one of whose keys
+/// equals `expr`. Without a default an expression no clause matches is an
+/// error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseDetails {
+    pub expression: Box<Node>,
+    pub clauses: Vec<CaseClause>,
+    pub default: Option<Box<Node>>,
+}
+
+/// An entry of a map literal, whose key is an expression like its value
+#[derive(Debug, Clone, PartialEq)]
 pub struct MapItem {
-    pub key: String,
+    pub key: Node,
     pub value: Node,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Node {
-    Null,
     Main(MainDetails),
     Def(VariableInformation),
     Function(FunctionDetails),
+    Lambda(LambdaDetails),
     Constant(ConstantLiteral),
     Keyword(KeywordDetails),
-    Variable(VariableName),
+    /// A keyword literal such as `:name`, which evaluates to itself. Its name
+    /// includes its namespace when it has one.
+    KeywordLiteral(KeywordName, Position),
+    Variable(VariableName, Position),
     Map(Vec<MapItem>),
+    Set(Vec<Node>),
     Vector(Vec<Node>),
     List(ListDetails),
+    If(IfDetails),
+    /// Expressions evaluated in turn, to the value of the last one
+    Do(Vec<Node>),
+    /// Clauses whose tests are evaluated in turn, to the value of the first
+    /// whose test is truthy, or to nil when there is none
+    Cond(Vec<Clause>),
+    Condp(CondpDetails),
+    Case(CaseDetails),
+    Let(LetDetails),
+    /// Bindings like those of `let`, which `recur` binds anew before running
+    /// the body again
+    Loop(LetDetails, Position),
+    /// Arguments `recur` binds the loop or function it is in to
+    Recur(Vec<Node>, Position),
 }
@@ -1,3 +1,4 @@
+pub(crate) mod analysis;
 pub mod ast;
 pub(crate) mod parser;
 pub(crate) mod scanner;
@@ -1,21 +1,32 @@
 use super::scanner::{scan_into_peekable, Lexeme, Token};
+use crate::frontend::analysis::{highest_argument, misplaced_recur};
 use crate::frontend::ast::Node::Constant;
 use crate::frontend::ast::{
-    ConstantLiteral, FunctionDetails, KeywordDetails, ListDetails, MainDetails, MapItem, Node,
+    CaseClause, CaseDetails, Clause, CondpDetails, ConstantLiteral, FunctionDetails, IfDetails,
+    KeywordDetails, LambdaDetails, LetDetails, ListDetails, MainDetails, MapItem, Node,
+    VariableInformation,
 };
 use crate::frontend::scanner::{Position, ScanError};
+use std::cell::Cell;
 use std::iter::Peekable;
 use std::option::NoneError;
 use std::vec::IntoIter;
 
 type TokenStream = Peekable<IntoIter<Token>>;
 
+/// Namespace of the program, which `::name` keywords are resolved in
+const NAMESPACE: &str = "user";
+
 #[derive(Debug, PartialEq)]
 pub enum ParseError {
     ScanError(ScanError),
     UnexpectedEndOfFile,
     UnexpectedToken(Position, Lexeme),
     InvalidFunctionName(Position, Lexeme),
+    InvalidVariableName(Position, Lexeme),
+    InvalidSpecialForm(Position, Lexeme),
+    DuplicateKey(Position, Lexeme),
+    RecurNotInTailPosition(Position),
 }
 
 impl From<NoneError> for ParseError {
@@ -26,12 +37,15 @@ impl From<NoneError> for ParseError {
 
 pub(crate) struct Parser {
     source: String,
+    /// Whether the parser is within `#(...)`, which can't be nested
+    in_anonymous_function: Cell<bool>,
 }
 
 impl Parser {
     pub(crate) fn new(text: &str) -> Self {
         Parser {
             source: String::from(text),
+            in_anonymous_function: Cell::new(false),
         }
     }
 
@@ -43,7 +57,11 @@ impl Parser {
 
         let mut nodes = vec![];
         while (tokens.peek()?).lexeme != Lexeme::EOF {
-            nodes.push(self.parse_token_stream(&mut tokens)?)
+            let node = self.parse_token_stream(&mut tokens)?;
+            if let Some(position) = misplaced_recur(&node) {
+                return Err(ParseError::RecurNotInTailPosition(position));
+            }
+            nodes.push(node)
         }
         Ok(nodes)
     }
@@ -62,6 +80,14 @@ impl Parser {
                 lexeme: Lexeme::LeftBracket,
                 ..
             } => self.parse_vector(tokens),
+            Token {
+                lexeme: Lexeme::HashLeftBrace,
+                ..
+            } => self.parse_set(tokens),
+            Token {
+                lexeme: Lexeme::HashLeftParen,
+                position,
+            } => self.parse_anonymous_function(position, tokens),
             random => Err(ParseError::UnexpectedToken(random.position, random.lexeme)),
         };
     }
@@ -72,10 +98,317 @@ impl Parser {
                 lexeme: Lexeme::Defn,
                 ..
             }) => self.parse_function_definition(token_stream),
+            Some(Token {
+                lexeme: Lexeme::Def,
+                ..
+            }) => self.parse_variable_definition(token_stream),
+            Some(Token {
+                lexeme: Lexeme::If, ..
+            }) => self.parse_if(token_stream),
+            Some(Token {
+                lexeme: Lexeme::Do, ..
+            }) => self.parse_do(token_stream),
+            Some(Token {
+                lexeme: Lexeme::When,
+                ..
+            })
+            | Some(Token {
+                lexeme: Lexeme::WhenNot,
+                ..
+            }) => self.parse_when(token_stream),
+            Some(Token {
+                lexeme: Lexeme::Cond,
+                ..
+            }) => self.parse_cond(token_stream),
+            Some(Token {
+                lexeme: Lexeme::Condp,
+                ..
+            }) => self.parse_condp(token_stream),
+            Some(Token {
+                lexeme: Lexeme::Case,
+                ..
+            }) => self.parse_case(token_stream),
+            Some(Token {
+                lexeme: Lexeme::Let,
+                ..
+            })
+            | Some(Token {
+                lexeme: Lexeme::Loop,
+                ..
+            }) => self.parse_let(token_stream),
+            Some(Token {
+                lexeme: Lexeme::Recur,
+                ..
+            }) => self.parse_recur(token_stream),
+            Some(Token {
+                lexeme: Lexeme::Fn, ..
+            }) => self.parse_lambda(token_stream),
             _ => self.parse_seq_list(token_stream),
         }
     }
 
+    fn parse_expression(
+        &self,
+        token: Token,
+        token_stream: &mut TokenStream,
+    ) -> Result<Node, ParseError> {
+        match token.lexeme {
+            Lexeme::LeftParen => self.parse_list(token_stream),
+            Lexeme::LeftBracket => self.parse_vector(token_stream),
+            Lexeme::LeftBrace => self.parse_map(token_stream),
+            Lexeme::HashLeftBrace => self.parse_set(token_stream),
+            Lexeme::HashLeftParen => self.parse_anonymous_function(token.position, token_stream),
+            _ => self.parse_item(token),
+        }
+    }
+
+    /// Parses the remaining expressions of a list, consuming its closing
+    /// parenthesis.
+    fn parse_forms(&self, token_stream: &mut TokenStream) -> Result<Vec<Node>, ParseError> {
+        let mut forms = Vec::<Node>::new();
+        loop {
+            let token = token_stream.next()?;
+            match token.lexeme {
+                Lexeme::RightParen => return Ok(forms),
+                Lexeme::EOF => return Err(ParseError::UnexpectedEndOfFile),
+                _ => forms.push(self.parse_expression(token, token_stream)?),
+            }
+        }
+    }
+
+    fn parse_if(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        let if_token = token_stream.next()?;
+        let mut forms = self.parse_forms(token_stream)?.into_iter();
+        match (forms.next(), forms.next(), forms.next(), forms.next()) {
+            (Some(test), Some(then), otherwise, None) => Ok(Node::If(IfDetails {
+                test: Box::new(test),
+                then: Box::new(then),
+                otherwise: otherwise.map(Box::new),
+            })),
+            _ => Err(ParseError::InvalidSpecialForm(
+                if_token.position,
+                if_token.lexeme,
+            )),
+        }
+    }
+
+    fn parse_do(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        // dump the do token
+        token_stream.next();
+        Ok(Node::Do(self.parse_forms(token_stream)?))
+    }
+
+    /// `(when test body)` is read as `(if test (do body))`, and `when-not` as
+    /// the same with its branches swapped, as Clojure defines them
+    fn parse_when(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        let when_token = token_stream.next()?;
+        let mut forms = self.parse_forms(token_stream)?.into_iter();
+        let test = match forms.next() {
+            Some(test) => test,
+            None => {
+                return Err(ParseError::InvalidSpecialForm(
+                    when_token.position,
+                    when_token.lexeme,
+                ))
+            }
+        };
+        let body = Node::Do(forms.collect());
+        let (then, otherwise) = match when_token.lexeme {
+            Lexeme::WhenNot => (Constant(ConstantLiteral::NilLiteral), Some(body)),
+            _ => (body, None),
+        };
+        Ok(Node::If(IfDetails {
+            test: Box::new(test),
+            then: Box::new(then),
+            otherwise: otherwise.map(Box::new),
+        }))
+    }
+
+    /// Tests and values alternate, so an odd number of forms is an error
+    fn parse_cond(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        let cond_token = token_stream.next()?;
+        let forms = self.parse_forms(token_stream)?;
+        match clauses(forms) {
+            (clauses, None) => Ok(Node::Cond(clauses)),
+            _ => Err(ParseError::InvalidSpecialForm(
+                cond_token.position,
+                cond_token.lexeme,
+            )),
+        }
+    }
+
+    /// Parses `(condp pred expr test value ... default)`, whose default is
+    /// the form left over after the clauses, if any
+    fn parse_condp(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        let condp_token = token_stream.next()?;
+        let mut forms = self.parse_forms(token_stream)?.into_iter();
+        match (forms.next(), forms.next()) {
+            (Some(predicate), Some(expression)) => {
+                let (clauses, default) = clauses(forms.collect());
+                Ok(Node::Condp(CondpDetails {
+                    predicate: Box::new(predicate),
+                    expression: Box::new(expression),
+                    clauses,
+                    default: default.map(Box::new),
+                }))
+            }
+            _ => Err(ParseError::InvalidSpecialForm(
+                condp_token.position,
+                condp_token.lexeme,
+            )),
+        }
+    }
+
+    /// Parses `(case expr key value ... default)`. Keys are constants or
+    /// keywords, or lists of them matching any of them. As for sets, a key
+    /// which reads the same as an earlier one is an error at the later key.
+    fn parse_case(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        let case_token = token_stream.next()?;
+        let invalid_case =
+            || ParseError::InvalidSpecialForm(case_token.position, case_token.lexeme.clone());
+        let mut forms = Vec::<(Position, Lexeme, Node)>::new();
+        loop {
+            let token = token_stream.next()?;
+            match token.lexeme {
+                Lexeme::RightParen => break,
+                Lexeme::EOF => return Err(ParseError::UnexpectedEndOfFile),
+                _ => {
+                    let (position, lexeme) = (token.position, token.lexeme.clone());
+                    let form = self.parse_expression(token, token_stream)?;
+                    forms.push((position, lexeme, form));
+                }
+            }
+        }
+        if forms.is_empty() {
+            return Err(invalid_case());
+        }
+        let (_, _, expression) = forms.remove(0);
+        let default = match forms.len() % 2 {
+            1 => forms.pop().map(|(_, _, default)| Box::new(default)),
+            _ => None,
+        };
+
+        let mut clauses = Vec::<CaseClause>::new();
+        let mut seen = Vec::<Node>::new();
+        let mut forms = forms.into_iter();
+        while let (Some((position, lexeme, key)), Some((_, _, value))) =
+            (forms.next(), forms.next())
+        {
+            let keys = match key {
+                Node::List(ListDetails { head, rest, .. }) => {
+                    let mut keys = vec![*head];
+                    keys.extend(rest);
+                    keys
+                }
+                key => vec![key],
+            };
+            for key in &keys {
+                match key {
+                    Node::Constant(_) | Node::KeywordLiteral(..) => {}
+                    _ => return Err(invalid_case()),
+                }
+                if seen.iter().any(|other| same_form(other, key)) {
+                    return Err(ParseError::DuplicateKey(position, lexeme));
+                }
+                seen.push(key.clone());
+            }
+            clauses.push(CaseClause { keys, value });
+        }
+        Ok(Node::Case(CaseDetails {
+            expression: Box::new(expression),
+            clauses,
+            default,
+        }))
+    }
+
+    fn parse_let(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        let let_token = token_stream.next()?;
+        let invalid_let =
+            || ParseError::InvalidSpecialForm(let_token.position, let_token.lexeme.clone());
+        if token_stream.next()?.lexeme != Lexeme::LeftBracket {
+            return Err(invalid_let());
+        }
+
+        let mut bindings = Vec::new();
+        loop {
+            let name_token = token_stream.next()?;
+            let name = match &name_token {
+                Token {
+                    lexeme: Lexeme::RightBracket,
+                    ..
+                } => break,
+                Token {
+                    lexeme: Lexeme::Identifier(_),
+                    ..
+                } => self.parse_item(name_token)?,
+                _ => {
+                    return Err(ParseError::InvalidVariableName(
+                        name_token.position,
+                        name_token.lexeme,
+                    ))
+                }
+            };
+            // every name needs a value
+            let token = token_stream.next()?;
+            if token.lexeme == Lexeme::RightBracket {
+                return Err(invalid_let());
+            }
+            let value = self.parse_expression(token, token_stream)?;
+            bindings.push(VariableInformation {
+                name: Box::new(name),
+                value: Box::new(value),
+            });
+        }
+
+        let body = self.parse_forms(token_stream)?;
+        let details = LetDetails { bindings, body };
+        Ok(match let_token.lexeme {
+            Lexeme::Loop => Node::Loop(details, let_token.position),
+            _ => Node::Let(details),
+        })
+    }
+
+    fn parse_recur(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        let recur_token = token_stream.next()?;
+        let args = self.parse_forms(token_stream)?;
+        Ok(Node::Recur(args, recur_token.position))
+    }
+
+    fn parse_variable_definition(
+        &self,
+        token_stream: &mut TokenStream,
+    ) -> Result<Node, ParseError> {
+        // dump the def token
+        token_stream.next();
+        let name_token = token_stream.next()?;
+        let name = match &name_token {
+            Token {
+                lexeme: Lexeme::Identifier(_),
+                ..
+            } => self.parse_item(name_token)?,
+            _ => {
+                return Err(ParseError::InvalidVariableName(
+                    name_token.position,
+                    name_token.lexeme,
+                ))
+            }
+        };
+
+        let token = token_stream.next()?;
+        let value = self.parse_expression(token, token_stream)?;
+
+        match token_stream.next()? {
+            Token {
+                lexeme: Lexeme::RightParen,
+                ..
+            } => Ok(Node::Def(VariableInformation {
+                name: Box::new(name),
+                value: Box::new(value),
+            })),
+            token => Err(ParseError::UnexpectedToken(token.position, token.lexeme)),
+        }
+    }
+
     fn parse_function_definition(
         &self,
         token_stream: &mut TokenStream,
@@ -86,8 +419,8 @@ impl Parser {
         let name = match &name_token {
             Token {
                 lexeme: Lexeme::Main,
-                ..
-            } => self.build_fake_main_node(),
+                position,
+            } => self.build_fake_main_node(*position),
             Token {
                 lexeme: Lexeme::Identifier(_),
                 ..
@@ -116,7 +449,11 @@ impl Parser {
         let body = self.parse_function_body(token_stream)?;
 
         match name {
-            Node::Main(..) => Ok(Node::Main(MainDetails { args, body })),
+            Node::Main(MainDetails { position, .. }) => Ok(Node::Main(MainDetails {
+                args,
+                body,
+                position,
+            })),
             _ => Ok(Node::Function(FunctionDetails {
                 name: Box::new(name),
                 args,
@@ -125,38 +462,79 @@ impl Parser {
         }
     }
 
-    fn parse_function_body(&self, token_stream: &mut TokenStream) -> Result<Vec<Node>, ParseError> {
-        let mut body = Vec::<Node>::new();
-        while let Some(token) = token_stream.peek() {
-            if token.lexeme == Lexeme::LeftParen {
-                // move to function body
-                token_stream.next();
-                body.push(self.parse_seq_list(token_stream)?);
-            } else {
-                break;
+    /// Parses `(fn [args] body)`, where a name may come before the arguments
+    fn parse_lambda(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        let fn_token = token_stream.next()?;
+        let mut token = token_stream.next()?;
+        let name = match &token.lexeme {
+            Lexeme::Identifier(name) => {
+                let name = name.to_owned();
+                token = token_stream.next()?;
+                Some(name)
             }
+            _ => None,
+        };
+        if token.lexeme != Lexeme::LeftBracket {
+            return Err(ParseError::InvalidSpecialForm(
+                fn_token.position,
+                fn_token.lexeme,
+            ));
         }
-        // skip trailing right parenthesis
-        token_stream.next();
+        let args = match self.parse_vector(token_stream)? {
+            Node::Vector(args) => args,
+            _ => vec![],
+        };
+        let body = self.parse_function_body(token_stream)?;
+        Ok(Node::Lambda(LambdaDetails { name, args, body }))
+    }
+
+    /// Parses `#(...)`, a function whose body is the list and whose
+    /// arguments are the `%1`, `%2` and so on it refers to, `%` being `%1`.
+    /// It takes as many arguments as the highest of them. Its body can't
+    /// have another `#(...)`, as the `%` arguments would be ambiguous.
+    fn parse_anonymous_function(
+        &self,
+        position: Position,
+        token_stream: &mut TokenStream,
+    ) -> Result<Node, ParseError> {
+        if self.in_anonymous_function.replace(true) {
+            return Err(ParseError::InvalidSpecialForm(
+                position,
+                Lexeme::HashLeftParen,
+            ));
+        }
+        let body = self.parse_seq_list(token_stream);
+        self.in_anonymous_function.set(false);
+        let body = body?;
+        let args = (1..=highest_argument(&body))
+            .map(|index| Node::Variable(format!("%{}", index), position))
+            .collect();
+        Ok(Node::Lambda(LambdaDetails {
+            name: None,
+            args,
+            body: vec![body],
+        }))
+    }
 
-        Ok(body)
+    fn parse_function_body(&self, token_stream: &mut TokenStream) -> Result<Vec<Node>, ParseError> {
+        self.parse_forms(token_stream)
     }
 
     fn parse_seq_list(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
         let mut list = Vec::<Node>::new();
+        let position = token_stream.peek()?.position;
         while let Some(token) = token_stream.next() {
             if token.lexeme == Lexeme::RightParen {
                 break;
-            } else if token.lexeme == Lexeme::LeftParen {
-                list.push(self.parse_seq_list(token_stream)?)
             } else {
-                list.push(self.parse_item(token)?);
+                list.push(self.parse_expression(token, token_stream)?);
             }
         }
         let top = list.remove(0);
         Ok(Node::List(ListDetails {
             head: Box::from(top),
             rest: list,
+            position,
         }))
     }
 
@@ -167,72 +545,183 @@ impl Parser {
             if token.lexeme == Lexeme::RightBracket {
                 break;
             } else {
-                list.push(self.parse_item(token)?);
+                list.push(self.parse_expression(token, token_stream)?);
             }
         }
 
         Ok(Node::Vector(list))
     }
 
+    /// Parses the entries of a map literal, consuming its closing brace. Keys
+    /// and values alternate, so a key without a value is an error at the
+    /// closing brace. As in Clojure, a key which reads the same as an earlier
+    /// one is an error at the later key.
     fn parse_map(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
-        let mut map_items = Vec::<MapItem>::new();
-        while let Some(token) = token_stream.next() {
+        let mut forms = Vec::<(Position, Lexeme, Node)>::new();
+        let closing = loop {
+            let token = token_stream.next()?;
             match token.lexeme {
-                Lexeme::MapKey(name) => {
-                    let item = match token_stream.next() {
-                        Some(value) => MapItem {
-                            key: name,
-                            value: self.parse_item(value)?,
-                        },
-                        None => return Err(ParseError::UnexpectedEndOfFile),
-                    };
-                    map_items.push(item);
-                }
-                Lexeme::RightBrace => break,
+                Lexeme::RightBrace => break token,
+                Lexeme::EOF => return Err(ParseError::UnexpectedEndOfFile),
                 _ => {
-                    map_items.push(MapItem {
-                        key: String::from(""),
-                        value: self.parse_item(token)?,
-                    });
+                    let (position, lexeme) = (token.position, token.lexeme.clone());
+                    let form = self.parse_expression(token, token_stream)?;
+                    forms.push((position, lexeme, form));
                 }
             }
+        };
+        if forms.len() % 2 == 1 {
+            return Err(ParseError::UnexpectedToken(
+                closing.position,
+                closing.lexeme,
+            ));
         }
 
+        let mut map_items = Vec::<MapItem>::new();
+        let mut forms = forms.into_iter();
+        while let (Some((position, lexeme, key)), Some((_, _, value))) =
+            (forms.next(), forms.next())
+        {
+            if map_items.iter().any(|item| same_form(&item.key, &key)) {
+                return Err(ParseError::DuplicateKey(position, lexeme));
+            }
+            map_items.push(MapItem { key, value });
+        }
         Ok(Node::Map(map_items))
     }
 
+    /// Parses the elements of a set literal, consuming its closing brace. An
+    /// element which reads the same as an earlier one is an error, as it is
+    /// for the keys of a map.
+    fn parse_set(&self, token_stream: &mut TokenStream) -> Result<Node, ParseError> {
+        let mut elements = Vec::<Node>::new();
+        loop {
+            let token = token_stream.next()?;
+            match token.lexeme {
+                Lexeme::RightBrace => return Ok(Node::Set(elements)),
+                Lexeme::EOF => return Err(ParseError::UnexpectedEndOfFile),
+                _ => {
+                    let (position, lexeme) = (token.position, token.lexeme.clone());
+                    let element = self.parse_expression(token, token_stream)?;
+                    if elements.iter().any(|other| same_form(other, &element)) {
+                        return Err(ParseError::DuplicateKey(position, lexeme));
+                    }
+                    elements.push(element);
+                }
+            }
+        }
+    }
+
     fn parse_item(&self, item: Token) -> Result<Node, ParseError> {
         return match item.lexeme {
             Lexeme::NumberLiteral(number) => {
                 Ok(Node::Constant(ConstantLiteral::IntegerLiteral(number)))
             }
+            Lexeme::FloatLiteral(float) => Ok(Node::Constant(ConstantLiteral::FloatLiteral(float))),
             Lexeme::StringLiteral(string) => {
                 Ok(Node::Constant(ConstantLiteral::StringLiteral(string)))
             }
-            Lexeme::Plus | Lexeme::Minus | Lexeme::And | Lexeme::Or | Lexeme::Print => {
-                Ok(Node::Keyword(KeywordDetails { token: item.lexeme }))
+            Lexeme::True => Ok(Node::Constant(ConstantLiteral::BooleanLiteral(true))),
+            Lexeme::False => Ok(Node::Constant(ConstantLiteral::BooleanLiteral(false))),
+            Lexeme::Nil => Ok(Node::Constant(ConstantLiteral::NilLiteral)),
+            Lexeme::Plus
+            | Lexeme::Minus
+            | Lexeme::And
+            | Lexeme::Or
+            | Lexeme::Print
+            | Lexeme::Star
+            | Lexeme::Slash
+            | Lexeme::Less
+            | Lexeme::LessEqual
+            | Lexeme::Greater
+            | Lexeme::GreaterEqual
+            | Lexeme::Equal
+            | Lexeme::DoubleEqual
+            | Lexeme::NotEqual => Ok(Node::Keyword(KeywordDetails {
+                token: item.lexeme,
+                position: item.position,
+            })),
+            Lexeme::MapKey(name) => Ok(Node::KeywordLiteral(name, item.position)),
+            Lexeme::AutoMapKey(name) => Ok(Node::KeywordLiteral(
+                format!("{}/{}", NAMESPACE, name),
+                item.position,
+            )),
+            Lexeme::Identifier(name) if name == "%" => {
+                Ok(Node::Variable("%1".to_owned(), item.position))
             }
-            Lexeme::Identifier(name) => Ok(Node::Variable(name)),
-            Lexeme::Main => Ok(Node::Variable("main".to_owned())),
-            _ => Ok(Node::Null),
+            Lexeme::Identifier(name) => Ok(Node::Variable(name, item.position)),
+            Lexeme::Main => Ok(Node::Variable("main".to_owned(), item.position)),
+            Lexeme::EOF => Err(ParseError::UnexpectedEndOfFile),
+            _ => Err(ParseError::UnexpectedToken(item.position, item.lexeme)),
         };
     }
 
-    fn build_fake_main_node(&self) -> Node {
+    fn build_fake_main_node(&self, position: Position) -> Node {
         Node::Main(MainDetails {
             args: Vec::new(),
             body: Vec::new(),
+            position,
         })
     }
 }
 
+/// Pairs up tests and values, returning the form left over if any
+fn clauses(forms: Vec<Node>) -> (Vec<Clause>, Option<Node>) {
+    let mut clauses = vec![];
+    let mut forms = forms.into_iter();
+    loop {
+        match (forms.next(), forms.next()) {
+            (Some(test), Some(value)) => clauses.push(Clause { test, value }),
+            (left, _) => return (clauses, left),
+        }
+    }
+}
+
+/// Whether two forms read the same, wherever they were read. The entries
+/// of maps and the elements of sets may be in any order.
+fn same_form(left: &Node, right: &Node) -> bool {
+    let same_forms = |left: &Vec<Node>, right: &Vec<Node>| {
+        left.len() == right.len()
+            && left
+                .iter()
+                .zip(right)
+                .all(|(left, right)| same_form(left, right))
+    };
+    match (left, right) {
+        (Node::Constant(left), Node::Constant(right)) => left == right,
+        (Node::Keyword(left), Node::Keyword(right)) => left.token == right.token,
+        (Node::KeywordLiteral(left, _), Node::KeywordLiteral(right, _)) => left == right,
+        (Node::Variable(left, _), Node::Variable(right, _)) => left == right,
+        (Node::Vector(left), Node::Vector(right)) => same_forms(left, right),
+        (Node::List(left), Node::List(right)) => {
+            same_form(&left.head, &right.head) && same_forms(&left.rest, &right.rest)
+        }
+        (Node::Set(left), Node::Set(right)) => {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .all(|element| right.iter().any(|other| same_form(element, other)))
+        }
+        (Node::Map(left), Node::Map(right)) => {
+            left.len() == right.len()
+                && left.iter().all(|item| {
+                    right.iter().any(|other| {
+                        same_form(&item.key, &other.key) && same_form(&item.value, &other.value)
+                    })
+                })
+        }
+        _ => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::frontend::ast::{
-        ConstantLiteral, FunctionDetails, KeywordDetails, ListDetails, MapItem, Node,
+        CaseClause, CaseDetails, Clause, CondpDetails, ConstantLiteral, FunctionDetails, IfDetails,
+        KeywordDetails, LambdaDetails, LetDetails, ListDetails, MapItem, Node, VariableInformation,
     };
-    use crate::frontend::parser::Parser;
-    use crate::frontend::scanner::Lexeme;
+    use crate::frontend::parser::{ParseError, Parser};
+    use crate::frontend::scanner::{Lexeme, Position};
 
     #[test]
     fn parse_list() {
@@ -242,11 +731,13 @@ mod tests {
         let tree = Node::List(ListDetails {
             head: Box::from(Node::Keyword(KeywordDetails {
                 token: Lexeme::Plus,
+                position: Position { line: 1, column: 2 },
             })),
             rest: vec![
-                Node::Constant(ConstantLiteral::IntegerLiteral(1 as i32)),
-                Node::Constant(ConstantLiteral::IntegerLiteral(2 as i32)),
+                Node::Constant(ConstantLiteral::IntegerLiteral(1 as i64)),
+                Node::Constant(ConstantLiteral::IntegerLiteral(2 as i64)),
             ],
+            position: Position { line: 1, column: 2 },
         });
         let nodes = parser.parse().unwrap();
 
@@ -261,19 +752,23 @@ mod tests {
         let tree = Node::List(ListDetails {
             head: Box::from(Node::Keyword(KeywordDetails {
                 token: Lexeme::Plus,
+                position: Position { line: 1, column: 2 },
             })),
             rest: vec![
-                Node::Constant(ConstantLiteral::IntegerLiteral(1 as i32)),
+                Node::Constant(ConstantLiteral::IntegerLiteral(1 as i64)),
                 Node::List(ListDetails {
                     head: Box::from(Node::Keyword(KeywordDetails {
                         token: Lexeme::Plus,
+                        position: Position { line: 1, column: 7 },
                     })),
                     rest: vec![
-                        Node::Constant(ConstantLiteral::IntegerLiteral(2 as i32)),
-                        Node::Constant(ConstantLiteral::IntegerLiteral(3 as i32)),
+                        Node::Constant(ConstantLiteral::IntegerLiteral(2 as i64)),
+                        Node::Constant(ConstantLiteral::IntegerLiteral(3 as i64)),
                     ],
+                    position: Position { line: 1, column: 7 },
                 }),
             ],
+            position: Position { line: 1, column: 2 },
         });
 
         let nodes = parser.parse().unwrap();
@@ -287,12 +782,41 @@ mod tests {
 
         let tree = Node::Map(vec![
             MapItem {
-                key: "guten".to_string(),
-                value: Node::Constant(ConstantLiteral::IntegerLiteral(1 as i32)),
+                key: Node::KeywordLiteral("guten".to_string(), Position { line: 1, column: 2 }),
+                value: Node::Constant(ConstantLiteral::IntegerLiteral(1 as i64)),
+            },
+            MapItem {
+                key: Node::KeywordLiteral(
+                    "tag".to_string(),
+                    Position {
+                        line: 1,
+                        column: 11,
+                    },
+                ),
+                value: Node::Constant(ConstantLiteral::IntegerLiteral(2 as i64)),
+            },
+        ]);
+
+        let nodes = parser.parse().unwrap();
+
+        assert_eq!(nodes[0], tree)
+    }
+
+    #[test]
+    fn parse_map_with_any_keys() {
+        let text = "{1 [2] \"three\" nil}".to_string();
+        let parser = Parser::new(&text);
+
+        let tree = Node::Map(vec![
+            MapItem {
+                key: Node::Constant(ConstantLiteral::IntegerLiteral(1 as i64)),
+                value: Node::Vector(vec![Node::Constant(ConstantLiteral::IntegerLiteral(
+                    2 as i64,
+                ))]),
             },
             MapItem {
-                key: "tag".to_string(),
-                value: Node::Constant(ConstantLiteral::IntegerLiteral(2 as i32)),
+                key: Node::Constant(ConstantLiteral::StringLiteral("three".to_string())),
+                value: Node::Constant(ConstantLiteral::NilLiteral),
             },
         ]);
 
@@ -301,14 +825,372 @@ mod tests {
         assert_eq!(nodes[0], tree)
     }
 
+    #[test]
+    fn parse_map_separated_by_commas() {
+        let text = "{:a 1, :b 2}".to_string();
+        let parser = Parser::new(&text);
+        let at = |column| Position { line: 1, column };
+
+        let tree = Node::Map(vec![
+            MapItem {
+                key: Node::KeywordLiteral("a".to_owned(), at(2)),
+                value: Node::Constant(ConstantLiteral::IntegerLiteral(1)),
+            },
+            MapItem {
+                key: Node::KeywordLiteral("b".to_owned(), at(8)),
+                value: Node::Constant(ConstantLiteral::IntegerLiteral(2)),
+            },
+        ]);
+
+        let nodes = parser.parse().unwrap();
+
+        assert_eq!(nodes[0], tree)
+    }
+
+    #[test]
+    fn parse_map_without_value() {
+        let text = "{:a 1 :b}".to_string();
+        let parser = Parser::new(&text);
+
+        assert_eq!(
+            parser.parse(),
+            Err(ParseError::UnexpectedToken(
+                Position { line: 1, column: 9 },
+                Lexeme::RightBrace
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_unknown_token() {
+        let text = "(print . 1)".to_string();
+        let parser = Parser::new(&text);
+
+        assert_eq!(
+            parser.parse(),
+            Err(ParseError::UnexpectedToken(
+                Position { line: 1, column: 8 },
+                Lexeme::Dot
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_lambdas() {
+        let nodes = Parser::new("(fn self [x] x) #(+ %2 %)").parse().unwrap();
+        let at = |column| Position { line: 1, column };
+
+        assert_eq!(
+            nodes[0],
+            Node::Lambda(LambdaDetails {
+                name: Some("self".to_owned()),
+                args: vec![Node::Variable("x".to_owned(), at(11))],
+                body: vec![Node::Variable("x".to_owned(), at(14))],
+            })
+        );
+        assert_eq!(
+            nodes[1],
+            Node::Lambda(LambdaDetails {
+                name: None,
+                args: vec![
+                    Node::Variable("%1".to_owned(), at(17)),
+                    Node::Variable("%2".to_owned(), at(17)),
+                ],
+                body: vec![Node::List(ListDetails {
+                    head: Box::from(Node::Keyword(KeywordDetails {
+                        token: Lexeme::Plus,
+                        position: at(19),
+                    })),
+                    rest: vec![
+                        Node::Variable("%2".to_owned(), at(21)),
+                        Node::Variable("%1".to_owned(), at(24)),
+                    ],
+                    position: at(19),
+                })],
+            })
+        );
+        assert_eq!(
+            Parser::new("(fn x)").parse(),
+            Err(ParseError::InvalidSpecialForm(at(2), Lexeme::Fn))
+        );
+    }
+
+    #[test]
+    fn parse_nested_anonymous_function() {
+        assert_eq!(
+            Parser::new("(def f #(map #(inc %) %))").parse(),
+            Err(ParseError::InvalidSpecialForm(
+                Position {
+                    line: 1,
+                    column: 14
+                },
+                Lexeme::HashLeftParen
+            ))
+        );
+        assert!(Parser::new("(def f #(+ % 1)) (def g #(- % 1))")
+            .parse()
+            .is_ok());
+    }
+
+    #[test]
+    fn parse_loop() {
+        let nodes = Parser::new("(loop [i 0] (if i (recur i)))")
+            .parse()
+            .unwrap();
+        let at = |column| Position { line: 1, column };
+
+        assert_eq!(
+            nodes[0],
+            Node::Loop(
+                LetDetails {
+                    bindings: vec![VariableInformation {
+                        name: Box::new(Node::Variable("i".to_owned(), at(8))),
+                        value: Box::new(Node::Constant(ConstantLiteral::IntegerLiteral(0))),
+                    }],
+                    body: vec![Node::If(IfDetails {
+                        test: Box::new(Node::Variable("i".to_owned(), at(17))),
+                        then: Box::new(Node::Recur(
+                            vec![Node::Variable("i".to_owned(), at(26))],
+                            at(20)
+                        )),
+                        otherwise: None,
+                    })],
+                },
+                at(2)
+            )
+        );
+        assert_eq!(
+            Parser::new("(loop i)").parse(),
+            Err(ParseError::InvalidSpecialForm(at(2), Lexeme::Loop))
+        );
+    }
+
+    #[test]
+    fn recur_outside_tail_position() {
+        let misplaced = |text: &str| match Parser::new(text).parse() {
+            Err(ParseError::RecurNotInTailPosition(position)) => Some(position.column),
+            _ => None,
+        };
+
+        assert_eq!(misplaced("(defn f [x] (recur x))"), None);
+        assert_eq!(misplaced("(loop [x 1] (+ 1 (recur x)))"), Some(19));
+        assert_eq!(misplaced("(loop [x 1] (if (recur x) x))"), Some(18));
+        assert_eq!(misplaced("(loop [x 1] (recur x) x)"), Some(14));
+        assert_eq!(misplaced("(loop [x (recur 1)] x)"), Some(11));
+        assert_eq!(misplaced("(defn f [x] (let [y x] (recur y)))"), None);
+        assert_eq!(
+            misplaced("(fn [x] (loop [y x] (if y (recur y) (recur x))))"),
+            None
+        );
+        assert_eq!(misplaced("(loop [x 1] (cond (recur x) x))"), Some(20));
+        assert_eq!(misplaced("(loop [x 1] (when x (print x) (recur x)))"), None);
+        assert_eq!(misplaced("(loop [x 1] (and x (recur x)))"), None);
+        assert_eq!(misplaced("(loop [x 1] (or (recur x) x))"), Some(18));
+        assert_eq!(misplaced("(print (recur 1))"), Some(9));
+    }
+
+    #[test]
+    fn parse_when() {
+        let nodes = Parser::new("(when x 1 2) (when-not x 1)").parse().unwrap();
+        let x = |column| Box::new(Node::Variable("x".to_owned(), Position { line: 1, column }));
+        let one = Node::Constant(ConstantLiteral::IntegerLiteral(1));
+
+        assert_eq!(
+            nodes[0],
+            Node::If(IfDetails {
+                test: x(7),
+                then: Box::new(Node::Do(vec![
+                    one.clone(),
+                    Node::Constant(ConstantLiteral::IntegerLiteral(2)),
+                ])),
+                otherwise: None,
+            })
+        );
+        assert_eq!(
+            nodes[1],
+            Node::If(IfDetails {
+                test: x(24),
+                then: Box::new(Node::Constant(ConstantLiteral::NilLiteral)),
+                otherwise: Some(Box::new(Node::Do(vec![one]))),
+            })
+        );
+        assert_eq!(
+            Parser::new("(when)").parse(),
+            Err(ParseError::InvalidSpecialForm(
+                Position { line: 1, column: 2 },
+                Lexeme::When
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_cond_clauses() {
+        let nodes = Parser::new("(cond x 1 :else 2) (condp = x 1 2 3)")
+            .parse()
+            .unwrap();
+        let at = |column| Position { line: 1, column };
+        let integer = |integer| Node::Constant(ConstantLiteral::IntegerLiteral(integer));
+
+        assert_eq!(
+            nodes[0],
+            Node::Cond(vec![
+                Clause {
+                    test: Node::Variable("x".to_owned(), at(7)),
+                    value: integer(1),
+                },
+                Clause {
+                    test: Node::KeywordLiteral("else".to_owned(), at(11)),
+                    value: integer(2),
+                },
+            ])
+        );
+        assert_eq!(
+            nodes[1],
+            Node::Condp(CondpDetails {
+                predicate: Box::new(Node::Keyword(KeywordDetails {
+                    token: Lexeme::Equal,
+                    position: at(27),
+                })),
+                expression: Box::new(Node::Variable("x".to_owned(), at(29))),
+                clauses: vec![Clause {
+                    test: integer(1),
+                    value: integer(2),
+                }],
+                default: Some(Box::new(integer(3))),
+            })
+        );
+        assert_eq!(
+            Parser::new("(cond x)").parse(),
+            Err(ParseError::InvalidSpecialForm(at(2), Lexeme::Cond))
+        );
+        assert_eq!(
+            Parser::new("(condp =)").parse(),
+            Err(ParseError::InvalidSpecialForm(at(2), Lexeme::Condp))
+        );
+    }
+
+    #[test]
+    fn parse_case_keys() {
+        let nodes = Parser::new("(case x (1 :a) 2 \"b\" 3)").parse().unwrap();
+        let at = |column| Position { line: 1, column };
+        let integer = |integer| Node::Constant(ConstantLiteral::IntegerLiteral(integer));
+
+        assert_eq!(
+            nodes[0],
+            Node::Case(CaseDetails {
+                expression: Box::new(Node::Variable("x".to_owned(), at(7))),
+                clauses: vec![
+                    CaseClause {
+                        keys: vec![integer(1), Node::KeywordLiteral("a".to_owned(), at(12))],
+                        value: integer(2),
+                    },
+                    CaseClause {
+                        keys: vec![Node::Constant(ConstantLiteral::StringLiteral(
+                            "b".to_owned()
+                        ))],
+                        value: integer(3),
+                    },
+                ],
+                default: None,
+            })
+        );
+        assert_eq!(
+            Parser::new("(case x 1 2 (3 1) 4)").parse(),
+            Err(ParseError::DuplicateKey(at(13), Lexeme::LeftParen))
+        );
+        assert_eq!(
+            Parser::new("(case x y 1)").parse(),
+            Err(ParseError::InvalidSpecialForm(at(2), Lexeme::Case))
+        );
+    }
+
+    #[test]
+    fn parse_set() {
+        let text = "#{1 [1]}".to_string();
+        let parser = Parser::new(&text);
+
+        let tree = Node::Set(vec![
+            Node::Constant(ConstantLiteral::IntegerLiteral(1 as i64)),
+            Node::Vector(vec![Node::Constant(ConstantLiteral::IntegerLiteral(
+                1 as i64,
+            ))]),
+        ]);
+
+        assert_eq!(parser.parse().unwrap()[0], tree)
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let duplicate = |text: &str| match Parser::new(text).parse() {
+            Err(ParseError::DuplicateKey(position, lexeme)) => Some((position.column, lexeme)),
+            _ => None,
+        };
+
+        assert_eq!(
+            duplicate("#{[x :a] 2 [x :a]}"),
+            Some((12, Lexeme::LeftBracket))
+        );
+        assert_eq!(
+            duplicate("{:a 1 :b 2 :a 3}"),
+            Some((12, Lexeme::MapKey("a".to_owned())))
+        );
+        assert_eq!(
+            duplicate("#{#{1 2} #{2 1}}"),
+            Some((10, Lexeme::HashLeftBrace))
+        );
+        assert_eq!(
+            duplicate("#{:a ::a :user/a}"),
+            Some((10, Lexeme::MapKey("user/a".to_owned())))
+        );
+        assert_eq!(duplicate("#{1 1.0 \"1\"} {1 1 [1] 1}"), None);
+    }
+
+    #[test]
+    fn parse_keywords() {
+        let text = "[:a :ns/b ::c]".to_string();
+        let parser = Parser::new(&text);
+
+        let tree = Node::Vector(vec![
+            Node::KeywordLiteral("a".to_owned(), Position { line: 1, column: 2 }),
+            Node::KeywordLiteral("ns/b".to_owned(), Position { line: 1, column: 5 }),
+            Node::KeywordLiteral(
+                "user/c".to_owned(),
+                Position {
+                    line: 1,
+                    column: 11,
+                },
+            ),
+        ]);
+
+        let nodes = parser.parse().unwrap();
+
+        assert_eq!(nodes[0], tree)
+    }
+
     #[test]
     fn parse_vector() {
         let text = "[1 2]".to_string();
         let parser = Parser::new(&text);
 
         let tree = Node::Vector(vec![
-            Node::Constant(ConstantLiteral::IntegerLiteral(1 as i32)),
-            Node::Constant(ConstantLiteral::IntegerLiteral(2 as i32)),
+            Node::Constant(ConstantLiteral::IntegerLiteral(1 as i64)),
+            Node::Constant(ConstantLiteral::IntegerLiteral(2 as i64)),
+        ]);
+
+        let nodes = parser.parse().unwrap();
+
+        assert_eq!(nodes[0], tree)
+    }
+
+    #[test]
+    fn parse_vector_separated_by_commas() {
+        let text = "[1, 2 ,3]".to_string();
+        let parser = Parser::new(&text);
+
+        let tree = Node::Vector(vec![
+            Node::Constant(ConstantLiteral::IntegerLiteral(1 as i64)),
+            Node::Constant(ConstantLiteral::IntegerLiteral(2 as i64)),
+            Node::Constant(ConstantLiteral::IntegerLiteral(3 as i64)),
         ]);
 
         let nodes = parser.parse().unwrap();
@@ -322,23 +1204,168 @@ mod tests {
         let parser = Parser::new(&text);
 
         let tree = Node::Function(FunctionDetails {
-            name: Box::new(Node::Variable("add".to_owned())),
+            name: Box::new(Node::Variable(
+                "add".to_owned(),
+                Position { line: 1, column: 7 },
+            )),
             args: vec![
-                Node::Variable("x".to_owned()),
-                Node::Variable("y".to_owned()),
+                Node::Variable(
+                    "x".to_owned(),
+                    Position {
+                        line: 1,
+                        column: 12,
+                    },
+                ),
+                Node::Variable(
+                    "y".to_owned(),
+                    Position {
+                        line: 1,
+                        column: 14,
+                    },
+                ),
             ],
             body: vec![Node::List(ListDetails {
                 head: Box::from(Node::Keyword(KeywordDetails {
                     token: Lexeme::Plus,
+                    position: Position {
+                        line: 1,
+                        column: 18,
+                    },
                 })),
                 rest: vec![
-                    Node::Variable("x".to_owned()),
-                    Node::Variable("y".to_owned()),
+                    Node::Variable(
+                        "x".to_owned(),
+                        Position {
+                            line: 1,
+                            column: 20,
+                        },
+                    ),
+                    Node::Variable(
+                        "y".to_owned(),
+                        Position {
+                            line: 1,
+                            column: 22,
+                        },
+                    ),
                 ],
+                position: Position {
+                    line: 1,
+                    column: 18,
+                },
             })],
         });
 
         let nodes = parser.parse().unwrap();
         assert_eq!(nodes[0], tree)
     }
+
+    #[test]
+    fn parse_variable_definition() {
+        let text = "(def answer 42)".to_string();
+        let parser = Parser::new(&text);
+
+        let tree = Node::Def(VariableInformation {
+            name: Box::new(Node::Variable(
+                "answer".to_owned(),
+                Position { line: 1, column: 6 },
+            )),
+            value: Box::new(Node::Constant(ConstantLiteral::IntegerLiteral(42))),
+        });
+
+        let nodes = parser.parse().unwrap();
+        assert_eq!(nodes[0], tree)
+    }
+
+    #[test]
+    fn parse_if() {
+        let text = "(if true (if nil 1) false)".to_string();
+        let parser = Parser::new(&text);
+
+        let tree = Node::If(IfDetails {
+            test: Box::new(Node::Constant(ConstantLiteral::BooleanLiteral(true))),
+            then: Box::new(Node::If(IfDetails {
+                test: Box::new(Node::Constant(ConstantLiteral::NilLiteral)),
+                then: Box::new(Node::Constant(ConstantLiteral::IntegerLiteral(1))),
+                otherwise: None,
+            })),
+            otherwise: Some(Box::new(Node::Constant(ConstantLiteral::BooleanLiteral(
+                false,
+            )))),
+        });
+
+        let nodes = parser.parse().unwrap();
+        assert_eq!(nodes[0], tree)
+    }
+
+    #[test]
+    fn parse_if_without_branches() {
+        let text = "(if true)".to_string();
+        let parser = Parser::new(&text);
+
+        assert_eq!(
+            parser.parse(),
+            Err(ParseError::InvalidSpecialForm(
+                Position { line: 1, column: 2 },
+                Lexeme::If
+            ))
+        )
+    }
+
+    #[test]
+    fn parse_let() {
+        let text = "(let [a 1 b a] b)".to_string();
+        let parser = Parser::new(&text);
+
+        let tree = Node::Let(LetDetails {
+            bindings: vec![
+                VariableInformation {
+                    name: Box::new(Node::Variable(
+                        "a".to_owned(),
+                        Position { line: 1, column: 7 },
+                    )),
+                    value: Box::new(Node::Constant(ConstantLiteral::IntegerLiteral(1))),
+                },
+                VariableInformation {
+                    name: Box::new(Node::Variable(
+                        "b".to_owned(),
+                        Position {
+                            line: 1,
+                            column: 11,
+                        },
+                    )),
+                    value: Box::new(Node::Variable(
+                        "a".to_owned(),
+                        Position {
+                            line: 1,
+                            column: 13,
+                        },
+                    )),
+                },
+            ],
+            body: vec![Node::Variable(
+                "b".to_owned(),
+                Position {
+                    line: 1,
+                    column: 16,
+                },
+            )],
+        });
+
+        let nodes = parser.parse().unwrap();
+        assert_eq!(nodes[0], tree)
+    }
+
+    #[test]
+    fn parse_let_without_value() {
+        let text = "(let [a 1 b] b)".to_string();
+        let parser = Parser::new(&text);
+
+        assert_eq!(
+            parser.parse(),
+            Err(ParseError::InvalidSpecialForm(
+                Position { line: 1, column: 2 },
+                Lexeme::Let
+            ))
+        )
+    }
 }
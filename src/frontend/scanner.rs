@@ -10,9 +10,12 @@ pub enum Lexeme {
     RightParen,
     LeftBrace,
     RightBrace,
+    /// Opening `#{` of a set literal
+    HashLeftBrace,
+    /// Opening `#(` of an anonymous function literal
+    HashLeftParen,
     LeftBracket,
     RightBracket,
-    Comma,
     Dot,
     Minus,
     Plus,
@@ -31,27 +34,42 @@ pub enum Lexeme {
 
     Identifier(String),
     StringLiteral(String),
-    NumberLiteral(i32),
+    NumberLiteral(i64),
+    FloatLiteral(f64),
 
     And,
+    /// `:name` or `:ns/name`, holding the name along with its namespace
     MapKey(String),
+    /// `::name`, whose namespace is the current one
+    AutoMapKey(String),
     False,
     For,
+    Case,
     Cond,
+    Condp,
     Def,
     Defn,
+    Do,
+    Fn,
+    If,
+    Let,
+    Loop,
     Nil,
+    NotEqual,
     Or,
     Print,
+    Recur,
     True,
     Main,
+    When,
+    WhenNot,
 
     Comment,
     Whitespace,
     EOF,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Position {
     pub line: usize,
     pub column: usize,
@@ -87,9 +105,10 @@ impl Token {
     }
 }
 
+/// Commas separate elements only for the reader's sake, like whitespace
 fn is_whitespace(c: char) -> bool {
     match c {
-        ' ' | '\r' | '\t' | '\n' => true,
+        ' ' | '\r' | '\t' | '\n' | ',' => true,
         _ => false,
     }
 }
@@ -102,6 +121,14 @@ fn is_alpha(c: char) -> bool {
     return (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_' || c == '-';
 }
 
+/// Characters which can appear in a symbol but not start it, as in `not=`
+fn is_symbol_suffix(c: char) -> bool {
+    match c {
+        '=' | '?' | '!' | '*' => true,
+        _ => false,
+    }
+}
+
 fn check_keyword(
     input_string: &String,
     index: usize,
@@ -118,6 +145,9 @@ fn check_keyword(
 #[derive(Debug, PartialEq)]
 pub enum ScanError {
     UnknownCharacter(Position, String),
+    UnknownEscape(Position, String),
+    UnterminatedString(Position),
+    InvalidNumber(Position, String),
 }
 
 impl fmt::Display for ScanError {
@@ -126,6 +156,15 @@ impl fmt::Display for ScanError {
             ScanError::UnknownCharacter(ref pos, ref string) => {
                 write!(f, "unknown character {:?} at {:?}", pos, string)
             }
+            ScanError::UnknownEscape(ref pos, ref string) => {
+                write!(f, "unknown escape sequence {:?} at {:?}", string, pos)
+            }
+            ScanError::UnterminatedString(ref pos) => {
+                write!(f, "string starting at {:?} is never closed", pos)
+            }
+            ScanError::InvalidNumber(ref pos, ref string) => {
+                write!(f, "invalid number {:?} at {:?}", string, pos)
+            }
         }
     }
 }
@@ -140,6 +179,7 @@ pub struct Scanner<'a> {
     source: MultiPeek<Chars<'a>>,
     current_string: String,
     current_position: Position,
+    token_start: Position,
 }
 
 impl<'a> Scanner<'a> {
@@ -148,16 +188,27 @@ impl<'a> Scanner<'a> {
             source: itertools::multipeek(text.chars()),
             current_string: String::new(),
             current_position: Position::reset(),
+            token_start: Position::reset(),
         }
     }
 
     pub fn scan_token(&mut self) -> Result<Token, ScanError> {
         self.current_string.clear();
+        self.token_start = self.current_position;
         match self.advance() {
             Some('(') => self.make_token(Lexeme::LeftParen),
             Some(')') => self.make_token(Lexeme::RightParen),
             Some('{') => self.make_token(Lexeme::LeftBrace),
             Some('}') => self.make_token(Lexeme::RightBrace),
+            Some('#') if self.peek_nth(0) == Some('{') => {
+                self.advance();
+                self.make_token(Lexeme::HashLeftBrace)
+            }
+            Some('#') if self.peek_nth(0) == Some('(') => {
+                self.advance();
+                self.make_token(Lexeme::HashLeftParen)
+            }
+            Some('%') => self.make_argument(),
             Some('[') => self.make_token(Lexeme::LeftBracket),
             Some(']') => self.make_token(Lexeme::RightBracket),
             Some(':') => {
@@ -176,8 +227,8 @@ impl<'a> Scanner<'a> {
                     self.make_token(Lexeme::SemiColon)
                 }
             }
-            Some(',') => self.make_token(Lexeme::Comma),
             Some('.') => self.make_token(Lexeme::Dot),
+            Some('-') if self.peek_nth(0).map_or(false, is_digit) => self.make_digit(),
             Some('-') => self.make_token(Lexeme::Minus),
             Some('+') => self.make_token(Lexeme::Plus),
             Some('*') => self.make_token(Lexeme::Star),
@@ -240,6 +291,9 @@ impl<'a> Scanner<'a> {
             self.source.next();
             return true;
         }
+        // a peek which does not match must not hide the character from the
+        // next one
+        self.source.reset_peek();
         false
     }
 
@@ -252,41 +306,83 @@ impl<'a> Scanner<'a> {
     }
 
     fn make_string(&mut self) -> Result<Token, ScanError> {
-        // remove the starting '"'
-        self.current_string.pop();
+        let mut string = String::new();
         loop {
-            self.advance();
-            if let Some('"') = self.source.peek() {
-                break;
+            match self.advance() {
+                Some('"') => break,
+                Some('\\') => {
+                    let escaped = match self.advance() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        other => {
+                            return Err(ScanError::UnknownEscape(
+                                self.current_position,
+                                other.map(|ch| format!("\\{}", ch)).unwrap_or_default(),
+                            ))
+                        }
+                    };
+                    string.push(escaped);
+                }
+                Some(ch) => string.push(ch),
+                None => return Err(ScanError::UnterminatedString(self.token_start)),
             }
         }
-        // skip the trailing '"'
-        self.source.next();
-        self.make_token(Lexeme::StringLiteral(String::from(&self.current_string)))
+        self.make_token(Lexeme::StringLiteral(string))
     }
 
+    /// Scans an integer or a floating point number with an optional fraction
+    /// and exponent, as in `42`, `-3.14` or `2.5E-3`
     fn make_digit(&mut self) -> Result<Token, ScanError> {
-        let mut decimal_count = 1;
-        loop {
-            match self.source.peek() {
-                // handle decimals if present
-                Some('.') if decimal_count != 0 => match self.source.peek() {
-                    // ensure digit after decimal is a valid number, if not we treat the
-                    // decimal as a dot instead
-                    Some(&ch) if is_digit(ch) => {
-                        decimal_count -= 1;
-                        self.advance();
-                    }
-                    _ => {}
-                },
-                Some(&c) if is_digit(c) => {
-                    self.advance();
-                }
-                _ => break,
+        self.scan_digits();
+        let mut is_float = false;
+        // the digits after a dot may be left out, `1.` being `1.0`
+        if self.peek_nth(0) == Some('.') {
+            self.advance();
+            self.scan_digits();
+            is_float = true;
+        }
+        // an exponent marker without digits after it makes an invalid number
+        if let Some('e') | Some('E') = self.peek_nth(0) {
+            self.advance();
+            if let Some('+') | Some('-') = self.peek_nth(0) {
+                self.advance();
             }
+            self.scan_digits();
+            is_float = true;
         }
 
-        self.make_token(Lexeme::NumberLiteral(self.current_string.parse().unwrap()))
+        let lexeme = if is_float {
+            self.current_string.parse().map(Lexeme::FloatLiteral).ok()
+        } else {
+            self.current_string.parse().map(Lexeme::NumberLiteral).ok()
+        };
+        match lexeme {
+            Some(lexeme) => self.make_token(lexeme),
+            None => Err(ScanError::InvalidNumber(
+                self.token_start,
+                String::from(&self.current_string),
+            )),
+        }
+    }
+
+    fn scan_digits(&mut self) {
+        while self.peek_nth(0).map_or(false, is_digit) {
+            self.advance();
+        }
+    }
+
+    /// Looks at the character `n` places ahead without consuming anything
+    fn peek_nth(&mut self, n: usize) -> Option<char> {
+        self.source.reset_peek();
+        let mut character = None;
+        for _ in 0..=n {
+            character = self.source.peek().cloned();
+        }
+        self.source.reset_peek();
+        character
     }
 
     fn make_identifier(&mut self) -> Result<Token, ScanError> {
@@ -297,17 +393,44 @@ impl<'a> Scanner<'a> {
     }
 
     fn make_map_key(&mut self) -> Result<Token, ScanError> {
-        // remove the starting ':'
-        self.current_string.pop();
+        let resolved = self.peek_nth(0) == Some(':');
+        if resolved {
+            self.advance();
+        }
+        // remove the starting colons
+        self.current_string.clear();
 
         self.scan_word();
-        self.make_token(Lexeme::MapKey(String::from(&self.current_string)))
+        if !resolved && self.peek_nth(0) == Some('/') && self.peek_nth(1).map_or(false, is_alpha) {
+            self.advance();
+            self.scan_word();
+        }
+        let name = String::from(&self.current_string);
+        self.make_token(if resolved {
+            Lexeme::AutoMapKey(name)
+        } else {
+            Lexeme::MapKey(name)
+        })
+    }
+
+    /// `%` names the argument of an anonymous function literal, and `%1`,
+    /// `%2` and so on each of its arguments
+    fn make_argument(&mut self) -> Result<Token, ScanError> {
+        loop {
+            match self.source.peek() {
+                Some(&ch) if is_digit(ch) => {
+                    self.advance();
+                }
+                _ => break,
+            }
+        }
+        self.make_token(Lexeme::Identifier(String::from(&self.current_string)))
     }
 
     fn scan_word(&mut self) {
         loop {
             match self.source.peek() {
-                Some(&ch) if is_alpha(ch) || is_digit(ch) => {
+                Some(&ch) if is_alpha(ch) || is_digit(ch) || is_symbol_suffix(ch) => {
                     self.advance();
                 }
                 _ => break,
@@ -322,22 +445,46 @@ impl<'a> Scanner<'a> {
             'f' if self.current_string.len() > 1 => match current_chars.peek().unwrap() {
                 'a' => check_keyword(&self.current_string, 2, "lse".into(), Lexeme::False),
                 'o' => check_keyword(&self.current_string, 2, "r".into(), Lexeme::For),
+                'n' => check_keyword(&self.current_string, 2, "".into(), Lexeme::Fn),
+                _ => Lexeme::Identifier(String::from(&self.current_string)),
+            },
+            'c' if self.current_string.len() > 1 => match current_chars.peek().unwrap() {
+                'a' => check_keyword(&self.current_string, 2, "se".into(), Lexeme::Case),
+                'o' if self.current_string.len() > 4 => {
+                    check_keyword(&self.current_string, 2, "ndp".into(), Lexeme::Condp)
+                }
+                'o' => check_keyword(&self.current_string, 2, "nd".into(), Lexeme::Cond),
                 _ => Lexeme::Identifier(String::from(&self.current_string)),
             },
-            'c' => check_keyword(&self.current_string, 1, "ond".into(), Lexeme::Cond),
             'd' if self.current_string.len() > 1 => match current_chars.peek().unwrap() {
-                'e' if self.current_string.len() > 2 => match current_chars.peek().unwrap() {
+                'e' if self.current_string.len() > 3 => match current_chars.peek().unwrap() {
                     'f' => check_keyword(&self.current_string, 3, "n".into(), Lexeme::Defn),
                     _ => Lexeme::Identifier(String::from(&self.current_string)),
                 },
                 'e' => check_keyword(&self.current_string, 2, "f".into(), Lexeme::Def),
+                'o' => check_keyword(&self.current_string, 2, "".into(), Lexeme::Do),
+                _ => Lexeme::Identifier(String::from(&self.current_string)),
+            },
+            'i' => check_keyword(&self.current_string, 1, "f".into(), Lexeme::If),
+            'l' if self.current_string.len() > 1 => match current_chars.peek().unwrap() {
+                'e' => check_keyword(&self.current_string, 2, "t".into(), Lexeme::Let),
+                'o' => check_keyword(&self.current_string, 2, "op".into(), Lexeme::Loop),
                 _ => Lexeme::Identifier(String::from(&self.current_string)),
             },
             'm' => check_keyword(&self.current_string, 1, "ain".into(), Lexeme::Main),
-            'n' => check_keyword(&self.current_string, 1, "il".into(), Lexeme::Nil),
+            'n' if self.current_string.len() > 1 => match current_chars.peek().unwrap() {
+                'i' => check_keyword(&self.current_string, 2, "l".into(), Lexeme::Nil),
+                'o' => check_keyword(&self.current_string, 2, "t=".into(), Lexeme::NotEqual),
+                _ => Lexeme::Identifier(String::from(&self.current_string)),
+            },
             'o' => check_keyword(&self.current_string, 1, "r".into(), Lexeme::Or),
             'p' => check_keyword(&self.current_string, 1, "rint".into(), Lexeme::Print),
+            'r' => check_keyword(&self.current_string, 1, "ecur".into(), Lexeme::Recur),
             't' => check_keyword(&self.current_string, 1, "rue".into(), Lexeme::True),
+            'w' if self.current_string.len() > 4 => {
+                check_keyword(&self.current_string, 1, "hen-not".into(), Lexeme::WhenNot)
+            }
+            'w' => check_keyword(&self.current_string, 1, "hen".into(), Lexeme::When),
             _ => Lexeme::Identifier(String::from(&self.current_string)),
         }
     }
@@ -345,7 +492,7 @@ impl<'a> Scanner<'a> {
     fn make_token(&self, token_type: Lexeme) -> Result<Token, ScanError> {
         Ok(Token {
             lexeme: token_type,
-            position: self.current_position,
+            position: self.token_start,
         })
     }
 }
@@ -381,8 +528,12 @@ pub fn scan_into_peekable(source: String) -> Result<Peekable<IntoIter<Token>>, S
 
 #[cfg(test)]
 mod tests {
-    use crate::frontend::scanner::Lexeme::NumberLiteral;
-    use crate::frontend::scanner::Scanner;
+    use crate::frontend::scanner::Lexeme::{
+        AutoMapKey, Case, Cond, Condp, Do, Dot, FloatLiteral, Fn, HashLeftBrace, HashLeftParen,
+        Identifier, LessEqual, MapKey, Minus, NotEqual, NumberLiteral, RightParen, Slash,
+        StringLiteral, When, WhenNot, Whitespace,
+    };
+    use crate::frontend::scanner::{Position, ScanError, Scanner};
 
     #[test]
     fn parse_numbers() {
@@ -390,8 +541,199 @@ mod tests {
         let mut scanner = Scanner::new(&text);
 
         assert_eq!(
-            NumberLiteral(123 as i32),
+            NumberLiteral(123 as i64),
             scanner.scan_token().unwrap().lexeme
         )
     }
+
+    #[test]
+    fn parse_floats() {
+        let text = "2.75 -2 1e10 2.5E-3 - 4. x".to_string();
+        let mut scanner = Scanner::new(&text);
+
+        let mut lexemes = vec![];
+        loop {
+            match scanner.scan_token().unwrap().lexeme {
+                Whitespace => {}
+                Identifier(_) => break,
+                lexeme => lexemes.push(lexeme),
+            }
+        }
+        assert_eq!(
+            lexemes,
+            vec![
+                FloatLiteral(2.75),
+                NumberLiteral(-2),
+                FloatLiteral(1e10),
+                FloatLiteral(2.5e-3),
+                Minus,
+                FloatLiteral(4.0),
+            ]
+        )
+    }
+
+    #[test]
+    fn integers_out_of_range() {
+        let text = "99999999999999999999".to_string();
+        let mut scanner = Scanner::new(&text);
+
+        assert_eq!(
+            Err(ScanError::InvalidNumber(
+                Position { line: 1, column: 1 },
+                "99999999999999999999".to_owned()
+            )),
+            scanner.scan_token()
+        )
+    }
+
+    #[test]
+    fn exponents_without_digits() {
+        let text = "1e 2E+".to_string();
+        let mut scanner = Scanner::new(&text);
+
+        assert_eq!(
+            Err(ScanError::InvalidNumber(
+                Position { line: 1, column: 1 },
+                "1e".to_owned()
+            )),
+            scanner.scan_token()
+        );
+        scanner.scan_token().unwrap();
+        assert_eq!(
+            Err(ScanError::InvalidNumber(
+                Position { line: 1, column: 4 },
+                "2E+".to_owned()
+            )),
+            scanner.scan_token()
+        )
+    }
+
+    #[test]
+    fn parse_string_escapes() {
+        let text = r#""say \"hi\"\n" """#.to_string();
+        let mut scanner = Scanner::new(&text);
+
+        assert_eq!(
+            StringLiteral("say \"hi\"\n".to_owned()),
+            scanner.scan_token().unwrap().lexeme
+        );
+        scanner.scan_token().unwrap();
+        assert_eq!(
+            StringLiteral(String::new()),
+            scanner.scan_token().unwrap().lexeme
+        )
+    }
+
+    #[test]
+    fn parse_comparison_operators() {
+        let text = "not= <= note valid?".to_string();
+        let mut scanner = Scanner::new(&text);
+
+        let mut next = || loop {
+            let lexeme = scanner.scan_token().unwrap().lexeme;
+            if lexeme != Whitespace {
+                return lexeme;
+            }
+        };
+        assert_eq!(next(), NotEqual);
+        assert_eq!(next(), LessEqual);
+        assert_eq!(next(), Identifier("note".to_owned()));
+        assert_eq!(next(), Identifier("valid?".to_owned()))
+    }
+
+    #[test]
+    fn parse_conditional_keywords() {
+        let text = "do when when-not whence cond condp conds case".to_string();
+        let mut scanner = Scanner::new(&text);
+        let mut next = || loop {
+            match scanner.scan_token().unwrap().lexeme {
+                Whitespace => continue,
+                lexeme => return lexeme,
+            }
+        };
+
+        assert_eq!(next(), Do);
+        assert_eq!(next(), When);
+        assert_eq!(next(), WhenNot);
+        assert_eq!(next(), Identifier("whence".to_owned()));
+        assert_eq!(next(), Cond);
+        assert_eq!(next(), Condp);
+        assert_eq!(next(), Identifier("conds".to_owned()));
+        assert_eq!(next(), Case);
+    }
+
+    #[test]
+    fn parse_short_map_keys() {
+        let text = ":a :bc".to_string();
+        let mut scanner = Scanner::new(&text);
+
+        assert_eq!(MapKey("a".to_owned()), scanner.scan_token().unwrap().lexeme);
+        scanner.scan_token().unwrap();
+        assert_eq!(
+            MapKey("bc".to_owned()),
+            scanner.scan_token().unwrap().lexeme
+        )
+    }
+
+    #[test]
+    fn parse_namespaced_map_keys() {
+        let text = ":ns/a ::b :c/ d".to_string();
+        let mut scanner = Scanner::new(&text);
+        let mut next = || loop {
+            match scanner.scan_token().unwrap().lexeme {
+                Whitespace => continue,
+                lexeme => return lexeme,
+            }
+        };
+
+        assert_eq!(next(), MapKey("ns/a".to_owned()));
+        assert_eq!(next(), AutoMapKey("b".to_owned()));
+        assert_eq!(next(), MapKey("c".to_owned()));
+        assert_eq!(next(), Slash);
+        assert_eq!(next(), Identifier("d".to_owned()));
+    }
+
+    #[test]
+    fn parse_anonymous_functions() {
+        let text = "#(fn % %12)".to_string();
+        let mut scanner = Scanner::new(&text);
+        let mut next = || loop {
+            match scanner.scan_token().unwrap().lexeme {
+                Whitespace => continue,
+                lexeme => return lexeme,
+            }
+        };
+
+        assert_eq!(next(), HashLeftParen);
+        assert_eq!(next(), Fn);
+        assert_eq!(next(), Identifier("%".to_owned()));
+        assert_eq!(next(), Identifier("%12".to_owned()));
+        assert_eq!(next(), RightParen);
+    }
+
+    #[test]
+    fn parse_set_braces() {
+        let text = "#{1}".to_string();
+        let mut scanner = Scanner::new(&text);
+
+        assert_eq!(HashLeftBrace, scanner.scan_token().unwrap().lexeme);
+        assert_eq!(
+            Position { line: 1, column: 3 },
+            scanner.scan_token().unwrap().position
+        )
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let text = "\"never closed".to_string();
+        let mut scanner = Scanner::new(&text);
+
+        assert_eq!(
+            Err(ScanError::UnterminatedString(Position {
+                line: 1,
+                column: 1
+            })),
+            scanner.scan_token()
+        )
+    }
 }
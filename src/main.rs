@@ -4,7 +4,9 @@
 mod codegen;
 mod frontend;
 
-use codegen::emitter::Emitter;
+use codegen::binary;
+use codegen::emitter::{EmitError, Emitter, Options, TailCalls};
+use codegen::module::Memory;
 use frontend::parser::{ParseError, Parser};
 use std::env;
 use std::fs::File;
@@ -13,7 +15,60 @@ use std::io::{BufReader, Read, Write};
 #[derive(Debug)]
 enum AppError {
     Parse(ParseError),
+    Emit(EmitError),
     Io(std::io::Error),
+    InvalidArgument(String),
+}
+
+enum OutputFormat {
+    Text,
+    Binary,
+}
+
+impl OutputFormat {
+    fn from_args(args: &[String]) -> Result<OutputFormat, AppError> {
+        match args.iter().position(|arg| arg == "--format") {
+            None => Ok(OutputFormat::Text),
+            Some(index) => match args.get(index + 1).map(String::as_str) {
+                Some("wat") => Ok(OutputFormat::Text),
+                Some("wasm") => Ok(OutputFormat::Binary),
+                other => Err(AppError::InvalidArgument(format!(
+                    "expected --format wat|wasm, found {:?}",
+                    other
+                ))),
+            },
+        }
+    }
+}
+
+fn memory_from_args(args: &[String]) -> Result<Memory, AppError> {
+    match args.iter().position(|arg| arg == "--memory") {
+        None => Ok(Options::default().memory),
+        Some(index) => match args.get(index + 1).map(String::as_str) {
+            Some("gc") => Ok(Memory::Collected),
+            Some("arena") => Ok(Memory::Arena),
+            Some("wasm-gc") => Ok(Memory::Engine),
+            other => Err(AppError::InvalidArgument(format!(
+                "expected --memory gc|arena|wasm-gc, found {:?}",
+                other
+            ))),
+        },
+    }
+}
+
+fn tail_calls_from_args(args: &[String]) -> Result<TailCalls, AppError> {
+    match args.iter().position(|arg| arg == "--tail-calls") {
+        None => Ok(Options::default().tail_calls),
+        Some(index) => match args.get(index + 1).map(String::as_str) {
+            Some("plain") => Ok(TailCalls::Plain),
+            Some("native") => Ok(TailCalls::Native),
+            Some("trampoline") => Ok(TailCalls::Trampoline),
+            other => Err(AppError::InvalidArgument(format!(
+                "expected --tail-calls plain|native|trampoline, found {:?}",
+                other
+            ))),
+        },
+    }
 }
 
 impl From<std::io::Error> for AppError {
@@ -28,8 +83,19 @@ impl From<ParseError> for AppError {
     }
 }
 
+impl From<EmitError> for AppError {
+    fn from(err: EmitError) -> Self {
+        AppError::Emit(err)
+    }
+}
+
 fn main() -> Result<(), AppError> {
     let args: Vec<String> = env::args().collect();
+    let format = OutputFormat::from_args(&args)?;
+    let options = Options {
+        memory: memory_from_args(&args)?,
+        tail_calls: tail_calls_from_args(&args)?,
+    };
     let file = File::open(args[1].to_owned())?;
     let mut buf_reader = BufReader::new(file);
     let mut contents = String::new();
@@ -37,10 +103,12 @@ fn main() -> Result<(), AppError> {
     let parser = Parser::new(&contents);
 
     let tree = parser.parse()?;
-    let mut emitter = Emitter::new();
-    let content = emitter.emit(tree);
+    let emitter = Emitter::new(options);
+    let module = emitter.emit(tree)?;
 
-    let mut out = File::create("main.wat")?;
-    out.write_all(content.as_bytes())?;
+    match format {
+        OutputFormat::Text => File::create("main.wat")?.write_all(module.to_string().as_bytes())?,
+        OutputFormat::Binary => File::create("main.wasm")?.write_all(&binary::encode(&module))?,
+    }
     Ok(())
 }
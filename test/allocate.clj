@@ -0,0 +1,10 @@
+(defn box [n]
+  (if true n 0.5))
+
+(defn fill [depth]
+  (if (= depth 0)
+    (box 1)
+    (+ (fill (dec depth)) (fill (dec depth)))))
+
+(defn main []
+  (print (fill 14) "\n"))
@@ -0,0 +1,22 @@
+(defn chain [n]
+  (loop [m {} i 0]
+    (if (< i n)
+      (recur {:next m :junk [i i i]} (inc i))
+      m)))
+
+(defn depth [m]
+  (loop [m m d 0]
+    (if (:next m)
+      (recur (:next m) (inc d))
+      d)))
+
+(defn churn [n]
+  (loop [i 0 v []]
+    (if (< i n)
+      (recur (inc i) (conj v [i i]))
+      (count v))))
+
+(defn main []
+  (let [m (chain 100000)]
+    (print (churn 100000) "\n")
+    (print (depth m) "\n")))
@@ -0,0 +1,11 @@
+(defn twice [f x] (f (f x)))
+
+(defn add [a b] (+ a b))
+
+(defn adder [n] (fn [x] (+ x n)))
+
+(defn main []
+  (let [add5 (adder 5)]
+    (print (add5 10) " " (twice add5 0) " " (twice #(* % 3) 2) "\n"))
+  (print ((fn fact [n] (if (< n 2) 1 (* n (fact (dec n))))) 10) " " (#(+ %1 %2) 40 2) "\n")
+  (print (twice :a {:a {:a 7}}) " " (twice #{1} 1) " " (let [f add] (f 1 2)) " " (= add add) "\n"))
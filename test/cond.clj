@@ -0,0 +1,50 @@
+(defn sign [n]
+  (cond
+    (< n 0) "negative"
+    (= n 0) "zero"
+    :else "positive"))
+
+(defn describe [x]
+  (cond
+    (= x 1) :one
+    (= x "a") "letter"))
+
+(defn day [n]
+  (case n
+    0 "sun"
+    1 "mon"
+    (2 3 4) "midweek"
+    5 "fri"
+    "weekend"))
+
+(defn kind [x]
+  (case x
+    :a 1
+    ("b" 2.5) 2
+    nil 3
+    10))
+
+(defn size [n]
+  (condp < n
+    100 "large"
+    10 "medium"
+    "small"))
+
+(defn collatz [n]
+  (loop [n n steps 0]
+    (cond
+      (= n 1) steps
+      (= 0 (mod n 2)) (recur (quot n 2) (inc steps))
+      :else (recur (+ 1 (* 3 n)) (inc steps)))))
+
+(defn main []
+  (print (sign -5) (sign 0) (sign 7) "\n")
+  (print (describe 1) (describe "a") (describe 2) "\n")
+  (print (day 0) (day 3) (day 5) (day 6) (day -1) (day 7.0) "\n")
+  (print (kind :a) (kind "b") (kind 2.5) (kind nil) (kind 3) "\n")
+  (print (size 1000) (size 50) (size 1) "\n")
+  (print (collatz 27) "\n")
+  (do (print "do ") (print "twice\n"))
+  (print (when (< 1 2) (print "when ") 1) (when false 2) (when-not false 3) (when-not 1 4) "\n")
+  (print (cond) (condp = 2 1 :a 2 :b) "\n")
+  (case 9 1 2))
@@ -0,0 +1,5 @@
+(defn main []
+  (print (+ 0.1 0.2) " " (= (+ 0.1 0.2) 0.3) " " 0.3 "\n")
+  (print (* 1.0 9007199254740993) " " -0.0 " " (- 0.0) "\n")
+  (print 1.0 " " 100.5 " " 1234567.0 " " 1e7 " " 0.001 " " 1e-4 "\n")
+  (print 5e-324 " " 1.7976931348623157e308 " " (/ 1.0 3) "\n"))
@@ -0,0 +1,6 @@
+(defn kind [] :lisp)
+
+(defn main []
+  (print (= (kind) :lisp) " " (= :lisp :wasm) " " (= ::lang :user/lang) "\n")
+  (print :tool/name " " ::lang " " (::lang {:user/lang "wasl"}) "\n")
+  (print (get {:a 1 :tool/a 2} :tool/a) " " (contains? #{:a ::a} :user/a) "\n"))
@@ -0,0 +1,19 @@
+(defn noisy [x]
+  (print "<" x ">")
+  x)
+
+(defn all-positive [v]
+  (loop [i 0]
+    (or (= i (count v))
+        (and (< 0 (nth v i)) (recur (inc i))))))
+
+(defn main []
+  (print (and) (or) (and 1) (or nil) "\n")
+  (print (and 1 2 3) (and 1 nil 3) (and 1 false 3) "\n")
+  (print (or nil false 3) (or nil 2 3) (or false nil) "\n")
+  (print (and (noisy 1) (noisy nil) (noisy 2)) "\n")
+  (print (or (noisy false) (noisy :a) (noisy 2)) "\n")
+  (print (not nil) (not false) (not 0) (not "a") (not (= 1 2)) "\n")
+  (print (and 1 "a") (or nil [1 2]) (and [] 2.5) "\n")
+  (print (all-positive [1 2 3]) (all-positive [1 -2 3]) (all-positive []) "\n")
+  (print (when (and (< 1 2) (not (> 1 2))) "both") "\n"))
@@ -0,0 +1,13 @@
+(defn sum-to [n acc]
+  (if (= n 0) acc (recur (dec n) (+ acc n))))
+
+(defn fill [v n]
+  (loop [v v i 0]
+    (if (< i n) (recur (conj v i) (inc i)) v)))
+
+(defn main []
+  (print (sum-to 100000 0) " " (count (fill [] 50000)) "\n")
+  (print (loop [i 0 acc 1.5] (if (< i 3) (recur (inc i) (* acc 2)) acc)) " "
+         (loop [x 1 seen #{}] (if (seen x) (count seen) (recur (mod (* x 3) 7) (conj seen x)))) "\n")
+  (print ((fn count-down [n] (if (> n 0) (recur (dec n)) :done)) 5) " "
+         (loop [x nil n 0] (if (< n 2) (recur n (inc n)) x)) "\n"))
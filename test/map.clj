@@ -0,0 +1,9 @@
+(defn main []
+  (let [m {:name "wasl" :tags [:lisp :wasm] 1 2}
+        n (assoc m :version 1 1 3)]
+    (print (:name m) " " (count n) "\n")
+    (print (get n 1) " " (contains? n :tags) "\n")
+    (print (dissoc n :tags :version) "\n")
+    (print (update {:hits 1} :hits inc) "\n")
+    (print (merge {:a 1} {:a 2 :b 3}) "\n")
+    (print (= {:a [1 2]} {:a [1 2]}) "\n")))
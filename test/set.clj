@@ -0,0 +1,10 @@
+(defn main []
+  (let [s #{:lisp :wasm 1}
+        t (conj s 2 :lisp)]
+    (print (count s) " " (count t) "\n")
+    (print (s :wasm) " " (s :java) " " (t 3 "none") "\n")
+    (print (contains? t 2) " " (get t :lisp) "\n")
+    (print (disj t :wasm :lisp 1) "\n")
+    (print (= #{[1 2] 3} #{3 [1 2]}) " " (= s t) "\n")
+    (print (= #{#{1} 2} (conj #{2} #{1})) "\n")
+    (print (#{1 2} 2) " " ({:a 1} :a) " " ([5 6] 1) "\n")))
@@ -0,0 +1,15 @@
+;; Deep mutual recursion which needs proper tail calls, so compile it with
+;; `--tail-calls native` or `--tail-calls trampoline`. It overflows the stack
+;; with the default plain calls.
+
+(defn is-even [n] (if (= n 0) true (is-odd (dec n))))
+
+(defn is-odd [n] (if (= n 0) false (is-even (dec n))))
+
+(defn count-up [v n] (if (= (count v) n) v (count-up (conj v (count v)) n)))
+
+(defn apply-times [f x n] (if (= n 0) x (let [again apply-times] (again f (f x) (dec n)))))
+
+(defn main []
+  (print (is-even 1000000) " " (is-odd 7) "\n")
+  (print (count (count-up [] 100000)) " " (apply-times #(+ % 2) 0 100000) "\n"))
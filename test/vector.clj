@@ -0,0 +1,12 @@
+(defn fill [v n]
+  (if (= n 0)
+    v
+    (fill (conj v n) (dec n))))
+
+(defn main []
+  (let [v (fill [] 100)
+        w (assoc (pop v) 0 "first")]
+    (print (count v) " " (nth v 40) " " (peek v) "\n")
+    (print (subvec w 0 3) " " (get w 99 "none") "\n")
+    (print (= (subvec v 97) [3 2 1]) "\n")
+    (print ([5 6] 1) " " (v 40) " " (w 0) "\n")))